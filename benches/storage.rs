@@ -0,0 +1,45 @@
+#[macro_use]
+extern crate criterion;
+extern crate midi;
+
+use criterion::{black_box, Criterion};
+use midi::{parse_midi_with, ParseOptions, Storage};
+use std::env;
+use std::fs;
+
+/// The files in the directory named by `MIDI_BENCH_DIR`, or a thousand
+/// random ones, with their mix of short and long tracks, if it isn't set.
+fn corpus() -> Vec<Vec<u8>> {
+    match env::var_os("MIDI_BENCH_DIR") {
+        Some(dir) => fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| fs::read(entry.ok()?.path()).ok())
+            .collect(),
+        None => (0..1000).map(midi::gen::random_file).collect(),
+    }
+}
+
+/// Parses the whole corpus with each storage strategy.
+fn bench_storage(c: &mut Criterion) {
+    let files = corpus();
+    let strategies = [
+        ("growable", Storage::Growable),
+        ("counted", Storage::Counted),
+        ("estimated", Storage::default()),
+        ("inline", Storage::Inline),
+        ("arena", Storage::Arena),
+    ];
+    for &(name, storage) in &strategies {
+        let options = ParseOptions { storage: storage, ..Default::default() };
+        c.bench_function(&format!("parse corpus, {} storage", name), |b| {
+            b.iter(|| {
+                for data in &files {
+                    let _ = black_box(parse_midi_with(data, &options));
+                }
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_storage);
+criterion_main!(benches);
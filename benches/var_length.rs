@@ -0,0 +1,100 @@
+#[macro_use]
+extern crate criterion;
+extern crate midi;
+extern crate nom;
+
+use criterion::{black_box, Criterion};
+use midi::{parse_midi, var_length, ErrorKind, IResult};
+use nom::{Err, Needed};
+use std::env;
+use std::fs;
+
+/// The byte-at-a-time implementation that `var_length` replaced, kept here
+/// to compare against.
+fn var_length_bytewise(input: &[u8]) -> IResult<&[u8], u32> {
+    let mut result = 0;
+    for i in 0..4 {
+        if i >= input.len() {
+            return Err(Err::Incomplete(Needed::Unknown));
+        }
+        result <<= 7;
+        result |= (input[i] & 0x7F) as u32;
+        if input[i] & 0x80 == 0 {
+            return Ok((&input[i + 1..], result));
+        }
+    }
+    Err(Err::Error(ErrorKind::Custom(0)))
+}
+
+/// A run of encoded values with the mix of lengths typical of delta times,
+/// mostly one byte with some longer ones.
+fn deltas() -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..10_000u32 {
+        let value = match i % 10 {
+            0...6 => i % 0x80,
+            7 | 8 => 0x80 + i,
+            _ => 0x4000 + i * 31,
+        };
+        midi::write::write_var_length(&mut data, value);
+    }
+    data
+}
+
+fn decode_all(data: &[u8], decode: fn(&[u8]) -> IResult<&[u8], u32>) -> u32 {
+    let mut input = data;
+    let mut sum = 0u32;
+    while let Ok((rest, value)) = decode(input) {
+        sum = sum.wrapping_add(value);
+        input = rest;
+    }
+    sum
+}
+
+fn bench_var_length(c: &mut Criterion) {
+    let data = deltas();
+    c.bench_function("var_length bytewise", |b| {
+        b.iter(|| decode_all(black_box(&data), var_length_bytewise))
+    });
+    c.bench_function("var_length", |b| b.iter(|| decode_all(black_box(&data), var_length)));
+}
+
+/// Parses every file in the directory named by `MIDI_BENCH_DIR`, if it's
+/// set, and decodes the delta times of those files with both
+/// implementations.
+fn bench_corpus(c: &mut Criterion) {
+    let dir = match env::var_os("MIDI_BENCH_DIR") {
+        Some(dir) => dir,
+        None => return,
+    };
+    let files: Vec<Vec<u8>> = fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|entry| fs::read(entry.ok()?.path()).ok())
+        .collect();
+    let mut deltas = Vec::new();
+    for data in &files {
+        if let Ok(midi) = parse_midi(data) {
+            for track in midi.tracks() {
+                for event in track.events() {
+                    midi::write::write_var_length(&mut deltas, event.delta());
+                }
+            }
+        }
+    }
+    c.bench_function("parse corpus", |b| {
+        b.iter(|| {
+            for data in &files {
+                let _ = black_box(parse_midi(data));
+            }
+        })
+    });
+    c.bench_function("var_length bytewise corpus", |b| {
+        b.iter(|| decode_all(black_box(&deltas), var_length_bytewise))
+    });
+    c.bench_function("var_length corpus", |b| {
+        b.iter(|| decode_all(black_box(&deltas), var_length))
+    });
+}
+
+criterion_group!(benches, bench_var_length, bench_corpus);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate midi;
+
+// Any input must give an error rather than a panic, from every parser.
+fuzz_target!(|data: &[u8]| {
+    midi::fuzz::all_parsers(data);
+    midi::fuzz::all_parsers(&midi::fuzz::as_track(data));
+});
@@ -0,0 +1,10 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate midi;
+
+// Raw event bytes, both on their own and wrapped in a whole file.
+fuzz_target!(|data: &[u8]| {
+    midi::fuzz::parse_events(data);
+    let _ = midi::parse_midi(&midi::fuzz::as_track(data));
+});
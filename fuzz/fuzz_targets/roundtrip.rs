@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate midi;
+
+fuzz_target!(|data: &[u8]| {
+    midi::fuzz::roundtrip(&midi::fuzz::as_track(data));
+});
@@ -0,0 +1,83 @@
+/* Generated with cbindgen from src/ffi.rs. To regenerate, run
+ *
+ *     cbindgen --config cbindgen.toml --output include/nommidi.h
+ */
+
+#ifndef NOMMIDI_H
+#define NOMMIDI_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * A parsed file, owning the encoded bytes of all of its events so that
+ * nothing borrows from the buffer the caller passed in.
+ */
+typedef struct NommidiFile NommidiFile;
+
+/**
+ * One event, as filled in by `nommidi_event`. `data` points into the file
+ * and stays valid until the file is freed.
+ */
+typedef struct NommidiEvent {
+  uint64_t tick;
+  uint32_t delta;
+  /**
+   * The status byte of a channel message, 0xFF for a meta event, 0xF0 or
+   * 0xF7 for sysex, or 0 for a message using running status.
+   */
+  uint8_t status;
+  /**
+   * The type of a meta event, or 0 for anything else.
+   */
+  uint8_t meta_type;
+  /**
+   * The data bytes of a channel message, or the contents of a meta or
+   * sysex event.
+   */
+  const uint8_t *data;
+  size_t len;
+} NommidiEvent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/**
+ * Parses the `len` bytes at `data` as a Standard MIDI File or RMID file,
+ * returning null if they don't parse. The bytes aren't needed afterwards.
+ */
+NommidiFile *nommidi_parse(const uint8_t *data, size_t len);
+
+/**
+ * Frees a file returned by `nommidi_parse`. Passing null does nothing.
+ */
+void nommidi_free(NommidiFile *file);
+
+uint16_t nommidi_format(const NommidiFile *file);
+
+/**
+ * The raw division field of the header. If the top bit is clear, it's the
+ * number of ticks per quarter note.
+ */
+uint16_t nommidi_division(const NommidiFile *file);
+
+size_t nommidi_track_count(const NommidiFile *file);
+
+/**
+ * The number of events in a track, or 0 if there's no such track.
+ */
+size_t nommidi_event_count(const NommidiFile *file, size_t track);
+
+/**
+ * Fills in `out` with an event of a track, returning false if there's no
+ * such event.
+ */
+bool nommidi_event(const NommidiFile *file, size_t track, size_t index, NommidiEvent *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* NOMMIDI_H */
@@ -0,0 +1,204 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use {Key, KeyKind, Midi};
+use meter::{note_value, Meter};
+use notation::{bars, melody, signature_alter, spell, tonic, BarItem};
+
+
+// ABC Export //////////////////////////////////////////////////////////////////
+
+/// Settings for `to_abc_with`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AbcOptions {
+    /// The shortest note kept, as a fraction of a whole note, so 16 rounds
+    /// every note to sixteenths. It's the unit note length of the tune too.
+    pub shortest: u16,
+    /// How many bars go on each line.
+    pub bars_per_line: usize,
+}
+
+impl Default for AbcOptions {
+    fn default() -> AbcOptions {
+        AbcOptions {
+            shortest: 16,
+            bars_per_line: 4,
+        }
+    }
+}
+
+impl<'a> Midi<'a> {
+    pub fn to_abc(&self) -> Option<String> {
+        self.to_abc_with(&AbcOptions::default())
+    }
+
+    /// Writes the file as an ABC tune, with a voice for each track that has
+    /// notes. Each voice is the melody of its track, the highest note at any
+    /// time, with notes rounded to the nearest `options.shortest`. The key
+    /// is the one the file starts in. Returns `None` for files using SMPTE
+    /// time, which have no beats to write notes against.
+    pub fn to_abc_with(&self, options: &AbcOptions) -> Option<String> {
+        let map = self.meter_map()?;
+        let shortest = cmp::max(1, options.shortest) as u64;
+        let grid = cmp::max(1, map.ticks_per_quarter() as u64 * 4 / shortest);
+        let metadata = self.metadata();
+        let (signature, kind) = metadata.initial_key_signature
+            .unwrap_or((Key::OfC, KeyKind::Major));
+        let notes = self.notes();
+
+        let mut out = String::new();
+        let title = metadata.sequence_name().map(String::from_utf8_lossy);
+        let _ = writeln!(out, "X:1");
+        let _ = writeln!(out, "T:{}", title.as_ref().map_or("Untitled", |title| title.trim()));
+        let _ = writeln!(out, "M:{}", meter(map.meter_at(0)));
+        let _ = writeln!(out, "L:1/{}", shortest);
+        let tempo = self.tempo_map().tempo_at(0);
+        let _ = writeln!(out, "Q:1/4={}", (60_000_000.0 / cmp::max(1, tempo) as f64).round());
+        let (letter, alter) = tonic(signature, kind);
+        let _ = writeln!(out, "K:{}{}{}", letter, accidental(alter),
+                         if kind == KeyKind::Minor { "m" } else { "" });
+
+        let tracks: Vec<_> = self.tracks().collect();
+        let lines: Vec<_> = (0..tracks.len())
+            .map(|track| {
+                let notes: Vec<_> = notes.iter().filter(|note| note.track == track).cloned()
+                    .collect();
+                (track, melody(&notes, grid))
+            })
+            .filter(|&(_, ref line)| !line.is_empty())
+            .collect();
+        let end = lines.iter().filter_map(|&(_, ref line)| line.last()).map(|note| note.end)
+            .max()
+            .unwrap_or(0);
+        for (voice, &(track, ref line)) in lines.iter().enumerate() {
+            if lines.len() > 1 {
+                let _ = write!(out, "V:{}", voice + 1);
+                if let Some(name) = tracks[track].name() {
+                    let _ = write!(out, " name=\"{}\"", String::from_utf8_lossy(name).trim());
+                }
+                out.push('\n');
+            }
+            let mut current = map.meter_at(0);
+            let bars = bars(line, &map, end);
+            for (i, &(measure, ref items)) in bars.iter().enumerate() {
+                if measure.meter != current {
+                    current = measure.meter;
+                    let _ = write!(out, "[M:{}] ", meter(current));
+                }
+                // Accidentals last until the end of the bar.
+                let mut altered = HashMap::new();
+                for item in items {
+                    out.push_str(&note(item, grid, signature, &mut altered));
+                    out.push(' ');
+                }
+                if i + 1 == bars.len() {
+                    out.push_str("|]\n");
+                } else if (i + 1) % cmp::max(1, options.bars_per_line) == 0 {
+                    out.push_str("|\n");
+                } else {
+                    out.push_str("| ");
+                }
+            }
+        }
+        Some(out)
+    }
+}
+
+fn meter(meter: Meter) -> String {
+    format!("{}/{}", meter.numerator, note_value(meter.denominator))
+}
+
+fn accidental(alter: i8) -> &'static str {
+    match alter {
+        1 => "#",
+        -1 => "b",
+        _ => "",
+    }
+}
+
+/// Writes a note or rest, marking an accidental when the pitch differs
+/// from what the key signature and earlier accidentals in the bar give.
+fn note(item: &BarItem, grid: u64, signature: Key, altered: &mut HashMap<(char, i32), i8>)
+        -> String {
+    let mut out = String::new();
+    match item.key {
+        Some(key) => {
+            let pitch = spell(key, signature);
+            let current = *altered.entry((pitch.letter, pitch.octave))
+                .or_insert_with(|| signature_alter(signature, pitch.letter));
+            if pitch.alter != current {
+                out.push_str(match pitch.alter {
+                    1 => "^",
+                    -1 => "_",
+                    _ => "=",
+                });
+                altered.insert((pitch.letter, pitch.octave), pitch.alter);
+            }
+            if pitch.octave >= 5 {
+                out.extend(pitch.letter.to_lowercase());
+                for _ in 5..pitch.octave {
+                    out.push('\'');
+                }
+            } else {
+                out.push(pitch.letter);
+                for _ in pitch.octave..4 {
+                    out.push(',');
+                }
+            }
+        }
+        None => out.push('z'),
+    }
+    let ticks = item.end - item.start;
+    let common = gcd(ticks, grid);
+    match (ticks / common, grid / common) {
+        (1, 1) => {}
+        (length, 1) => out.push_str(&length.to_string()),
+        (length, unit) => out.push_str(&format!("{}/{}", length, unit)),
+    }
+    if item.tied {
+        out.push('-');
+    }
+    out
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { cmp::max(1, a) } else { gcd(b, a % b) }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_abc() {
+    let abc = ::gen::scale(60, &::gen::MAJOR).to_abc().unwrap();
+    assert_eq!(abc, "X:1\nT:Untitled\nM:4/4\nL:1/16\nQ:1/4=120\nK:C\n\
+                     C4 D4 E4 F4 | G4 A4 B4 c4 |]\n");
+    // The top notes of the chords, held for a bar each.
+    let abc = ::gen::chords(&[60, 65, 70]).to_abc().unwrap();
+    assert!(abc.ends_with("\nG16 | c16 | f16 |]\n"), "{}", abc);
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x2A,
+        0x00, 0xFF, 0x03, 0x03, b'J', b'i', b'g',
+        0x00, 0xFF, 0x58, 0x04, 0x06, 0x03, 0x18, 0x08,
+        0x00, 0xFF, 0x59, 0x02, 0x01, 0x01,
+        0x00, 0x90, 0x42, 0x40,
+        0x30, 0x41, 0x40,
+        0x30, 0x41, 0x00,
+        0x00, 0x42, 0x00,
+        0x83, 0x30, 0x42, 0x40,
+        0x30, 0xFF, 0x2F, 0x00,
+    ];
+    let abc = ::parse_midi(&data).unwrap().to_abc_with(&AbcOptions {
+        shortest: 8,
+        ..AbcOptions::default()
+    }).unwrap();
+    assert_eq!(abc, "X:1\nT:Jig\nM:6/8\nL:1/8\nQ:1/4=120\nK:Em\n\
+                     F =F z4 | z5 F |]\n");
+}
@@ -0,0 +1,541 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt::Write;
+
+use nom::Err;
+
+use {track_body, Chunk, ControlChange, ErrorKind, Event, Header, Key, KeyKind, MetaEvent, Midi,
+     MidiEvent, SysexEvent, TextType};
+use meter::note_value;
+use pitch::NoteNames;
+
+
+// Disassembler ////////////////////////////////////////////////////////////////
+
+static TEXT_NAMES: &'static [(TextType, &'static str)] = &[
+    (TextType::Text, "text"),
+    (TextType::Copyright, "copyright"),
+    (TextType::TrackName, "track_name"),
+    (TextType::InstrumentName, "instrument_name"),
+    (TextType::Lyric, "lyric"),
+    (TextType::Marker, "marker"),
+    (TextType::CuePoint, "cue_point"),
+    (TextType::ProgramName, "program_name"),
+    (TextType::DeviceName, "device_name"),
+];
+
+impl<'a> Midi<'a> {
+    /// Writes the file as text that `assemble` turns back into the same
+    /// file. Each event goes on its own line after the absolute tick it
+    /// happens on, like:
+    ///
+    /// ```text
+    /// format 1
+    /// division 96
+    /// track
+    ///     0 track_name "Piano"
+    ///     0 note_on 0 C4 64
+    ///     96 note_off 0 C4 0
+    ///     96 end_of_track
+    /// ```
+    pub fn to_asm(&self) -> String {
+        self.to_asm_with(&NoteNames::default())
+    }
+
+    /// Writes the file like `to_asm`, naming notes by a given convention.
+    pub fn to_asm_with(&self, names: &NoteNames) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "format {}", self.header.format);
+        let _ = match self.header.division() {
+            ::Division::TicksPerQuarter(n) => writeln!(out, "division {}", n),
+            ::Division::Smpte { frames_per_second, ticks_per_frame } => {
+                writeln!(out, "division smpte {} {}", frames_per_second, ticks_per_frame)
+            }
+        };
+        for chunk in &self.chunks {
+            match *chunk {
+                Chunk::Track(ref track) => {
+                    out.push_str("track\n");
+                    for (tick, event) in track.absolute_events() {
+                        let _ = write!(out, "    {} ", tick);
+                        write_event(&mut out, event, names);
+                        out.push('\n');
+                    }
+                }
+                Chunk::Other { kind, data } => {
+                    out.push_str("chunk ");
+                    write_string(&mut out, kind);
+                    write_hex(&mut out, data);
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+}
+
+fn write_event(out: &mut String, event: &Event, names: &NoteNames) {
+    use MidiEvent::*;
+    let _ = match *event {
+        Event::Midi(_, NoteOff { channel, number, velocity }) => {
+            write!(out, "note_off {} {} {}", channel, names.name(number), velocity)
+        }
+        Event::Midi(_, NoteOn { channel, number, velocity }) => {
+            write!(out, "note_on {} {} {}", channel, names.name(number), velocity)
+        }
+        Event::Midi(_, PolyphonicAftertouch { channel, number, pressure }) => {
+            write!(out, "aftertouch {} {} {}", channel, names.name(number), pressure)
+        }
+        Event::Midi(_, Control { channel, change }) => {
+            let (controller, value) = change.parts();
+            write!(out, "control {} {} {}", channel, controller, value)
+        }
+        Event::Midi(_, ProgramChange { channel, program_number }) => {
+            write!(out, "program {} {}", channel, program_number)
+        }
+        Event::Midi(_, ChannelAftertouch { channel, pressure }) => {
+            write!(out, "pressure {} {}", channel, pressure)
+        }
+        Event::Midi(_, PitchBend { channel, pitch }) => {
+            write!(out, "pitch_bend {} {}", channel, pitch as i32 - 0x2000)
+        }
+        // Running status is resolved while parsing, so this never comes up.
+        Event::Midi(_, Previous(..)) => Ok(()),
+        Event::Meta(_, ref meta) => write_meta(out, meta),
+        Event::Sysex(_, ref sysex) => {
+            out.push_str(if sysex.is_start() { "sysex" } else { "sysex_escape" });
+            write_hex(out, sysex.data());
+            Ok(())
+        }
+    };
+}
+
+fn write_meta(out: &mut String, meta: &MetaEvent) -> ::std::fmt::Result {
+    use MetaEvent::*;
+    match *meta {
+        SequenceNumber(n) => write!(out, "sequence_number {}", n),
+        Text { kind, text } => {
+            match TEXT_NAMES.iter().find(|&&(k, _)| k == kind) {
+                Some(&(_, name)) => out.push_str(name),
+                None => write!(out, "text_{}", u8::from(kind))?,
+            }
+            out.push(' ');
+            write_string(out, text);
+            Ok(())
+        }
+        ChannelPrefix(channel) => write!(out, "channel_prefix {}", channel),
+        EndOfTrack => write!(out, "end_of_track"),
+        SetTempo(tempo) => write!(out, "tempo {}", tempo),
+        SmpteOffset { hours, minutes, seconds, frames, fractional_frames } => {
+            write!(out, "smpte_offset {} {} {} {} {}",
+                   hours, minutes, seconds, frames, fractional_frames)
+        }
+        TimeSignature { numerator, denominator, clocks_per_metronome, notated_divisions } => {
+            write!(out, "time_signature {}/{} {} {}",
+                   numerator, note_value(denominator), clocks_per_metronome,
+                   notated_divisions)
+        }
+        KeySignature { key, kind } => {
+            let sharps = match key {
+                Key::Flats(n) => -(n as i16),
+                Key::OfC => 0,
+                Key::Sharps(n) => n as i16,
+            };
+            let mode = if kind == KeyKind::Minor { "minor" } else { "major" };
+            write!(out, "key_signature {} {}", sharps, mode)
+        }
+        SequencerSpecific { data } => {
+            out.push_str("sequencer_specific");
+            write_hex(out, data);
+            Ok(())
+        }
+        Other { kind, data } => {
+            write!(out, "meta {:02X}", kind)?;
+            write_hex(out, data);
+            Ok(())
+        }
+    }
+}
+
+fn write_hex(out: &mut String, data: &[u8]) {
+    for b in data {
+        let _ = write!(out, " {:02X}", b);
+    }
+}
+
+/// Quotes text, escaping quotes, backslashes and any byte outside printable
+/// ASCII.
+fn write_string(out: &mut String, text: &[u8]) {
+    out.push('"');
+    for &b in text {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20...0x7E => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\x{:02X}", b);
+            }
+        }
+    }
+    out.push('"');
+}
+
+
+// Assembler ///////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A line that can't be assembled, numbered from 1.
+    Line(usize),
+    /// The events described don't form a valid file.
+    Midi(ErrorKind),
+}
+
+/// Assembles the text format that `Midi::to_asm` writes into the bytes of a
+/// file. Blank lines and anything after a `;` are ignored, notes can be
+/// given by name or number, and events within a track have to be in order.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
+    assemble_with(source, &NoteNames::default())
+}
+
+/// Assembles text like `assemble`, reading note names by a given
+/// convention.
+pub fn assemble_with(source: &str, names: &NoteNames) -> Result<Vec<u8>, Error> {
+    let mut format = None;
+    let mut division = None;
+    // Tracks and other chunks in order, as encoded bodies.
+    let mut chunks: Vec<(Option<Vec<u8>>, Vec<u8>)> = Vec::new();
+    let mut last_tick = 0;
+    for (index, line) in source.lines().enumerate() {
+        let error = Error::Line(index + 1);
+        let words = split_words(line).ok_or_else(|| error.clone())?;
+        let (first, rest) = match words.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+        match first.text() {
+            Some("format") => format = Some(number(rest, 0, 0xFFFF).ok_or(error)? as u16),
+            Some("division") => {
+                division = Some(match rest.first().and_then(Word::text) {
+                    Some("smpte") => {
+                        let fps = number(&rest[1..], 0, 0x7F).ok_or_else(|| error.clone())? as u8;
+                        let ticks = number(&rest[1..], 1, 0xFF).ok_or(error)? as u16;
+                        (fps.wrapping_neg() as u16) << 8 | ticks
+                    }
+                    _ => number(rest, 0, 0x7FFF).ok_or(error)? as u16,
+                });
+            }
+            Some("track") if rest.is_empty() => {
+                chunks.push((None, Vec::new()));
+                last_tick = 0;
+            }
+            Some("chunk") => {
+                let kind = match rest.first() {
+                    Some(&Word::Quoted(ref kind)) if kind.len() == 4 => kind.clone(),
+                    _ => return Err(error),
+                };
+                chunks.push((Some(kind), hex(&rest[1..]).ok_or(error)?));
+            }
+            _ => {
+                let tick = first.text().and_then(|t| t.parse::<u64>().ok());
+                let body = match (tick, chunks.last_mut()) {
+                    (Some(tick), Some(&mut (None, ref mut body))) if tick >= last_tick => body,
+                    _ => return Err(error),
+                };
+                let tick = tick.unwrap_or(0);
+                if tick - last_tick > 0x0FFF_FFFF {
+                    return Err(error);
+                }
+                let delta = (tick - last_tick) as u32;
+                last_tick = tick;
+                assemble_event(rest, delta, body, names).ok_or(error)?;
+            }
+        }
+    }
+    let mut parsed = Vec::with_capacity(chunks.len());
+    for &(ref kind, ref data) in &chunks {
+        parsed.push(match *kind {
+            None => match track_body(data) {
+                Ok((_, track)) => Chunk::Track(track),
+                Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(Error::Midi(e)),
+                Err(Err::Incomplete(_)) => return Err(Error::Midi(ErrorKind::Eof)),
+            },
+            Some(ref kind) => Chunk::Other {
+                kind: kind,
+                data: data,
+            },
+        });
+    }
+    let midi = Midi {
+        header: Header {
+            len: 6,
+            format: format.unwrap_or(1),
+            tracks: chunks.iter().filter(|chunk| chunk.0.is_none()).count() as u16,
+            division: division.unwrap_or(96),
+        },
+        chunks: parsed,
+        rmid: None,
+    };
+    Ok(midi.to_bytes())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Word<'s> {
+    Bare(&'s str),
+    Quoted(Vec<u8>),
+}
+
+impl<'s> Word<'s> {
+    fn text(&self) -> Option<&'s str> {
+        match *self {
+            Word::Bare(text) => Some(text),
+            Word::Quoted(_) => None,
+        }
+    }
+}
+
+fn split_words(line: &str) -> Option<Vec<Word>> {
+    let bytes = line.as_bytes();
+    let mut words = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b' ' | b'\t' => pos += 1,
+            b';' => break,
+            b'"' => {
+                let mut text = Vec::new();
+                pos += 1;
+                loop {
+                    match *bytes.get(pos)? {
+                        b'"' => break,
+                        b'\\' => {
+                            match *bytes.get(pos + 1)? {
+                                b'x' => {
+                                    let digits = line.get(pos + 2..pos + 4)?;
+                                    text.push(u8::from_str_radix(digits, 16).ok()?);
+                                    pos += 4;
+                                }
+                                b'n' => {
+                                    text.push(b'\n');
+                                    pos += 2;
+                                }
+                                b => {
+                                    text.push(b);
+                                    pos += 2;
+                                }
+                            }
+                        }
+                        b => {
+                            text.push(b);
+                            pos += 1;
+                        }
+                    }
+                }
+                pos += 1;
+                words.push(Word::Quoted(text));
+            }
+            _ => {
+                let end = bytes[pos..].iter()
+                    .position(|&b| b == b' ' || b == b'\t' || b == b';')
+                    .map_or(bytes.len(), |i| pos + i);
+                words.push(Word::Bare(&line[pos..end]));
+                pos = end;
+            }
+        }
+    }
+    Some(words)
+}
+
+fn number(words: &[Word], index: usize, max: u64) -> Option<u64> {
+    let n = words.get(index)?.text()?.parse().ok()?;
+    if n <= max { Some(n) } else { None }
+}
+
+fn signed(words: &[Word], index: usize, min: i64, max: i64) -> Option<i64> {
+    let n = words.get(index)?.text()?.parse().ok()?;
+    if n >= min && n <= max { Some(n) } else { None }
+}
+
+fn hex(words: &[Word]) -> Option<Vec<u8>> {
+    words.iter().map(|word| u8::from_str_radix(word.text()?, 16).ok()).collect()
+}
+
+/// Reads a key number or a note name like C4, F#2 or Bb-1.
+fn note(words: &[Word], index: usize, names: &NoteNames) -> Option<u8> {
+    let text = words.get(index)?.text()?;
+    if let Ok(n) = text.parse::<u8>() {
+        return if n < 0x80 { Some(n) } else { None };
+    }
+    names.parse(text)
+}
+
+/// Encodes one event line, or returns `None` if its arguments don't fit.
+fn assemble_event(words: &[Word], delta: u32, out: &mut Vec<u8>, names: &NoteNames)
+                  -> Option<()> {
+    let (name, args) = words.split_first()?;
+    let name = name.text()?;
+    let channel = || number(args, 0, 0x0F).map(|n| n as u8);
+    let u7 = |i| number(args, i, 0x7F).map(|n| n as u8);
+    let u8_at = |i| number(args, i, 0xFF).map(|n| n as u8);
+    let data;
+    let event = match name {
+        "note_off" => Event::Midi(delta, MidiEvent::NoteOff {
+            channel: channel()?,
+            number: note(args, 1, names)?,
+            velocity: u7(2)?,
+        }),
+        "note_on" => Event::Midi(delta, MidiEvent::NoteOn {
+            channel: channel()?,
+            number: note(args, 1, names)?,
+            velocity: u7(2)?,
+        }),
+        "aftertouch" => Event::Midi(delta, MidiEvent::PolyphonicAftertouch {
+            channel: channel()?,
+            number: note(args, 1, names)?,
+            pressure: u7(2)?,
+        }),
+        "control" => Event::Midi(delta, MidiEvent::Control {
+            channel: channel()?,
+            change: ControlChange::from_parts(u7(1)?, u7(2)?),
+        }),
+        "program" => Event::Midi(delta, MidiEvent::ProgramChange {
+            channel: channel()?,
+            program_number: u7(1)?,
+        }),
+        "pressure" => Event::Midi(delta, MidiEvent::ChannelAftertouch {
+            channel: channel()?,
+            pressure: u7(1)?,
+        }),
+        "pitch_bend" => Event::Midi(delta, MidiEvent::PitchBend {
+            channel: channel()?,
+            pitch: (signed(args, 1, -0x2000, 0x1FFF)? + 0x2000) as u16,
+        }),
+        "sequence_number" => {
+            Event::Meta(delta, MetaEvent::SequenceNumber(number(args, 0, 0xFFFF)? as u16))
+        }
+        "channel_prefix" => Event::Meta(delta, MetaEvent::ChannelPrefix(u8_at(0)?)),
+        "end_of_track" => Event::Meta(delta, MetaEvent::EndOfTrack),
+        "tempo" => Event::Meta(delta, MetaEvent::SetTempo(number(args, 0, 0x00FF_FFFF)? as u32)),
+        "smpte_offset" => Event::Meta(delta, MetaEvent::SmpteOffset {
+            hours: u8_at(0)?,
+            minutes: u8_at(1)?,
+            seconds: u8_at(2)?,
+            frames: u8_at(3)?,
+            fractional_frames: u8_at(4)?,
+        }),
+        "time_signature" => {
+            let mut parts = args.get(0)?.text()?.splitn(2, '/');
+            let numerator = parts.next()?.parse().ok()?;
+            let denominator = match parts.next()? {
+                text if text.starts_with("2^") => text[2..].parse().ok()?,
+                text => {
+                    let value: u64 = text.parse().ok()?;
+                    if !value.is_power_of_two() {
+                        return None;
+                    }
+                    value.trailing_zeros() as u8
+                }
+            };
+            Event::Meta(delta, MetaEvent::TimeSignature {
+                numerator: numerator,
+                denominator: denominator,
+                clocks_per_metronome: u8_at(1)?,
+                notated_divisions: u8_at(2)?,
+            })
+        }
+        "key_signature" => {
+            let sharps = signed(args, 0, -127, 127)?;
+            let kind = match args.get(1)?.text()? {
+                "major" => KeyKind::Major,
+                "minor" => KeyKind::Minor,
+                _ => return None,
+            };
+            Event::Meta(delta, MetaEvent::KeySignature {
+                key: if sharps > 0 {
+                    Key::Sharps(sharps as u8)
+                } else if sharps < 0 {
+                    Key::Flats(-sharps as u8)
+                } else {
+                    Key::OfC
+                },
+                kind: kind,
+            })
+        }
+        "sequencer_specific" => {
+            data = hex(args)?;
+            Event::Meta(delta, MetaEvent::SequencerSpecific { data: &data })
+        }
+        "meta" => {
+            let kind = u8::from_str_radix(args.get(0)?.text()?, 16).ok()?;
+            data = hex(&args[1..])?;
+            Event::Meta(delta, MetaEvent::Other {
+                kind: kind,
+                data: &data,
+            })
+        }
+        "sysex" | "sysex_escape" => {
+            data = hex(args)?;
+            Event::Sysex(delta, SysexEvent {
+                start: name == "sysex",
+                end: data.last() == Some(&0xF7),
+                data: &data,
+            })
+        }
+        _ => {
+            let kind = match TEXT_NAMES.iter().find(|&&(_, n)| n == name) {
+                Some(&(kind, _)) => kind,
+                None if name.starts_with("text_") => {
+                    match name[5..].parse() {
+                        Ok(kind @ 0x01...0x0F) => TextType::from(kind),
+                        _ => return None,
+                    }
+                }
+                None => return None,
+            };
+            data = match args {
+                &[Word::Quoted(ref text)] => text.clone(),
+                _ => return None,
+            };
+            Event::Meta(delta, MetaEvent::Text {
+                kind: kind,
+                text: &data,
+            })
+        }
+    };
+    event.write_to(out);
+    Some(())
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_asm_round_trip() {
+    let source = "format 1\n\
+                  division 96\n\
+                  track\n\
+                  \x20   0 track_name \"Piano \\\"1\\\"\"\n\
+                  \x20   0 time_signature 6/8 24 8\n\
+                  \x20   0 time_signature 3/2^70 24 8\n\
+                  \x20   0 note_on 0 C4 64\n\
+                  \x20   0 pitch_bend 0 -8192\n\
+                  \x20   96 note_off 0 C4 0\n\
+                  \x20   96 sysex 7E 7F 09 01 F7\n\
+                  \x20   96 end_of_track\n\
+                  chunk \"XFIH\" 00 FF 2F 00\n";
+    let bytes = assemble(source).unwrap();
+    let midi = ::parse_midi(&bytes).unwrap();
+    assert_eq!(midi.to_asm(), source);
+    assert_eq!(assemble("track\n0 note_on 0 Db4 64 ; a comment\n0 note_on 0 61 64"),
+               assemble("track\n0 note_on 0 C#4 64\n0 note_on 0 C#4 64"));
+    assert_eq!(assemble("track\n10 end_of_track\n5 end_of_track"), Err(Error::Line(3)));
+
+    let names = NoteNames {
+        octaves: ::pitch::Octaves::MiddleC3,
+        accidentals: ::pitch::Accidentals::Flats,
+    };
+    let yamaha = assemble_with("track\n0 note_on 0 Db3 64", &names).unwrap();
+    assert_eq!(Ok(yamaha.clone()), assemble("track\n0 note_on 0 C#4 64"));
+    assert!(::parse_midi(&yamaha).unwrap().to_asm_with(&names).contains("note_on 0 Db3 64"));
+}
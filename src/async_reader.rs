@@ -0,0 +1,312 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use {parse_midi, Midi, ParseError};
+use reader::{self, Item, Reader};
+
+
+// Async Parsing ///////////////////////////////////////////////////////////////
+
+/// The most bytes `parse_midi_async` keeps before giving up on a file.
+pub const DEFAULT_MAX_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Midi(ParseError),
+    /// The file goes on past the most bytes that were to be kept.
+    TooLong,
+}
+
+/// A parsed file, kept as its bytes.
+pub struct OwnedMidi {
+    data: Vec<u8>,
+}
+
+impl OwnedMidi {
+    fn new(data: Vec<u8>) -> Result<OwnedMidi, ParseError> {
+        parse_midi(&data)?;
+        Ok(OwnedMidi {
+            data: data,
+        })
+    }
+
+    /// Parses the file again, borrowing from its bytes. They parsed when
+    /// they were read, so this can't fail.
+    pub fn midi(&self) -> Midi {
+        parse_midi(&self.data).expect("the file parsed when it was read")
+    }
+
+    /// The raw bytes of the file.
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Reads a Standard MIDI File from an `AsyncRead` without blocking, handing
+/// each piece of it to `f` as soon as it's complete. Only the `Reader`'s
+/// buffer is held, however large the file is, and reading stops at the end
+/// of the last track the header counts, so the other end doesn't have to
+/// close the connection.
+pub fn read_midi_async<R, F>(reader: R, f: F) -> ReadMidi<R, F>
+    where R: AsyncRead + Unpin,
+          F: FnMut(Item)
+{
+    ReadMidi {
+        reader: Reader::new(Source::new(reader, None)),
+        tracks_left: None,
+        f: f,
+    }
+}
+
+/// The future returned by `read_midi_async`.
+pub struct ReadMidi<R, F> {
+    reader: Reader<Source<R>>,
+    tracks_left: Option<u16>,
+    f: F,
+}
+
+impl<R: AsyncRead + Unpin, F: FnMut(Item) + Unpin> Future for ReadMidi<R, F> {
+    type Output = Result<(), reader::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), reader::Error>> {
+        let this = &mut *self;
+        poll_items(&mut this.reader, &mut this.tracks_left, cx, &mut this.f)
+    }
+}
+
+/// Reads and parses a Standard MIDI File from an `AsyncRead` without
+/// blocking, keeping at most `DEFAULT_MAX_LEN` bytes of it. The streaming
+/// `Reader` checks each piece of the file as it arrives, so a malformed
+/// upload fails as soon as the bad bytes come in.
+pub fn parse_midi_async<R: AsyncRead + Unpin>(reader: R) -> ParseMidi<R> {
+    parse_midi_async_with(reader, DEFAULT_MAX_LEN)
+}
+
+/// Reads and parses a file like `parse_midi_async`, failing with
+/// `Error::TooLong` once more than `max_len` bytes have come in.
+pub fn parse_midi_async_with<R: AsyncRead + Unpin>(reader: R, max_len: usize) -> ParseMidi<R> {
+    ParseMidi {
+        reader: Some(Reader::new(Source::new(reader, Some(max_len)))),
+        tracks_left: None,
+    }
+}
+
+/// The future returned by `parse_midi_async`.
+pub struct ParseMidi<R> {
+    reader: Option<Reader<Source<R>>>,
+    tracks_left: Option<u16>,
+}
+
+impl<R: AsyncRead + Unpin> Future for ParseMidi<R> {
+    type Output = Result<OwnedMidi, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<OwnedMidi, Error>> {
+        let this = &mut *self;
+        let result = {
+            let reader = this.reader.as_mut().expect("ParseMidi polled after completion");
+            match poll_items(reader, &mut this.tracks_left, cx, |_| {}) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(result) => result,
+            }
+        };
+        let reader = this.reader.take().unwrap();
+        let unread = reader.buffered();
+        let source = reader.into_inner();
+        match result {
+            Err(reader::Error::Io(_)) if source.too_long => Poll::Ready(Err(Error::TooLong)),
+            Err(reader::Error::Io(e)) => Poll::Ready(Err(Error::Io(e))),
+            // Parsing what's arrived so far gives the full error.
+            Ok(()) | Err(reader::Error::Midi(_)) => {
+                let mut data = source.data.unwrap_or_default();
+                let len = data.len() - unread;
+                data.truncate(len);
+                Poll::Ready(OwnedMidi::new(data).map_err(Error::Midi))
+            }
+        }
+    }
+}
+
+/// Hands each item to `f` until the end of the last track the header counts,
+/// the end of the input or an error, or until a read would block.
+fn poll_items<R, F>(reader: &mut Reader<Source<R>>, tracks_left: &mut Option<u16>,
+                    cx: &mut Context, mut f: F)
+                    -> Poll<Result<(), reader::Error>>
+    where R: AsyncRead + Unpin,
+          F: FnMut(Item)
+{
+    reader.get_mut().waker = Some(cx.waker().clone());
+    loop {
+        let item = match reader.next_item() {
+            Ok(Some(item)) => item,
+            Ok(None) => return Poll::Ready(Ok(())),
+            Err(reader::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Poll::Pending;
+            }
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        let done = match item {
+            Item::Header(ref header) => {
+                *tracks_left = Some(header.tracks());
+                header.tracks() == 0
+            }
+            Item::TrackEnd => {
+                let left = tracks_left.map_or(0, |left| left.saturating_sub(1));
+                *tracks_left = Some(left);
+                left == 0
+            }
+            _ => false,
+        };
+        f(item);
+        if done {
+            return Poll::Ready(Ok(()));
+        }
+    }
+}
+
+/// Lets the blocking `Reader` read from an `AsyncRead`, turning a pending
+/// read into a `WouldBlock` error, and keeps a copy of every byte read when
+/// there's a limit on how many to keep.
+struct Source<R> {
+    inner: R,
+    waker: Option<Waker>,
+    data: Option<Vec<u8>>,
+    max_len: usize,
+    too_long: bool,
+}
+
+impl<R> Source<R> {
+    fn new(inner: R, max_len: Option<usize>) -> Source<R> {
+        Source {
+            inner: inner,
+            waker: None,
+            data: max_len.map(|_| Vec::new()),
+            max_len: max_len.unwrap_or(0),
+            too_long: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let waker = match self.waker {
+            Some(ref waker) => waker.clone(),
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+        let room = match self.data {
+            Some(ref data) if data.len() >= self.max_len => {
+                self.too_long = true;
+                return Err(io::Error::new(io::ErrorKind::Other, "the file is too long"));
+            }
+            Some(ref data) => cmp::min(buf.len(), self.max_len - data.len()),
+            None => buf.len(),
+        };
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = ReadBuf::new(&mut buf[..room]);
+        match Pin::new(&mut self.inner).poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Ok(())) => {
+                if let Some(ref mut data) = self.data {
+                    data.extend_from_slice(buf.filled());
+                }
+                Ok(buf.filled().len())
+            }
+            Poll::Ready(Err(e)) => Err(e),
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_parse_midi_async() {
+    use std::task::{RawWaker, RawWakerVTable};
+
+    /// Hands over one byte at a time, and is pending before each one.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        ready: bool,
+    }
+
+    impl<'a> AsyncRead for Trickle<'a> {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf)
+                     -> Poll<io::Result<()>> {
+            if !self.ready {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.ready = false;
+            let (first, rest) = self.data.split_at(::std::cmp::min(1, self.data.len()));
+            buf.put_slice(first);
+            self.data = rest;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn noop(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(::std::ptr::null(), &VTABLE)
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    let waker = unsafe { Waker::from_raw(clone(::std::ptr::null())) };
+    let mut cx = Context::from_waker(&waker);
+
+    let mut data = vec![
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let len = data.len();
+    // Nothing after the last track is read.
+    data.extend_from_slice(b"trailing");
+    let mut future = parse_midi_async(Trickle {
+        data: &data,
+        ready: false,
+    });
+    let mut polls = 0;
+    let parsed = loop {
+        polls += 1;
+        if let Poll::Ready(parsed) = Pin::new(&mut future).poll(&mut cx) {
+            break parsed.unwrap();
+        }
+    };
+    assert_eq!(polls, len + 1);
+    assert_eq!(parsed.bytes(), &data[..len]);
+    assert_eq!(parsed.midi(), parse_midi(&data[..len]).unwrap());
+
+    data[len - 3] = 0x2E;
+    match Pin::new(&mut parse_midi_async(&data[..len])).poll(&mut cx) {
+        Poll::Ready(Err(Error::Midi(_))) => {}
+        _ => panic!("a malformed file should fail"),
+    }
+    data[len - 3] = 0x2F;
+    match Pin::new(&mut parse_midi_async_with(&data[..len], len - 1)).poll(&mut cx) {
+        Poll::Ready(Err(Error::TooLong)) => {}
+        _ => panic!("a file over the limit should fail"),
+    }
+
+    // Streaming keeps none of the file, and gets the same events.
+    let mut events = Vec::new();
+    let mut future = read_midi_async(Trickle {
+        data: &data,
+        ready: false,
+    }, |item| if let Item::Event(event) = item {
+        events.push(event.delta());
+    });
+    while let Poll::Pending = Pin::new(&mut future).poll(&mut cx) {}
+    drop(future);
+    assert_eq!(events, [0x00, 0x60, 0x00]);
+}
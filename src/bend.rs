@@ -0,0 +1,150 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Event, Midi, MidiEvent};
+
+
+// Pitch Bend Map //////////////////////////////////////////////////////////////
+
+/// The range a full pitch bend covers until a channel sets it with RPN 0.
+pub const DEFAULT_BEND_RANGE: f64 = 2.0;
+
+/// The pitch bends on each channel of a file, along with the bend range each
+/// channel sets with RPN 0 (pitch bend sensitivity), so that bends can be
+/// read as semitones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BendMap {
+    /// For each channel, the raw bend from each tick on.
+    bends: Vec<Vec<(u64, u16)>>,
+    /// For each channel, the range in semitones from each tick on.
+    ranges: Vec<Vec<(u64, f64)>>,
+}
+
+impl<'a> Midi<'a> {
+    pub fn bend_map(&self) -> BendMap {
+        BendMap::new(self)
+    }
+}
+
+impl BendMap {
+    /// Follows every track of the file together, so an RPN selected in one
+    /// track applies to data entry in another.
+    pub fn new(midi: &Midi) -> BendMap {
+        let mut events = Vec::new();
+        for track in midi.tracks() {
+            events.extend(track.absolute_events().filter_map(|(tick, event)| match *event {
+                Event::Midi(_, ref event) => Some((tick, event)),
+                _ => None,
+            }));
+        }
+        // The sort is stable, so events on the same tick stay in track order.
+        events.sort_by_key(|&(tick, _)| tick);
+
+        let mut bends = vec![Vec::new(); 16];
+        let mut ranges = vec![Vec::new(); 16];
+        let mut rpns = [(0x7Fu8, 0x7Fu8); 16];
+        // The semitones and cents of each channel's range.
+        let mut range = [(2u8, 0u8); 16];
+        for (tick, event) in events {
+            match *event {
+                MidiEvent::PitchBend { channel, pitch } => {
+                    bends[channel as usize & 0x0F].push((tick, pitch));
+                }
+                MidiEvent::Control { channel, change } => {
+                    let channel = channel as usize & 0x0F;
+                    match change.parts() {
+                        (101, value) => rpns[channel].0 = value,
+                        (100, value) => rpns[channel].1 = value,
+                        (6, value) if rpns[channel] == (0, 0) => range[channel].0 = value,
+                        (38, value) if rpns[channel] == (0, 0) => range[channel].1 = value,
+                        _ => continue,
+                    }
+                    let (semitones, cents) = range[channel];
+                    let semitones = semitones as f64 + cents as f64 / 100.0;
+                    let changes = &mut ranges[channel];
+                    // A range is usually set with two messages on the same tick.
+                    if changes.last().map_or(false, |&(last, _)| last == tick) {
+                        changes.pop();
+                    }
+                    if changes.last().map_or(DEFAULT_BEND_RANGE, |&(_, last)| last) != semitones {
+                        changes.push((tick, semitones));
+                    }
+                }
+                _ => {}
+            }
+        }
+        BendMap {
+            bends: bends,
+            ranges: ranges,
+        }
+    }
+
+    /// The raw pitch bends on a channel, from 0 to 0x3FFF with 0x2000 in
+    /// the middle.
+    pub fn bends(&self, channel: u8) -> &[(u64, u16)] {
+        &self.bends[channel as usize & 0x0F]
+    }
+
+    /// The changes to a channel's bend range, in semitones.
+    pub fn ranges(&self, channel: u8) -> &[(u64, f64)] {
+        &self.ranges[channel as usize & 0x0F]
+    }
+
+    /// The range in semitones of a full bend on a channel at a tick.
+    pub fn range_at(&self, channel: u8, tick: u64) -> f64 {
+        self.ranges(channel)
+            .iter()
+            .take_while(|&&(start, _)| start <= tick)
+            .last()
+            .map_or(DEFAULT_BEND_RANGE, |&(_, range)| range)
+    }
+
+    /// The raw pitch bend on a channel at a tick, which is centered until
+    /// it's first bent.
+    pub fn bend_at(&self, channel: u8, tick: u64) -> u16 {
+        self.bends(channel)
+            .iter()
+            .take_while(|&&(start, _)| start <= tick)
+            .last()
+            .map_or(0x2000, |&(_, pitch)| pitch)
+    }
+
+    /// How many semitones a channel is bent by at a tick. A new range
+    /// applies to a bend that's already held.
+    pub fn semitones_at(&self, channel: u8, tick: u64) -> f64 {
+        semitones(self.bend_at(channel, tick), self.range_at(channel, tick))
+    }
+}
+
+/// How many semitones a raw pitch bend moves by when a full bend covers
+/// `range` semitones.
+pub fn semitones(pitch: u16, range: f64) -> f64 {
+    (pitch as f64 - 8192.0) / 8192.0 * range
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_bend_map() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x19,
+        0x00, 0xE1, 0x00, 0x60,
+        0x10, 0xB1, 0x65, 0x00,
+        0x00, 0x64, 0x00,
+        0x00, 0x06, 0x0C,
+        0x00, 0x26, 0x32,
+        0x10, 0xE1, 0x00, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let map = ::parse_midi(&data).unwrap().bend_map();
+    assert_eq!(map.bend_at(1, 0), 0x3000);
+    assert_eq!(map.semitones_at(1, 0x0F), 1.0);
+    assert_eq!(map.ranges(1), &[(0x10, 12.5)][..]);
+    assert_eq!(map.range_at(1, 0x10), 12.5);
+    assert_eq!(map.semitones_at(1, 0x10), 6.25);
+    assert_eq!(map.semitones_at(1, 0x20), -12.5);
+    assert_eq!(map.semitones_at(0, 0x20), 0.0);
+}
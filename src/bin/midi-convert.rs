@@ -0,0 +1,98 @@
+extern crate midi;
+
+use midi::parse_midi;
+use midi::convert::MergeOptions;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+const USAGE: &'static str = "usage: midi-convert [OPTION]... INPUT OUTPUT
+
+Converts a MIDI file, reading standard input or writing standard output
+for a file named -. Options are applied in this order:
+
+    --format 0|1     merge the tracks into one, or split them by channel
+    --dedupe         drop events that another track repeats when merging
+    --ppqn N         rescale to N ticks per quarter note
+    --smf            unwrap an RMID file to a plain Standard MIDI File
+    --rmid           wrap the file in a RIFF RMID container";
+
+fn main() {
+    let mut format = None;
+    let mut ppqn = None;
+    let mut merge = MergeOptions::default();
+    let mut container = None;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--format" => match args.next().as_ref().map(|n| &n[..]) {
+                Some("0") => format = Some(0),
+                Some("1") => format = Some(1),
+                _ => fail("--format needs to be 0 or 1"),
+            },
+            "--ppqn" => match args.next().and_then(|n| n.parse::<u16>().ok()) {
+                Some(n) if n > 0 && n < 0x8000 => ppqn = Some(n),
+                _ => fail("--ppqn needs a number of ticks from 1 to 32767"),
+            },
+            "--dedupe" => merge.dedupe = true,
+            "--smf" => container = Some(false),
+            "--rmid" => container = Some(true),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        fail("needs an input and an output file, see --help");
+    }
+
+    let mut input = Vec::new();
+    let read = if paths[0] == "-" {
+        io::stdin().read_to_end(&mut input)
+    } else {
+        File::open(&paths[0]).and_then(|mut file| file.read_to_end(&mut input))
+    };
+    if let Err(e) = read {
+        fail(&format!("couldn't read {}: {}", paths[0], e));
+    }
+    let mut midi = match parse_midi(&input) {
+        Ok(midi) => midi,
+        Err(e) => fail(&format!("couldn't parse {}: {}", paths[0], e.with_input(&input))),
+    };
+
+    midi = match format {
+        Some(0) => midi.to_format_0_with(&merge),
+        Some(_) => midi.to_format_1(),
+        None => midi,
+    };
+    if let Some(ppqn) = ppqn {
+        midi = match midi.rescale(ppqn) {
+            Some(midi) => midi,
+            None => fail("files with SMPTE timing can't be rescaled"),
+        };
+    }
+    midi = match container {
+        Some(false) => midi.into_smf(),
+        Some(true) => midi.into_rmid(),
+        None => midi,
+    };
+
+    let output = midi.to_bytes();
+    let written = if paths[1] == "-" {
+        io::stdout().write_all(&output)
+    } else {
+        File::create(&paths[1]).and_then(|mut file| file.write_all(&output))
+    };
+    if let Err(e) = written {
+        fail(&format!("couldn't write {}: {}", paths[1], e));
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("midi-convert: {}", message);
+    process::exit(1);
+}
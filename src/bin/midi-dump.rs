@@ -0,0 +1,197 @@
+extern crate midi;
+
+use midi::{parse_midi, ControlChange, Division, Event, Key, KeyKind, MetaEvent, MidiEvent,
+           TextType};
+use midi::meter::note_value;
+use midi::pitch::{Accidentals, NoteNames, Octaves};
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+const USAGE: &'static str = "usage: midi-dump [--track N]... [--explain] [--middle-c3] [--flats]
+                 [FILE]
+
+Lists the events in a MIDI file with their absolute ticks and times. Reads
+standard input if no file is given. Each --track limits the listing to that
+track, counting from 0. --explain shows a hex dump of the whole file instead,
+with each part of it labeled. Notes are named with middle C as C4 and black
+keys as sharps, unless --middle-c3 or --flats say otherwise.";
+
+fn main() {
+    let mut path = None;
+    let mut tracks = Vec::new();
+    let mut explain = false;
+    let mut names = NoteNames::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--explain" => explain = true,
+            "--middle-c3" => names.octaves = Octaves::MiddleC3,
+            "--flats" => names.accidentals = Accidentals::Flats,
+            "--track" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) => tracks.push(n),
+                None => fail("--track needs a track number"),
+            },
+            _ if path.is_none() => path = Some(arg),
+            _ => fail("only one file can be dumped at a time"),
+        }
+    }
+
+    let mut input = Vec::new();
+    let read = match path {
+        Some(ref path) if path != "-" => {
+            File::open(path).and_then(|mut file| file.read_to_end(&mut input))
+        }
+        _ => io::stdin().read_to_end(&mut input),
+    };
+    if let Err(e) = read {
+        fail(&format!("couldn't read the file: {}", e));
+    }
+    if explain {
+        match midi::hexdump::explain_with(&input, &names) {
+            Ok(dump) => print!("{}", dump),
+            Err(e) => fail(&format!("couldn't parse the file: {}", e.with_input(&input))),
+        }
+        return;
+    }
+    let midi = match parse_midi(&input) {
+        Ok(midi) => midi,
+        Err(e) => fail(&format!("couldn't parse the file: {}", e.with_input(&input))),
+    };
+
+    let header = midi.header();
+    let division = match header.division() {
+        Division::TicksPerQuarter(n) => format!("{} ticks per quarter note", n),
+        Division::Smpte { frames_per_second, ticks_per_frame } => {
+            format!("{} frames per second, {} ticks per frame", frames_per_second,
+                    ticks_per_frame)
+        }
+    };
+    let count = midi.tracks().count();
+    println!("Format {}, {} track{}, {}", header.format(), count,
+             if count == 1 { "" } else { "s" }, division);
+
+    let tempo_map = midi.tempo_map();
+    for (index, track) in midi.tracks().enumerate() {
+        if !tracks.is_empty() && !tracks.contains(&index) {
+            continue;
+        }
+        println!();
+        println!("Track {}", index);
+        let mut cursor = tempo_map.cursor();
+        for (tick, event) in track.absolute_events() {
+            println!("{:>10} {:>10.3}  {}", tick, cursor.seconds_at(tick),
+                     describe(event, &names));
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("midi-dump: {}", message);
+    process::exit(1);
+}
+
+fn describe(event: &Event, names: &NoteNames) -> String {
+    match *event {
+        Event::Midi(_, ref event) => describe_midi(event, names),
+        Event::Meta(_, ref event) => describe_meta(event),
+        Event::Sysex(_, ref sysex) => {
+            let mut text = String::from(if sysex.is_start() { "Sysex" } else { "Sysex packet" });
+            if let Some(standard) = sysex.standard() {
+                text.push_str(&format!(" ({:?})", standard));
+            } else if let Some(manufacturer) = sysex.manufacturer() {
+                text.push_str(&format!(" ({})", manufacturer));
+            }
+            text + &hex(sysex.data())
+        }
+    }
+}
+
+fn describe_midi(event: &MidiEvent, names: &NoteNames) -> String {
+    use MidiEvent::*;
+    match *event {
+        NoteOn { channel, number, velocity } => {
+            format!("Note on      ch {:>2}  key {:>3} {:<4}  velocity {}",
+                    channel, number, names.name(number), velocity)
+        }
+        NoteOff { channel, number, velocity } => {
+            format!("Note off     ch {:>2}  key {:>3} {:<4}  velocity {}",
+                    channel, number, names.name(number), velocity)
+        }
+        PolyphonicAftertouch { channel, number, pressure } => {
+            format!("Aftertouch   ch {:>2}  key {:>3} {:<4}  pressure {}",
+                    channel, number, names.name(number), pressure)
+        }
+        ChannelAftertouch { channel, pressure } => {
+            format!("Pressure     ch {:>2}  pressure {}", channel, pressure)
+        }
+        Control { channel, change: ControlChange::Mode(mode) } => {
+            format!("Mode         ch {:>2}  {:?}", channel, mode)
+        }
+        Control { channel, change: ControlChange::Raw(controller, value) } => {
+            format!("Control      ch {:>2}  controller {:>3}  value {}",
+                    channel, controller, value)
+        }
+        ProgramChange { channel, program_number } => {
+            format!("Program      ch {:>2}  program {}", channel, program_number)
+        }
+        PitchBend { channel, pitch } => {
+            format!("Pitch bend   ch {:>2}  {:+}", channel, pitch as i32 - 0x2000)
+        }
+        Previous(a, b) => format!("Running status {:02X} {:02X}", a, b),
+    }
+}
+
+fn describe_meta(event: &MetaEvent) -> String {
+    use MetaEvent::*;
+    match *event {
+        SequenceNumber(n) => format!("Sequence number {}", n),
+        Text { kind, text } => {
+            let kind = match kind {
+                TextType::Text => "Text".to_string(),
+                TextType::Copyright => "Copyright".to_string(),
+                TextType::TrackName => "Track name".to_string(),
+                TextType::InstrumentName => "Instrument name".to_string(),
+                TextType::Lyric => "Lyric".to_string(),
+                TextType::Marker => "Marker".to_string(),
+                TextType::CuePoint => "Cue point".to_string(),
+                TextType::ProgramName => "Program name".to_string(),
+                TextType::DeviceName => "Device name".to_string(),
+                TextType::Other(n) => format!("Text {:02X}", n),
+            };
+            format!("{} {:?}", kind, String::from_utf8_lossy(text))
+        }
+        ChannelPrefix(channel) => format!("Channel prefix {}", channel),
+        EndOfTrack => "End of track".to_string(),
+        SetTempo(tempo) => format!("Tempo {} ({:.2} bpm)", tempo, midi::tempo::bpm(tempo)),
+        SmpteOffset { hours, minutes, seconds, frames, fractional_frames } => {
+            format!("SMPTE offset {:02}:{:02}:{:02}:{:02}.{:02}",
+                    hours, minutes, seconds, frames, fractional_frames)
+        }
+        TimeSignature { numerator, denominator, clocks_per_metronome, notated_divisions } => {
+            format!("Time signature {}/{}, {} clocks per click, {} 32nds per quarter",
+                    numerator, note_value(denominator), clocks_per_metronome,
+                    notated_divisions)
+        }
+        KeySignature { key, kind } => {
+            let key = match key {
+                Key::Flats(n) => format!("{} flats", n),
+                Key::OfC => "no sharps or flats".to_string(),
+                Key::Sharps(n) => format!("{} sharps", n),
+            };
+            let kind = if kind == KeyKind::Minor { "minor" } else { "major" };
+            format!("Key signature {}, {}", key, kind)
+        }
+        SequencerSpecific { data } => format!("Sequencer specific{}", hex(data)),
+        Other { kind, data } => format!("Meta {:02X}{}", kind, hex(data)),
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!(" {:02X}", b)).collect()
+}
@@ -0,0 +1,74 @@
+extern crate midi;
+
+use midi::parse_midi;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+const USAGE: &'static str = "usage: midi-lyrics [--lrc] [FILE]
+
+Prints the lyrics of a MIDI or karaoke (.kar) file as plain text, one line
+per line of lyrics with a blank line between verses. With --lrc, prints an
+LRC file with the time of each line instead. Reads standard input if no file
+is given.";
+
+fn main() {
+    let mut path = None;
+    let mut lrc = false;
+    for arg in env::args().skip(1) {
+        match &arg[..] {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--lrc" => lrc = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => fail("only one file can be read at a time"),
+        }
+    }
+
+    let mut input = Vec::new();
+    let read = match path {
+        Some(ref path) if path != "-" => {
+            File::open(path).and_then(|mut file| file.read_to_end(&mut input))
+        }
+        _ => io::stdin().read_to_end(&mut input),
+    };
+    if let Err(e) = read {
+        fail(&format!("couldn't read the file: {}", e));
+    }
+    let midi = match parse_midi(&input) {
+        Ok(midi) => midi,
+        Err(e) => fail(&format!("couldn't parse the file: {}", e.with_input(&input))),
+    };
+
+    let lyrics = midi.lyrics();
+    if lrc {
+        // Karaoke files give the title first and the artist second.
+        let titles = lyrics.header(b'T');
+        let tags = [("ti", titles.get(0)), ("ar", titles.get(1))];
+        for &(tag, value) in &tags {
+            if let Some(value) = value {
+                println!("[{}:{}]", tag, String::from_utf8_lossy(value).trim());
+            }
+        }
+        for line in lyrics.lines() {
+            let hundredths = (line.seconds * 100.0).round() as u64;
+            println!("[{:02}:{:02}.{:02}]{}", hundredths / 6000, hundredths / 100 % 60,
+                     hundredths % 100, line.text);
+        }
+    } else {
+        for (index, line) in lyrics.lines().iter().enumerate() {
+            if line.starts_paragraph && index > 0 {
+                println!();
+            }
+            println!("{}", line.text);
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("midi-lyrics: {}", message);
+    process::exit(1);
+}
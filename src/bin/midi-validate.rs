@@ -0,0 +1,81 @@
+extern crate midi;
+
+use midi::hexdump::hexdump;
+use midi::parse_midi;
+use midi::validate::{validate_strict, Severity};
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+const USAGE: &'static str = "usage: midi-validate [--deny-warnings] [--conformance] [FILE]...
+
+Checks MIDI files against the standard and lists every error and warning
+with its byte offset, checking the events of files that parse too. Reads
+standard input if no file is given. Exits with status 1 if any file has
+errors, or warnings too with --deny-warnings.
+
+  --conformance  also list the rules of the standard that each file breaks,
+                 as warnings";
+
+fn main() {
+    let mut paths = Vec::new();
+    let mut deny_warnings = false;
+    let mut conformance = false;
+    for arg in env::args().skip(1) {
+        match &arg[..] {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--deny-warnings" => deny_warnings = true,
+            "--conformance" => conformance = true,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        paths.push("-".to_string());
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        let mut input = Vec::new();
+        let read = if path == "-" {
+            io::stdin().read_to_end(&mut input)
+        } else {
+            File::open(path).and_then(|mut file| file.read_to_end(&mut input))
+        };
+        if let Err(e) = read {
+            eprintln!("midi-validate: couldn't read {}: {}", path, e);
+            failed = true;
+            continue;
+        }
+
+        let issues = validate_strict(&input);
+        let count = |severity| issues.iter().filter(|issue| issue.severity == severity).count();
+        let errors = count(Severity::Error);
+        let mut warnings = count(Severity::Warning);
+        for issue in &issues {
+            println!("{}: {}", path, issue);
+            if issue.severity == Severity::Error {
+                print!("{}", hexdump(&input, issue.offset));
+            }
+        }
+        if conformance {
+            if let Ok(midi) = parse_midi(&input) {
+                for violation in midi.conformance().violations {
+                    println!("{}: conformance: {}", path, violation);
+                    warnings += 1;
+                }
+            }
+        }
+        println!("{}: {} error{}, {} warning{}", path, errors, if errors == 1 { "" } else { "s" },
+                 warnings, if warnings == 1 { "" } else { "s" });
+        if errors > 0 || (deny_warnings && warnings > 0) {
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt;
+
+use {Division, Event, Key, MetaEvent, Midi, TextType};
+
+
+// Conformance Rules ///////////////////////////////////////////////////////////
+
+/// A rule of the Standard MIDI File specification that a file which parses
+/// can still break.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rule {
+    /// The MThd chunk is 6 bytes long.
+    HeaderLength,
+    /// The format is 0, 1 or 2.
+    Format,
+    /// A format 0 file has exactly one track.
+    SingleTrack,
+    /// The header gives the number of track chunks in the file.
+    TrackCount,
+    /// The division is a nonzero number of ticks, or an SMPTE rate of 24,
+    /// 25, 29 (drop frame) or 30 frames per second.
+    Division,
+    /// Every track ends with exactly one end of track event.
+    EndOfTrack,
+    /// A sequence number comes at the start of a track, before any time has
+    /// passed or any channel event, and in format 1 only in the first track.
+    SequenceNumber,
+    /// An SMPTE offset comes before any time has passed, and in format 1
+    /// only in the first track.
+    SmpteOffset,
+    /// In format 1, tempo changes and time signatures are in the first
+    /// track.
+    ConductorTrack,
+    /// Tempos are nonzero and time signatures have a nonzero numerator.
+    MetaValues,
+    /// Key signatures have at most 7 sharps or flats.
+    KeySignature,
+    /// Channel prefixes name channels from 0 to 15.
+    ChannelPrefix,
+    /// A sysex message that isn't finished with F7 is continued by F7
+    /// packets before any other kind of event.
+    SysexContinuation,
+    /// A format 0 file has at most one track name, which names the whole
+    /// sequence.
+    SequenceName,
+    /// The reserved top bit of an SMPTE offset's hour byte is clear.
+    ReservedBits,
+}
+
+impl Rule {
+    pub fn all() -> &'static [Rule] {
+        use self::Rule::*;
+        static ALL: [Rule; 15] = [HeaderLength, Format, SingleTrack, TrackCount, Division,
+                                  EndOfTrack, SequenceNumber, SmpteOffset, ConductorTrack,
+                                  MetaValues, KeySignature, ChannelPrefix, SysexContinuation,
+                                  SequenceName, ReservedBits];
+        &ALL
+    }
+
+    /// A short, stable name for the rule, for reports and configuration.
+    pub fn id(&self) -> &'static str {
+        use self::Rule::*;
+        match *self {
+            HeaderLength => "header-length",
+            Format => "format",
+            SingleTrack => "single-track",
+            TrackCount => "track-count",
+            Division => "division",
+            EndOfTrack => "end-of-track",
+            SequenceNumber => "sequence-number",
+            SmpteOffset => "smpte-offset",
+            ConductorTrack => "conductor-track",
+            MetaValues => "meta-values",
+            KeySignature => "key-signature",
+            ChannelPrefix => "channel-prefix",
+            SysexContinuation => "sysex-continuation",
+            SequenceName => "sequence-name",
+            ReservedBits => "reserved-bits",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// A place where a file breaks a rule. Problems with the header have no
+/// track, and problems with a whole track have no event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: Rule,
+    /// The index of the track among the file's tracks.
+    pub track: Option<usize>,
+    /// The index of the event in its track.
+    pub event: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] ", self.rule)?;
+        match (self.track, self.event) {
+            (Some(track), Some(event)) => write!(f, "track {}, event {}: ", track, event)?,
+            (Some(track), None) => write!(f, "track {}: ", track)?,
+            _ => {}
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// How a file measures up against every rule.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Conformance {
+    pub violations: Vec<Violation>,
+}
+
+impl Conformance {
+    pub fn conforms(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn passes(&self, rule: Rule) -> bool {
+        self.violations.iter().all(|violation| violation.rule != rule)
+    }
+
+    /// The rules the file breaks, each listed once, in the order of
+    /// `Rule::all`.
+    pub fn failed(&self) -> Vec<Rule> {
+        Rule::all().iter().cloned().filter(|&rule| !self.passes(rule)).collect()
+    }
+}
+
+impl<'a> Midi<'a> {
+    pub fn conformance(&self) -> Conformance {
+        check(self)
+    }
+}
+
+/// Checks a parsed file against each rule, listing the header's
+/// violations first and then each track's in order.
+pub fn check(midi: &Midi) -> Conformance {
+    let mut violations = Vec::new();
+    {
+        let mut violation = |rule, track, event, message: String| {
+            violations.push(Violation {
+                rule: rule,
+                track: track,
+                event: event,
+                message: message,
+            })
+        };
+        let header = midi.header();
+        let format = header.format();
+        let tracks = midi.tracks().count();
+        if header.len != 6 {
+            violation(Rule::HeaderLength, None, None,
+                      format!("header is {} bytes long", header.len));
+        }
+        if format > 2 {
+            violation(Rule::Format, None, None, format!("unknown format {}", format));
+        }
+        if format == 0 && tracks != 1 {
+            violation(Rule::SingleTrack, None, None,
+                      format!("format 0 file has {} tracks", tracks));
+        }
+        if header.tracks() as usize != tracks {
+            violation(Rule::TrackCount, None, None,
+                      format!("header says {} tracks, but there are {}", header.tracks(), tracks));
+        }
+        match header.division() {
+            Division::TicksPerQuarter(0) => {
+                violation(Rule::Division, None, None, "zero ticks per quarter note".to_string())
+            }
+            Division::Smpte { frames_per_second, .. } => match frames_per_second {
+                24 | 25 | 29 | 30 => {}
+                fps => violation(Rule::Division, None, None,
+                                 format!("{} frames per second isn't an SMPTE rate", fps)),
+            },
+            _ => {}
+        }
+
+        let mut names = 0;
+        for (index, track) in midi.tracks().enumerate() {
+            let mut ends = 0;
+            let mut unfinished_sysex = None;
+            let mut channel_events = false;
+            for (i, (tick, event)) in track.absolute_events().enumerate() {
+                let (t, e) = (Some(index), Some(i));
+                match *event {
+                    Event::Sysex(_, ref sysex) if !sysex.is_start() => {}
+                    _ => if let Some(start) = unfinished_sysex.take() {
+                        violation(Rule::SysexContinuation, t, Some(start),
+                                  "sysex message is never finished".to_string());
+                    },
+                }
+                match *event {
+                    Event::Meta(_, MetaEvent::EndOfTrack) => {
+                        ends += 1;
+                        if i + 1 != track.events().len() {
+                            violation(Rule::EndOfTrack, t, e,
+                                      "end of track isn't the last event".to_string());
+                        }
+                    }
+                    Event::Meta(_, MetaEvent::SequenceNumber(_)) => {
+                        // Other meta events, like the track's name, may come first.
+                        if tick > 0 || channel_events || (format == 1 && index > 0) {
+                            violation(Rule::SequenceNumber, t, e,
+                                      "sequence number isn't at the start of the file"
+                                          .to_string());
+                        }
+                    }
+                    Event::Meta(_, MetaEvent::SmpteOffset { hours, .. }) => {
+                        if tick > 0 || (format == 1 && index > 0) {
+                            violation(Rule::SmpteOffset, t, e,
+                                      "SMPTE offset isn't at the start of the file".to_string());
+                        }
+                        if hours & 0x80 != 0 {
+                            violation(Rule::ReservedBits, t, e,
+                                      "SMPTE offset sets the reserved bit of its hour"
+                                          .to_string());
+                        }
+                    }
+                    Event::Meta(_, MetaEvent::SetTempo(tempo)) => {
+                        if format == 1 && index > 0 {
+                            violation(Rule::ConductorTrack, t, e,
+                                      "tempo change outside the first track".to_string());
+                        }
+                        if tempo == 0 {
+                            violation(Rule::MetaValues, t, e, "tempo of zero".to_string());
+                        }
+                    }
+                    Event::Meta(_, MetaEvent::TimeSignature { numerator, .. }) => {
+                        if format == 1 && index > 0 {
+                            violation(Rule::ConductorTrack, t, e,
+                                      "time signature outside the first track".to_string());
+                        }
+                        if numerator == 0 {
+                            violation(Rule::MetaValues, t, e,
+                                      "time signature with a numerator of zero".to_string());
+                        }
+                    }
+                    Event::Meta(_, MetaEvent::KeySignature { key, .. }) => match key {
+                        Key::Flats(n) | Key::Sharps(n) if n > 7 => {
+                            violation(Rule::KeySignature, t, e,
+                                      format!("key signature with {} accidentals", n));
+                        }
+                        _ => {}
+                    },
+                    Event::Meta(_, MetaEvent::Text { kind: TextType::TrackName, .. }) => {
+                        names += 1;
+                        if format == 0 && names > 1 {
+                            violation(Rule::SequenceName, t, e,
+                                      "format 0 file has more than one track name".to_string());
+                        }
+                    }
+                    Event::Meta(_, MetaEvent::ChannelPrefix(channel)) if channel > 15 => {
+                        violation(Rule::ChannelPrefix, t, e,
+                                  format!("channel prefix for channel {}", channel));
+                    }
+                    Event::Midi(..) => channel_events = true,
+                    Event::Sysex(_, ref sysex) => {
+                        // An F7 packet with nothing to continue is an escape, which
+                        // doesn't need finishing.
+                        let start = if sysex.is_start() { Some(i) } else { unfinished_sysex };
+                        unfinished_sysex = if sysex.is_end() { None } else { start };
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(start) = unfinished_sysex {
+                violation(Rule::SysexContinuation, Some(index), Some(start),
+                          "sysex message is never finished".to_string());
+            }
+            if ends == 0 {
+                violation(Rule::EndOfTrack, Some(index), None,
+                          "track has no end of track".to_string());
+            } else if ends > 1 {
+                violation(Rule::EndOfTrack, Some(index), None,
+                          format!("track has {} end of track events", ends));
+            }
+        }
+    }
+    Conformance { violations: violations }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_conformance() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0D,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x10, 0xFF, 0x00, 0x02, 0x00, 0x01,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0F,
+        0x00, 0xF0, 0x02, 0x7E, 0x7F,
+        0x00, 0xFF, 0x59, 0x02, 0x09, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let conformance = ::parse_midi(&data).unwrap().conformance();
+    assert!(!conformance.conforms());
+    assert_eq!(conformance.failed(), [
+        Rule::SingleTrack,
+        Rule::EndOfTrack,
+        Rule::SequenceNumber,
+        Rule::KeySignature,
+        Rule::SysexContinuation,
+    ]);
+    assert!(conformance.passes(Rule::ConductorTrack));
+    let messages: Vec<_> = conformance.violations.iter().map(|v| v.to_string()).collect();
+    assert_eq!(messages, [
+        "[single-track] format 0 file has 2 tracks",
+        "[sequence-number] track 0, event 1: sequence number isn't at the start of the file",
+        "[end-of-track] track 0: track has no end of track",
+        "[sysex-continuation] track 1, event 0: sysex message is never finished",
+        "[key-signature] track 1, event 1: key signature with 9 accidentals",
+    ]);
+    assert!(::gen::scale(60, &::gen::MAJOR).conformance().conforms());
+
+    // A track's name can come before its sequence number.
+    let mut data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x18,
+        0x00, 0xFF, 0x03, 0x01, 0x41,
+        0x00, 0xFF, 0x00, 0x02, 0x00, 0x01,
+        0x00, 0xFF, 0x54, 0x05, 0x21, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    assert!(::parse_midi(&data).unwrap().conformance().conforms());
+    data[37] |= 0x80;
+    assert_eq!(::parse_midi(&data).unwrap().conformance().failed(), [Rule::ReservedBits]);
+}
@@ -0,0 +1,879 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::collections::HashMap;
+
+use {Chunk, Division, Event, Header, MetaEvent, Midi, MidiEvent, TextType, TrackChunk};
+use tempo::{TempoMap, DEFAULT_TEMPO};
+
+
+// File Conversions ////////////////////////////////////////////////////////////
+
+/// How `to_format_0_with` merges tracks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MergeOptions {
+    /// Drops an event when another track has exactly the same event on the
+    /// same tick, such as a tempo map or program change copied into
+    /// several tracks, which some synths glitch on. Notes are always kept,
+    /// since their note offs may not line up.
+    pub dedupe: bool,
+    pub order: Order,
+}
+
+/// How the events on the same tick are ordered when tracks are merged or
+/// combined, or when a file is written with `to_bytes_with`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// In order of `standard_rank`, then of track, and then as they come.
+    Standard,
+    /// In order of track, as they come.
+    Tracks,
+}
+
+impl Default for Order {
+    fn default() -> Order {
+        Order::Standard
+    }
+}
+
+/// Where an event goes among the events on the same tick, with lower ranks
+/// first, so that every setting is in place before the notes that need it
+/// and a key released and struck again on one tick is struck last:
+///
+/// 0. meta events, like tempo and time signature changes
+/// 1. sysex messages
+/// 2. note offs, including note ons with a velocity of zero
+/// 3. controllers, so bank selects come before program changes
+/// 4. program changes
+/// 5. pitch bends and aftertouch
+/// 6. note ons
+///
+/// End of track events always go last, whatever the ranking, and a note off
+/// ending a note struck earlier on the same tick stays just after its note
+/// on, so that a note of no length isn't turned into a stuck one.
+pub fn standard_rank(event: &Event) -> u8 {
+    match *event {
+        Event::Meta(..) => 0,
+        Event::Sysex(..) => 1,
+        Event::Midi(_, ref midi) => match midi.normalized() {
+            MidiEvent::NoteOff { .. } => 2,
+            MidiEvent::Control { .. } => 3,
+            MidiEvent::ProgramChange { .. } => 4,
+            MidiEvent::NoteOn { .. } => 6,
+            _ => 5,
+        },
+    }
+}
+
+impl<'a> TrackChunk<'a> {
+    /// Reorders the events on each tick by a ranking, like `standard_rank`,
+    /// keeping the order of events that rank the same. Deltas are fixed up
+    /// so every event keeps its tick, the end of track stays last, and a
+    /// note off never goes ahead of the note on it ends.
+    pub fn sort_simultaneous_by<F: Fn(&Event) -> u8>(&mut self, rank: F) {
+        let keys = simultaneous_keys(self.absolute_events(), rank);
+        let mut events: Vec<_> = keys.into_iter().zip(self.events().iter().cloned()).collect();
+        events.sort_by_key(|&(key, _)| key);
+        let mut time = 0;
+        self.events = events.into_iter()
+            .map(|((tick, _, _), event)| {
+                let event = with_delta(event, (tick - time) as u32);
+                time = tick;
+                event
+            })
+            .collect();
+    }
+
+    /// Reorders the events on each tick as `order` says. Within one track
+    /// `Order::Tracks` keeps them as they are.
+    pub fn sort_simultaneous(&mut self, order: Order) {
+        if let Order::Standard = order {
+            self.sort_simultaneous_by(standard_rank);
+        }
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// Merges every track into one, as a format 0 file. Events on the same
+    /// tick go in the standard order of `standard_rank`, and the end of
+    /// track events are replaced by one at the end of the longest track.
+    pub fn to_format_0(&self) -> Midi<'a> {
+        self.to_format_0_with(&MergeOptions::default())
+    }
+
+    pub fn to_format_0_with(&self, options: &MergeOptions) -> Midi<'a> {
+        match options.order {
+            Order::Standard => self.to_format_0_by(options, standard_rank),
+            Order::Tracks => self.to_format_0_by(options, |_| 0),
+        }
+    }
+
+    /// Merges every track into one, ordering the events on each tick by a
+    /// ranking of its own instead of `options.order`.
+    pub fn to_format_0_by<F: Fn(&Event) -> u8>(&self, options: &MergeOptions, rank: F)
+                                               -> Midi<'a> {
+        let mut events = Vec::new();
+        let mut end = 0;
+        for (index, track) in self.tracks().enumerate() {
+            for (tick, event) in track.absolute_events() {
+                if let Event::Meta(_, MetaEvent::EndOfTrack) = *event {
+                    continue;
+                }
+                events.push((tick, index, with_delta(event.clone(), 0)));
+            }
+            end = ::std::cmp::max(end, track.duration());
+        }
+        // The events are gathered a track at a time, so ties keep them in order
+        // of track and then as they come.
+        let keys = simultaneous_keys(events.iter().map(|&(tick, _, ref event)| (tick, event)),
+                                     rank);
+        let mut keyed: Vec<_> = keys.into_iter().zip(events).collect();
+        keyed.sort_by_key(|&(key, _)| key);
+        let mut events: Vec<_> = keyed.into_iter().map(|(_, event)| event).collect();
+        if options.dedupe {
+            events = dedupe(events);
+        }
+        let track = from_absolute(events.into_iter().map(|(tick, _, event)| (tick, event)), end);
+        self.converted(0, vec![track])
+    }
+
+    /// Splits the file into a format 1 file with a first track for
+    /// everything that isn't a channel message, followed by a track for each
+    /// channel used, in channel order.
+    pub fn to_format_1(&self) -> Midi<'a> {
+        let mut tracks: Vec<Vec<(u64, Event<'a>)>> = vec![Vec::new(); 17];
+        let mut end = 0;
+        for track in self.tracks() {
+            // `Previous` continues whichever channel message came before it.
+            let mut last = 0;
+            for (tick, event) in track.absolute_events() {
+                let index = match *event {
+                    Event::Meta(_, MetaEvent::EndOfTrack) => continue,
+                    Event::Midi(_, MidiEvent::Previous(..)) => last,
+                    Event::Midi(_, ref midi) => midi.channel().map_or(0, |ch| ch as usize + 1),
+                    _ => 0,
+                };
+                last = index;
+                tracks[index].push((tick, event.clone()));
+            }
+            end = ::std::cmp::max(end, track.duration());
+        }
+        // Stable, so a merged format 1 file keeps its tracks in order.
+        for events in &mut tracks {
+            events.sort_by_key(|&(tick, _)| tick);
+        }
+        let mut chunks = vec![from_absolute(tracks[0].drain(..), end)];
+        for events in &mut tracks[1..] {
+            if !events.is_empty() {
+                chunks.push(from_absolute(events.drain(..), end));
+            }
+        }
+        self.converted(1, chunks)
+    }
+
+    /// Moves the tempo changes, time signatures and key signatures of a
+    /// format 1 file into its first track, where the standard puts them,
+    /// keeping their times. Events the first track already has on the same
+    /// tick are dropped. Returns `None` for files of other formats.
+    pub fn normalize_conductor(&self) -> Option<Midi<'a>> {
+        if self.header.format != 1 {
+            return None;
+        }
+        let is_conductor = |event: &Event| match *event {
+            Event::Meta(_, MetaEvent::SetTempo(_)) |
+            Event::Meta(_, MetaEvent::TimeSignature { .. }) |
+            Event::Meta(_, MetaEvent::KeySignature { .. }) => true,
+            _ => false,
+        };
+        let mut tracks: Vec<(u64, Vec<(u64, Event<'a>)>)> = Vec::new();
+        let mut moved = Vec::new();
+        for (index, track) in self.tracks().enumerate() {
+            let mut events = Vec::new();
+            for (tick, event) in track.absolute_events() {
+                match *event {
+                    Event::Meta(_, MetaEvent::EndOfTrack) => continue,
+                    _ if index > 0 && is_conductor(event) => moved.push((tick, event.clone())),
+                    _ => events.push((tick, event.clone())),
+                }
+            }
+            tracks.push((track.duration(), events));
+        }
+        if let Some(&mut (ref mut end, ref mut conductor)) = tracks.first_mut() {
+            for (tick, event) in moved {
+                let event = with_delta(event, 0);
+                let duplicate = conductor.iter().any(|&(t, ref e)| {
+                    t == tick && with_delta(e.clone(), 0) == event
+                });
+                if !duplicate {
+                    *end = ::std::cmp::max(*end, tick);
+                    conductor.push((tick, event));
+                }
+            }
+            // Stable, so the moved events follow the first track's own.
+            conductor.sort_by_key(|&(tick, _)| tick);
+        }
+        let chunks = tracks.into_iter()
+            .map(|(end, events)| from_absolute(events.into_iter(), end))
+            .collect();
+        Some(self.converted(1, chunks))
+    }
+
+    /// Repairs a format 0 file that has several tracks, or events that only
+    /// make sense with several tracks, by merging the tracks into one and
+    /// making every track name after the first a plain text event, and
+    /// dropping every sequence number after the first. Returns `None` for
+    /// files of other formats.
+    pub fn fix_format_0(&self) -> Option<Midi<'a>> {
+        if self.header.format != 0 {
+            return None;
+        }
+        let merged = self.to_format_0();
+        let track = match merged.tracks().next() {
+            Some(track) => track,
+            None => return Some(merged),
+        };
+        let mut named = false;
+        let mut numbered = false;
+        let mut events = Vec::new();
+        for (tick, event) in track.absolute_events() {
+            let event = match *event {
+                Event::Meta(_, MetaEvent::EndOfTrack) => continue,
+                Event::Meta(dt, MetaEvent::Text { kind: TextType::TrackName, text }) if named => {
+                    Event::Meta(dt, MetaEvent::Text {
+                        kind: TextType::Text,
+                        text: text,
+                    })
+                }
+                Event::Meta(_, MetaEvent::Text { kind: TextType::TrackName, .. }) => {
+                    named = true;
+                    event.clone()
+                }
+                Event::Meta(_, MetaEvent::SequenceNumber(_)) if numbered => continue,
+                Event::Meta(_, MetaEvent::SequenceNumber(_)) => {
+                    numbered = true;
+                    event.clone()
+                }
+                _ => event.clone(),
+            };
+            events.push((tick, event));
+        }
+        let track = from_absolute(events.into_iter(), track.duration());
+        Some(self.converted(0, vec![track]))
+    }
+
+    /// Changes the number of ticks per quarter note, rounding every event
+    /// to the nearest new tick. Returns `None` for files with SMPTE timing.
+    pub fn rescale(&self, ticks_per_quarter: u16) -> Option<Midi<'a>> {
+        match self.header.division() {
+            Division::TicksPerQuarter(old) if old > 0 => {}
+            _ => return None,
+        }
+        let tempo_map = TempoMap::from_changes(self.header.division(), Vec::new());
+        let chunks = self.retimed(ticks_per_quarter & 0x7FFF, &tempo_map);
+        let mut midi = self.converted(self.header.format, chunks);
+        midi.header.division = ticks_per_quarter & 0x7FFF;
+        Some(midi)
+    }
+
+    /// Takes the file out of its RIFF container, if it was in one.
+    pub fn into_smf(mut self) -> Midi<'a> {
+        self.rmid = None;
+        self
+    }
+
+    /// Puts the file in a RIFF RMID container when written, keeping any
+    /// chunks of the container it already had.
+    pub fn into_rmid(mut self) -> Midi<'a> {
+        if self.rmid.is_none() {
+            self.rmid = Some(::rmid::Rmid { chunks: Vec::new() });
+        }
+        self
+    }
+
+    /// A copy of the file with new tracks, keeping the chunks that aren't
+    /// tracks after them.
+    fn converted(&self, format: u16, mut chunks: Vec<Chunk<'a>>) -> Midi<'a> {
+        for chunk in &self.chunks {
+            if let Chunk::Other { kind, data } = *chunk {
+                chunks.push(Chunk::Other {
+                    kind: kind,
+                    data: data,
+                });
+            }
+        }
+        let tracks = chunks.iter().filter(|chunk| match **chunk {
+            Chunk::Track(_) => true,
+            Chunk::Other { .. } => false,
+        });
+        Midi {
+            header: Header {
+                len: 6,
+                format: format,
+                tracks: tracks.count() as u16,
+                division: self.header.division,
+            },
+            chunks: chunks,
+            rmid: self.rmid.clone(),
+        }
+    }
+}
+
+/// Builds a track from events in order of absolute time, ending it at `end`
+/// or the last event, whichever is later.
+fn from_absolute<'a, I>(events: I, end: u64) -> Chunk<'a>
+    where I: Iterator<Item = (u64, Event<'a>)>
+{
+    let mut time = 0;
+    let mut track = Vec::with_capacity(events.size_hint().0 + 1);
+    for (tick, event) in events {
+        track.push(with_delta(event, (tick - time) as u32));
+        time = tick;
+    }
+    let delta = end.saturating_sub(time) as u32;
+    track.push(Event::Meta(delta, MetaEvent::EndOfTrack));
+    Chunk::Track(TrackChunk { events: track.into() })
+}
+
+/// Drops events that an earlier track already has on the same tick, from
+/// events sorted by tick and then track.
+fn dedupe<'a>(events: Vec<(u64, usize, Event<'a>)>) -> Vec<(u64, usize, Event<'a>)> {
+    let mut kept: Vec<(u64, usize, Event<'a>)> = Vec::with_capacity(events.len());
+    // Where the events kept on the current tick start.
+    let mut tick_start = 0;
+    for (tick, index, event) in events {
+        if kept.last().map_or(true, |&(last, _, _)| last != tick) {
+            tick_start = kept.len();
+        }
+        let note = match event {
+            Event::Midi(_, MidiEvent::NoteOn { .. }) |
+            Event::Midi(_, MidiEvent::NoteOff { .. }) => true,
+            _ => false,
+        };
+        let duplicate = !note && kept[tick_start..].iter().any(|&(_, other, ref kept)| {
+            other != index && *kept == event
+        });
+        if !duplicate {
+            kept.push((tick, index, event));
+        }
+    }
+    kept
+}
+
+fn with_delta(event: Event, delta: u32) -> Event {
+    match event {
+        Event::Midi(_, event) => Event::Midi(delta, event),
+        Event::Meta(_, event) => Event::Meta(delta, event),
+        Event::Sysex(_, event) => Event::Sysex(delta, event),
+    }
+}
+
+
+// Combining Files /////////////////////////////////////////////////////////////
+
+/// How `overlay` and `append` combine files.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CombineOptions {
+    /// The ticks per quarter note of the combined file, or `None` for the
+    /// finest division among the files, so no timing is lost.
+    pub ticks_per_quarter: Option<u16>,
+    /// How the events that end up on the same tick of a track are ordered,
+    /// like where one file ends and the next starts, or where rescaling
+    /// moves events together.
+    pub order: Order,
+}
+
+/// The division used when every file being combined uses SMPTE timing.
+const COMBINED_TICKS_PER_QUARTER: u16 = 480;
+
+impl<'a> Midi<'a> {
+    /// Adds the tracks of `other` after this file's tracks, so they play at
+    /// the same time, as a format 1 file (or 2, if either file is). Both
+    /// files are rescaled to one division first. A file with SMPTE timing
+    /// keeps its timing in seconds under the other file's tempo changes,
+    /// and its own tempo changes, which never applied to it, are dropped.
+    pub fn overlay(&self, other: &Midi<'a>, options: &CombineOptions) -> Midi<'a> {
+        let target = combined_division(&[self, other], options);
+        let mut changes = Vec::new();
+        for midi in &[self, other] {
+            if let Division::TicksPerQuarter(old) = midi.header.division() {
+                let old = ::std::cmp::max(1, old) as u64;
+                for track in midi.tracks() {
+                    for (tick, event) in track.absolute_events() {
+                        if let Event::Meta(_, MetaEvent::SetTempo(tempo)) = *event {
+                            changes.push(((tick * target as u64 + old / 2) / old, tempo));
+                        }
+                    }
+                }
+            }
+        }
+        let tempo_map = TempoMap::from_changes(Division::TicksPerQuarter(target), changes);
+        let mut chunks = self.retimed(target, &tempo_map);
+        chunks.extend(other.retimed(target, &tempo_map));
+        let format = if self.header.format == 2 || other.header.format == 2 { 2 } else { 1 };
+        combined(format, target, chunks, options.order)
+    }
+
+    /// Plays `other` once this file's longest track ends, continuing each
+    /// track with the track of `other` at the same index. Both files are
+    /// rescaled to one division first, and a tempo change is added where
+    /// `other` starts if it would otherwise inherit this file's last tempo.
+    pub fn append(&self, other: &Midi<'a>, options: &CombineOptions) -> Midi<'a> {
+        let target = combined_division(&[self, other], options);
+        let division = Division::TicksPerQuarter(target);
+        let mut first = Vec::new();
+        let mut rest = Vec::new();
+        split_chunks(self.retimed(target, &TempoMap::from_changes(division, Vec::new())),
+                     &mut first, &mut rest);
+        let end = first.iter().map(|track| track.duration()).max().unwrap_or(0);
+        let last_tempo = match self.header.division() {
+            Division::TicksPerQuarter(_) => self.tempo_map().tempo_at(::std::u64::MAX),
+            Division::Smpte { .. } => DEFAULT_TEMPO,
+        };
+
+        let mut second = Vec::new();
+        let tempo_map = TempoMap::from_changes(division, vec![(0, last_tempo)]);
+        split_chunks(other.retimed(target, &tempo_map), &mut second, &mut rest);
+        if let Division::TicksPerQuarter(_) = other.header.division() {
+            let starts_with_tempo = second.iter().any(|track| {
+                track.absolute_events().take_while(|&(tick, _)| tick == 0).any(|(_, event)| {
+                    match *event {
+                        Event::Meta(_, MetaEvent::SetTempo(_)) => true,
+                        _ => false,
+                    }
+                })
+            });
+            if !starts_with_tempo && last_tempo != DEFAULT_TEMPO {
+                if let Some(track) = second.first_mut() {
+                    track.events.insert(0, Event::Meta(0, MetaEvent::SetTempo(DEFAULT_TEMPO)));
+                }
+            }
+        }
+
+        let mut chunks = Vec::new();
+        for i in 0..::std::cmp::max(first.len(), second.len()) {
+            let mut events = Vec::new();
+            let mut duration = end;
+            if let Some(track) = first.get(i) {
+                for (tick, event) in track.absolute_events() {
+                    if !is_end(event) {
+                        events.push((tick, event.clone()));
+                    }
+                }
+            }
+            if let Some(track) = second.get(i) {
+                for (tick, event) in track.absolute_events() {
+                    if !is_end(event) {
+                        events.push((end + tick, event.clone()));
+                    }
+                }
+                duration += track.duration();
+            }
+            chunks.push(from_absolute(events.into_iter(), duration));
+        }
+        chunks.extend(rest);
+        let format = ::std::cmp::max(self.header.format, other.header.format);
+        let format = if format == 0 && first.len().max(second.len()) > 1 { 1 } else { format };
+        combined(format, target, chunks, options.order)
+    }
+
+    /// The chunks of the file with every tick moved to the nearest tick at
+    /// a new division. Files with SMPTE timing are placed by their times in
+    /// seconds under `tempo_map`, and lose their tempo changes.
+    fn retimed(&self, ticks_per_quarter: u16, tempo_map: &TempoMap) -> Vec<Chunk<'a>> {
+        let smpte = match self.header.division() {
+            Division::TicksPerQuarter(_) => false,
+            Division::Smpte { .. } => true,
+        };
+        let own = TempoMap::from_changes(self.header.division(), Vec::new());
+        let old = match self.header.division() {
+            Division::TicksPerQuarter(old) => ::std::cmp::max(1, old) as u64,
+            Division::Smpte { .. } => 1,
+        };
+        let new = ticks_per_quarter as u64;
+        let scale = |tick: u64| if smpte {
+            tempo_map.tick_at(own.seconds_at(tick))
+        } else {
+            (tick * new + old / 2) / old
+        };
+        self.chunks.iter().map(|chunk| match *chunk {
+            Chunk::Track(ref track) => {
+                let events = track.absolute_events()
+                    .filter(|&(_, event)| match *event {
+                        Event::Meta(_, MetaEvent::SetTempo(_)) => !smpte,
+                        _ => true,
+                    })
+                    .map(|(tick, event)| (scale(tick), event.clone()));
+                // from_absolute adds an end of track, so leave the old one
+                // out to keep its time.
+                let mut track = from_absolute(events, 0);
+                if let Chunk::Track(TrackChunk { ref mut events }) = track {
+                    events.pop();
+                }
+                track
+            }
+            Chunk::Other { kind, data } => Chunk::Other {
+                kind: kind,
+                data: data,
+            },
+        }).collect()
+    }
+}
+
+fn combined_division(files: &[&Midi], options: &CombineOptions) -> u16 {
+    let finest = files.iter()
+        .filter_map(|midi| match midi.header.division() {
+            Division::TicksPerQuarter(n) => Some(n),
+            Division::Smpte { .. } => None,
+        })
+        .max();
+    let target = options.ticks_per_quarter.or(finest).unwrap_or(COMBINED_TICKS_PER_QUARTER);
+    ::std::cmp::max(1, target & 0x7FFF)
+}
+
+fn split_chunks<'a>(chunks: Vec<Chunk<'a>>, tracks: &mut Vec<TrackChunk<'a>>,
+                    rest: &mut Vec<Chunk<'a>>) {
+    for chunk in chunks {
+        match chunk {
+            Chunk::Track(track) => tracks.push(track),
+            other => rest.push(other),
+        }
+    }
+}
+
+/// Gives each event a key to sort by for `sort_simultaneous_by`: its tick,
+/// its rank and where it came. A note off that can only end a note struck on
+/// the same tick takes the key of that note on, and ties keep the order the
+/// events came in.
+fn simultaneous_keys<'e, 'a: 'e, I, F>(events: I, rank: F) -> Vec<(u64, u8, usize)>
+    where I: Iterator<Item = (u64, &'e Event<'a>)>,
+          F: Fn(&Event) -> u8
+{
+    let events: Vec<_> = events.collect();
+    let mut keys: Vec<_> = events.iter()
+        .enumerate()
+        .map(|(index, &(tick, event))| {
+            (tick, if is_end(event) { 0xFF } else { rank(event) }, index)
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..events.len()).collect();
+    order.sort_by_key(|&index| events[index].0);
+    // How many notes of each key are sounding, and which were struck on the
+    // current tick.
+    let mut held: HashMap<(u8, u8), usize> = HashMap::new();
+    let mut struck: HashMap<(u8, u8), Vec<usize>> = HashMap::new();
+    let mut time = None;
+    for index in order {
+        let (tick, event) = events[index];
+        if time != Some(tick) {
+            struck.clear();
+            time = Some(tick);
+        }
+        let midi = match *event {
+            Event::Midi(_, ref midi) => midi.normalized(),
+            _ => continue,
+        };
+        match midi {
+            MidiEvent::NoteOn { channel, number, .. } => {
+                *held.entry((channel, number)).or_insert(0) += 1;
+                struck.entry((channel, number)).or_insert_with(Vec::new).push(index);
+            }
+            MidiEvent::NoteOff { channel, number, .. } => {
+                let held = held.entry((channel, number)).or_insert(0);
+                let struck = struck.entry((channel, number)).or_insert_with(Vec::new);
+                if *held <= struck.len() {
+                    if let Some(on) = struck.pop() {
+                        keys[index] = keys[on];
+                    }
+                }
+                *held = held.saturating_sub(1);
+            }
+            _ => {}
+        }
+    }
+    keys
+}
+
+fn is_end(event: &Event) -> bool {
+    match *event {
+        Event::Meta(_, MetaEvent::EndOfTrack) => true,
+        _ => false,
+    }
+}
+
+fn combined<'a>(format: u16, ticks_per_quarter: u16, mut chunks: Vec<Chunk<'a>>, order: Order)
+                -> Midi<'a> {
+    for chunk in &mut chunks {
+        if let Chunk::Track(ref mut track) = *chunk {
+            track.sort_simultaneous(order);
+        }
+    }
+    let tracks = chunks.iter().filter(|chunk| match **chunk {
+        Chunk::Track(_) => true,
+        Chunk::Other { .. } => false,
+    });
+    Midi {
+        header: Header {
+            len: 6,
+            format: format,
+            tracks: tracks.count() as u16,
+            division: ticks_per_quarter,
+        },
+        chunks: chunks,
+        rmid: None,
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_convert() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x17,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0x91, 0x40, 0x40,
+        0x60, 0x91, 0x40, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let split = midi.to_format_1();
+    assert_eq!(split.header().format(), 1);
+    let lens: Vec<_> = split.tracks().map(|track| track.events().len()).collect();
+    assert_eq!(lens, [2, 2, 3]);
+    assert!(split.tracks().all(|track| track.duration() == 0x60));
+    assert_eq!(split.to_format_0().to_bytes(), data);
+
+    let rescaled = midi.rescale(480).unwrap();
+    assert_eq!(rescaled.header().division(), Division::TicksPerQuarter(480));
+    let deltas: Vec<_> = rescaled.tracks().next().unwrap().events().iter()
+        .map(|event| event.delta()).collect();
+    assert_eq!(deltas, [0, 0, 0, 480, 0]);
+    assert_eq!(midi.into_rmid().to_bytes()[..4], b"RIFF"[..]);
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x11,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xC0, 0x05,
+        0x00, 0xC0, 0x05,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x11,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xC0, 0x05,
+        0x00, 0x90, 0x3C, 0x40,
+        0x10, 0xC0, 0x05,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let merged = midi.to_format_0_with(&MergeOptions {
+        dedupe: true,
+        ..MergeOptions::default()
+    });
+    let kept: Vec<_> = merged.tracks().next().unwrap().absolute_events()
+        .map(|(tick, event)| (tick, event.clone()))
+        .collect();
+    // The doubled program change within the first track stays.
+    assert_eq!(kept, [
+        (0, Event::Meta(0, MetaEvent::SetTempo(500000))),
+        (0, Event::Midi(0, MidiEvent::ProgramChange { channel: 0, program_number: 5 })),
+        (0, Event::Midi(0, MidiEvent::ProgramChange { channel: 0, program_number: 5 })),
+        (0, Event::Midi(0, MidiEvent::NoteOn { channel: 0, number: 0x3C, velocity: 0x40 })),
+        (0x10, Event::Midi(0x10, MidiEvent::ProgramChange { channel: 0, program_number: 5 })),
+        (0x10, Event::Meta(0, MetaEvent::EndOfTrack)),
+    ]);
+    assert_eq!(midi.to_format_0().tracks().next().unwrap().events().len(), 8);
+}
+
+#[cfg(test)]
+#[test]
+fn test_normalize_conductor() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1B,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap().normalize_conductor().unwrap();
+    let tracks: Vec<Vec<_>> = midi.tracks()
+        .map(|track| track.absolute_events().map(|(tick, event)| (tick, event.clone())).collect())
+        .collect();
+    assert_eq!(tracks[0], [
+        (0, Event::Meta(0, MetaEvent::SetTempo(500000))),
+        (0x60, Event::Meta(0x60, MetaEvent::TimeSignature {
+            numerator: 3,
+            denominator: 2,
+            clocks_per_metronome: 0x18,
+            notated_divisions: 8,
+        })),
+        (0x60, Event::Meta(0, MetaEvent::EndOfTrack)),
+    ]);
+    let ticks: Vec<_> = tracks[1].iter().map(|&(tick, _)| tick).collect();
+    assert_eq!(ticks, [0, 0xC0, 0xC0]);
+    assert!(midi.conformance().passes(::conformance::Rule::ConductorTrack));
+}
+
+#[cfg(test)]
+#[test]
+fn test_fix_format_0() {
+    use conformance::Rule;
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0xFF, 0x03, 0x03, b'S', b'e', b'q',
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x14,
+        0x00, 0xFF, 0x03, 0x04, b'B', b'a', b's', b's',
+        0x00, 0x90, 0x28, 0x40,
+        0x60, 0x80, 0x28, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    assert_eq!(midi.conformance().failed(), [Rule::SingleTrack, Rule::SequenceName]);
+    let fixed = midi.fix_format_0().unwrap();
+    assert!(fixed.conformance().conforms());
+    let track = fixed.tracks().next().unwrap();
+    assert_eq!(track.events()[1], Event::Meta(0, MetaEvent::Text {
+        kind: TextType::Text,
+        text: b"Bass",
+    }));
+    assert_eq!((track.events().len(), track.duration()), (5, 0x60));
+}
+
+#[cfg(test)]
+#[test]
+fn test_combine() {
+    let fast = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x13,
+        0x00, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let fine = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x01, 0xE0,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0D,
+        0x00, 0x90, 0x40, 0x40,
+        0x83, 0x60, 0x80, 0x40, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let smpte = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0xE7, 0x28,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0D,
+        0x00, 0x90, 0x43, 0x40,
+        0x87, 0x68, 0x80, 0x43, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let (fast, fine, smpte) = (::parse_midi(&fast).unwrap(), ::parse_midi(&fine).unwrap(),
+                               ::parse_midi(&smpte).unwrap());
+    let options = CombineOptions::default();
+    let spans = |midi: &Midi| {
+        midi.notes().iter().map(|note| (note.key, note.start, note.end)).collect::<Vec<_>>()
+    };
+
+    let both = fast.overlay(&fine, &options);
+    assert_eq!((both.header().format(), both.header().division()),
+               (1, Division::TicksPerQuarter(480)));
+    assert_eq!(spans(&both), [(0x3C, 0, 480), (0x40, 0, 480)]);
+
+    let joined = fast.append(&fine, &options);
+    assert_eq!((joined.header().format(), joined.tracks().count()), (0, 1));
+    assert_eq!(spans(&joined), [(0x3C, 0, 480), (0x40, 480, 960)]);
+    // The second file is back at the default tempo.
+    assert_eq!(joined.notes()[1].duration_seconds, 0.5);
+
+    let timed = fast.overlay(&smpte, &options);
+    assert_eq!(timed.header().division(), Division::TicksPerQuarter(96));
+    // A second at a quarter of a second per quarter note.
+    assert_eq!(spans(&timed)[1], (0x43, 0, 384));
+    let options = CombineOptions { ticks_per_quarter: Some(24), ..CombineOptions::default() };
+    assert_eq!(spans(&fine.append(&smpte, &options)), [(0x40, 0, 24), (0x43, 24, 72)]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_simultaneous_order() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x90, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x12,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0xC0, 0x05,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let ranks = |track: &TrackChunk| {
+        track.events().iter().map(|event| standard_rank(event)).collect::<Vec<_>>()
+    };
+    let merged = midi.to_format_0();
+    assert_eq!(ranks(merged.tracks().next().unwrap()), [6, 0, 2, 4, 6, 0]);
+    let merged = midi.to_format_0_with(&MergeOptions {
+        order: Order::Tracks,
+        ..MergeOptions::default()
+    });
+    assert_eq!(ranks(merged.tracks().next().unwrap()), [6, 6, 2, 4, 0, 0]);
+    // Reversing the ranks still leaves the end of track last.
+    let merged = midi.to_format_0_by(&MergeOptions::default(), |event| 6 - standard_rank(event));
+    assert_eq!(ranks(merged.tracks().next().unwrap()), [6, 6, 4, 2, 0, 0]);
+
+    let mut track = midi.tracks().nth(1).unwrap().clone();
+    track.sort_simultaneous_by(standard_rank);
+    assert_eq!(ranks(&track), [0, 2, 4, 0]);
+    assert_eq!(track.events()[0].delta(), 0x60);
+    assert_eq!(track.duration(), 0x60);
+
+    // A note of no length keeps its note off after its note on, while other
+    // note offs still go first.
+    let (_, mut track) = ::track_body(&[
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0x80, 0x3C, 0x40,
+        0x00, 0x80, 0x40, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ]).unwrap();
+    track.sort_simultaneous(Order::Standard);
+    let keys: Vec<_> = track.events()
+        .iter()
+        .map(|event| (standard_rank(event), event.key()))
+        .collect();
+    assert_eq!(keys, [(2, Some(0x40)), (6, Some(0x3C)), (2, Some(0x3C)), (0, None)]);
+
+    // The second copy starts with its tempo, on the tick of the note off
+    // that ends the first.
+    let fast = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x13,
+        0x00, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let fast = ::parse_midi(&fast).unwrap();
+    let joined = fast.append(&fast, &CombineOptions::default());
+    assert_eq!(ranks(joined.tracks().next().unwrap()), [0, 6, 0, 2, 6, 2, 0]);
+    let joined = fast.append(&fast, &CombineOptions {
+        order: Order::Tracks,
+        ..CombineOptions::default()
+    });
+    assert_eq!(ranks(joined.tracks().next().unwrap()), [0, 6, 2, 0, 6, 2, 0]);
+}
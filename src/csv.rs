@@ -0,0 +1,487 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt::Write;
+
+use nom::Err;
+
+use {track_body, Chunk, ControlChange, ErrorKind, Event, Header, Key, KeyKind, MetaEvent, Midi,
+     MidiEvent, SysexEvent, TextType};
+
+
+// midicsv Export //////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A record that's malformed or out of order, by line number from 1.
+    Line(usize),
+    /// The events described don't form a valid file.
+    Midi(ErrorKind),
+}
+
+static TEXT_RECORDS: &'static [(TextType, &'static str)] = &[
+    (TextType::Text, "Text_t"),
+    (TextType::Copyright, "Copyright_t"),
+    (TextType::TrackName, "Title_t"),
+    (TextType::InstrumentName, "Instrument_name_t"),
+    (TextType::Lyric, "Lyric_t"),
+    (TextType::Marker, "Marker_t"),
+    (TextType::CuePoint, "Cue_point_t"),
+];
+
+impl<'a> Midi<'a> {
+    /// Writes the file in the text format used by midicsv, with one record
+    /// per line giving the track, the absolute time and the event. Chunks
+    /// other than tracks and RMID data have no place in the format and are
+    /// left out. Keys are written as numbers, as midicsv has them, not as
+    /// note names.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "0, 0, Header, {}, {}, {}",
+                         self.header.format, self.tracks().count(), self.header.division);
+        for (index, track) in self.tracks().enumerate() {
+            let track_number = index + 1;
+            let _ = writeln!(out, "{}, 0, Start_track", track_number);
+            for (time, event) in track.absolute_events() {
+                let _ = write!(out, "{}, {}, ", track_number, time);
+                write_record(&mut out, event);
+                out.push('\n');
+            }
+        }
+        out.push_str("0, 0, End_of_file\n");
+        out
+    }
+}
+
+fn write_record(out: &mut String, event: &Event) {
+    use MidiEvent::*;
+    let _ = match *event {
+        Event::Midi(_, NoteOff { channel, number, velocity }) => {
+            write!(out, "Note_off_c, {}, {}, {}", channel, number, velocity)
+        }
+        Event::Midi(_, NoteOn { channel, number, velocity }) => {
+            write!(out, "Note_on_c, {}, {}, {}", channel, number, velocity)
+        }
+        Event::Midi(_, PolyphonicAftertouch { channel, number, pressure }) => {
+            write!(out, "Poly_aftertouch_c, {}, {}, {}", channel, number, pressure)
+        }
+        Event::Midi(_, Control { channel, change }) => {
+            let (controller, value) = change.parts();
+            write!(out, "Control_c, {}, {}, {}", channel, controller, value)
+        }
+        Event::Midi(_, ProgramChange { channel, program_number }) => {
+            write!(out, "Program_c, {}, {}", channel, program_number)
+        }
+        Event::Midi(_, ChannelAftertouch { channel, pressure }) => {
+            write!(out, "Channel_aftertouch_c, {}, {}", channel, pressure)
+        }
+        Event::Midi(_, PitchBend { channel, pitch }) => {
+            write!(out, "Pitch_bend_c, {}, {}", channel, pitch)
+        }
+        // Running status is resolved while parsing, so this never comes up.
+        Event::Midi(_, Previous(..)) => Ok(()),
+        Event::Meta(_, ref meta) => write_meta(out, meta),
+        Event::Sysex(_, ref sysex) => {
+            let name = if sysex.is_start() { "System_exclusive" } else { "System_exclusive_packet" };
+            write!(out, "{}, ", name).and_then(|_| write_data(out, sysex.data()))
+        }
+    };
+}
+
+fn write_meta(out: &mut String, meta: &MetaEvent) -> ::std::fmt::Result {
+    use MetaEvent::*;
+    match *meta {
+        SequenceNumber(n) => write!(out, "Sequence_number, {}", n),
+        Text { kind, text } => match TEXT_RECORDS.iter().find(|&&(k, _)| k == kind) {
+            Some(&(_, name)) => {
+                write!(out, "{}, ", name)?;
+                write_text(out, text);
+                Ok(())
+            }
+            None => {
+                write!(out, "Unknown_meta_event, {}, ", u8::from(kind))?;
+                write_data(out, text)
+            }
+        },
+        ChannelPrefix(channel) => write!(out, "Channel_prefix, {}", channel),
+        EndOfTrack => write!(out, "End_track"),
+        SetTempo(tempo) => write!(out, "Tempo, {}", tempo),
+        SmpteOffset { hours, minutes, seconds, frames, fractional_frames } => {
+            write!(out, "SMPTE_offset, {}, {}, {}, {}, {}",
+                   hours, minutes, seconds, frames, fractional_frames)
+        }
+        TimeSignature { numerator, denominator, clocks_per_metronome, notated_divisions } => {
+            write!(out, "Time_signature, {}, {}, {}, {}",
+                   numerator, denominator, clocks_per_metronome, notated_divisions)
+        }
+        KeySignature { key, kind } => {
+            let sharps = match key {
+                Key::Flats(n) => -(n as i16),
+                Key::OfC => 0,
+                Key::Sharps(n) => n as i16,
+            };
+            let mode = if kind == KeyKind::Minor { "minor" } else { "major" };
+            write!(out, "Key_signature, {}, \"{}\"", sharps, mode)
+        }
+        SequencerSpecific { data } => {
+            write!(out, "Sequencer_specific, ")?;
+            write_data(out, data)
+        }
+        Other { kind: 0x21, data: &[port] } => write!(out, "MIDI_port, {}", port),
+        Other { kind, data } => {
+            write!(out, "Unknown_meta_event, {}, ", kind)?;
+            write_data(out, data)
+        }
+    }
+}
+
+/// Writes the length of the data followed by each byte.
+fn write_data(out: &mut String, data: &[u8]) -> ::std::fmt::Result {
+    write!(out, "{}", data.len())?;
+    for b in data {
+        write!(out, ", {}", b)?;
+    }
+    Ok(())
+}
+
+/// Quotes text, escaping quotes by doubling them and backslashes and
+/// unprintable bytes with backslash escapes.
+fn write_text(out: &mut String, text: &[u8]) {
+    out.push('"');
+    for &b in text {
+        match b {
+            b'"' => out.push_str("\"\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20...0x7E => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\{:03o}", b);
+            }
+        }
+    }
+    out.push('"');
+}
+
+
+// midicsv Import //////////////////////////////////////////////////////////////
+
+/// Reads the midicsv text format back into the bytes of a file. Records are
+/// checked against each other the way csvmidi does: they have to name a
+/// declared track and can't go back in time within it.
+pub fn from_csv(csv: &str) -> Result<Vec<u8>, Error> {
+    let mut header = None;
+    let mut tracks: Vec<(u64, Vec<u8>)> = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line_number = index + 1;
+        let error = Error::Line(line_number);
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+        let fields = split_fields(trimmed).ok_or_else(|| error.clone())?;
+        if fields.len() < 3 {
+            return Err(error);
+        }
+        let track = number(&fields[0]).ok_or_else(|| error.clone())? as usize;
+        let time = number(&fields[1]).ok_or_else(|| error.clone())?;
+        let kind = match fields[2] {
+            Field::Bare(ref kind) => &kind[..],
+            Field::Quoted(_) => return Err(error),
+        };
+        match kind {
+            "Header" => match &fields[3..] {
+                &[ref format, _, ref division] => {
+                    let format = number(format).ok_or_else(|| error.clone())?;
+                    let division = number(division).ok_or_else(|| error.clone())?;
+                    header = Some((format as u16, division as u16));
+                }
+                _ => return Err(error),
+            },
+            "End_of_file" => break,
+            "Start_track" => {
+                if track != tracks.len() + 1 {
+                    return Err(error);
+                }
+                tracks.push((0, Vec::new()));
+            }
+            _ => {
+                let &mut (ref mut last, ref mut body) = match tracks.get_mut(track.wrapping_sub(1)) {
+                    Some(track) if time >= track.0 => track,
+                    _ => return Err(error),
+                };
+                let delta = time - *last;
+                if delta > 0x0FFF_FFFF {
+                    return Err(error);
+                }
+                *last = time;
+                write_event(kind, &fields[3..], delta as u32, body).ok_or(error)?;
+            }
+        }
+    }
+    let (format, division) = header.ok_or(Error::Line(1))?;
+    let mut chunks = Vec::with_capacity(tracks.len());
+    for &(_, ref body) in &tracks {
+        match track_body(body) {
+            Ok((_, track)) => chunks.push(Chunk::Track(track)),
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(Error::Midi(e)),
+            Err(Err::Incomplete(_)) => return Err(Error::Midi(ErrorKind::Eof)),
+        }
+    }
+    let midi = Midi {
+        header: Header {
+            len: 6,
+            format: format,
+            tracks: tracks.len() as u16,
+            division: division,
+        },
+        chunks: chunks,
+        rmid: None,
+    };
+    Ok(midi.to_bytes())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Bare(String),
+    Quoted(Vec<u8>),
+}
+
+fn number(field: &Field) -> Option<u64> {
+    match *field {
+        Field::Bare(ref text) => text.parse().ok(),
+        Field::Quoted(_) => None,
+    }
+}
+
+fn split_fields(line: &str) -> Option<Vec<Field>> {
+    let bytes = line.as_bytes();
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    loop {
+        while pos < bytes.len() && bytes[pos] == b' ' {
+            pos += 1;
+        }
+        if bytes.get(pos) == Some(&b'"') {
+            let mut text = Vec::new();
+            pos += 1;
+            loop {
+                match (bytes.get(pos), bytes.get(pos + 1)) {
+                    (Some(&b'"'), Some(&b'"')) => {
+                        text.push(b'"');
+                        pos += 2;
+                    }
+                    (Some(&b'"'), _) => {
+                        pos += 1;
+                        break;
+                    }
+                    (Some(&b'\\'), Some(&b'\\')) => {
+                        text.push(b'\\');
+                        pos += 2;
+                    }
+                    (Some(&b'\\'), Some(&(b'0'...b'7'))) => {
+                        let digits = bytes[pos + 1..].iter()
+                            .take(3)
+                            .take_while(|&&b| b >= b'0' && b <= b'7')
+                            .count();
+                        let octal = ::std::str::from_utf8(&bytes[pos + 1..pos + 1 + digits]).ok()?;
+                        text.push(u16::from_str_radix(octal, 8).ok()? as u8);
+                        pos += 1 + digits;
+                    }
+                    (Some(&b), _) => {
+                        text.push(b);
+                        pos += 1;
+                    }
+                    (None, _) => return None,
+                }
+            }
+            while pos < bytes.len() && bytes[pos] == b' ' {
+                pos += 1;
+            }
+            fields.push(Field::Quoted(text));
+        } else {
+            let end = bytes[pos..].iter().position(|&b| b == b',').map_or(bytes.len(), |i| pos + i);
+            fields.push(Field::Bare(line[pos..end].trim().to_string()));
+            pos = end;
+        }
+        match bytes.get(pos) {
+            Some(&b',') => pos += 1,
+            None => return Some(fields),
+            Some(_) => return None,
+        }
+    }
+}
+
+/// Encodes one event record, or returns `None` if its fields don't fit the
+/// record type.
+fn write_event(kind: &str, fields: &[Field], delta: u32, out: &mut Vec<u8>) -> Option<()> {
+    let n = |i: usize, max: u64| {
+        fields.get(i).and_then(number).and_then(|n| if n <= max { Some(n) } else { None })
+    };
+    let channel = || n(0, 0x0F).map(|n| n as u8);
+    let data;
+    let event = match kind {
+        "Note_off_c" => Event::Midi(delta, MidiEvent::NoteOff {
+            channel: channel()?,
+            number: n(1, 0x7F)? as u8,
+            velocity: n(2, 0x7F)? as u8,
+        }),
+        "Note_on_c" => Event::Midi(delta, MidiEvent::NoteOn {
+            channel: channel()?,
+            number: n(1, 0x7F)? as u8,
+            velocity: n(2, 0x7F)? as u8,
+        }),
+        "Poly_aftertouch_c" => Event::Midi(delta, MidiEvent::PolyphonicAftertouch {
+            channel: channel()?,
+            number: n(1, 0x7F)? as u8,
+            pressure: n(2, 0x7F)? as u8,
+        }),
+        "Control_c" => Event::Midi(delta, MidiEvent::Control {
+            channel: channel()?,
+            change: ControlChange::from_parts(n(1, 0x7F)? as u8, n(2, 0x7F)? as u8),
+        }),
+        "Program_c" => Event::Midi(delta, MidiEvent::ProgramChange {
+            channel: channel()?,
+            program_number: n(1, 0x7F)? as u8,
+        }),
+        "Channel_aftertouch_c" => Event::Midi(delta, MidiEvent::ChannelAftertouch {
+            channel: channel()?,
+            pressure: n(1, 0x7F)? as u8,
+        }),
+        "Pitch_bend_c" => Event::Midi(delta, MidiEvent::PitchBend {
+            channel: channel()?,
+            pitch: n(1, 0x3FFF)? as u16,
+        }),
+        "Sequence_number" => Event::Meta(delta, MetaEvent::SequenceNumber(n(0, 0xFFFF)? as u16)),
+        "Channel_prefix" => Event::Meta(delta, MetaEvent::ChannelPrefix(n(0, 0xFF)? as u8)),
+        "MIDI_port" => {
+            data = vec![n(0, 0xFF)? as u8];
+            Event::Meta(delta, MetaEvent::Other {
+                kind: 0x21,
+                data: &data,
+            })
+        }
+        "End_track" => Event::Meta(delta, MetaEvent::EndOfTrack),
+        "Tempo" => Event::Meta(delta, MetaEvent::SetTempo(n(0, 0x00FF_FFFF)? as u32)),
+        "SMPTE_offset" => Event::Meta(delta, MetaEvent::SmpteOffset {
+            hours: n(0, 0xFF)? as u8,
+            minutes: n(1, 0xFF)? as u8,
+            seconds: n(2, 0xFF)? as u8,
+            frames: n(3, 0xFF)? as u8,
+            fractional_frames: n(4, 0xFF)? as u8,
+        }),
+        "Time_signature" => Event::Meta(delta, MetaEvent::TimeSignature {
+            numerator: n(0, 0xFF)? as u8,
+            denominator: n(1, 0xFF)? as u8,
+            clocks_per_metronome: n(2, 0xFF)? as u8,
+            notated_divisions: n(3, 0xFF)? as u8,
+        }),
+        "Key_signature" => {
+            let sharps: i8 = match fields.get(0) {
+                Some(&Field::Bare(ref text)) => text.parse().ok()?,
+                _ => return None,
+            };
+            let mode = match fields.get(1) {
+                Some(&Field::Quoted(ref mode)) => &mode[..],
+                Some(&Field::Bare(ref mode)) => mode.as_bytes(),
+                None => return None,
+            };
+            let kind = if mode.eq_ignore_ascii_case(b"minor") {
+                KeyKind::Minor
+            } else if mode.eq_ignore_ascii_case(b"major") {
+                KeyKind::Major
+            } else {
+                return None;
+            };
+            Event::Meta(delta, MetaEvent::KeySignature {
+                key: if sharps > 0 {
+                    Key::Sharps(sharps as u8)
+                } else if sharps < 0 {
+                    Key::Flats(sharps.wrapping_neg() as u8)
+                } else {
+                    Key::OfC
+                },
+                kind: kind,
+            })
+        }
+        "Sequencer_specific" => {
+            data = byte_fields(fields)?;
+            Event::Meta(delta, MetaEvent::SequencerSpecific { data: &data })
+        }
+        "Unknown_meta_event" => {
+            let kind = n(0, 0xFF)? as u8;
+            data = byte_fields(&fields[1..])?;
+            match kind {
+                0x01...0x0F => Event::Meta(delta, MetaEvent::Text {
+                    kind: TextType::from(kind),
+                    text: &data,
+                }),
+                _ => Event::Meta(delta, MetaEvent::Other {
+                    kind: kind,
+                    data: &data,
+                }),
+            }
+        }
+        "System_exclusive" | "System_exclusive_packet" => {
+            data = byte_fields(fields)?;
+            Event::Sysex(delta, SysexEvent {
+                start: kind == "System_exclusive",
+                end: data.last() == Some(&0xF7),
+                data: &data,
+            })
+        }
+        _ => {
+            let text_kind = TEXT_RECORDS.iter().find(|&&(_, name)| name == kind)?.0;
+            data = match fields {
+                &[Field::Quoted(ref text)] => text.clone(),
+                _ => return None,
+            };
+            Event::Meta(delta, MetaEvent::Text {
+                kind: text_kind,
+                text: &data,
+            })
+        }
+    };
+    event.write_to(out);
+    Some(())
+}
+
+/// Reads a length field followed by that many bytes.
+fn byte_fields(fields: &[Field]) -> Option<Vec<u8>> {
+    let len = fields.get(0).and_then(number)? as usize;
+    if fields.len() != len + 1 {
+        return None;
+    }
+    fields[1..].iter()
+        .map(|field| number(field).and_then(|b| if b <= 0xFF { Some(b as u8) } else { None }))
+        .collect()
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_csv_round_trip() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1F,
+        0x00, 0xFF, 0x03, 0x04, b'a', b'"', b',', 0x01,
+        0x00, 0xFF, 0x59, 0x02, 0xFD, 0x01,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x00,
+        0x00, 0xF0, 0x02, 0x7E, 0xF7,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let csv = ::parse_midi(&data).unwrap().to_csv();
+    assert_eq!(csv, "0, 0, Header, 1, 1, 96\n\
+                     1, 0, Start_track\n\
+                     1, 0, Title_t, \"a\"\",\\001\"\n\
+                     1, 0, Key_signature, -3, \"minor\"\n\
+                     1, 0, Note_on_c, 0, 60, 64\n\
+                     1, 96, Note_off_c, 0, 60, 0\n\
+                     1, 96, System_exclusive, 2, 126, 247\n\
+                     1, 96, End_track\n\
+                     0, 0, End_of_file\n");
+    assert_eq!(&from_csv(&csv).unwrap()[..], &data[..]);
+    assert_eq!(from_csv("0, 0, Header, 0, 1, 96\n1, 10, Note_on_c, 0, 60, 64\n"),
+               Err(Error::Line(2)));
+}
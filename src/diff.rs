@@ -0,0 +1,323 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt;
+use std::mem;
+
+use {Event, MetaEvent, Midi, TrackChunk};
+
+
+// Structural Diffs ////////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct DiffOptions {
+    /// Ignores differences in how a file is written that don't change how
+    /// it plays: note offs written as note ons with a velocity of zero, the
+    /// length the header gives itself, the header's count of tracks, and
+    /// whether the file is in an RMID container.
+    pub ignore_encoding: bool,
+}
+
+/// One difference between two files. Events are compared by their absolute
+/// ticks, so they're given here with a delta time of zero, and the order of
+/// events on the same tick doesn't matter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<'a> {
+    Header {
+        field: &'static str,
+        old: u32,
+        new: u32,
+    },
+    /// Whether the file is in an RMID container.
+    Container {
+        old: bool,
+        new: bool,
+    },
+    TrackAdded(usize),
+    TrackRemoved(usize),
+    Added {
+        track: usize,
+        tick: u64,
+        event: Event<'a>,
+    },
+    Removed {
+        track: usize,
+        tick: u64,
+        event: Event<'a>,
+    },
+    /// An event replaced by one of the same kind on the same tick, like a
+    /// note with a new velocity or a new tempo.
+    Changed {
+        track: usize,
+        tick: u64,
+        old: Event<'a>,
+        new: Event<'a>,
+    },
+    /// The same event on a different tick.
+    Moved {
+        track: usize,
+        old_tick: u64,
+        new_tick: u64,
+        event: Event<'a>,
+    },
+}
+
+impl<'a> fmt::Display for Change<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Header { field, old, new } => {
+                write!(f, "header {}: {} -> {}", field, old, new)
+            }
+            Change::Container { new: true, .. } => f.write_str("put in an RMID container"),
+            Change::Container { .. } => f.write_str("taken out of its RMID container"),
+            Change::TrackAdded(track) => write!(f, "track {} added", track),
+            Change::TrackRemoved(track) => write!(f, "track {} removed", track),
+            Change::Added { track, tick, ref event } => {
+                write!(f, "track {}, tick {}: added {:?}", track, tick, event)
+            }
+            Change::Removed { track, tick, ref event } => {
+                write!(f, "track {}, tick {}: removed {:?}", track, tick, event)
+            }
+            Change::Changed { track, tick, ref old, ref new } => {
+                write!(f, "track {}, tick {}: {:?} -> {:?}", track, tick, old, new)
+            }
+            Change::Moved { track, old_tick, new_tick, ref event } => {
+                write!(f, "track {}, tick {} -> {}: {:?}", track, old_tick, new_tick, event)
+            }
+        }
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// What changed from this file to `other`, as in `diff`.
+    pub fn diff(&self, other: &Midi<'a>, options: &DiffOptions) -> Vec<Change<'a>> {
+        diff(self, other, options)
+    }
+}
+
+/// Compares two files, listing the changes to the header first and then
+/// the changes to each track in order of tick. Tracks are matched up by
+/// their index, so extra tracks at the end are added or removed whole.
+pub fn diff<'a>(old: &Midi<'a>, new: &Midi<'a>, options: &DiffOptions) -> Vec<Change<'a>> {
+    let mut changes = Vec::new();
+    let mut header = |field, old: u32, new: u32| if old != new {
+        changes.push(Change::Header {
+            field: field,
+            old: old,
+            new: new,
+        });
+    };
+    header("format", old.header.format as u32, new.header.format as u32);
+    header("division", old.header.division as u32, new.header.division as u32);
+    if !options.ignore_encoding {
+        header("length", old.header.len, new.header.len);
+        header("tracks", old.header.tracks as u32, new.header.tracks as u32);
+    }
+    if !options.ignore_encoding && old.rmid.is_some() != new.rmid.is_some() {
+        changes.push(Change::Container {
+            old: old.rmid.is_some(),
+            new: new.rmid.is_some(),
+        });
+    }
+
+    let old_tracks: Vec<_> = old.tracks().collect();
+    let new_tracks: Vec<_> = new.tracks().collect();
+    for (index, (old, new)) in old_tracks.iter().zip(&new_tracks).enumerate() {
+        diff_track(index, old, new, options, &mut changes);
+    }
+    for index in new_tracks.len()..old_tracks.len() {
+        changes.push(Change::TrackRemoved(index));
+    }
+    for index in old_tracks.len()..new_tracks.len() {
+        changes.push(Change::TrackAdded(index));
+    }
+    changes
+}
+
+fn diff_track<'a>(track: usize, old: &TrackChunk<'a>, new: &TrackChunk<'a>,
+                  options: &DiffOptions, changes: &mut Vec<Change<'a>>) {
+    let prepare = |chunk: &TrackChunk<'a>| -> Vec<(u64, Event<'a>)> {
+        chunk.absolute_events().map(|(tick, event)| (tick, normalize(event, options))).collect()
+    };
+    let (old, new) = (prepare(old), prepare(new));
+
+    // Match up the events that are the same on the same tick, taking both
+    // tracks a tick at a time.
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        let tick = match (old.get(i), new.get(j)) {
+            (Some(&(a, _)), Some(&(b, _))) => ::std::cmp::min(a, b),
+            (Some(&(a, _)), None) => a,
+            (None, Some(&(b, _))) => b,
+            (None, None) => break,
+        };
+        let old_end = i + old[i..].iter().take_while(|&&(t, _)| t == tick).count();
+        let new_end = j + new[j..].iter().take_while(|&&(t, _)| t == tick).count();
+        let mut matched = vec![false; new_end - j];
+        for &(_, ref event) in &old[i..old_end] {
+            let found = (j..new_end).find(|&k| !matched[k - j] && new[k].1 == *event);
+            match found {
+                Some(k) => matched[k - j] = true,
+                None => removed.push((tick, event.clone())),
+            }
+        }
+        for k in j..new_end {
+            if !matched[k - j] {
+                added.push(Some(new[k].clone()));
+            }
+        }
+        i = old_end;
+        j = new_end;
+    }
+
+    let mut found = Vec::new();
+    // The same event elsewhere was moved.
+    let mut rest = Vec::new();
+    for (tick, event) in removed {
+        let moved = added.iter().position(|a| a.as_ref().map_or(false, |a| a.1 == event));
+        match moved {
+            Some(k) => {
+                let (new_tick, _) = added[k].take().unwrap();
+                found.push(Change::Moved {
+                    track: track,
+                    old_tick: tick,
+                    new_tick: new_tick,
+                    event: event,
+                });
+            }
+            None => rest.push((tick, event)),
+        }
+    }
+    // An event of the same kind on the same tick was changed.
+    for (tick, event) in rest {
+        let changed = added.iter().position(|a| {
+            a.as_ref().map_or(false, |a| a.0 == tick && same_kind(&a.1, &event))
+        });
+        match changed {
+            Some(k) => {
+                let (_, new) = added[k].take().unwrap();
+                found.push(Change::Changed {
+                    track: track,
+                    tick: tick,
+                    old: event,
+                    new: new,
+                });
+            }
+            None => found.push(Change::Removed {
+                track: track,
+                tick: tick,
+                event: event,
+            }),
+        }
+    }
+    for (tick, event) in added.into_iter().filter_map(|a| a) {
+        found.push(Change::Added {
+            track: track,
+            tick: tick,
+            event: event,
+        });
+    }
+    // The sort is stable, so changes on the same tick stay in order.
+    found.sort_by_key(|change| match *change {
+        Change::Added { tick, .. } |
+        Change::Removed { tick, .. } |
+        Change::Changed { tick, .. } |
+        Change::Moved { old_tick: tick, .. } => tick,
+        _ => 0,
+    });
+    changes.extend(found);
+}
+
+fn normalize<'a>(event: &Event<'a>, options: &DiffOptions) -> Event<'a> {
+    match *event {
+        Event::Midi(_, midi) if options.ignore_encoding => Event::Midi(0, midi.normalized()),
+        _ => {
+            let mut event = event.clone();
+            event.set_delta(0);
+            event
+        }
+    }
+}
+
+/// Whether one event could have been edited into the other: channel
+/// messages of the same type on the same channel, meta events of the same
+/// type, or two sysex messages.
+fn same_kind(a: &Event, b: &Event) -> bool {
+    match (a, b) {
+        (&Event::Midi(_, ref a), &Event::Midi(_, ref b)) => {
+            mem::discriminant(a) == mem::discriminant(b) && a.channel() == b.channel()
+        }
+        (&Event::Meta(_, MetaEvent::Text { kind: a, .. }),
+         &Event::Meta(_, MetaEvent::Text { kind: b, .. })) => a == b,
+        (&Event::Meta(_, MetaEvent::Other { kind: a, .. }),
+         &Event::Meta(_, MetaEvent::Other { kind: b, .. })) => a == b,
+        (&Event::Meta(_, ref a), &Event::Meta(_, ref b)) => {
+            mem::discriminant(a) == mem::discriminant(b)
+        }
+        (&Event::Sysex(..), &Event::Sysex(..)) => true,
+        _ => false,
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_diff() {
+    use MidiEvent;
+
+    let old = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1B,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0x90, 0x3E, 0x40,
+        0x60, 0x90, 0x3E, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    // The tempo changes, the first note gets louder, the second is
+    // released with a note off and moves later, and a note is added.
+    let new = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1F,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x00,
+        0x00, 0x90, 0x3C, 0x50,
+        0x60, 0x80, 0x3C, 0x40,
+        0x10, 0x90, 0x3E, 0x40,
+        0x50, 0x80, 0x3E, 0x40,
+        0x00, 0x90, 0x40, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let (old, new) = (::parse_midi(&old).unwrap(), ::parse_midi(&new).unwrap());
+    assert!(old.diff(&old, &DiffOptions::default()).is_empty());
+    let options = DiffOptions { ignore_encoding: true };
+    let changes: Vec<_> = old.diff(&new, &options).iter().map(|c| c.to_string()).collect();
+    assert_eq!(changes, [
+        "track 0, tick 0: Meta(0, SetTempo(500000)) -> Meta(0, SetTempo(499968))",
+        "track 0, tick 0: Midi(0, NoteOn { channel: 0, number: 60, velocity: 64 }) -> \
+         Midi(0, NoteOn { channel: 0, number: 60, velocity: 80 })",
+        "track 0, tick 96 -> 112: Midi(0, NoteOn { channel: 0, number: 62, velocity: 64 })",
+        "track 0, tick 192: added Midi(0, NoteOn { channel: 0, number: 64, velocity: 64 })",
+    ]);
+    // Without normalizing, the note off is a different event.
+    let changes = old.diff(&new, &DiffOptions::default());
+    assert_eq!(changes[3], Change::Changed {
+        track: 0,
+        tick: 192,
+        old: Event::Midi(0, MidiEvent::NoteOn {
+            channel: 0,
+            number: 0x3E,
+            velocity: 0,
+        }),
+        new: Event::Midi(0, MidiEvent::NoteOn {
+            channel: 0,
+            number: 0x40,
+            velocity: 0x40,
+        }),
+    });
+}
@@ -0,0 +1,408 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Chunk, Event, MetaEvent, Midi, TrackChunk};
+use selection::EventHandle;
+
+
+// Editing Sessions ////////////////////////////////////////////////////////////
+
+/// A file being edited, which remembers each edit so it can be undone and
+/// redone. Edits only record the events they touch, not copies of the file.
+///
+/// Tracks are numbered among the file's tracks, and events by their index in
+/// the track, as in `TrackChunk::events`. Events are placed by absolute time,
+/// and the other events of the track keep their times.
+pub struct EditSession<'a> {
+    midi: Midi<'a>,
+    undo: Vec<Vec<Step<'a>>>,
+    redo: Vec<Vec<Step<'a>>>,
+}
+
+/// One change to a track, holding what it needs to be reversed.
+#[derive(Debug, Clone)]
+enum Step<'a> {
+    Put {
+        track: usize,
+        index: usize,
+        tick: u64,
+        event: Event<'a>,
+    },
+    Take {
+        track: usize,
+        index: usize,
+        tick: u64,
+        event: Event<'a>,
+    },
+    Retime {
+        track: usize,
+        index: usize,
+        from: u64,
+        to: u64,
+    },
+    Replace {
+        track: usize,
+        index: usize,
+        from: Event<'a>,
+        to: Event<'a>,
+    },
+}
+
+impl<'a> EditSession<'a> {
+    pub fn new(midi: Midi<'a>) -> EditSession<'a> {
+        EditSession {
+            midi: midi,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    pub fn midi(&self) -> &Midi<'a> {
+        &self.midi
+    }
+
+    pub fn into_midi(self) -> Midi<'a> {
+        self.midi
+    }
+
+    /// Inserts an event at an absolute time, after any events already at
+    /// that time, moving the end of track to it if it comes earlier. Returns
+    /// the index of the new event, or `None` if there's no such track.
+    pub fn insert(&mut self, track: usize, tick: u64, event: Event<'a>) -> Option<usize> {
+        let mut steps = Vec::new();
+        let index = self.place(&mut steps, track, tick, event)?;
+        self.record(steps);
+        Some(index)
+    }
+
+    /// Removes an event, returning it, or `None` if there's no such event.
+    pub fn delete(&mut self, track: usize, index: usize) -> Option<Event<'a>> {
+        let (tick, event) = self.event_at(track, index)?;
+        self.commit(vec![Step::Take {
+            track: track,
+            index: index,
+            tick: tick,
+            event: event.clone(),
+        }]);
+        Some(event)
+    }
+
+    /// Moves an event to another time, returning its new index.
+    pub fn move_event(&mut self, track: usize, index: usize, tick: u64) -> Option<usize> {
+        let (from, event) = self.event_at(track, index)?;
+        let mut steps = vec![Step::Take {
+            track: track,
+            index: index,
+            tick: from,
+            event: event.clone(),
+        }];
+        apply(&mut self.midi, &steps[0]);
+        let new_index = self.place(&mut steps, track, tick, event);
+        self.record(steps);
+        new_index
+    }
+
+    /// Replaces an event with what `f` makes of it, keeping its time.
+    /// Returns false if there's no such event.
+    pub fn transform<F>(&mut self, track: usize, index: usize, f: F) -> bool
+        where F: FnOnce(&Event<'a>) -> Event<'a>
+    {
+        let (_, event) = match self.event_at(track, index) {
+            Some(found) => found,
+            None => return false,
+        };
+        let mut new = f(&event);
+        new.set_delta(event.delta());
+        self.commit(vec![Step::Replace {
+            track: track,
+            index: index,
+            from: event,
+            to: new,
+        }]);
+        true
+    }
+
+    /// Removes every event a selection picked out, as one edit.
+    pub fn delete_all(&mut self, handles: &[EventHandle]) {
+        let mut handles = handles.to_vec();
+        handles.sort();
+        handles.dedup();
+        // Later events go first, so the earlier handles still point at theirs.
+        let steps = handles.iter()
+            .rev()
+            .filter_map(|handle| {
+                self.event_at(handle.track, handle.index).map(|(tick, event)| Step::Take {
+                    track: handle.track,
+                    index: handle.index,
+                    tick: tick,
+                    event: event,
+                })
+            })
+            .collect();
+        self.commit(steps);
+    }
+
+    /// Replaces every event a selection picked out with what `f` makes of
+    /// it, as one edit.
+    pub fn transform_all<F>(&mut self, handles: &[EventHandle], mut f: F)
+        where F: FnMut(&Event<'a>) -> Event<'a>
+    {
+        let mut steps = Vec::new();
+        for handle in handles {
+            if let Some((_, event)) = self.event_at(handle.track, handle.index) {
+                let mut new = f(&event);
+                new.set_delta(event.delta());
+                steps.push(Step::Replace {
+                    track: handle.track,
+                    index: handle.index,
+                    from: event,
+                    to: new,
+                });
+            }
+        }
+        self.commit(steps);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Undoes the last edit, returning false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(steps) => {
+                for step in steps.iter().rev() {
+                    apply(&mut self.midi, &step.inverse());
+                }
+                self.redo.push(steps);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Redoes the last undone edit, returning false if there was nothing to
+    /// redo. Making a new edit forgets the undone ones.
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(steps) => {
+                for step in &steps {
+                    apply(&mut self.midi, step);
+                }
+                self.undo.push(steps);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn commit(&mut self, steps: Vec<Step<'a>>) {
+        for step in &steps {
+            apply(&mut self.midi, step);
+        }
+        self.record(steps);
+    }
+
+    /// Remembers steps that have already been applied.
+    fn record(&mut self, steps: Vec<Step<'a>>) {
+        self.undo.push(steps);
+        self.redo.clear();
+    }
+
+    fn event_at(&self, track: usize, index: usize) -> Option<(u64, Event<'a>)> {
+        let track = self.midi.tracks().nth(track)?;
+        track.absolute_events().nth(index).map(|(tick, event)| (tick, event.clone()))
+    }
+
+    /// Applies the steps that put an event at a time, adding them to `steps`.
+    fn place(&mut self, steps: &mut Vec<Step<'a>>, track: usize, tick: u64, event: Event<'a>)
+             -> Option<usize> {
+        let (index, end) = {
+            let chunk = self.midi.tracks().nth(track)?;
+            let mut index = chunk.events.len();
+            let mut end = None;
+            for (i, (time, e)) in chunk.absolute_events().enumerate() {
+                if let Event::Meta(_, MetaEvent::EndOfTrack) = *e {
+                    if time < tick {
+                        end = Some((i, time));
+                    }
+                    index = i;
+                    break;
+                }
+                if time > tick {
+                    index = i;
+                    break;
+                }
+            }
+            (index, end)
+        };
+        if let Some((end_index, from)) = end {
+            let step = Step::Retime {
+                track: track,
+                index: end_index,
+                from: from,
+                to: tick,
+            };
+            apply(&mut self.midi, &step);
+            steps.push(step);
+        }
+        let step = Step::Put {
+            track: track,
+            index: index,
+            tick: tick,
+            event: event,
+        };
+        apply(&mut self.midi, &step);
+        steps.push(step);
+        Some(index)
+    }
+}
+
+impl<'a> Step<'a> {
+    fn inverse(&self) -> Step<'a> {
+        match *self {
+            Step::Put { track, index, tick, ref event } => Step::Take {
+                track: track,
+                index: index,
+                tick: tick,
+                event: event.clone(),
+            },
+            Step::Take { track, index, tick, ref event } => Step::Put {
+                track: track,
+                index: index,
+                tick: tick,
+                event: event.clone(),
+            },
+            Step::Retime { track, index, from, to } => Step::Retime {
+                track: track,
+                index: index,
+                from: to,
+                to: from,
+            },
+            Step::Replace { track, index, ref from, ref to } => Step::Replace {
+                track: track,
+                index: index,
+                from: to.clone(),
+                to: from.clone(),
+            },
+        }
+    }
+}
+
+fn track_mut<'m, 'a>(midi: &'m mut Midi<'a>, index: usize) -> &'m mut TrackChunk<'a> {
+    midi.chunks
+        .iter_mut()
+        .filter_map(|chunk| match *chunk {
+            Chunk::Track(ref mut track) => Some(track),
+            Chunk::Other { .. } => None,
+        })
+        .nth(index)
+        .expect("edits only refer to tracks that exist")
+}
+
+/// The absolute time of the event before `index`, or 0 for the first event.
+fn time_before(track: &TrackChunk, index: usize) -> u64 {
+    track.events[..index].iter().map(|e| e.delta() as u64).sum()
+}
+
+/// Changes the delta of the event at `index`, if there is one, so it stays
+/// at the same absolute time after the delta before it changed by `change`.
+fn keep_time(track: &mut TrackChunk, index: usize, change: i64) {
+    if let Some(event) = track.events.get_mut(index) {
+        let delta = event.delta() as i64 - change;
+        event.set_delta(delta as u32);
+    }
+}
+
+fn apply<'a>(midi: &mut Midi<'a>, step: &Step<'a>) {
+    match *step {
+        Step::Put { track, index, tick, ref event } => {
+            let track = track_mut(midi, track);
+            let delta = tick - time_before(track, index);
+            let mut event = event.clone();
+            event.set_delta(delta as u32);
+            track.events.insert(index, event);
+            keep_time(track, index + 1, delta as i64);
+        }
+        Step::Take { track, index, .. } => {
+            let track = track_mut(midi, track);
+            let delta = track.events.remove(index).delta();
+            keep_time(track, index, -(delta as i64));
+        }
+        Step::Retime { track, index, from, to } => {
+            let track = track_mut(midi, track);
+            let delta = to - time_before(track, index);
+            track.events[index].set_delta(delta as u32);
+            keep_time(track, index + 1, to as i64 - from as i64);
+        }
+        Step::Replace { track, index, ref to, .. } => {
+            track_mut(midi, track).events[index] = to.clone();
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_edit_session() {
+    use MidiEvent;
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let original = ::parse_midi(&data).unwrap();
+    let ticks = |session: &EditSession| {
+        let track = session.midi().tracks().next().unwrap();
+        track.absolute_events().map(|(tick, _)| tick).collect::<Vec<_>>()
+    };
+    let mut session = EditSession::new(::parse_midi(&data).unwrap());
+    let note = Event::Midi(0, MidiEvent::NoteOn { channel: 0, number: 0x40, velocity: 0x40 });
+    assert_eq!(session.insert(0, 0x30, note.clone()), Some(1));
+    assert_eq!(ticks(&session), [0, 0x30, 0x60, 0x60]);
+    // Past the end of the track, which follows the new event.
+    assert_eq!(session.insert(0, 0x90, note), Some(3));
+    assert_eq!(ticks(&session), [0, 0x30, 0x60, 0x90, 0x90]);
+    assert_eq!(session.move_event(0, 1, 0x70), Some(2));
+    assert_eq!(ticks(&session), [0, 0x60, 0x70, 0x90, 0x90]);
+    assert!(session.transform(0, 0, |event| match *event {
+        Event::Midi(dt, MidiEvent::NoteOn { channel, number, velocity }) => {
+            Event::Midi(dt, MidiEvent::NoteOn {
+                channel: channel,
+                number: number + 12,
+                velocity: velocity,
+            })
+        }
+        ref other => other.clone(),
+    }));
+    assert!(session.delete(0, 1).is_some());
+    assert_eq!(ticks(&session), [0, 0x70, 0x90, 0x90]);
+    assert_eq!(session.delete(1, 0), None);
+
+    while session.undo() {}
+    assert_eq!(session.midi(), &original);
+    assert!(session.redo() && session.redo());
+    assert_eq!(ticks(&session), [0, 0x30, 0x60, 0x90, 0x90]);
+    session.delete(0, 0);
+    assert!(!session.can_redo());
+
+    let notes = session.midi().select(&::selection::Selection {
+        notes_only: true,
+        ..Default::default()
+    });
+    assert_eq!(notes.len(), 3);
+    session.transform_all(&notes, |event| event.clone());
+    session.delete_all(&notes);
+    assert_eq!(ticks(&session), [0x90]);
+    assert!(session.undo());
+    assert_eq!(ticks(&session), [0x30, 0x60, 0x90, 0x90]);
+}
@@ -0,0 +1,181 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::ptr;
+use std::slice;
+
+use {parse_midi, Event};
+
+
+// C Interface /////////////////////////////////////////////////////////////////
+
+/// A parsed file, owning the encoded bytes of all of its events so that
+/// nothing borrows from the buffer the caller passed in.
+pub struct NommidiFile {
+    format: u16,
+    division: u16,
+    tracks: Vec<Vec<Encoded>>,
+    bytes: Vec<u8>,
+}
+
+struct Encoded {
+    tick: u64,
+    delta: u32,
+    start: usize,
+    payload: usize,
+    end: usize,
+}
+
+/// One event, as filled in by `nommidi_event`. `data` points into the file
+/// and stays valid until the file is freed.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NommidiEvent {
+    pub tick: u64,
+    pub delta: u32,
+    /// The status byte of a channel message, 0xFF for a meta event, 0xF0 or
+    /// 0xF7 for sysex, or 0 for a message using running status.
+    pub status: u8,
+    /// The type of a meta event, or 0 for anything else.
+    pub meta_type: u8,
+    /// The data bytes of a channel message, or the contents of a meta or
+    /// sysex event.
+    pub data: *const u8,
+    pub len: usize,
+}
+
+/// Parses the `len` bytes at `data` as a Standard MIDI File or RMID file,
+/// returning null if they don't parse. The bytes aren't needed afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nommidi_parse(data: *const u8, len: usize) -> *mut NommidiFile {
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+    let midi = match parse_midi(slice::from_raw_parts(data, len)) {
+        Ok(midi) => midi,
+        Err(_) => return ptr::null_mut(),
+    };
+    let mut file = NommidiFile {
+        format: midi.header().format(),
+        division: midi.header().division,
+        tracks: Vec::new(),
+        bytes: Vec::new(),
+    };
+    for track in midi.tracks() {
+        let mut events = Vec::with_capacity(track.events().len());
+        for (tick, event) in track.absolute_events() {
+            let start = file.bytes.len();
+            match *event {
+                Event::Midi(_, ref event) => event.write_to(&mut file.bytes),
+                Event::Meta(_, ref event) => event.write_to(&mut file.bytes),
+                Event::Sysex(_, ref event) => event.write_to(&mut file.bytes),
+            }
+            events.push(Encoded {
+                tick: tick,
+                delta: event.delta(),
+                start: start,
+                payload: start + header_len(&file.bytes[start..]),
+                end: file.bytes.len(),
+            });
+        }
+        file.tracks.push(events);
+    }
+    Box::into_raw(Box::new(file))
+}
+
+/// The length of the status, meta type and length before an encoded event's
+/// data.
+fn header_len(encoded: &[u8]) -> usize {
+    let skip = match encoded[0] {
+        0xFF => 2,
+        0xF0 | 0xF7 => 1,
+        0x80...0xEF => return 1,
+        _ => return 0,
+    };
+    skip + encoded[skip..].iter().take_while(|&&b| b & 0x80 != 0).count() + 1
+}
+
+/// Frees a file returned by `nommidi_parse`. Passing null does nothing.
+#[no_mangle]
+pub unsafe extern "C" fn nommidi_free(file: *mut NommidiFile) {
+    if !file.is_null() {
+        drop(Box::from_raw(file));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nommidi_format(file: *const NommidiFile) -> u16 {
+    (*file).format
+}
+
+/// The raw division field of the header. If the top bit is clear, it's the
+/// number of ticks per quarter note.
+#[no_mangle]
+pub unsafe extern "C" fn nommidi_division(file: *const NommidiFile) -> u16 {
+    (*file).division
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nommidi_track_count(file: *const NommidiFile) -> usize {
+    (&*file).tracks.len()
+}
+
+/// The number of events in a track, or 0 if there's no such track.
+#[no_mangle]
+pub unsafe extern "C" fn nommidi_event_count(file: *const NommidiFile, track: usize) -> usize {
+    (&*file).tracks.get(track).map_or(0, |events| events.len())
+}
+
+/// Fills in `out` with an event of a track, returning false if there's no
+/// such event.
+#[no_mangle]
+pub unsafe extern "C" fn nommidi_event(file: *const NommidiFile, track: usize, index: usize,
+                                       out: *mut NommidiEvent)
+                                       -> bool {
+    let file = &*file;
+    let encoded = match file.tracks.get(track).and_then(|events| events.get(index)) {
+        Some(encoded) => encoded,
+        None => return false,
+    };
+    let bytes = &file.bytes[encoded.start..encoded.end];
+    let status = if bytes[0] >= 0x80 { bytes[0] } else { 0 };
+    *out = NommidiEvent {
+        tick: encoded.tick,
+        delta: encoded.delta,
+        status: status,
+        meta_type: if status == 0xFF { bytes[1] } else { 0 },
+        data: file.bytes[encoded.payload..].as_ptr(),
+        len: encoded.end - encoded.payload,
+    };
+    true
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_ffi() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    unsafe {
+        assert!(nommidi_parse(data.as_ptr(), 20).is_null());
+        let file = nommidi_parse(data.as_ptr(), data.len());
+        assert_eq!(nommidi_division(file), 0x60);
+        assert_eq!(nommidi_track_count(file), 1);
+        assert_eq!(nommidi_event_count(file, 0), 3);
+        let mut event = ::std::mem::zeroed();
+        assert!(nommidi_event(file, 0, 1, &mut event));
+        assert_eq!((event.tick, event.status), (0x60, 0x90));
+        assert_eq!(slice::from_raw_parts(event.data, event.len), [0x3C, 0x00]);
+        assert!(nommidi_event(file, 0, 2, &mut event));
+        assert_eq!((event.status, event.meta_type, event.len), (0xFF, 0x2F, 0));
+        assert!(!nommidi_event(file, 1, 0, &mut event));
+        nommidi_free(file);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt;
+
+use {Division, Event, Midi, MidiEvent};
+
+
+// Content Fingerprints ////////////////////////////////////////////////////////
+
+/// A hash of the music in a file: its notes, tempo changes and program
+/// changes. Files that only differ in how they're written, such as in
+/// running status, delta times, division or how the music is split into
+/// tracks, have the same fingerprint. The hash is FNV-1a and never depends
+/// on the platform or the version of Rust, so fingerprints can be stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fingerprint(pub u64);
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Positions are measured in 960ths of a quarter note, so that files at
+/// any common division agree, or in milliseconds with SMPTE timing.
+const RESOLUTION: u64 = 960;
+
+impl<'a> Midi<'a> {
+    pub fn fingerprint(&self) -> Fingerprint {
+        let position: Box<Fn(u64) -> u64> = match self.header.division() {
+            Division::TicksPerQuarter(n) => {
+                let n = ::std::cmp::max(1, n) as u64;
+                Box::new(move |tick| (tick * RESOLUTION + n / 2) / n)
+            }
+            Division::Smpte { .. } => {
+                let tempo_map = self.tempo_map();
+                Box::new(move |tick| (tempo_map.seconds_at(tick) * 1000.0).round() as u64)
+            }
+        };
+
+        let mut notes: Vec<_> = self.notes()
+            .iter()
+            .map(|note| (position(note.start), position(note.end), note.channel, note.key,
+                         note.velocity))
+            .collect();
+        notes.sort();
+        let mut programs = Vec::new();
+        for track in self.tracks() {
+            for (tick, event) in track.absolute_events() {
+                if let Event::Midi(_, MidiEvent::ProgramChange { channel, program_number }) =
+                    *event {
+                    programs.push((position(tick), channel, program_number));
+                }
+            }
+        }
+        programs.sort();
+        let tempos: Vec<_> = match self.header.division() {
+            Division::TicksPerQuarter(_) => {
+                self.tempo_map().changes().iter().map(|&(tick, tempo)| (position(tick), tempo))
+                    .collect()
+            }
+            // Tempo changes don't affect SMPTE timing.
+            Division::Smpte { .. } => Vec::new(),
+        };
+
+        let mut hash = Fnv::new();
+        hash.write_u64(notes.len() as u64);
+        for (start, end, channel, key, velocity) in notes {
+            hash.write_u64(start);
+            hash.write_u64(end);
+            hash.write(&[channel, key, velocity]);
+        }
+        hash.write_u64(programs.len() as u64);
+        for (position, channel, program) in programs {
+            hash.write_u64(position);
+            hash.write(&[channel, program]);
+        }
+        hash.write_u64(tempos.len() as u64);
+        for (position, tempo) in tempos {
+            hash.write_u64(position);
+            hash.write_u64(tempo as u64);
+        }
+        Fingerprint(hash.0)
+    }
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xCBF2_9CE4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01B3);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        let bytes: Vec<u8> = (0..8).map(|i| (value >> (i * 8)) as u8).collect();
+        self.write(&bytes);
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_fingerprint() {
+    let plain = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x17,
+        0x00, 0xC0, 0x05,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0x90, 0x40, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0x80, 0x40, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    // The same notes at 192 ticks per quarter note, in two tracks the other
+    // way round, with running status and note ons for note offs.
+    let encoded = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0xC0,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0x90, 0x40, 0x40,
+        0x81, 0x40, 0x40, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0F,
+        0x00, 0xC0, 0x05,
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x40, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let fingerprint = ::parse_midi(&plain).unwrap().fingerprint();
+    assert_eq!(::parse_midi(&encoded).unwrap().fingerprint(), fingerprint);
+    assert_eq!(fingerprint.to_string().len(), 16);
+    let mut louder = plain;
+    louder[28] = 0x41;
+    assert!(::parse_midi(&louder).unwrap().fingerprint() != fingerprint);
+    let mut program = plain;
+    program[24] = 0x06;
+    assert!(::parse_midi(&program).unwrap().fingerprint() != fingerprint);
+}
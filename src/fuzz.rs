@@ -0,0 +1,62 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {lazy, parse_midi, reader, scan, track_body, validate, warnings, ParseOptions};
+
+
+// Fuzzing Harness /////////////////////////////////////////////////////////////
+
+/// Wraps arbitrary bytes in a header and a track chunk of the right length,
+/// so that fuzzed input reaches the event parser instead of failing the
+/// header checks.
+pub fn as_track(data: &[u8]) -> Vec<u8> {
+    let mut out = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk".to_vec();
+    let len = data.len() as u32;
+    out.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+    out.extend_from_slice(data);
+    out
+}
+
+/// Runs the event parser over the bytes of a track body.
+pub fn parse_events(data: &[u8]) {
+    let _ = track_body(data);
+}
+
+/// Runs every parser that takes a whole file, none of which may panic.
+pub fn all_parsers(data: &[u8]) {
+    let _ = parse_midi(data);
+    let _ = warnings::parse_midi_lenient(data, &ParseOptions::default());
+    let _ = validate::validate(data);
+    let _ = scan::scan(data).map(|scan| scan.count());
+    if let Ok(lazy) = lazy::scan_midi(data) {
+        for i in 0..lazy.track_count() {
+            let _ = lazy.track(i);
+        }
+    }
+    let mut reader = reader::Reader::with_buffer_size(data, 16);
+    while let Ok(Some(_)) = reader.next_item() {}
+}
+
+/// Parses a file, and if it parses, checks that writing it out and parsing
+/// that gives back the same chunks. Panics if it doesn't.
+pub fn roundtrip(data: &[u8]) {
+    let midi = match parse_midi(data) {
+        Ok(midi) => midi,
+        Err(_) => return,
+    };
+    let bytes = midi.to_bytes();
+    let again = parse_midi(&bytes).expect("the written file doesn't parse");
+    assert_eq!(midi.chunks(), again.chunks());
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_roundtrip() {
+    let file = as_track(&[0x00, 0x90, 0x3C, 0x40, 0x60, 0x3C, 0x00, 0x00, 0xFF, 0x2F, 0x00]);
+    assert!(parse_midi(&file).is_ok());
+    roundtrip(&file);
+    parse_events(&file[22..]);
+    all_parsers(&file);
+}
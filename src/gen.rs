@@ -0,0 +1,291 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+
+use {Chunk, ControlChange, Event, Header, Key, KeyKind, MetaEvent, Midi, MidiEvent, SysexEvent,
+     TextType, TrackChunk};
+use write::write_var_length;
+
+
+// Generated Files /////////////////////////////////////////////////////////////
+
+/// The resolution of every generated file, in ticks per quarter note.
+pub const TICKS_PER_QUARTER: u16 = 96;
+
+/// The steps, in semitones, of a major scale.
+pub const MAJOR: [u8; 7] = [2, 2, 1, 2, 2, 2, 1];
+/// The steps of a natural minor scale.
+pub const MINOR: [u8; 7] = [2, 1, 2, 2, 1, 2, 2];
+
+/// One octave of a scale up from `root`, as quarter notes on channel 0.
+pub fn scale(root: u8, steps: &[u8]) -> Midi<'static> {
+    let mut key = root;
+    let mut keys = vec![root];
+    for &step in steps {
+        key = key.saturating_add(step);
+        keys.push(key);
+    }
+    let mut events = Vec::new();
+    for (i, &key) in keys.iter().enumerate() {
+        note(&mut events, i as u64 * TICKS_PER_QUARTER as u64, TICKS_PER_QUARTER as u64, key);
+    }
+    file(events)
+}
+
+/// A chord played one note at a time as sixteenth notes, up and back down,
+/// `times` over.
+pub fn arpeggio(chord: &[u8], times: usize) -> Midi<'static> {
+    let sixteenth = TICKS_PER_QUARTER as u64 / 4;
+    let mut pattern = chord.to_vec();
+    pattern.extend(chord.iter().rev().skip(1).take(chord.len().saturating_sub(2)));
+    let mut events = Vec::new();
+    let keys = pattern.iter().cycle().take(pattern.len() * times);
+    for (i, &key) in keys.enumerate() {
+        note(&mut events, i as u64 * sixteenth, sixteenth, key);
+    }
+    file(events)
+}
+
+/// A major triad on each root, held for a bar of 4/4.
+pub fn chords(roots: &[u8]) -> Midi<'static> {
+    let bar = TICKS_PER_QUARTER as u64 * 4;
+    let mut events = Vec::new();
+    for (i, &root) in roots.iter().enumerate() {
+        for &interval in &[0, 4, 7] {
+            note(&mut events, i as u64 * bar, bar, root.saturating_add(interval));
+        }
+    }
+    file(events)
+}
+
+/// A controller moving one step at a time from `from` to `to`, a step every
+/// `ticks` ticks, on channel 0. Values above 127 are taken as 127.
+pub fn cc_sweep(controller: u8, from: u8, to: u8, ticks: u64) -> Midi<'static> {
+    let (from, to) = (cmp::min(from, 0x7F), cmp::min(to, 0x7F));
+    let values: Vec<u8> = if from <= to {
+        (from..=to).collect()
+    } else {
+        (to..=from).rev().collect()
+    };
+    let events = values.into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            (i as u64 * ticks, Event::Midi(0, MidiEvent::Control {
+                channel: 0,
+                change: ControlChange::from_parts(controller, value),
+            }))
+        })
+        .collect();
+    file(events)
+}
+
+/// A bar of 4/4 at each tempo, with a note on every beat.
+pub fn tempo_changes(bpms: &[f64]) -> Midi<'static> {
+    let quarter = TICKS_PER_QUARTER as u64;
+    let mut events = Vec::new();
+    for (i, &bpm) in bpms.iter().enumerate() {
+        let start = i as u64 * quarter * 4;
+        let tempo = (60_000_000.0 / bpm).round() as u32;
+        events.push((start, Event::Meta(0, MetaEvent::SetTempo(tempo))));
+        for beat in 0..4 {
+            note(&mut events, start + beat * quarter, quarter / 2, 60);
+        }
+    }
+    file(events)
+}
+
+fn note(events: &mut Vec<(u64, Event<'static>)>, tick: u64, length: u64, key: u8) {
+    let key = key & 0x7F;
+    events.push((tick, Event::Midi(0, MidiEvent::NoteOn {
+        channel: 0,
+        number: key,
+        velocity: 100,
+    })));
+    events.push((tick + length, Event::Midi(0, MidiEvent::NoteOff {
+        channel: 0,
+        number: key,
+        velocity: 64,
+    })));
+}
+
+/// A format 0 file of events at absolute times, which go in order of time,
+/// with note offs before anything else on the same tick.
+fn file(mut events: Vec<(u64, Event<'static>)>) -> Midi<'static> {
+    events.sort_by_key(|&(tick, ref event)| match *event {
+        Event::Midi(_, MidiEvent::NoteOff { .. }) => (tick, 0),
+        _ => (tick, 1),
+    });
+    let mut track = Vec::new();
+    let mut time = 0;
+    for (tick, mut event) in events {
+        event.set_delta((tick - time) as u32);
+        track.push(event);
+        time = tick;
+    }
+    track.push(Event::Meta(0, MetaEvent::EndOfTrack));
+    Midi {
+        header: Header {
+            len: 6,
+            format: 0,
+            tracks: 1,
+            division: TICKS_PER_QUARTER,
+        },
+        chunks: vec![Chunk::Track(TrackChunk { events: track.into() })],
+        rmid: None,
+    }
+}
+
+
+// Random Files ////////////////////////////////////////////////////////////////
+
+/// The bytes of a random but well-formed file, the same for the same seed.
+/// Files have one to four tracks of random channel messages, meta events and
+/// sysex messages, with running status used at random, for testing that the
+/// parser and the writer agree.
+pub fn random_file(seed: u64) -> Vec<u8> {
+    let mut rng = Rng::new(seed);
+    let tracks = rng.below(4) as u16 + 1;
+    let format = if tracks == 1 { 0 } else { 1 };
+    let mut out = b"MThd\0\0\0\x06".to_vec();
+    out.extend_from_slice(&[0, format, 0, tracks as u8]);
+    let division = rng.below(0x7FFF) as u16 + 1;
+    out.extend_from_slice(&[(division >> 8) as u8, division as u8]);
+    for _ in 0..tracks {
+        let body = random_track(&mut rng);
+        out.extend_from_slice(b"MTrk");
+        let len = body.len() as u32;
+        out.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8,
+                                (len >> 8) as u8, len as u8]);
+        out.extend_from_slice(&body);
+    }
+    out
+}
+
+fn random_track(rng: &mut Rng) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut running_status = None;
+    for _ in 0..rng.below(64) {
+        // Mostly short deltas, with the odd long one.
+        let delta = if rng.below(8) == 0 { rng.below(0x0FFF_FFFF) } else { rng.below(0x100) };
+        write_var_length(&mut out, delta as u32);
+        match rng.below(10) {
+            0 => {
+                running_status = None;
+                let kind = [0x01, 0x03, 0x05, 0x06][rng.below(4) as usize];
+                let len = rng.below(16) as usize;
+                let text: Vec<u8> = (0..len).map(|_| rng.below(0x100) as u8).collect();
+                MetaEvent::Text {
+                    kind: TextType::from(kind),
+                    text: &text,
+                }.write_to(&mut out);
+            }
+            1 => {
+                running_status = None;
+                match rng.below(3) {
+                    0 => MetaEvent::SetTempo(rng.below(0x1000000) as u32),
+                    1 => MetaEvent::TimeSignature {
+                        numerator: rng.below(16) as u8 + 1,
+                        denominator: rng.below(6) as u8,
+                        clocks_per_metronome: 24,
+                        notated_divisions: 8,
+                    },
+                    _ => MetaEvent::KeySignature {
+                        key: match rng.below(15) as u8 {
+                            n @ 0...6 => Key::Flats(n + 1),
+                            7 => Key::OfC,
+                            n => Key::Sharps(n - 7),
+                        },
+                        kind: if rng.below(2) == 0 { KeyKind::Major } else { KeyKind::Minor },
+                    },
+                }.write_to(&mut out);
+            }
+            2 => {
+                running_status = None;
+                let len = rng.below(16) as usize;
+                let mut data: Vec<u8> = (0..len).map(|_| rng.below(0x80) as u8).collect();
+                data.push(0xF7);
+                SysexEvent {
+                    start: true,
+                    end: true,
+                    data: &data,
+                }.write_to(&mut out);
+            }
+            _ => {
+                let status = 0x80 | (rng.below(7) as u8) << 4 | rng.below(16) as u8;
+                if running_status != Some(status) || rng.below(2) == 0 {
+                    out.push(status);
+                }
+                running_status = Some(status);
+                out.push(rng.below(0x80) as u8);
+                if status & 0xE0 != 0xC0 {
+                    out.push(rng.below(0x80) as u8);
+                }
+            }
+        }
+    }
+    out.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+    out
+}
+
+/// A small xorshift generator, so the files don't depend on an outside
+/// source of randomness.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // The state must never be zero.
+        Rng { state: seed ^ 0x9E37_79B9_7F4A_7C15 | 1 }
+    }
+
+    /// A number from 0 up to but not including `n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state % n
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_generators() {
+    use validate::{validate_midi, Severity};
+
+    let files = [
+        scale(60, &MAJOR),
+        arpeggio(&[60, 64, 67, 72], 2),
+        chords(&[60, 65, 67, 60]),
+        cc_sweep(7, 100, 0, 10),
+        tempo_changes(&[120.0, 90.0]),
+    ];
+    for midi in &files {
+        assert!(validate_midi(midi).findings.iter().all(|f| f.severity == Severity::Info));
+        assert_eq!(&::parse_midi(&midi.to_bytes()).unwrap(), midi);
+    }
+    let notes = files[0].notes();
+    assert_eq!(notes.iter().map(|note| note.key).collect::<Vec<_>>(),
+               [60, 62, 64, 65, 67, 69, 71, 72]);
+    // Up and back down, without repeating the top or bottom note.
+    assert_eq!(files[1].notes().len(), 12);
+    assert_eq!(files[3].tracks().next().unwrap().duration(), 1000);
+    assert_eq!(files[4].tempo_map().tempo_at(0x180), 666_667);
+    assert_eq!(cc_sweep(7, 120, 255, 1).tracks().next().unwrap().events().len(), 9);
+
+    assert_eq!(random_file(7), random_file(7));
+    let mut running_status = false;
+    for seed in 0..100 {
+        let data = random_file(seed);
+        let midi = ::parse_midi(&data).unwrap();
+        assert_eq!(::parse_midi(&midi.to_bytes()).unwrap().chunks(), midi.chunks());
+        let spans = ::spans::spans(&data).unwrap();
+        running_status |= spans.chunks.iter().any(|chunk| {
+            chunk.events.iter().any(|event| event.is_running_status())
+        });
+    }
+    assert!(running_status);
+}
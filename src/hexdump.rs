@@ -0,0 +1,262 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::fmt;
+
+use {parse_midi, Chunk, ControlChange, Division, Event, MetaEvent, MidiEvent, ParseError};
+use meter::note_value;
+use pitch::NoteNames;
+use spans::spans;
+use tempo::bpm;
+
+
+// Error Context ///////////////////////////////////////////////////////////////
+
+/// Writes the rows of a hex dump around `offset`, with a row of context on
+/// either side and a marker under the byte at `offset`. An offset at the end
+/// of the input marks where the next byte would be.
+pub fn hexdump(input: &[u8], offset: usize) -> String {
+    let row = offset / 16;
+    let first = row.saturating_sub(1);
+    let last = cmp::min(row + 1, input.len().saturating_sub(1) / 16);
+    let mut out = String::new();
+    for r in first..cmp::max(last, row) + 1 {
+        let start = r * 16;
+        let bytes = &input[cmp::min(start, input.len())..cmp::min(start + 16, input.len())];
+        out.push_str(&format!("{:08X} ", start));
+        for i in 0..16 {
+            match bytes.get(i) {
+                Some(b) => out.push_str(&format!(" {:02X}", b)),
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(bytes.iter().map(|&b| if b >= 0x20 && b < 0x7F { b as char } else { '.' }));
+        out.push_str("|\n");
+        if r == row {
+            let column = 10 + (offset % 16) * 3;
+            out.push_str(&format!("{:>1$}\n", "^^", column + 2));
+        }
+    }
+    out
+}
+
+/// A parse error along with the input it came from, displayed with a hex
+/// dump of the bytes where parsing failed.
+pub struct Context<'e, 'i> {
+    error: &'e ParseError,
+    input: &'i [u8],
+}
+
+impl ParseError {
+    pub fn with_input<'e, 'i>(&'e self, input: &'i [u8]) -> Context<'e, 'i> {
+        Context {
+            error: self,
+            input: input,
+        }
+    }
+}
+
+impl<'e, 'i> fmt::Display for Context<'e, 'i> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dump = hexdump(self.input, self.error.offset);
+        write!(f, "{}\n{}", self.error, dump.trim_end_matches('\n'))
+    }
+}
+
+
+// Explained Dumps /////////////////////////////////////////////////////////////
+
+/// The bytes shown on each line of an explained dump.
+const EXPLAIN_WIDTH: usize = 8;
+
+/// Writes a hex dump of a whole file that labels what each group of bytes
+/// means: the chunk headers, the header fields, and the delta time and
+/// message of every event. Fails the same way `parse_midi` does.
+pub fn explain(input: &[u8]) -> Result<String, ParseError> {
+    explain_with(input, &NoteNames::default())
+}
+
+/// Writes an explained dump like `explain`, naming notes by a given
+/// convention.
+pub fn explain_with(input: &[u8], names: &NoteNames) -> Result<String, ParseError> {
+    let midi = parse_midi(input)?;
+    let spans = spans(input)?;
+    let mut out = String::new();
+    let header = midi.header();
+    let start = spans.header.as_ptr() as usize - input.as_ptr() as usize;
+    if start > 0 {
+        line(&mut out, 0, &input[..start], "RIFF container");
+    }
+    line(&mut out, start, &spans.header[..4], "MThd header chunk");
+    line(&mut out, start + 4, &spans.header[4..8], &format!("length {}", header.len()));
+    line(&mut out, start + 8, &spans.header[8..10], &format!("format {}", header.format()));
+    let tracks = header.tracks();
+    let tracks = format!("{} track{}", tracks, if tracks == 1 { "" } else { "s" });
+    line(&mut out, start + 10, &spans.header[10..12], &tracks);
+    let division = match header.division() {
+        Division::TicksPerQuarter(n) => format!("{} ticks per quarter note", n),
+        Division::Smpte { frames_per_second, ticks_per_frame } => {
+            format!("{} frames per second, {} ticks per frame", frames_per_second,
+                    ticks_per_frame)
+        }
+    };
+    line(&mut out, start + 12, &spans.header[12..14], &division);
+    for (chunk, span) in midi.chunks().iter().zip(&spans.chunks) {
+        let kind = String::from_utf8_lossy(&span.header[..4]);
+        line(&mut out, span.offset, &span.header[..4], &format!("{} chunk", kind));
+        line(&mut out, span.offset + 4, &span.header[4..],
+             &format!("length {}", span.data.len()));
+        let track = match *chunk {
+            Chunk::Track(ref track) => track,
+            Chunk::Other { .. } => {
+                line(&mut out, span.offset + 8, span.data, "unknown chunk data");
+                continue;
+            }
+        };
+        for (event, bytes) in track.events().iter().zip(&span.events) {
+            let delta = bytes.delta();
+            line(&mut out, bytes.offset, delta, &format!("delta {}", event.delta()));
+            let label = if bytes.is_running_status() {
+                format!("{} (running status)", describe(event, names))
+            } else {
+                describe(event, names)
+            };
+            line(&mut out, bytes.offset + delta.len(), bytes.message(), &label);
+        }
+    }
+    Ok(out)
+}
+
+/// Writes the bytes at `offset` along with a label, wrapping long runs of
+/// bytes onto unlabeled lines.
+fn line(out: &mut String, offset: usize, bytes: &[u8], label: &str) {
+    for (i, row) in bytes.chunks(EXPLAIN_WIDTH).enumerate() {
+        out.push_str(&format!("{:08X} ", offset + i * EXPLAIN_WIDTH));
+        for b in row {
+            out.push_str(&format!(" {:02X}", b));
+        }
+        if i == 0 {
+            let padding = (EXPLAIN_WIDTH - row.len()) * 3;
+            out.push_str(&format!("{:1$}  {2}", "", padding, label));
+        }
+        out.push('\n');
+    }
+}
+
+fn describe(event: &Event, names: &NoteNames) -> String {
+    match *event {
+        Event::Midi(_, ref event) => describe_midi(event, names),
+        Event::Meta(_, ref event) => describe_meta(event),
+        Event::Sysex(_, ref sysex) if sysex.is_start() => {
+            format!("sysex, {} bytes", sysex.data().len())
+        }
+        Event::Sysex(_, ref sysex) => format!("sysex packet, {} bytes", sysex.data().len()),
+    }
+}
+
+fn describe_midi(event: &MidiEvent, names: &NoteNames) -> String {
+    use MidiEvent::*;
+    match *event {
+        NoteOn { channel, number, velocity } => {
+            format!("note on, channel {}, key {} ({}), velocity {}",
+                    channel, number, names.name(number), velocity)
+        }
+        NoteOff { channel, number, velocity } => {
+            format!("note off, channel {}, key {} ({}), velocity {}",
+                    channel, number, names.name(number), velocity)
+        }
+        PolyphonicAftertouch { channel, number, pressure } => {
+            format!("aftertouch, channel {}, key {} ({}), pressure {}",
+                    channel, number, names.name(number), pressure)
+        }
+        ChannelAftertouch { channel, pressure } => {
+            format!("channel pressure, channel {}, pressure {}", channel, pressure)
+        }
+        Control { channel, change: ControlChange::Mode(mode) } => {
+            format!("channel mode, channel {}, {:?}", channel, mode)
+        }
+        Control { channel, change: ControlChange::Raw(controller, value) } => {
+            format!("control change, channel {}, controller {}, value {}",
+                    channel, controller, value)
+        }
+        ProgramChange { channel, program_number } => {
+            format!("program change, channel {}, program {}", channel, program_number)
+        }
+        PitchBend { channel, pitch } => {
+            format!("pitch bend, channel {}, {:+}", channel, pitch as i32 - 0x2000)
+        }
+        Previous(..) => "running status".to_string(),
+    }
+}
+
+fn describe_meta(event: &MetaEvent) -> String {
+    use MetaEvent::*;
+    match *event {
+        SequenceNumber(n) => format!("sequence number {}", n),
+        Text { kind, text } => {
+            format!("text ({:?}) {:?}", kind, String::from_utf8_lossy(text))
+        }
+        ChannelPrefix(channel) => format!("channel prefix {}", channel),
+        EndOfTrack => "end of track".to_string(),
+        SetTempo(tempo) => format!("tempo {} ({:.2} bpm)", tempo, bpm(tempo)),
+        SmpteOffset { hours, minutes, seconds, frames, fractional_frames } => {
+            format!("SMPTE offset {:02}:{:02}:{:02}:{:02}.{:02}",
+                    hours, minutes, seconds, frames, fractional_frames)
+        }
+        TimeSignature { numerator, denominator, .. } => {
+            format!("time signature {}/{}", numerator, note_value(denominator))
+        }
+        KeySignature { key, kind } => format!("key signature {:?} {:?}", key, kind),
+        SequencerSpecific { data } => format!("sequencer specific, {} bytes", data.len()),
+        Other { kind, data } => format!("meta {:02X}, {} bytes", kind, data.len()),
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_hexdump() {
+    let data: Vec<u8> = (0x30..0x58).collect();
+    assert_eq!(hexdump(&data, 0x12), "\
+00000000  30 31 32 33 34 35 36 37 38 39 3A 3B 3C 3D 3E 3F  |0123456789:;<=>?|
+00000010  40 41 42 43 44 45 46 47 48 49 4A 4B 4C 4D 4E 4F  |@ABCDEFGHIJKLMNO|
+                ^^
+00000020  50 51 52 53 54 55 56 57                          |PQRSTUVW|
+");
+    assert_eq!(hexdump(&data[..16], 16), "\
+00000000  30 31 32 33 34 35 36 37 38 39 3A 3B 3C 3D 3E 3F  |0123456789:;<=>?|
+00000010                                                   ||
+          ^^
+");
+}
+
+#[cfg(test)]
+#[test]
+fn test_explain() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x00, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    assert_eq!(explain(&data).unwrap(), "\
+00000000  4D 54 68 64              MThd header chunk
+00000004  00 00 00 06              length 6
+00000008  00 00                    format 0
+0000000A  00 01                    1 track
+0000000C  00 60                    96 ticks per quarter note
+0000000E  4D 54 72 6B              MTrk chunk
+00000012  00 00 00 0C              length 12
+00000016  00                       delta 0
+00000017  90 3C 40                 note on, channel 0, key 60 (C4), velocity 64
+0000001A  81 00                    delta 128
+0000001C  3C 00                    note on, channel 0, key 60 (C4), velocity 0 (running status)
+0000001E  00                       delta 0
+0000001F  FF 2F 00                 end of track
+");
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::slice;
+
+use {Event, MetaEvent, MidiEvent, TrackChunk};
+
+
+// Absolute Time Iteration /////////////////////////////////////////////////////
+
+/// Walks the events of a track, pairing each one with its absolute time in
+/// ticks since the start of the track.
+pub struct AbsoluteEvents<'m, 'a: 'm> {
+    events: slice::Iter<'m, Event<'a>>,
+    time: u64,
+}
+
+impl<'a> TrackChunk<'a> {
+    pub fn absolute_events<'m>(&'m self) -> AbsoluteEvents<'m, 'a> {
+        AbsoluteEvents::new(&self.events)
+    }
+
+    /// The absolute time of the last event in the track.
+    pub fn duration(&self) -> u64 {
+        self.events.iter().map(|e| e.delta() as u64).sum()
+    }
+
+    /// Inserts an event at an absolute time, after any events already at
+    /// that time, keeping the times of the other events. An end of track
+    /// that comes before the new event is moved to it.
+    pub fn insert(&mut self, tick: u64, mut event: Event<'a>) {
+        let mut time = 0;
+        let mut index = self.events.len();
+        for (i, e) in self.events.iter().enumerate() {
+            let next = time + e.delta() as u64;
+            let end_of_track = match *e {
+                Event::Meta(_, MetaEvent::EndOfTrack) => true,
+                _ => false,
+            };
+            if next > tick || end_of_track {
+                index = i;
+                break;
+            }
+            time = next;
+        }
+        if let Some(after) = self.events.get_mut(index) {
+            let end = time + after.delta() as u64;
+            after.set_delta(end.saturating_sub(tick) as u32);
+        }
+        event.set_delta((tick - time) as u32);
+        self.events.insert(index, event);
+    }
+}
+
+impl<'m, 'a> AbsoluteEvents<'m, 'a> {
+    pub fn new(events: &'m [Event<'a>]) -> AbsoluteEvents<'m, 'a> {
+        AbsoluteEvents {
+            events: events.iter(),
+            time: 0,
+        }
+    }
+}
+
+impl<'m, 'a> Iterator for AbsoluteEvents<'m, 'a> {
+    type Item = (u64, &'m Event<'a>);
+
+    fn next(&mut self) -> Option<(u64, &'m Event<'a>)> {
+        self.events.next().map(|event| {
+            self.time += event.delta() as u64;
+            (self.time, event)
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+
+// Filtering Adapters //////////////////////////////////////////////////////////
+
+/// Adapters for iterators over timed events. They filter after the times are
+/// worked out, so every event keeps its absolute time, and they chain, as in
+/// `track.absolute_events().on_channel(3).notes()`.
+pub trait TimedEvents<'m, 'a: 'm>: Iterator<Item = (u64, &'m Event<'a>)> + Sized {
+    /// Only note ons and note offs.
+    fn notes(self) -> Filter<Self> {
+        Filter { events: self, only: Only::Notes }
+    }
+
+    /// Only channel messages on a channel, from 0 to 15.
+    fn on_channel(self, channel: u8) -> Filter<Self> {
+        Filter { events: self, only: Only::Channel(channel) }
+    }
+
+    /// Only control changes to one controller, such as 64 for the sustain
+    /// pedal.
+    fn controllers(self, controller: u8) -> Filter<Self> {
+        Filter { events: self, only: Only::Controller(controller) }
+    }
+
+    fn metas(self) -> Filter<Self> {
+        Filter { events: self, only: Only::Metas }
+    }
+}
+
+impl<'m, 'a: 'm, I> TimedEvents<'m, 'a> for I where I: Iterator<Item = (u64, &'m Event<'a>)> {}
+
+pub struct Filter<I> {
+    events: I,
+    only: Only,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Only {
+    Notes,
+    Channel(u8),
+    Controller(u8),
+    Metas,
+}
+
+impl Only {
+    fn matches(&self, event: &Event) -> bool {
+        match (*self, event) {
+            (Only::Notes, &Event::Midi(_, MidiEvent::NoteOn { .. })) |
+            (Only::Notes, &Event::Midi(_, MidiEvent::NoteOff { .. })) |
+            (Only::Metas, &Event::Meta(..)) => true,
+            (Only::Channel(channel), &Event::Midi(_, ref event)) => {
+                event.channel() == Some(channel)
+            }
+            (Only::Controller(number), &Event::Midi(_, MidiEvent::Control { change, .. })) => {
+                change.parts().0 == number
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'m, 'a: 'm, I> Iterator for Filter<I>
+    where I: Iterator<Item = (u64, &'m Event<'a>)>
+{
+    type Item = (u64, &'m Event<'a>);
+
+    fn next(&mut self) -> Option<(u64, &'m Event<'a>)> {
+        let only = self.only;
+        self.events.find(|&(_, event)| only.matches(event))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.events.size_hint().1)
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_filters() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x18,
+        0x00, 0x90, 0x3C, 0x40,
+        0x10, 0xB3, 0x40, 0x7F,
+        0x10, 0x93, 0x3E, 0x40,
+        0x10, 0xB3, 0x07, 0x64,
+        0x10, 0x83, 0x3E, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let track = midi.tracks().next().unwrap();
+    let ticks = |events: Vec<(u64, &Event)>| events.iter().map(|&(t, _)| t).collect::<Vec<_>>();
+    assert_eq!(ticks(track.absolute_events().notes().collect()), vec![0, 0x20, 0x40]);
+    assert_eq!(ticks(track.absolute_events().on_channel(3).notes().collect()), vec![0x20, 0x40]);
+    assert_eq!(ticks(track.absolute_events().controllers(64).collect()), vec![0x10]);
+    assert_eq!(ticks(track.absolute_events().metas().collect()), vec![0x40]);
+}
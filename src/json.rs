@@ -0,0 +1,834 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::char;
+use std::fmt::Write;
+
+use nom::Err;
+
+use {track_body, Chunk, ControlChange, Division, ErrorKind, Event, Header, Key, KeyKind, MetaEvent,
+     Midi, MidiEvent, SysexEvent, TextType};
+use rmid::{RiffChunk, Rmid};
+use summary::{FileSummary, TrackSummary};
+
+
+// JSON Values /////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The input isn't valid JSON, failing at the given byte offset.
+    Syntax(usize),
+    /// The JSON doesn't describe a file. Holds the field that's missing or
+    /// out of range.
+    Field(String),
+    /// The events described don't form a valid file.
+    Midi(ErrorKind),
+}
+
+fn obj(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+fn num<T: Into<f64>>(n: T) -> Value {
+    Value::Number(n.into())
+}
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+fn bytes(data: &[u8]) -> Value {
+    Value::Array(data.iter().map(|&b| num(b)).collect())
+}
+
+/// Chunk IDs are four bytes of what's nearly always ASCII, kept readable by
+/// mapping each byte to the character with the same code.
+fn latin1(data: &[u8]) -> Value {
+    Value::String(data.iter().map(|&b| b as char).collect())
+}
+
+impl Value {
+    fn write(&self, out: &mut String) {
+        match *self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                let _ = write!(out, "{}", n as i64);
+            }
+            Value::Number(n) => {
+                let _ = write!(out, "{}", n);
+            }
+            Value::String(ref s) => write_string(out, s),
+            Value::Array(ref values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    value.write(out);
+                }
+                out.push(']');
+            }
+            Value::Object(ref fields) => {
+                out.push('{');
+                for (i, &(ref key, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(out, key);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref fields) => fields.iter().find(|&&(ref k, _)| k == key).map(|f| &f.1),
+            _ => None,
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+
+// JSON Export /////////////////////////////////////////////////////////////////
+
+impl<'a> Midi<'a> {
+    /// Describes the whole file as JSON. The top level object holds the
+    /// `format`, `division`, `chunks` and `rmid` fields, and each event is an
+    /// object with a `delta`, a `type` like `"note_on"` or `"set_tempo"`, and
+    /// fields named after the ones in the event types. Byte data is written
+    /// as arrays of numbers, and text as a string when it's valid UTF-8.
+    pub fn to_json(&self) -> String {
+        let division = match self.header.division() {
+            Division::TicksPerQuarter(n) => obj(vec![("ticks_per_quarter", num(n))]),
+            Division::Smpte { frames_per_second, ticks_per_frame } => obj(vec![
+                ("frames_per_second", num(frames_per_second)),
+                ("ticks_per_frame", num(ticks_per_frame)),
+            ]),
+        };
+        let chunks = self.chunks.iter().map(|chunk| match *chunk {
+            Chunk::Track(ref track) => obj(vec![
+                ("type", string("track")),
+                ("events", Value::Array(track.events().iter().map(event_value).collect())),
+            ]),
+            Chunk::Other { kind, data } => obj(vec![
+                ("type", string("other")),
+                ("kind", latin1(kind)),
+                ("data", bytes(data)),
+            ]),
+        }).collect();
+        let rmid = match self.rmid {
+            Some(ref rmid) => obj(vec![("chunks", Value::Array(rmid.chunks.iter().map(|chunk| {
+                obj(vec![("id", latin1(chunk.id)), ("data", bytes(chunk.data))])
+            }).collect()))]),
+            None => Value::Null,
+        };
+        let mut out = String::new();
+        obj(vec![
+            ("format", num(self.header.format)),
+            ("division", division),
+            ("chunks", Value::Array(chunks)),
+            ("rmid", rmid),
+        ]).write(&mut out);
+        out
+    }
+}
+
+fn event_value(event: &Event) -> Value {
+    let mut fields = vec![("delta", num(event.delta()))];
+    match *event {
+        Event::Midi(_, ref event) => fields.extend(midi_fields(event)),
+        Event::Meta(_, ref event) => fields.extend(meta_fields(event)),
+        Event::Sysex(_, ref event) => fields.extend(vec![
+            ("type", string("sysex")),
+            ("start", Value::Bool(event.is_start())),
+            ("end", Value::Bool(event.is_end())),
+            ("data", bytes(event.payload())),
+        ]),
+    }
+    obj(fields)
+}
+
+fn midi_fields(event: &MidiEvent) -> Vec<(&'static str, Value)> {
+    use MidiEvent::*;
+    match *event {
+        NoteOff { channel, number, velocity } => vec![
+            ("type", string("note_off")),
+            ("channel", num(channel)),
+            ("note", num(number)),
+            ("velocity", num(velocity)),
+        ],
+        NoteOn { channel, number, velocity } => vec![
+            ("type", string("note_on")),
+            ("channel", num(channel)),
+            ("note", num(number)),
+            ("velocity", num(velocity)),
+        ],
+        PolyphonicAftertouch { channel, number, pressure } => vec![
+            ("type", string("polyphonic_aftertouch")),
+            ("channel", num(channel)),
+            ("note", num(number)),
+            ("pressure", num(pressure)),
+        ],
+        Control { channel, change } => vec![
+            ("type", string("control")),
+            ("channel", num(channel)),
+            ("controller", num(change.parts().0)),
+            ("value", num(change.parts().1)),
+        ],
+        ProgramChange { channel, program_number } => vec![
+            ("type", string("program_change")),
+            ("channel", num(channel)),
+            ("program", num(program_number)),
+        ],
+        ChannelAftertouch { channel, pressure } => vec![
+            ("type", string("channel_aftertouch")),
+            ("channel", num(channel)),
+            ("pressure", num(pressure)),
+        ],
+        PitchBend { channel, pitch } => vec![
+            ("type", string("pitch_bend")),
+            ("channel", num(channel)),
+            ("pitch", num(pitch)),
+        ],
+        Previous(a, b) => vec![("type", string("previous")), ("data", bytes(&[a, b]))],
+    }
+}
+
+fn meta_fields(event: &MetaEvent) -> Vec<(&'static str, Value)> {
+    use MetaEvent::*;
+    match *event {
+        SequenceNumber(n) => vec![("type", string("sequence_number")), ("number", num(n))],
+        Text { kind, text } => {
+            let kind = match TEXT_KINDS.iter().find(|&&(k, _)| k == kind) {
+                Some(&(_, name)) => string(name),
+                None => num(u8::from(kind)),
+            };
+            let text = match ::std::str::from_utf8(text) {
+                Ok(s) => ("text", string(s)),
+                Err(_) => ("bytes", bytes(text)),
+            };
+            vec![("type", string("text")), ("kind", kind), text]
+        }
+        ChannelPrefix(channel) => {
+            vec![("type", string("channel_prefix")), ("channel", num(channel))]
+        }
+        EndOfTrack => vec![("type", string("end_of_track"))],
+        SetTempo(tempo) => vec![("type", string("set_tempo")), ("tempo", num(tempo))],
+        SmpteOffset { hours, minutes, seconds, frames, fractional_frames } => vec![
+            ("type", string("smpte_offset")),
+            ("hours", num(hours)),
+            ("minutes", num(minutes)),
+            ("seconds", num(seconds)),
+            ("frames", num(frames)),
+            ("fractional_frames", num(fractional_frames)),
+        ],
+        TimeSignature { numerator, denominator, clocks_per_metronome, notated_divisions } => vec![
+            ("type", string("time_signature")),
+            ("numerator", num(numerator)),
+            ("denominator", num(denominator)),
+            ("clocks_per_metronome", num(clocks_per_metronome)),
+            ("notated_divisions", num(notated_divisions)),
+        ],
+        KeySignature { key, kind } => {
+            let sharps = match key {
+                Key::Flats(n) => -(n as f64),
+                Key::OfC => 0.0,
+                Key::Sharps(n) => n as f64,
+            };
+            vec![
+                ("type", string("key_signature")),
+                ("sharps", num(sharps)),
+                ("minor", Value::Bool(kind == KeyKind::Minor)),
+            ]
+        }
+        SequencerSpecific { data } => {
+            vec![("type", string("sequencer_specific")), ("data", bytes(data))]
+        }
+        Other { kind, data } => {
+            vec![("type", string("meta")), ("kind", num(kind)), ("data", bytes(data))]
+        }
+    }
+}
+
+static TEXT_KINDS: &'static [(TextType, &'static str)] = &[
+    (TextType::Text, "text"),
+    (TextType::Copyright, "copyright"),
+    (TextType::TrackName, "track_name"),
+    (TextType::InstrumentName, "instrument_name"),
+    (TextType::Lyric, "lyric"),
+    (TextType::Marker, "marker"),
+    (TextType::CuePoint, "cue_point"),
+    (TextType::ProgramName, "program_name"),
+    (TextType::DeviceName, "device_name"),
+];
+
+
+// Summary Export //////////////////////////////////////////////////////////////
+
+impl TrackSummary {
+    /// Describes the summary as a JSON object with fields named after the
+    /// ones here, and `null` for ticks that are missing.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        summary_value(self).write(&mut out);
+        out
+    }
+}
+
+impl FileSummary {
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        obj(vec![
+            ("tracks", Value::Array(self.tracks.iter().map(summary_value).collect())),
+            ("total", summary_value(&self.total)),
+        ]).write(&mut out);
+        out
+    }
+}
+
+fn summary_value(summary: &TrackSummary) -> Value {
+    let counts = &summary.events;
+    let tick = |tick: Option<u64>| tick.map_or(Value::Null, |tick| num(tick as f64));
+    obj(vec![
+        ("events", obj(vec![
+            ("note_on", num(counts.note_on as f64)),
+            ("note_off", num(counts.note_off as f64)),
+            ("polyphonic_aftertouch", num(counts.polyphonic_aftertouch as f64)),
+            ("channel_aftertouch", num(counts.channel_aftertouch as f64)),
+            ("control", num(counts.control as f64)),
+            ("program_change", num(counts.program_change as f64)),
+            ("pitch_bend", num(counts.pitch_bend as f64)),
+            ("previous", num(counts.previous as f64)),
+            ("meta", num(counts.meta as f64)),
+            ("sysex", num(counts.sysex as f64)),
+            ("total", num(counts.total() as f64)),
+        ])),
+        ("first_tick", tick(summary.first_tick)),
+        ("last_tick", tick(summary.last_tick)),
+        ("duration", num(summary.duration as f64)),
+        ("bytes", num(summary.bytes as f64)),
+        ("channels", bytes(&summary.channels)),
+        ("programs", Value::Array(summary.programs.iter().map(|&(channel, program)| {
+            obj(vec![("channel", num(channel)), ("program", num(program))])
+        }).collect())),
+    ])
+}
+
+
+// JSON Import /////////////////////////////////////////////////////////////////
+
+/// Reads a file back from the JSON that `Midi::to_json` writes, producing
+/// the bytes of the file to pass to `parse_midi`.
+pub fn from_json(json: &str) -> Result<Vec<u8>, Error> {
+    let root = parse(json)?;
+    let format = uint(&root, "format", 0xFFFF)? as u16;
+    let division_value = field(&root, "division")?;
+    let division = match division_value.get("ticks_per_quarter") {
+        Some(_) => uint(division_value, "ticks_per_quarter", 0x7FFF)? as u16,
+        None => {
+            let fps = uint(division_value, "frames_per_second", 0x7F)? as u8;
+            let ticks = uint(division_value, "ticks_per_frame", 0xFF)? as u8;
+            (fps.wrapping_neg() as u16) << 8 | ticks as u16
+        }
+    };
+
+    // Events are encoded as they're read so they can borrow their data, and
+    // then parsed back to build the file around them.
+    let mut raw_chunks = Vec::new();
+    for chunk in array(&root, "chunks")? {
+        let kind = match text_field(chunk, "type")? {
+            "track" => None,
+            _ => Some(latin1_field(chunk, "kind")?),
+        };
+        let data = match kind {
+            None => {
+                let mut body = Vec::new();
+                for event in array(chunk, "events")? {
+                    write_event(event, &mut body)?;
+                }
+                body
+            }
+            Some(_) => byte_field(chunk, "data")?,
+        };
+        raw_chunks.push((kind, data));
+    }
+    let mut raw_rmid = None;
+    if let Some(rmid) = root.get("rmid").and_then(|rmid| rmid.get("chunks")) {
+        let mut chunks = Vec::new();
+        for chunk in elements(rmid, "rmid.chunks")? {
+            chunks.push((latin1_field(chunk, "id")?, byte_field(chunk, "data")?));
+        }
+        raw_rmid = Some(chunks);
+    }
+
+    let mut chunks = Vec::with_capacity(raw_chunks.len());
+    for &(ref kind, ref data) in &raw_chunks {
+        chunks.push(match *kind {
+            None => match track_body(data) {
+                Ok((_, track)) => Chunk::Track(track),
+                Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(Error::Midi(e)),
+                Err(Err::Incomplete(_)) => return Err(Error::Midi(ErrorKind::Eof)),
+            },
+            Some(ref kind) => Chunk::Other {
+                kind: kind,
+                data: data,
+            },
+        });
+    }
+    let midi = Midi {
+        header: Header {
+            len: 6,
+            format: format,
+            tracks: raw_chunks.iter().filter(|chunk| chunk.0.is_none()).count() as u16,
+            division: division,
+        },
+        chunks: chunks,
+        rmid: raw_rmid.as_ref().map(|chunks| Rmid {
+            chunks: chunks.iter()
+                .map(|&(ref id, ref data)| RiffChunk {
+                    id: id,
+                    data: data,
+                })
+                .collect(),
+        }),
+    };
+    Ok(midi.to_bytes())
+}
+
+fn write_event(value: &Value, out: &mut Vec<u8>) -> Result<(), Error> {
+    let delta = uint(value, "delta", 0x0FFF_FFFF)? as u32;
+    let u7 = |key| uint(value, key, 0x7F).map(|n| n as u8);
+    let channel = || uint(value, "channel", 0x0F).map(|n| n as u8);
+    let data;
+    let event = match text_field(value, "type")? {
+        "note_off" => Event::Midi(delta, MidiEvent::NoteOff {
+            channel: channel()?,
+            number: u7("note")?,
+            velocity: u7("velocity")?,
+        }),
+        "note_on" => Event::Midi(delta, MidiEvent::NoteOn {
+            channel: channel()?,
+            number: u7("note")?,
+            velocity: u7("velocity")?,
+        }),
+        "polyphonic_aftertouch" => Event::Midi(delta, MidiEvent::PolyphonicAftertouch {
+            channel: channel()?,
+            number: u7("note")?,
+            pressure: u7("pressure")?,
+        }),
+        "control" => Event::Midi(delta, MidiEvent::Control {
+            channel: channel()?,
+            change: ControlChange::from_parts(u7("controller")?, u7("value")?),
+        }),
+        "program_change" => Event::Midi(delta, MidiEvent::ProgramChange {
+            channel: channel()?,
+            program_number: u7("program")?,
+        }),
+        "channel_aftertouch" => Event::Midi(delta, MidiEvent::ChannelAftertouch {
+            channel: channel()?,
+            pressure: u7("pressure")?,
+        }),
+        "pitch_bend" => Event::Midi(delta, MidiEvent::PitchBend {
+            channel: channel()?,
+            pitch: uint(value, "pitch", 0x3FFF)? as u16,
+        }),
+        "previous" => match &byte_field(value, "data")?[..] {
+            &[a, b] => Event::Midi(delta, MidiEvent::Previous(a, b)),
+            _ => return Err(Error::Field("data".to_string())),
+        },
+        "sequence_number" => {
+            Event::Meta(delta, MetaEvent::SequenceNumber(uint(value, "number", 0xFFFF)? as u16))
+        }
+        "text" => {
+            let kind = match *field(value, "kind")? {
+                Value::String(ref name) => match TEXT_KINDS.iter().find(|&&(_, n)| n == name) {
+                    Some(&(kind, _)) => kind,
+                    None => return Err(Error::Field("kind".to_string())),
+                },
+                _ => TextType::from(uint(value, "kind", 0x0F)? as u8),
+            };
+            data = match value.get("text") {
+                Some(&Value::String(ref text)) => text.as_bytes().to_vec(),
+                _ => byte_field(value, "bytes")?,
+            };
+            Event::Meta(delta, MetaEvent::Text {
+                kind: kind,
+                text: &data,
+            })
+        }
+        "channel_prefix" => Event::Meta(delta, MetaEvent::ChannelPrefix(channel()?)),
+        "end_of_track" => Event::Meta(delta, MetaEvent::EndOfTrack),
+        "set_tempo" => {
+            Event::Meta(delta, MetaEvent::SetTempo(uint(value, "tempo", 0x00FF_FFFF)? as u32))
+        }
+        "smpte_offset" => Event::Meta(delta, MetaEvent::SmpteOffset {
+            hours: uint(value, "hours", 0xFF)? as u8,
+            minutes: uint(value, "minutes", 0xFF)? as u8,
+            seconds: uint(value, "seconds", 0xFF)? as u8,
+            frames: uint(value, "frames", 0xFF)? as u8,
+            fractional_frames: uint(value, "fractional_frames", 0xFF)? as u8,
+        }),
+        "time_signature" => Event::Meta(delta, MetaEvent::TimeSignature {
+            numerator: uint(value, "numerator", 0xFF)? as u8,
+            denominator: uint(value, "denominator", 0xFF)? as u8,
+            clocks_per_metronome: uint(value, "clocks_per_metronome", 0xFF)? as u8,
+            notated_divisions: uint(value, "notated_divisions", 0xFF)? as u8,
+        }),
+        "key_signature" => {
+            let sharps = match value.get("sharps") {
+                Some(&Value::Number(n)) if n.fract() == 0.0 && n >= -127.0 && n <= 127.0 => n,
+                _ => return Err(Error::Field("sharps".to_string())),
+            };
+            let minor = match value.get("minor") {
+                Some(&Value::Bool(minor)) => minor,
+                _ => return Err(Error::Field("minor".to_string())),
+            };
+            Event::Meta(delta, MetaEvent::KeySignature {
+                key: if sharps > 0.0 {
+                    Key::Sharps(sharps as u8)
+                } else if sharps < 0.0 {
+                    Key::Flats(-sharps as u8)
+                } else {
+                    Key::OfC
+                },
+                kind: if minor { KeyKind::Minor } else { KeyKind::Major },
+            })
+        }
+        "sequencer_specific" => {
+            data = byte_field(value, "data")?;
+            Event::Meta(delta, MetaEvent::SequencerSpecific { data: &data })
+        }
+        "meta" => {
+            data = byte_field(value, "data")?;
+            Event::Meta(delta, MetaEvent::Other {
+                kind: uint(value, "kind", 0xFF)? as u8,
+                data: &data,
+            })
+        }
+        "sysex" => {
+            let flag = |key| match value.get(key) {
+                Some(&Value::Bool(flag)) => Ok(flag),
+                _ => Err(Error::Field(key.to_string())),
+            };
+            let (start, end) = (flag("start")?, flag("end")?);
+            let mut payload = byte_field(value, "data")?;
+            if end {
+                payload.push(0xF7);
+            }
+            data = payload;
+            Event::Sysex(delta, SysexEvent {
+                start: start,
+                end: end,
+                data: &data,
+            })
+        }
+        _ => return Err(Error::Field("type".to_string())),
+    };
+    event.write_to(out);
+    Ok(())
+}
+
+fn field<'v>(value: &'v Value, key: &str) -> Result<&'v Value, Error> {
+    value.get(key).ok_or_else(|| Error::Field(key.to_string()))
+}
+
+fn uint(value: &Value, key: &str, max: u64) -> Result<u64, Error> {
+    match *field(value, key)? {
+        Value::Number(n) if n.fract() == 0.0 && n >= 0.0 && n <= max as f64 => Ok(n as u64),
+        _ => Err(Error::Field(key.to_string())),
+    }
+}
+
+fn text_field<'v>(value: &'v Value, key: &str) -> Result<&'v str, Error> {
+    match *field(value, key)? {
+        Value::String(ref s) => Ok(s),
+        _ => Err(Error::Field(key.to_string())),
+    }
+}
+
+fn array<'v>(value: &'v Value, key: &str) -> Result<&'v [Value], Error> {
+    elements(field(value, key)?, key)
+}
+
+fn elements<'v>(value: &'v Value, key: &str) -> Result<&'v [Value], Error> {
+    match *value {
+        Value::Array(ref values) => Ok(values),
+        _ => Err(Error::Field(key.to_string())),
+    }
+}
+
+fn byte_field(value: &Value, key: &str) -> Result<Vec<u8>, Error> {
+    array(value, key)?.iter()
+        .map(|b| match *b {
+            Value::Number(n) if n.fract() == 0.0 && n >= 0.0 && n <= 255.0 => Ok(n as u8),
+            _ => Err(Error::Field(key.to_string())),
+        })
+        .collect()
+}
+
+fn latin1_field(value: &Value, key: &str) -> Result<Vec<u8>, Error> {
+    text_field(value, key)?.chars()
+        .map(|c| if (c as u32) < 0x100 {
+            Ok(c as u8)
+        } else {
+            Err(Error::Field(key.to_string()))
+        })
+        .collect()
+}
+
+
+// JSON Parser /////////////////////////////////////////////////////////////////
+
+fn parse(json: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        input: json.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    if parser.pos == parser.input.len() {
+        Ok(value)
+    } else {
+        Err(Error::Syntax(parser.pos))
+    }
+}
+
+/// How deeply arrays and objects can nest, so that deep input can't
+/// overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'s> {
+    input: &'s [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn error<T>(&self) -> Result<T, Error> {
+        Err(Error::Syntax(self.pos))
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &[u8]) -> Result<(), Error> {
+        if self.input[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            self.error()
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect(b"null").map(|_| Value::Null),
+            Some(b't') => self.expect(b"true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect(b"false").map(|_| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') | Some(b'{') if self.depth == MAX_DEPTH => self.error(),
+            Some(b'[') => {
+                self.pos += 1;
+                self.depth += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(Value::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            self.depth -= 1;
+                            return Ok(Value::Array(values));
+                        }
+                        _ => return self.error(),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                self.depth += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.whitespace();
+                    if self.peek() != Some(b'"') {
+                        return self.error();
+                    }
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(b":")?;
+                    fields.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            self.depth -= 1;
+                            return Ok(Value::Object(fields));
+                        }
+                        _ => return self.error(),
+                    }
+                }
+            }
+            Some(b'-') | Some(b'0'...b'9') => {
+                let start = self.pos;
+                while let Some(b'0'...b'9') | Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') |
+                          Some(b'E') = self.peek() {
+                    self.pos += 1;
+                }
+                let text = ::std::str::from_utf8(&self.input[start..self.pos]).unwrap_or("");
+                match text.parse() {
+                    Ok(n) => Ok(Value::Number(n)),
+                    Err(_) => Err(Error::Syntax(start)),
+                }
+            }
+            _ => self.error(),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\x08',
+                        Some(b'f') => '\x0C',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return self.error(),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(b) if b >= 0x20 => {
+                    bytes.push(b);
+                    self.pos += 1;
+                }
+                _ => return self.error(),
+            }
+        }
+        // The input is a str, so unescaped runs are already valid UTF-8.
+        String::from_utf8(bytes).or_else(|_| self.error())
+    }
+
+    /// Reads the hex digits of a \u escape, leaving the position on the last
+    /// digit, and combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, Error> {
+        let high = self.hex4()?;
+        let code = if high >= 0xD800 && high < 0xDC00 {
+            self.pos += 1;
+            self.expect(b"\\u")?;
+            self.pos -= 1;
+            let low = self.hex4()?;
+            if low < 0xDC00 || low >= 0xE000 {
+                return self.error();
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).map_or_else(|| self.error(), Ok)
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let digits = self.input.get(self.pos + 1..self.pos + 5).unwrap_or(&[]);
+        let text = ::std::str::from_utf8(digits).unwrap_or("");
+        match u32::from_str_radix(text, 16) {
+            Ok(n) if text.len() == 4 => {
+                self.pos += 4;
+                Ok(n)
+            }
+            _ => self.error(),
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_json_round_trip() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1A,
+        0x00, 0xFF, 0x03, 0x03, b'"', 0xC3, 0xA9,
+        0x00, 0xFF, 0x59, 0x02, 0xFD, 0x01,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0xF0, 0x02, 0x7E, 0xF7,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let json = ::parse_midi(&data).unwrap().to_json();
+    assert!(json.contains(r#"{"delta":0,"type":"note_on","channel":0,"note":60,"velocity":64}"#));
+    assert!(json.contains(r#""kind":"track_name","text":"\"é""#));
+    assert_eq!(&from_json(&json).unwrap()[..], &data[..]);
+    assert_eq!(from_json(r#"{"format": 0, "division": {"ticks_per_quarter": 96},
+                              "chunks": [{"type": "track", "events": [{"delta": 0}]}]}"#),
+               Err(Error::Field("type".to_string())));
+    let deep = "[".repeat(100_000);
+    assert_eq!(from_json(&deep), Err(Error::Syntax(MAX_DEPTH)));
+}
@@ -0,0 +1,121 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use nom::Err;
+
+use {header, parse_smf, track_body, ErrorKind, Header, Midi, ParseError, ParseOptions, TrackChunk};
+
+
+// Lazy Chunk Index ////////////////////////////////////////////////////////////
+
+/// A file where only the header and the chunk boundaries have been read, so
+/// that opening a large file doesn't cost more than the tracks used from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LazyMidi<'a> {
+    input: &'a [u8],
+    header: Header,
+    chunks: Vec<RawChunk<'a>>,
+}
+
+/// A chunk that hasn't been parsed yet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RawChunk<'a> {
+    pub kind: &'a [u8],
+    pub data: &'a [u8],
+    /// Where the chunk header starts in the file.
+    pub offset: usize,
+}
+
+impl<'a> RawChunk<'a> {
+    pub fn is_track(&self) -> bool {
+        self.kind == b"MTrk"
+    }
+}
+
+/// Reads the header and chunk boundaries of a Standard MIDI File, without
+/// parsing any events.
+pub fn scan_midi(input: &[u8]) -> Result<LazyMidi, ErrorKind> {
+    let (mut rest, header) = match header(input) {
+        Ok((rest, header)) => (rest, header),
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(e),
+        Err(Err::Incomplete(_)) => return Err(ErrorKind::Eof),
+    };
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return Err(ErrorKind::Eof);
+        }
+        let len = (rest[4] as usize) << 24 | (rest[5] as usize) << 16 |
+                  (rest[6] as usize) << 8 | rest[7] as usize;
+        if rest.len() - 8 < len {
+            return Err(ErrorKind::Eof);
+        }
+        chunks.push(RawChunk {
+            kind: &rest[..4],
+            data: &rest[8..8 + len],
+            offset: input.len() - rest.len(),
+        });
+        rest = &rest[8 + len..];
+    }
+    Ok(LazyMidi {
+        input: input,
+        header: header,
+        chunks: chunks,
+    })
+}
+
+impl<'a> LazyMidi<'a> {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn chunks(&self) -> &[RawChunk<'a>] {
+        &self.chunks
+    }
+
+    /// The number of track chunks, which may not match the header.
+    pub fn track_count(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_track()).count()
+    }
+
+    /// Parses the events of the track at `index` among the track chunks.
+    /// Nothing is cached, so each call parses the track again.
+    pub fn track(&self, index: usize) -> Option<Result<TrackChunk<'a>, ErrorKind>> {
+        let chunk = self.chunks.iter().filter(|chunk| chunk.is_track()).nth(index)?;
+        Some(match track_body(chunk.data) {
+            Ok((_, track)) => Ok(track),
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(e),
+            Err(Err::Incomplete(_)) => Err(ErrorKind::Eof),
+        })
+    }
+
+    /// Parses the whole file.
+    pub fn parse(&self) -> Result<Midi<'a>, ParseError> {
+        parse_smf(self.input, &ParseOptions::default())
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_lazy() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        // The first track is broken, but never gets parsed.
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x90,
+        0x58, 0x46, 0x49, 0x48, 0x00, 0x00, 0x00, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = scan_midi(&data).unwrap();
+    assert_eq!(midi.header().tracks(), 2);
+    assert_eq!(midi.chunks().len(), 3);
+    assert_eq!(midi.chunks()[1].kind, b"XFIH");
+    assert_eq!(midi.chunks()[2].offset, 32);
+    assert_eq!(midi.track_count(), 2);
+    assert_eq!(midi.track(1).unwrap().unwrap().events().len(), 1);
+    assert!(midi.track(2).is_none());
+}
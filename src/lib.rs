@@ -1,31 +1,470 @@
 // Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
 
-#[macro_use]
 extern crate nom;
+#[cfg(feature = "memmap")]
+extern crate memmap;
+#[cfg(feature = "python")]
+#[macro_use]
+extern crate pyo3;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "wasm")]
+extern crate js_sys;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+use std::sync::Arc;
 
-use nom::{be_i8, be_u8, be_u16, be_u32, IResult, ErrorKind};
+use nom::branch::alt;
+use nom::bytes::streaming::{tag, take};
+use nom::combinator::{complete, eof, map, value};
+use nom::multi::{length_data, many0};
+use nom::number::streaming::{be_i8, be_u8, be_u16, be_u32};
+use nom::sequence::{pair, preceded, tuple};
+use nom::Err;
+
+use storage::EventList;
+
+pub mod abc;
+pub mod asm;
+#[cfg(feature = "async")]
+pub mod async_reader;
+pub mod bend;
+pub mod conformance;
+pub mod convert;
+pub mod edit;
+pub mod csv;
+pub mod diff;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzz;
+pub mod fingerprint;
+pub mod gen;
+pub mod hexdump;
+pub mod iter;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod lazy;
+pub mod lilypond;
+pub mod lyrics;
+pub mod markers;
+pub mod metadata;
+pub mod meter;
+pub mod metronome;
+#[cfg(feature = "memmap")]
+pub mod mmap;
+pub mod mpe;
+#[cfg(feature = "musicxml")]
+pub mod musicxml;
+pub mod notation;
+pub mod notes;
+pub mod optimize;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+pub mod patch;
+pub mod pattern;
+pub mod pitch;
+pub mod player;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod push;
+pub mod reader;
+pub mod recorder;
+pub mod rmid;
+pub mod roll;
+pub mod rtp;
+pub mod scan;
+pub mod selection;
+pub mod sequencer;
+pub mod smf2;
+pub mod sniff;
+pub mod spans;
+pub mod storage;
+pub mod stream;
+pub mod strip;
+pub mod summary;
+pub mod sysex;
+pub mod tempo;
+pub mod tone;
+pub mod ump;
+pub mod validate;
+pub mod visitor;
+pub mod warnings;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod write;
+pub mod xf;
 
 
 // Main Parser Entry Point /////////////////////////////////////////////////////
 
-pub fn parse_midi(input: &[u8]) -> Result<Midi, ErrorKind> {
-    match complete!(input, parse_file) {
-        IResult::Done(_, midi) => Ok(midi),
-        IResult::Error(e) => Err(e),
-        IResult::Incomplete(_) => unreachable!(),
+/// Parses a standard MIDI file, or one wrapped in a RIFF RMID container.
+pub fn parse_midi(input: &[u8]) -> Result<Midi, ParseError> {
+    parse_midi_with(input, &ParseOptions::default())
+}
+
+pub fn parse_midi_with<'a>(input: &'a [u8], options: &ParseOptions)
+                           -> Result<Midi<'a>, ParseError> {
+    if input.starts_with(b"RIFF") {
+        rmid::parse_rmid_with(input, options)
+    } else {
+        parse_smf(input, options)
     }
 }
 
+/// Parses several standard MIDI files written back to back, as some ripping
+/// tools leave them. Each file runs from its MThd chunk up to the next one.
+/// Error offsets count from the start of the whole input.
+pub fn parse_midi_multi(input: &[u8]) -> Result<Vec<Midi>, ParseError> {
+    let mut files = Vec::new();
+    let mut start = 0;
+    while start < input.len() {
+        let mut end = start + smf_chunk_len(&input[start..]);
+        while end < input.len() && !input[end..].starts_with(b"MThd") {
+            end += smf_chunk_len(&input[end..]);
+        }
+        match parse_smf(&input[start..end], &ParseOptions::default()) {
+            Ok(midi) => files.push(midi),
+            Err(mut error) => {
+                error.offset += start;
+                return Err(error);
+            }
+        }
+        start = end;
+    }
+    Ok(files)
+}
+
+/// The length of the chunk at the start of some input, including its id and
+/// length, cut short where the input ends.
+fn smf_chunk_len(input: &[u8]) -> usize {
+    match be_u32::<_, ErrorKind>(input.get(4..).unwrap_or(&[])) {
+        Ok((_, len)) => ::std::cmp::min(input.len() - 8, len as usize) + 8,
+        Err(_) => input.len(),
+    }
+}
+
+fn parse_smf<'a>(input: &'a [u8], options: &ParseOptions) -> Result<Midi<'a>, ParseError> {
+    match parse_file(input, options) {
+        Ok((_, midi)) => Ok(midi),
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(locate_error(input, e, options)),
+        // The input ran out, which is found at the part that was cut short,
+        // or at the end of the input otherwise.
+        Err(Err::Incomplete(_)) => Err(locate_error(input, ErrorKind::Eof, options)),
+    }
+}
+
+
+// Parse Errors ////////////////////////////////////////////////////////////////
+
+/// The result of one of the parsers, which fails with an `ErrorKind`.
+pub type IResult<I, O> = nom::IResult<I, O, ErrorKind>;
+
+/// What kind of problem stopped a parser.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A `Malformed` problem, by its code.
+    Custom(u32),
+    /// The input ran out.
+    Eof,
+    /// A chunk id or a fixed length didn't match.
+    Tag,
+    /// A byte that should have been a data byte had its top bit set.
+    Switch,
+    /// Any other failure of one of nom's combinators.
+    Nom(nom::error::ErrorKind),
+}
+
+impl From<nom::error::ErrorKind> for ErrorKind {
+    fn from(kind: nom::error::ErrorKind) -> ErrorKind {
+        match kind {
+            // `complete` turns the input running out into a `Complete` error.
+            nom::error::ErrorKind::Eof | nom::error::ErrorKind::Complete => ErrorKind::Eof,
+            nom::error::ErrorKind::Tag => ErrorKind::Tag,
+            nom::error::ErrorKind::Switch => ErrorKind::Switch,
+            kind => ErrorKind::Nom(kind),
+        }
+    }
+}
+
+impl<I> nom::error::ParseError<I> for ErrorKind {
+    fn from_error_kind(_: I, kind: nom::error::ErrorKind) -> ErrorKind {
+        ErrorKind::from(kind)
+    }
+
+    /// Keeps the innermost error, which says the most about what was wrong.
+    fn append(_: I, _: nom::error::ErrorKind, other: ErrorKind) -> ErrorKind {
+        other
+    }
+}
+
+/// Why a file didn't parse, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    /// Where the header, chunk or event that didn't parse starts, counting
+    /// from the start of the input.
+    pub offset: usize,
+    /// The index of the chunk among all of the file's chunks, if the problem
+    /// was in one.
+    pub chunk: Option<usize>,
+    /// The index of the event among the events of its track, if it was an
+    /// event that didn't parse.
+    pub event: Option<usize>,
+}
+
+impl ParseError {
+    /// What was malformed, if the problem was specific to MIDI rather than
+    /// something like the input running out.
+    pub fn malformed(&self) -> Option<Malformed> {
+        Malformed::from_kind(&self.kind)
+    }
+}
+
+/// The problems that are reported as `ErrorKind::Custom` errors, each with
+/// its code as the discriminant.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Malformed {
+    /// A variable length quantity longer than four bytes.
+    VarLengthOverflow = 0,
+    /// A status byte that can't start an event.
+    BadStatusByte = 1,
+    /// Running status that isn't a channel message status.
+    BadRunningStatus = 2,
+    /// Data bytes with no status byte before them to run on.
+    NoRunningStatus = 3,
+    /// An RMID file without a `data` chunk holding the MIDI file.
+    MissingRmidData = 4,
+    /// An SMF2 clip without a delta clockstamp or an end of clip.
+    IncompleteClip = 5,
+    /// A track longer than `Limits::max_track_len`.
+    TrackTooLong = 6,
+    /// A track with more events than `Limits::max_events`.
+    TooManyEvents = 7,
+    /// A system exclusive message longer than `Limits::max_sysex_len`.
+    SysexTooLong = 8,
+}
+
+impl Malformed {
+    pub fn from_kind(kind: &ErrorKind) -> Option<Malformed> {
+        use Malformed::*;
+        match *kind {
+            ErrorKind::Custom(0) => Some(VarLengthOverflow),
+            ErrorKind::Custom(1) => Some(BadStatusByte),
+            ErrorKind::Custom(2) => Some(BadRunningStatus),
+            ErrorKind::Custom(3) => Some(NoRunningStatus),
+            ErrorKind::Custom(4) => Some(MissingRmidData),
+            ErrorKind::Custom(5) => Some(IncompleteClip),
+            ErrorKind::Custom(6) => Some(TrackTooLong),
+            ErrorKind::Custom(7) => Some(TooManyEvents),
+            ErrorKind::Custom(8) => Some(SysexTooLong),
+            _ => None,
+        }
+    }
+}
+
+impl From<Malformed> for ErrorKind {
+    fn from(malformed: Malformed) -> ErrorKind {
+        ErrorKind::Custom(malformed as u32)
+    }
+}
+
+impl From<ParseError> for ErrorKind {
+    fn from(error: ParseError) -> ErrorKind {
+        error.kind
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?} at byte {}", self.kind, self.offset)?;
+        if let Some(chunk) = self.chunk {
+            write!(f, " in chunk {}", chunk)?;
+        }
+        if let Some(event) = self.event {
+            write!(f, ", event {}", event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Walks a file that failed to parse one piece at a time to find where it
+/// went wrong. Since this only runs after a failure, it costs nothing when
+/// parsing good files. `kind` is used if nothing wrong turns up.
+fn locate_error(input: &[u8], kind: ErrorKind, options: &ParseOptions) -> ParseError {
+    let error = |kind, offset, chunk, event| ParseError {
+        kind: kind,
+        offset: offset,
+        chunk: chunk,
+        event: event,
+    };
+    let mut rest = match header(input) {
+        Ok((rest, _)) => rest,
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => return error(e, 0, None, None),
+        Err(Err::Incomplete(_)) => return error(ErrorKind::Eof, 0, None, None),
+    };
+    let mut index = 0;
+    while !rest.is_empty() {
+        let offset = input.len() - rest.len();
+        let len = match rest.get(4..8) {
+            Some(len) => {
+                (len[0] as usize) << 24 | (len[1] as usize) << 16 | (len[2] as usize) << 8 |
+                len[3] as usize
+            }
+            None => return error(ErrorKind::Eof, offset, Some(index), None),
+        };
+        if &rest[..4] == b"MTrk" && len > options.limits.max_track_len as usize {
+            return error(ErrorKind::from(Malformed::TrackTooLong), offset, Some(index), None);
+        }
+        if rest.len() - 8 < len {
+            return error(ErrorKind::Eof, offset, Some(index), None);
+        }
+        if &rest[..4] == b"MTrk" {
+            let body = &rest[8..8 + len];
+            let mut data = body;
+            let mut running_status = None;
+            let mut ordinal = 0;
+            while !data.is_empty() {
+                let at = offset + 8 + body.len() - data.len();
+                match event(data, &mut running_status) {
+                    Ok((next, ref event)) => {
+                        if let Some(e) = options.limits.check(event, ordinal) {
+                            return error(ErrorKind::from(e), at, Some(index), Some(ordinal));
+                        }
+                        data = next;
+                    }
+                    Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+                        return error(e, at, Some(index), Some(ordinal))
+                    }
+                    Err(Err::Incomplete(_)) => {
+                        return error(ErrorKind::Eof, at, Some(index), Some(ordinal))
+                    }
+                }
+                ordinal += 1;
+            }
+        }
+        rest = &rest[8 + len..];
+        index += 1;
+    }
+    error(kind, input.len(), None, None)
+}
+
 
+// Parse Options ///////////////////////////////////////////////////////////////
+
+/// Settings that change how a file is parsed. The storage only changes how
+/// fast parsing is, while the limits turn files that exceed them into
+/// errors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ParseOptions {
+    pub storage: Storage,
+    pub limits: Limits,
+    /// Parse note ons with a velocity of zero as note offs, so that there's
+    /// only one way to end a note. The file is no longer written back out
+    /// exactly as it was.
+    pub normalize_note_offs: bool,
+}
+
+/// How the list of events in each track is allocated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Storage {
+    /// Grow the list as events are parsed, reallocating as it fills up.
+    Growable,
+    /// Skim each track to count its events before parsing it, so the list is
+    /// allocated once at exactly the right size. Skimming is much cheaper
+    /// than parsing, which makes up for the extra pass on long tracks.
+    Counted,
+    /// Guess the number of events from the length of the track, and shrink
+    /// the list afterwards if the guess wasted more than a quarter of it.
+    /// A guess that's too low falls back to growing.
+    Estimated {
+        bytes_per_event: u32,
+    },
+    /// Count each track's events like `Counted`, but keep tracks of up to
+    /// `storage::INLINE_EVENTS` in place instead of allocating for them, which suits
+    /// files with many short tracks.
+    Inline,
+    /// Count the events of the whole file up front and parse them into one
+    /// arena that its tracks share, so the file takes a single allocation
+    /// for its events. A track moves to an allocation of its own when
+    /// edited.
+    Arena,
+}
+
+/// Bounds on what a file may contain, so that parsing files from untrusted
+/// sources can't be made to take unbounded memory or time. The default is
+/// no limits at all.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// The most bytes of events a track chunk may declare.
+    pub max_track_len: u32,
+    /// The most events a single track may hold.
+    pub max_events: usize,
+    /// The most bytes of data a system exclusive event may carry.
+    pub max_sysex_len: usize,
+}
+
+impl Limits {
+    /// Checks an event that's already been parsed, which is preceded by
+    /// `count` others in its track.
+    fn check(&self, event: &Event, count: usize) -> Option<Malformed> {
+        if count >= self.max_events {
+            return Some(Malformed::TooManyEvents);
+        }
+        match *event {
+            Event::Sysex(_, ref sysex) if sysex.data.len() > self.max_sysex_len => {
+                Some(Malformed::SysexTooLong)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Applies the options that rewrite events to one that's been parsed.
+    fn finish<'a>(&self, event: Event<'a>) -> Event<'a> {
+        match event {
+            Event::Midi(delta, midi) if self.normalize_note_offs => {
+                Event::Midi(delta, midi.normalized())
+            }
+            event => event,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_track_len: u32::max_value(),
+            max_events: usize::max_value(),
+            max_sysex_len: usize::max_value(),
+        }
+    }
+}
+
+impl Default for Storage {
+    /// Channel messages take three or four bytes including the delta time,
+    /// depending on whether they use running status.
+    fn default() -> Storage {
+        Storage::Estimated { bytes_per_event: 4 }
+    }
+}
+
+
 // Midi Data Structures ////////////////////////////////////////////////////////
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Midi<'a> {
     header: Header,
     chunks: Vec<Chunk<'a>>,
+    rmid: Option<rmid::Rmid<'a>>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     len: u32,
     format: u16,
@@ -33,133 +472,454 @@ pub struct Header {
     division: u16,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Event<'a> {
     Midi(u32, MidiEvent),
     Meta(u32, MetaEvent<'a>),
     Sysex(u32, SysexEvent<'a>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TrackChunk<'a> {
-    events: Vec<Event<'a>>,
+    events: EventList<'a>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Chunk<'a> {
     Track(TrackChunk<'a>),
+    /// A chunk type other than MTrk, which readers are expected to skip.
+    Other {
+        kind: &'a [u8],
+        data: &'a [u8],
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Division {
+    TicksPerQuarter(u16),
+    Smpte {
+        frames_per_second: u8,
+        ticks_per_frame: u8,
+    },
+}
+
+impl<'a> Midi<'a> {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn chunks(&self) -> &[Chunk<'a>] {
+        &self.chunks
+    }
+
+    /// The RIFF container the file was wrapped in, if it was an RMID file.
+    pub fn rmid(&self) -> Option<&rmid::Rmid<'a>> {
+        self.rmid.as_ref()
+    }
+
+    /// Iterates over the track chunks, skipping any other kind of chunk.
+    pub fn tracks<'m>(&'m self) -> Tracks<'m, 'a> {
+        Tracks { chunks: self.chunks.iter() }
+    }
+}
+
+impl Header {
+    /// The length the header chunk gives itself, which is 6 in every file
+    /// following the standard.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn format(&self) -> u16 {
+        self.format
+    }
+
+    pub fn tracks(&self) -> u16 {
+        self.tracks
+    }
+
+    pub fn division(&self) -> Division {
+        if self.division & 0x8000 == 0 {
+            Division::TicksPerQuarter(self.division)
+        } else {
+            // The upper byte is the negated frame rate in two's complement.
+            Division::Smpte {
+                frames_per_second: ((self.division >> 8) as u8 as i8).wrapping_neg() as u8,
+                ticks_per_frame: self.division as u8,
+            }
+        }
+    }
+}
+
+impl<'a> TrackChunk<'a> {
+    pub fn events(&self) -> &[Event<'a>] {
+        &self.events
+    }
+}
+
+impl<'a> Event<'a> {
+    /// The time since the previous event in the track, in ticks.
+    pub fn delta(&self) -> u32 {
+        match *self {
+            Event::Midi(dt, _) | Event::Meta(dt, _) | Event::Sysex(dt, _) => dt,
+        }
+    }
+
+    fn set_delta(&mut self, delta: u32) {
+        match *self {
+            Event::Midi(ref mut dt, _) |
+            Event::Meta(ref mut dt, _) |
+            Event::Sysex(ref mut dt, _) => *dt = delta,
+        }
+    }
+
+    pub fn as_midi(&self) -> Option<&MidiEvent> {
+        match *self {
+            Event::Midi(_, ref event) => Some(event),
+            _ => None,
+        }
+    }
+
+    pub fn as_meta(&self) -> Option<&MetaEvent<'a>> {
+        match *self {
+            Event::Meta(_, ref event) => Some(event),
+            _ => None,
+        }
+    }
+
+    pub fn as_sysex(&self) -> Option<&SysexEvent<'a>> {
+        match *self {
+            Event::Sysex(_, ref event) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// The channel of a channel message, from 0 to 15. A `Previous` message
+    /// has no channel of its own.
+    pub fn channel(&self) -> Option<u8> {
+        self.as_midi().and_then(MidiEvent::channel)
+    }
+
+    /// Whether the event is a note on or a note off.
+    pub fn is_note(&self) -> bool {
+        match self.as_midi() {
+            Some(&MidiEvent::NoteOn { .. }) | Some(&MidiEvent::NoteOff { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the event starts a note, which a note on with a velocity of
+    /// zero doesn't.
+    pub fn is_note_on(&self) -> bool {
+        match self.as_midi() {
+            Some(&MidiEvent::NoteOn { velocity, .. }) => velocity > 0,
+            _ => false,
+        }
+    }
+
+    /// Whether the event ends a note, including a note on with a velocity of
+    /// zero.
+    pub fn is_note_off(&self) -> bool {
+        self.is_note() && !self.is_note_on()
+    }
+
+    /// The key of a note or of polyphonic aftertouch.
+    pub fn key(&self) -> Option<u8> {
+        match self.as_midi() {
+            Some(&MidiEvent::NoteOn { number, .. }) |
+            Some(&MidiEvent::NoteOff { number, .. }) |
+            Some(&MidiEvent::PolyphonicAftertouch { number, .. }) => Some(number),
+            _ => None,
+        }
+    }
+
+    pub fn is_meta(&self) -> bool {
+        self.as_meta().is_some()
+    }
+
+    pub fn is_sysex(&self) -> bool {
+        self.as_sysex().is_some()
+    }
+
+    pub fn is_end_of_track(&self) -> bool {
+        self.as_meta() == Some(&MetaEvent::EndOfTrack)
+    }
+}
+
+pub struct Tracks<'m, 'a: 'm> {
+    chunks: std::slice::Iter<'m, Chunk<'a>>,
+}
+
+impl<'m, 'a> Iterator for Tracks<'m, 'a> {
+    type Item = &'m TrackChunk<'a>;
+
+    fn next(&mut self) -> Option<&'m TrackChunk<'a>> {
+        loop {
+            match self.chunks.next() {
+                Some(&Chunk::Track(ref track)) => return Some(track),
+                Some(&Chunk::Other { .. }) => continue,
+                None => return None,
+            }
+        }
+    }
 }
 
 
 // Midi Container Parsers //////////////////////////////////////////////////////
 
-named!(parse_file<&[u8], Midi>,
-  complete!(do_parse!(
-    header: header >>
-    chunks: many0!(chunk) >>
-    eof!() >>
-    (Midi {
+fn parse_file<'a>(input: &'a [u8], options: &ParseOptions) -> IResult<&'a [u8], Midi<'a>> {
+    if options.storage == Storage::Arena {
+        return parse_file_in_arena(input, options);
+    }
+    // The whole file is in memory, so a chunk that runs out of input is an
+    // error rather than something to wait for more of.
+    let chunks = many0(complete(|input| chunk(input, options)));
+    complete(map(tuple((header, chunks, eof)), |(header, chunks, _)| Midi {
+        header: header,
+        chunks: chunks,
+        rmid: None,
+    }))(input)
+}
+
+/// Parses a file with the events of every track in one arena.
+fn parse_file_in_arena<'a>(input: &'a [u8], options: &ParseOptions)
+                           -> IResult<&'a [u8], Midi<'a>> {
+    let (mut rest, header) = header(input)?;
+    let mut arena = EventList::with_capacity(count_file_events(rest));
+    let mut chunks = Vec::new();
+    let mut tracks = Vec::new();
+    while !rest.is_empty() {
+        if rest.starts_with(b"MTrk") {
+            let (next, data) = track_data(rest, options)?;
+            let start = arena.len();
+            events_into(data, options, &mut arena)?;
+            tracks.push((chunks.len(), start..arena.len()));
+            chunks.push(Chunk::Track(TrackChunk { events: EventList::new() }));
+            rest = next;
+        } else {
+            let (next, chunk) = other_chunk(rest)?;
+            chunks.push(chunk);
+            rest = next;
+        }
+    }
+    let arena = Arc::new(arena.into_vec());
+    for (index, range) in tracks {
+        if let Chunk::Track(ref mut track) = chunks[index] {
+            track.events = EventList::from_arena(arena.clone(), range);
+        }
+    }
+    Ok((rest, Midi {
         header: header,
-        chunks: chunks.into_iter().filter_map(|x| x).collect(),
-    })
-  ))
-);
-
-named!(header<&[u8], Header>,
-  do_parse!(
-    tag!(b"MThd") >>
-    len: be_u32 >>
-    format: be_u16 >>
-    tracks: be_u16 >>
-    division: be_u16 >>
-    (Header {
+        chunks: chunks,
+        rmid: None,
+    }))
+}
+
+/// Counts the events in every track chunk after the header, as a capacity
+/// hint.
+fn count_file_events(mut rest: &[u8]) -> usize {
+    let mut count = 0;
+    while rest.len() >= 8 {
+        let len = smf_chunk_len(rest);
+        if rest.starts_with(b"MTrk") {
+            count += count_events(&rest[8..len]);
+        }
+        rest = &rest[len..];
+    }
+    count
+}
+
+fn header(input: &[u8]) -> IResult<&[u8], Header> {
+    let fields = tuple((tag(b"MThd"), be_u32, be_u16, be_u16, be_u16));
+    map(fields, |(_, len, format, tracks, division)| Header {
         len: len,
         format: format,
         tracks: tracks,
         division: division,
-    })
-  )
-);
-
-fn chunk(input: &[u8]) -> IResult<&[u8], Option<Chunk>> {
-    let (_, check) = try_parse!(input, opt!(tag!(b"MTrk")));
-    if check.is_some() {
-        map!(input, track, |x| Some(Chunk::Track(x)))
+    })(input)
+}
+
+fn chunk<'a>(input: &'a [u8], options: &ParseOptions) -> IResult<&'a [u8], Chunk<'a>> {
+    if input.starts_with(b"MTrk") {
+        map(|input| track(input, options), Chunk::Track)(input)
     } else {
-        ignore(input)
+        other_chunk(input)
     }
 }
 
-fn track(input: &[u8]) -> IResult<&[u8], TrackChunk> {
-    let (rest, data) = try_parse!(input, do_parse!(
-      tag!(b"MTrk") >>
-      len: be_u32 >>
-      data: take!(len) >>
-      (data)
-    ));
-    let mut events = Vec::new();
+fn track<'a>(input: &'a [u8], options: &ParseOptions) -> IResult<&'a [u8], TrackChunk<'a>> {
+    let (rest, data) = track_data(input, options)?;
+    let (_, track) = track_body_with(data, options)?;
+    Ok((rest, track))
+}
+
+/// Parses the id and length of a track chunk, giving its body.
+fn track_data<'a>(input: &'a [u8], options: &ParseOptions) -> IResult<&'a [u8], &'a [u8]> {
+    let (rest, len) = preceded(tag(b"MTrk"), be_u32)(input)?;
+    if len > options.limits.max_track_len {
+        return Err(Err::Error(ErrorKind::from(Malformed::TrackTooLong)));
+    }
+    take(len)(rest)
+}
+
+/// Parses the events making up the body of a track chunk, which some other
+/// chunk types reuse.
+fn track_body(data: &[u8]) -> IResult<&[u8], TrackChunk> {
+    track_body_with(data, &ParseOptions::default())
+}
+
+fn track_body_with<'a>(data: &'a [u8], options: &ParseOptions)
+                       -> IResult<&'a [u8], TrackChunk<'a>> {
+    let max_events = options.limits.max_events;
+    let mut events = match options.storage {
+        Storage::Growable => EventList::new(),
+        Storage::Counted | Storage::Arena => {
+            EventList::with_capacity(::std::cmp::min(count_events(data), max_events))
+        }
+        Storage::Estimated { bytes_per_event } => {
+            let estimate = data.len() / ::std::cmp::max(1, bytes_per_event as usize);
+            EventList::with_capacity(::std::cmp::min(estimate, max_events))
+        }
+        Storage::Inline => match count_events(data) {
+            count if count <= storage::INLINE_EVENTS => EventList::inline(),
+            count => EventList::with_capacity(::std::cmp::min(count, max_events)),
+        },
+    };
+    let (input, _) = events_into(data, options, &mut events)?;
+    if let Storage::Estimated { .. } = options.storage {
+        if events.capacity() - events.len() > events.capacity() / 4 {
+            events.shrink_to_fit();
+        }
+    }
+    Ok((input, TrackChunk {
+        events: events,
+    }))
+}
+
+/// Parses the events of a track body onto the end of a list.
+fn events_into<'a>(data: &'a [u8], options: &ParseOptions, events: &mut EventList<'a>)
+                   -> IResult<&'a [u8], ()> {
+    let start = events.len();
     let mut running_status = None;
     let mut input = data;
     loop {
         match event(input, &mut running_status) {
-            IResult::Done(new_inp, item) => {
-                events.push(item);
+            Ok((new_inp, item)) => {
+                if let Some(e) = options.limits.check(&item, events.len() - start) {
+                    return Err(Err::Error(ErrorKind::from(e)));
+                }
+                events.push(options.finish(item));
                 input = new_inp;
             }
-            IResult::Error(_) => break,
-            IResult::Incomplete(i) => return IResult::Incomplete(i),
+            Err(Err::Error(_)) => break,
+            // An event cut off by the end of the data leaves the track
+            // incomplete rather than malformed.
+            Err(e) => return Err(e),
         }
     }
-    try_parse!(input, eof!());
-    IResult::Done(rest, TrackChunk {
-        events: events,
-    })
+    map(eof, |_| ())(input)
+}
+
+/// Counts the events in a track body by finding where each one ends, without
+/// decoding them. Stops early at anything malformed, since the count is only
+/// a capacity hint.
+fn count_events(mut data: &[u8]) -> usize {
+    let mut count = 0;
+    let mut running_status = None;
+    while let Some(len) = event_len(data, &mut running_status) {
+        data = &data[len..];
+        count += 1;
+    }
+    count
+}
+
+/// Finds the length of the event at the start of a track body without
+/// decoding it, or `None` if it's cut off or malformed.
+fn event_len(data: &[u8], running_status: &mut Option<u8>) -> Option<usize> {
+    let mut pos = match var_length(data) {
+        Ok((rest, _)) => data.len() - rest.len(),
+        Err(_) => return None,
+    };
+    let status = match *data.get(pos)? {
+        status if status >= 0x80 => {
+            pos += 1;
+            status
+        }
+        _ => (*running_status)?,
+    };
+    let len = match status {
+        0xF0 | 0xF7 | 0xFF => {
+            if status == 0xFF {
+                pos += 1;
+            }
+            *running_status = None;
+            let rest = data.get(pos..)?;
+            match var_length(rest) {
+                Ok((after, len)) => {
+                    pos += rest.len() - after.len();
+                    len as usize
+                }
+                Err(_) => return None,
+            }
+        }
+        0xC0...0xDF => {
+            *running_status = Some(status);
+            1
+        }
+        0x80...0xEF => {
+            *running_status = Some(status);
+            2
+        }
+        _ => return None,
+    };
+    if data.len() - pos < len {
+        return None;
+    }
+    Some(pos + len)
 }
 
-named!(ignore<&[u8], Option<Chunk> >,
-  do_parse!(
-    take!(4) >>
-    len: be_u32 >>
-    take!(len) >>
-    (None)
-  )
-);
+fn other_chunk(input: &[u8]) -> IResult<&[u8], Chunk> {
+    map(pair(take(4usize), length_data(be_u32)), |(kind, data)| Chunk::Other {
+        kind: kind,
+        data: data,
+    })(input)
+}
 
 fn event<'a>(input: &'a [u8], running_status: &mut Option<u8>) -> IResult<&'a [u8], Event<'a>> {
     // Sysex events and meta events cancel any running status which was in
     // effect. Running status does not apply to and may not be used for these
     // messages.
-    let (input, dt) = try_parse!(input, complete!(var_length));
-    let (_, kind) = try_parse!(input, be_u8);
+    //
+    // A delta time cut short is an error, while an event cut short after it
+    // is incomplete, for the readers that wait for more input.
+    let (input, dt) = complete(var_length)(input)?;
+    let (_, kind) = be_u8(input)?;
     match kind {
         0xFF => {
             *running_status = None;
-            map!(input, meta_event, |x| Event::Meta(dt, x))
+            map(meta_event, |x| Event::Meta(dt, x))(input)
         }
         0xF0 => {
             *running_status = None;
-            map!(input, sysex_event, |x| Event::Sysex(dt, x))
+            map(sysex_event, |x| Event::Sysex(dt, x))(input)
         }
         0xF7 => {
             *running_status = None;
-            map!(input, sysex_event, |x| Event::Sysex(dt, x))
+            map(sysex_event, |x| Event::Sysex(dt, x))(input)
         }
-        0xF1...0xF6 | 0xF8...0xFE => unimplemented!(),
+        // System common and real-time messages can't appear in files.
+        0xF1...0xF6 | 0xF8...0xFE => Err(Err::Error(ErrorKind::from(Malformed::BadStatusByte))),
         n@0x80...0xEF => {
             *running_status = Some(n);
-            do_parse!(input,
-              prefix: be_u8 >>
-              event: call!(midi_event, prefix) >>
-              (Event::Midi(dt, event))
-            )
+            let (input, prefix) = be_u8(input)?;
+            map(move |input| midi_event(input, prefix), |event| Event::Midi(dt, event))(input)
         }
         0x00...0x7F => match *running_status {
-            Some(n@0x80...0xEF) =>
-                do_parse!(input,
-                event: call!(midi_event, n) >>
-                (Event::Midi(dt, event))),
-            Some(_) => IResult::Error(ErrorKind::Custom(2)),
-            None => IResult::Error(ErrorKind::Custom(3)),
+            Some(n@0x80...0xEF) => {
+                map(move |input| midi_event(input, n), |event| Event::Midi(dt, event))(input)
+            }
+            Some(_) => Err(Err::Error(ErrorKind::from(Malformed::BadRunningStatus))),
+            None => Err(Err::Error(ErrorKind::from(Malformed::NoRunningStatus))),
         },
         _ => unreachable!(),
     }
@@ -168,7 +928,7 @@ fn event<'a>(input: &'a [u8], running_status: &mut Option<u8>) -> IResult<&'a [u
 
 // MIDI Events /////////////////////////////////////////////////////////////////
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MidiEvent {
     NoteOn {
         channel: u8,
@@ -207,73 +967,211 @@ pub enum MidiEvent {
     Previous(u8, u8),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl MidiEvent {
+    /// The channel the message is on, from 0 to 15.
+    pub fn channel(&self) -> Option<u8> {
+        use MidiEvent::*;
+        match *self {
+            NoteOn { channel, .. } |
+            NoteOff { channel, .. } |
+            PolyphonicAftertouch { channel, .. } |
+            ChannelAftertouch { channel, .. } |
+            Control { channel, .. } |
+            ProgramChange { channel, .. } |
+            PitchBend { channel, .. } => Some(channel),
+            Previous(..) => None,
+        }
+    }
+
+    /// Turns a note on with a velocity of zero into the note off it stands
+    /// for, leaving every other message alone. The standard gives these note
+    /// offs a velocity of 64.
+    pub fn normalized(self) -> MidiEvent {
+        match self {
+            MidiEvent::NoteOn { channel, number, velocity: 0 } => {
+                MidiEvent::NoteOff {
+                    channel: channel,
+                    number: number,
+                    velocity: 64,
+                }
+            }
+            event => event,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ControlChange {
     Raw(u8, u8),
+    /// Controllers 120 to 127, which change how the channel responds rather
+    /// than controlling the sound. A mode message with a value the standard
+    /// doesn't allow is left as `Raw`.
+    Mode(ChannelMode),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelMode {
+    AllSoundOff,
+    ResetAllControllers,
+    LocalControl(bool),
+    AllNotesOff,
+    OmniOff,
+    OmniOn,
+    /// Monophonic mode over this many channels, or over as many as the
+    /// receiver has if it's 0.
+    MonoOn(u8),
+    PolyOn,
+}
+
+impl ControlChange {
+    /// Recognizes channel mode messages among the controllers.
+    pub fn from_parts(controller: u8, value: u8) -> ControlChange {
+        use ChannelMode::*;
+        ControlChange::Mode(match (controller, value) {
+            (120, 0) => AllSoundOff,
+            (121, 0) => ResetAllControllers,
+            (122, 0) => LocalControl(false),
+            (122, 127) => LocalControl(true),
+            (123, 0) => AllNotesOff,
+            (124, 0) => OmniOff,
+            (125, 0) => OmniOn,
+            (126, 0...16) => MonoOn(value),
+            (127, 0) => PolyOn,
+            _ => return ControlChange::Raw(controller, value),
+        })
+    }
+
+    /// The controller number and value.
+    pub fn parts(&self) -> (u8, u8) {
+        use ChannelMode::*;
+        match *self {
+            ControlChange::Raw(controller, value) => (controller, value),
+            ControlChange::Mode(AllSoundOff) => (120, 0),
+            ControlChange::Mode(ResetAllControllers) => (121, 0),
+            ControlChange::Mode(LocalControl(on)) => (122, if on { 127 } else { 0 }),
+            ControlChange::Mode(AllNotesOff) => (123, 0),
+            ControlChange::Mode(OmniOff) => (124, 0),
+            ControlChange::Mode(OmniOn) => (125, 0),
+            ControlChange::Mode(MonoOn(channels)) => (126, channels),
+            ControlChange::Mode(PolyOn) => (127, 0),
+        }
+    }
+}
+
+/// System common and real-time messages. These never appear in files, but
+/// show up on the wire and in the protocols built around it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SystemMessage {
+    TimeCodeQuarterFrame {
+        /// Which nibble of the time code this frame carries, from 0 to 7.
+        piece: u8,
+        value: u8,
+    },
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+impl SystemMessage {
+    /// Builds a message from its status byte and up to two data bytes, which
+    /// are ignored by messages that don't use them.
+    pub fn from_parts(status: u8, data1: u8, data2: u8) -> Option<SystemMessage> {
+        use SystemMessage::*;
+        Some(match status {
+            0xF1 => TimeCodeQuarterFrame {
+                piece: (data1 >> 4) & 0x07,
+                value: data1 & 0x0F,
+            },
+            0xF2 => SongPosition((data2 as u16 & 0x7F) << 7 | (data1 as u16 & 0x7F)),
+            0xF3 => SongSelect(data1 & 0x7F),
+            0xF6 => TuneRequest,
+            0xF8 => TimingClock,
+            0xFA => Start,
+            0xFB => Continue,
+            0xFC => Stop,
+            0xFE => ActiveSensing,
+            0xFF => Reset,
+            _ => return None,
+        })
+    }
+
+    /// The message's status byte and two data bytes, the inverse of
+    /// `from_parts`, with unused data bytes left as 0.
+    pub fn parts(&self) -> (u8, u8, u8) {
+        use SystemMessage::*;
+        match *self {
+            TimeCodeQuarterFrame { piece, value } => (0xF1, (piece & 0x07) << 4 | value & 0x0F, 0),
+            SongPosition(position) => (0xF2, position as u8 & 0x7F, (position >> 7) as u8 & 0x7F),
+            SongSelect(song) => (0xF3, song & 0x7F, 0),
+            TuneRequest => (0xF6, 0, 0),
+            TimingClock => (0xF8, 0, 0),
+            Start => (0xFA, 0, 0),
+            Continue => (0xFB, 0, 0),
+            Stop => (0xFC, 0, 0),
+            ActiveSensing => (0xFE, 0, 0),
+            Reset => (0xFF, 0, 0),
+        }
+    }
+
+    /// The number of data bytes that follow the status byte.
+    pub fn data_len(status: u8) -> usize {
+        match status {
+            0xF1 | 0xF3 => 1,
+            0xF2 => 2,
+            _ => 0,
+        }
+    }
 }
 
 
 // TODO: Fix parsing the running-status events.
 fn midi_event(input: &[u8], prefix: u8) -> IResult<&[u8], MidiEvent> {
     match prefix {
-        n@0x80...0x8F => do_parse!(input,
-          num: u7 >>
-          vel: u7 >>
-          (MidiEvent::NoteOff {
-              channel: n & 0x0F,
-              number: num,
-              velocity: vel,
-          })),
-        n@0x90...0x9F => do_parse!(input,
-          num: u7 >>
-          vel: u7 >>
-          (MidiEvent::NoteOn {
-              channel: n & 0x0F,
-              number: num,
-              velocity: vel,
-          })),
-        n@0xA0...0xAF => do_parse!(input,
-          num: u7 >>
-          pres: u7 >>
-          (MidiEvent::PolyphonicAftertouch {
-              channel: n & 0x0F,
-              number: num,
-              pressure: pres,
-          })),
-        n@0xB0...0xBF => do_parse!(input,
-          controller: u7 >>
-          value: u7 >>
-          (MidiEvent::Control {
-              channel: n & 0x0F,
-              change: ControlChange::Raw(controller, value),
-          })),
-        n@0xC0...0xCF => do_parse!(input,
-          patch: u7 >>
-          (MidiEvent::ProgramChange {
-              channel: n & 0x0F,
-              program_number: patch,
-          })),
-        n@0xD0...0xDF => do_parse!(input,
-          pres: u7 >>
-          (MidiEvent::ChannelAftertouch {
-              channel: n & 0x0F,
-              pressure: pres,
-          })),
-        n@0xE0...0xEF => do_parse!(input,
-          lsb: u7 >>
-          msb: u7 >>
-          (MidiEvent::PitchBend {
-              channel: n & 0x0F,
-              pitch: (msb as u16) << 7 | lsb as u16,
-          })),
-        _ => IResult::Error(ErrorKind::Custom(1))
-  }
+        n@0x80...0x8F => map(pair(u7, u7), |(num, vel)| MidiEvent::NoteOff {
+            channel: n & 0x0F,
+            number: num,
+            velocity: vel,
+        })(input),
+        n@0x90...0x9F => map(pair(u7, u7), |(num, vel)| MidiEvent::NoteOn {
+            channel: n & 0x0F,
+            number: num,
+            velocity: vel,
+        })(input),
+        n@0xA0...0xAF => map(pair(u7, u7), |(num, pres)| MidiEvent::PolyphonicAftertouch {
+            channel: n & 0x0F,
+            number: num,
+            pressure: pres,
+        })(input),
+        n@0xB0...0xBF => map(pair(u7, u7), |(controller, value)| MidiEvent::Control {
+            channel: n & 0x0F,
+            change: ControlChange::from_parts(controller, value),
+        })(input),
+        n@0xC0...0xCF => map(u7, |patch| MidiEvent::ProgramChange {
+            channel: n & 0x0F,
+            program_number: patch,
+        })(input),
+        n@0xD0...0xDF => map(u7, |pres| MidiEvent::ChannelAftertouch {
+            channel: n & 0x0F,
+            pressure: pres,
+        })(input),
+        n@0xE0...0xEF => map(pair(u7, u7), |(lsb, msb)| MidiEvent::PitchBend {
+            channel: n & 0x0F,
+            pitch: (msb as u16) << 7 | lsb as u16,
+        })(input),
+        _ => Err(Err::Error(ErrorKind::from(Malformed::BadStatusByte))),
+    }
 }
 
 
 // Meta Events /////////////////////////////////////////////////////////////////
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MetaEvent<'a> {
     SequenceNumber(u16),
     Text {
@@ -319,6 +1217,12 @@ pub enum TextType {
     Lyric,
     Marker,
     CuePoint,
+    /// The name of the program (patch) that the track's next program change
+    /// selects, from the standard's RP-019.
+    ProgramName,
+    /// The name of the device, such as a port or synthesizer, that the track
+    /// is meant to play on, from the standard's RP-019.
+    DeviceName,
     Other(u8),
 }
 
@@ -342,107 +1246,207 @@ impl From<u8> for TextType {
             0x01 => Text,
             0x02 => Copyright,
             0x03 => TrackName,
-            0x04 => Lyric,
-            0x05 => Marker,
-            0x06 => CuePoint,
+            0x04 => InstrumentName,
+            0x05 => Lyric,
+            0x06 => Marker,
+            0x07 => CuePoint,
+            0x08 => ProgramName,
+            0x09 => DeviceName,
             x => Other(x),
         }
     }
 }
 
-named!(meta_event<&[u8], MetaEvent>,
-  preceded!(tag!([0xFF]),
-    switch!(be_u8,
-      0x00 => do_parse!(
-          tag!([0x02]) >>
-          seq: be_u16 >>
-          (MetaEvent::SequenceNumber(seq))) |
-      kind@0x01...0x0F => do_parse!(
-          len: var_length >>
-          data: take!(len) >>
-          (MetaEvent::Text {
-              kind: TextType::from(kind),
-              text: data,
-          })) |
-      0x20 => do_parse!(
-          tag!([0x01]) >>
-          prefix: be_u8 >>
-          (MetaEvent::ChannelPrefix(prefix))) |
-      0x2F => do_parse!(
-          tag!([0x00]) >>
-          (MetaEvent::EndOfTrack)) |
-      0x51 => do_parse!(
-          tag!([0x03]) >>
-          b: take!(3) >>
-          ({
-              let t = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[0] as u32;
-              MetaEvent::SetTempo(t)
-          })) |
-      0x54 => do_parse!(
-          tag!([0x05]) >>
-          hr: be_u8 >>
-          min: be_u8 >>
-          sec: be_u8 >>
-          frame: be_u8 >>
-          frac: be_u8 >>
-          (MetaEvent::SmpteOffset {
-              hours: hr,
-              minutes: min,
-              seconds: sec,
-              frames: frame,
-              fractional_frames: frac,
-          })) |
-      0x58 => do_parse!(
-          tag!([0x04]) >>
-          num: be_u8 >>
-          denom: be_u8 >>
-          clock: be_u8 >>
-          div: be_u8 >>
-          (MetaEvent::TimeSignature {
-              numerator: num,
-              denominator: denom,
-              clocks_per_metronome: clock,
-              notated_divisions: div,
-          })) |
-      0x59 => do_parse!(
-          tag!([0x02]) >>
-          sharp_flat: be_i8 >>
-          major_minor: alt!(tag!([0]) | tag!([1])) >>
-          (MetaEvent::KeySignature {
-              key: match sharp_flat {
-                  x if x > 0 => Key::Sharps(x as u8),
-                  x if x < 0 => Key::Flats(-x as u8),
-                  _ => Key::OfC,
-              },
-              kind: match major_minor[0] {
-                  0 => KeyKind::Major,
-                  1 => KeyKind::Minor,
-                  _ => unreachable!(),
-              }
-          })) |
-      0x7F => do_parse!(
-          len: var_length >>
-          data: take!(len) >>
-          (MetaEvent::SequencerSpecific {
-              data: data,
-          })) |
-      // We have to request the whole range because nom generates bad parsers
-      // for exhaustive switch.
-      kind@0x00...0xFF => do_parse!(
-          len: var_length >>
-          data: take!(len) >>
-          (MetaEvent::Other {
-              kind: kind,
-              data: data,
-          }))
-    )
-  )
-);
+/// The kind byte that comes after the FF of a meta event, named where the
+/// standard names it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MetaKind {
+    SequenceNumber,
+    /// Any kind from 01 to 0F, all of which hold text.
+    Text(TextType),
+    ChannelPrefix,
+    /// The output port for the track, from RP-033.
+    Port,
+    EndOfTrack,
+    SetTempo,
+    SmpteOffset,
+    TimeSignature,
+    KeySignature,
+    SequencerSpecific,
+    Other(u8),
+}
+
+impl MetaKind {
+    pub fn from_u8(kind: u8) -> MetaKind {
+        use MetaKind::*;
+        match kind {
+            0x00 => SequenceNumber,
+            0x01...0x0F => Text(TextType::from(kind)),
+            0x20 => ChannelPrefix,
+            0x21 => Port,
+            0x2F => EndOfTrack,
+            0x51 => SetTempo,
+            0x54 => SmpteOffset,
+            0x58 => TimeSignature,
+            0x59 => KeySignature,
+            0x7F => SequencerSpecific,
+            x => Other(x),
+        }
+    }
+
+    /// A short name for the kind, like "tempo" or "track name".
+    pub fn name(&self) -> &'static str {
+        use MetaKind::*;
+        match *self {
+            SequenceNumber => "sequence number",
+            Text(TextType::Text) => "text",
+            Text(TextType::Copyright) => "copyright",
+            Text(TextType::TrackName) => "track name",
+            Text(TextType::InstrumentName) => "instrument name",
+            Text(TextType::Lyric) => "lyric",
+            Text(TextType::Marker) => "marker",
+            Text(TextType::CuePoint) => "cue point",
+            Text(TextType::ProgramName) => "program name",
+            Text(TextType::DeviceName) => "device name",
+            Text(TextType::Other(_)) => "text",
+            ChannelPrefix => "channel prefix",
+            Port => "port",
+            EndOfTrack => "end of track",
+            SetTempo => "tempo",
+            SmpteOffset => "SMPTE offset",
+            TimeSignature => "time signature",
+            KeySignature => "key signature",
+            SequencerSpecific => "sequencer specific",
+            Other(_) => "meta",
+        }
+    }
+}
+
+impl From<u8> for MetaKind {
+    fn from(kind: u8) -> MetaKind {
+        MetaKind::from_u8(kind)
+    }
+}
+
+impl From<MetaKind> for u8 {
+    fn from(kind: MetaKind) -> u8 {
+        use MetaKind::*;
+        match kind {
+            SequenceNumber => 0x00,
+            Text(kind) => u8::from(kind),
+            ChannelPrefix => 0x20,
+            Port => 0x21,
+            EndOfTrack => 0x2F,
+            SetTempo => 0x51,
+            SmpteOffset => 0x54,
+            TimeSignature => 0x58,
+            KeySignature => 0x59,
+            SequencerSpecific => 0x7F,
+            Other(x) => x,
+        }
+    }
+}
+
+/// Kinds without a name of their own show their byte, as in "meta 21" or
+/// "text 0A".
+impl std::fmt::Display for MetaKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            MetaKind::Text(TextType::Other(x)) | MetaKind::Other(x) => {
+                write!(f, "{} {:02X}", self.name(), x)
+            }
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+impl<'a> MetaEvent<'a> {
+    pub fn kind(&self) -> MetaKind {
+        use MetaEvent::*;
+        match *self {
+            SequenceNumber(_) => MetaKind::SequenceNumber,
+            Text { kind, .. } => MetaKind::Text(kind),
+            ChannelPrefix(_) => MetaKind::ChannelPrefix,
+            EndOfTrack => MetaKind::EndOfTrack,
+            SetTempo(_) => MetaKind::SetTempo,
+            SmpteOffset { .. } => MetaKind::SmpteOffset,
+            TimeSignature { .. } => MetaKind::TimeSignature,
+            KeySignature { .. } => MetaKind::KeySignature,
+            SequencerSpecific { .. } => MetaKind::SequencerSpecific,
+            Other { kind, .. } => MetaKind::from_u8(kind),
+        }
+    }
+
+    pub fn kind_name(&self) -> &'static str {
+        self.kind().name()
+    }
+}
+
+fn meta_event(input: &[u8]) -> IResult<&[u8], MetaEvent> {
+    let (input, kind) = preceded(tag(b"\xFF"), be_u8)(input)?;
+    match kind {
+        0x00 => map(preceded(tag(b"\x02"), be_u16), MetaEvent::SequenceNumber)(input),
+        0x01...0x0F => map(length_data(var_length), |data| MetaEvent::Text {
+            kind: TextType::from(kind),
+            text: data,
+        })(input),
+        0x20 => map(preceded(tag(b"\x01"), be_u8), MetaEvent::ChannelPrefix)(input),
+        0x2F => value(MetaEvent::EndOfTrack, tag(b"\x00"))(input),
+        0x51 => map(preceded(tag(b"\x03"), take(3usize)), |b: &[u8]| {
+            let t = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+            MetaEvent::SetTempo(t)
+        })(input),
+        0x54 => {
+            let fields = tuple((be_u8, be_u8, be_u8, be_u8, be_u8));
+            map(preceded(tag(b"\x05"), fields), |(hr, min, sec, frame, frac)| {
+                MetaEvent::SmpteOffset {
+                    hours: hr,
+                    minutes: min,
+                    seconds: sec,
+                    frames: frame,
+                    fractional_frames: frac,
+                }
+            })(input)
+        }
+        0x58 => {
+            let fields = tuple((be_u8, be_u8, be_u8, be_u8));
+            map(preceded(tag(b"\x04"), fields), |(num, denom, clock, div)| {
+                MetaEvent::TimeSignature {
+                    numerator: num,
+                    denominator: denom,
+                    clocks_per_metronome: clock,
+                    notated_divisions: div,
+                }
+            })(input)
+        }
+        0x59 => {
+            let major_minor = alt((value(KeyKind::Major, tag(b"\x00")),
+                                   value(KeyKind::Minor, tag(b"\x01"))));
+            map(preceded(tag(b"\x02"), pair(be_i8, major_minor)), |(sharp_flat, kind)| {
+                MetaEvent::KeySignature {
+                    key: match sharp_flat {
+                        x if x > 0 => Key::Sharps(x as u8),
+                        x if x < 0 => Key::Flats(x.wrapping_neg() as u8),
+                        _ => Key::OfC,
+                    },
+                    kind: kind,
+                }
+            })(input)
+        }
+        0x7F => map(length_data(var_length), |data| MetaEvent::SequencerSpecific {
+            data: data,
+        })(input),
+        kind => map(length_data(var_length), |data| MetaEvent::Other {
+            kind: kind,
+            data: data,
+        })(input),
+    }
+}
 
 
 // System Exclusive Events /////////////////////////////////////////////////////
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SysexEvent<'a> {
     /// Set when parsing an F0 message, and unset on an F7 message
     start: bool,
@@ -451,42 +1455,81 @@ pub struct SysexEvent<'a> {
     data: &'a [u8],
 }
 
-named!(sysex_event<&[u8], SysexEvent>,
-  do_parse!(
-    kind: alt!(tag!([0xF0]) | tag!([0xF7])) >>
-    len: var_length >>
-    data: take!(len) >>
-    (SysexEvent {
+impl<'a> SysexEvent<'a> {
+    pub fn is_start(&self) -> bool {
+        self.start
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.end
+    }
+
+    /// The raw bytes of the event, including the terminating F7 if present.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The bytes of the event with the terminating F7 stripped.
+    pub fn payload(&self) -> &'a [u8] {
+        match self.data.split_last() {
+            Some((&0xF7, payload)) if self.end => payload,
+            _ => self.data,
+        }
+    }
+}
+
+fn sysex_event(input: &[u8]) -> IResult<&[u8], SysexEvent> {
+    let parts = pair(alt((tag(b"\xF0"), tag(b"\xF7"))), length_data(var_length));
+    map(parts, |(kind, data): (&[u8], &[u8])| SysexEvent {
         start: kind == [0xF0],
-        end: data[data.len()-1] == 0xF7,
+        end: data.last() == Some(&0xF7),
         data: data,
-    })
-  )
-);
+    })(input)
+}
 
 
 // Utility Parsers /////////////////////////////////////////////////////////////
 
 pub fn var_length(input: &[u8]) -> IResult<&[u8], u32> {
+    // Nearly every delta time is a single byte, so that's checked first, and
+    // with four bytes available the rest can be decoded without checking
+    // bounds at each step.
+    match input {
+        [a, rest @ ..] if *a < 0x80 => return Ok((rest, *a as u32)),
+        [a, b, c, d, ..] => {
+            let (a, b, c, d) = (*a as u32 & 0x7F, *b as u32, *c as u32, *d as u32);
+            return if b < 0x80 {
+                Ok((&input[2..], a << 7 | b))
+            } else if c < 0x80 {
+                Ok((&input[3..], a << 14 | (b & 0x7F) << 7 | c))
+            } else if d < 0x80 {
+                Ok((&input[4..], a << 21 | (b & 0x7F) << 14 | (c & 0x7F) << 7 | d))
+            } else {
+                Err(Err::Error(ErrorKind::from(Malformed::VarLengthOverflow)))
+            };
+        }
+        _ => {}
+    }
     let mut result = 0;
     for i in 0..4 {
         if i >= input.len() {
-            return IResult::Incomplete(nom::Needed::Unknown);
+            return Err(Err::Incomplete(nom::Needed::Unknown));
         }
         result <<= 7;
         result |= (input[i] & 0x7F) as u32;
         if input[i] & 0x80 == 0 {
-            return IResult::Done(&input[i+1..], result);
+            return Ok((&input[i+1..], result));
         }
     }
-    IResult::Error(ErrorKind::Custom(0))
+    Err(Err::Error(ErrorKind::from(Malformed::VarLengthOverflow)))
 }
 
-named!(u7<&[u8], u8>,
-  switch!(be_u8,
-    n@0x00...0x7F => value!(n)
-  )
-);
+fn u7(input: &[u8]) -> IResult<&[u8], u8> {
+    match be_u8(input)? {
+        (rest, n@0x00...0x7F) => Ok((rest, n)),
+        _ => Err(Err::Error(ErrorKind::Switch)),
+    }
+}
 
 
 // Tests ///////////////////////////////////////////////////////////////////////
@@ -510,22 +1553,262 @@ fn test_var_length() {
     ];
 
     for &(number, ref bytes) in &cases {
-        assert_eq!(var_length(&bytes[..]), IResult::Done(&b""[..], number));
+        assert_eq!(var_length(&bytes[..]), Ok((&b""[..], number)));
+        // Trailing bytes take the four byte path for short values.
+        let mut padded = bytes.clone();
+        padded.extend_from_slice(&[0x80, 0x80, 0x80]);
+        assert_eq!(var_length(&padded[..]), Ok((&[0x80, 0x80, 0x80][..], number)));
     }
+    assert_eq!(var_length(&[0x81, 0x80]), Err(Err::Incomplete(nom::Needed::Unknown)));
+    assert_eq!(var_length(&[0x81, 0x80, 0x80, 0x80, 0x00]),
+               Err(Err::Error(ErrorKind::from(Malformed::VarLengthOverflow))));
 }
 
 #[cfg(test)]
 #[test]
 fn test_event() {
     assert_eq!(event(&[0x00, 0xFF, 0x00, 0x02, 0x00, 0x01], &mut None),
-               IResult::Done(&b""[..], Event::Meta(0, MetaEvent::SequenceNumber(1))));
+               Ok((&b""[..], Event::Meta(0, MetaEvent::SequenceNumber(1)))));
     assert_eq!(event(&[0x00, 0xFF, 0x58, 0x04, 0x06, 0x03, 0x24, 0x08], &mut None),
-               IResult::Done(&b""[..], Event::Meta(0, MetaEvent::TimeSignature {
+               Ok((&b""[..], Event::Meta(0, MetaEvent::TimeSignature {
                    numerator: 6,
                    denominator: 3,
                    clocks_per_metronome: 36,
                    notated_divisions: 8,
-               })));
+               }))));
+    assert_eq!(event(&[0x00, 0xB1, 0x7B, 0x00], &mut None),
+               Ok((&b""[..], Event::Midi(0, MidiEvent::Control {
+                   channel: 1,
+                   change: ControlChange::Mode(ChannelMode::AllNotesOff),
+               }))));
+    assert_eq!(ControlChange::from_parts(0x7A, 0x40), ControlChange::Raw(0x7A, 0x40));
+    assert_eq!(ControlChange::from_parts(0x7E, 4).parts(), (0x7E, 4));
+
+    let data = [0x00, 0x90, 0x3C, 0x40, 0x10, 0x3C, 0x00];
+    let options = ParseOptions { normalize_note_offs: true, ..Default::default() };
+    match track_body_with(&data, &options) {
+        Ok((_, track)) => {
+            assert_eq!(track.events[1], Event::Midi(0x10, MidiEvent::NoteOff {
+                channel: 0,
+                number: 0x3C,
+                velocity: 64,
+            }));
+        }
+        result => panic!("failed to parse the track: {:?}", result),
+    }
+
+    let off = Event::Midi(0x10, MidiEvent::NoteOn { channel: 2, number: 0x3C, velocity: 0 });
+    assert_eq!((off.delta(), off.channel(), off.key()), (0x10, Some(2), Some(0x3C)));
+    assert!(off.is_note() && off.is_note_off() && !off.is_note_on());
+    let end = Event::Meta(0, MetaEvent::EndOfTrack);
+    assert!(end.is_meta() && end.is_end_of_track() && !end.is_note());
+    assert_eq!((end.channel(), end.key(), end.as_sysex()), (None, None, None));
+}
+
+#[cfg(test)]
+#[test]
+fn test_meta_kind() {
+    for kind in 0..0x80 {
+        assert_eq!(u8::from(MetaKind::from_u8(kind)), kind);
+    }
+    assert_eq!(MetaKind::from_u8(0x51), MetaKind::SetTempo);
+    assert_eq!(MetaKind::from_u8(0x03), MetaKind::Text(TextType::TrackName));
+    assert_eq!(MetaKind::SetTempo.to_string(), "tempo");
+    assert_eq!(MetaKind::from_u8(0x0A).to_string(), "text 0A");
+    assert_eq!(MetaKind::from_u8(0x60).to_string(), "meta 60");
+    assert_eq!(MetaEvent::EndOfTrack.kind_name(), "end of track");
+    let other = MetaEvent::Other { kind: 0x21, data: &[0] };
+    assert_eq!(other.kind(), MetaKind::Port);
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_midi_multi() {
+    let first = gen::scale(60, &gen::MAJOR);
+    let second = gen::chords(&[60, 65]);
+    let mut data = first.to_bytes();
+    data.extend_from_slice(&second.to_bytes());
+    assert_eq!(parse_midi_multi(&data).unwrap(), [first, second]);
+    assert_eq!(parse_midi_multi(&[]).unwrap(), []);
+    let len = data.len();
+    data[len - 1] = 0x01;
+    let error = parse_midi_multi(&data).unwrap_err();
+    assert!(error.offset > gen::scale(60, &gen::MAJOR).to_bytes().len());
+}
+
+#[cfg(test)]
+#[test]
+fn test_count_events() {
+    let data = [
+        0x00, 0xFF, 0x03, 0x01, b'a',
+        0x00, 0x90, 0x3C, 0x40,
+        0x10, 0x3C, 0x00,
+        0x00, 0xC0, 0x05,
+        0x00, 0xF0, 0x02, 0x7E, 0xF7,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    assert_eq!(count_events(&data), 6);
+    let options = ParseOptions { storage: Storage::Counted, ..Default::default() };
+    let counted = track_body_with(&data, &options);
+    match counted {
+        Ok((_, ref track)) => assert_eq!(track.events.capacity(), 6),
+        ref result => panic!("failed to parse the track: {:?}", result),
+    }
+    assert_eq!(counted, track_body(&data));
+    let options = ParseOptions {
+        storage: Storage::Estimated { bytes_per_event: 1 },
+        ..Default::default()
+    };
+    match track_body_with(&data, &options) {
+        Ok((_, ref track)) => assert_eq!(track.events.capacity(), 6),
+        ref result => panic!("failed to parse the track: {:?}", result),
+    }
+    let options = ParseOptions { storage: Storage::Inline, ..Default::default() };
+    match track_body_with(&data, &options) {
+        Ok((_, ref track)) => assert!(track.events.is_inline()),
+        ref result => panic!("failed to parse the track: {:?}", result),
+    }
+
+    let mut file = b"MThd\0\0\0\x06\0\x01\0\x02\0\x60".to_vec();
+    for _ in 0..2 {
+        file.extend_from_slice(b"MTrk\0\0\0");
+        file.push(data.len() as u8);
+        file.extend_from_slice(&data);
+    }
+    let options = ParseOptions { storage: Storage::Arena, ..Default::default() };
+    let midi = parse_midi_with(&file, &options).unwrap();
+    assert!(midi.tracks().all(|track| track.events.is_shared()));
+    assert_eq!(midi, parse_midi(&file).unwrap());
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_error() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0x3C, 0x00,
+    ];
+    let error = ParseError {
+        kind: ErrorKind::Eof,
+        offset: 26,
+        chunk: Some(1),
+        event: None,
+    };
+    assert_eq!(parse_midi(&data[..40]), Err(error.clone()));
+    let mut bad = data;
+    bad[35] = 0x00;
+    assert_eq!(parse_midi(&bad), Err(ParseError {
+        kind: ErrorKind::from(Malformed::NoRunningStatus),
+        offset: 34,
+        event: Some(0),
+        ..error
+    }));
+    assert_eq!(parse_midi(&data[..10]).map_err(|e| e.offset), Err(0));
+    for len in 27..data.len() {
+        assert_eq!(parse_midi(&data[..len]), Err(error.clone()));
+    }
+    let error = parse_midi(&bad).unwrap_err();
+    assert_eq!(error.malformed(), Some(Malformed::NoRunningStatus));
+    assert_eq!(Malformed::from_kind(&ErrorKind::from(Malformed::IncompleteClip)),
+               Some(Malformed::IncompleteClip));
+}
+
+#[cfg(test)]
+#[test]
+fn test_limits() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0x3C, 0x00,
+    ];
+    let parse = |limits| {
+        parse_midi_with(&data, &ParseOptions { limits: limits, ..Default::default() })
+    };
+    assert!(parse(Limits { max_track_len: 7, max_events: 2, ..Default::default() }).is_ok());
+    assert_eq!(parse(Limits { max_track_len: 6, ..Default::default() }), Err(ParseError {
+        kind: ErrorKind::from(Malformed::TrackTooLong),
+        offset: 26,
+        chunk: Some(1),
+        event: None,
+    }));
+    assert_eq!(parse(Limits { max_events: 1, ..Default::default() }), Err(ParseError {
+        kind: ErrorKind::from(Malformed::TooManyEvents),
+        offset: 38,
+        chunk: Some(1),
+        event: Some(1),
+    }));
+
+    let sysex = [0x00, 0xF0, 0x03, 0x7E, 0x7F, 0xF7];
+    let options = ParseOptions {
+        limits: Limits { max_sysex_len: 2, ..Default::default() },
+        ..Default::default()
+    };
+    assert_eq!(track_body_with(&sysex, &options),
+               Err(Err::Error(ErrorKind::from(Malformed::SysexTooLong))));
+}
+
+#[cfg(test)]
+#[test]
+fn test_no_panics() {
+    // Every parser has to turn any input into an error rather than a panic,
+    // so throw mutations of a good file and plain noise at all of them.
+    let file = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1A,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x59, 0x02, 0xFD, 0x01,
+        0x00, 0xF0, 0x03, 0x7E, 0x09, 0xF7,
+        0x00, 0xC0, 0x05,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let mut seed = 0x2545_F491_4F6C_DD1Du64;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    for round in 0..20000 {
+        let mut data = file.to_vec();
+        if round % 4 == 3 {
+            let len = random() as usize % 64;
+            data.truncate(22);
+            data.extend((0..len).map(|_| random() as u8));
+        } else {
+            for _ in 0..random() % 4 + 1 {
+                let at = random() as usize % data.len();
+                data[at] = random() as u8;
+            }
+            let len = random() as usize % (data.len() + 1);
+            data.truncate(len);
+        }
+        let _ = parse_midi(&data);
+        let _ = warnings::parse_midi_lenient(&data, &ParseOptions::default());
+        let _ = warnings::parse_midi_partial(&data);
+        let _ = validate::validate(&data);
+        let _ = scan::scan(&data).map(|scan| scan.count());
+        if let Ok(lazy) = lazy::scan_midi(&data) {
+            for i in 0..lazy.track_count() {
+                let _ = lazy.track(i);
+            }
+        }
+        let mut reader = reader::Reader::with_buffer_size(&data[..], 16);
+        while let Ok(Some(_)) = reader.next_item() {}
+    }
 }
 
 // let data = [
@@ -0,0 +1,200 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::fmt::Write;
+
+use {Key, KeyKind, Midi};
+use meter::{note_value, Meter, MeterMap};
+use notation::{bars, note_values, spell, tonic, voices, LineNote};
+
+
+// LilyPond Export /////////////////////////////////////////////////////////////
+
+/// Settings for `to_lilypond_with`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LilyPondOptions {
+    /// The shortest note kept, as a fraction of a whole note, so 16 rounds
+    /// every note to sixteenths.
+    pub shortest: u16,
+}
+
+impl Default for LilyPondOptions {
+    fn default() -> LilyPondOptions {
+        LilyPondOptions { shortest: 16 }
+    }
+}
+
+impl<'a> Midi<'a> {
+    pub fn to_lilypond(&self) -> Option<String> {
+        self.to_lilypond_with(&LilyPondOptions::default())
+    }
+
+    /// Writes the file as LilyPond source, with a staff for each track that
+    /// has notes. A track with overlapping notes is split into voices, from
+    /// the top down, and notes are rounded to the nearest
+    /// `options.shortest`. The key is the one the file starts in. Returns
+    /// `None` for files using SMPTE time.
+    pub fn to_lilypond_with(&self, options: &LilyPondOptions) -> Option<String> {
+        let map = self.meter_map()?;
+        let shortest = cmp::max(1, options.shortest) as u64;
+        let grid = cmp::max(1, map.ticks_per_quarter() as u64 * 4 / shortest);
+        let metadata = self.metadata();
+        let (signature, kind) = metadata.initial_key_signature
+            .unwrap_or((Key::OfC, KeyKind::Major));
+        let notes = self.notes();
+
+        let tracks: Vec<_> = self.tracks().collect();
+        let staves: Vec<_> = (0..tracks.len())
+            .map(|track| {
+                let notes: Vec<_> = notes.iter().filter(|note| note.track == track).cloned()
+                    .collect();
+                let mean = notes.iter().map(|note| note.key as u64).sum::<u64>() /
+                           cmp::max(1, notes.len() as u64);
+                (track, mean, voices(&notes, grid))
+            })
+            .filter(|&(_, _, ref voices)| !voices.is_empty())
+            .collect();
+        let end = staves.iter()
+            .flat_map(|&(_, _, ref voices)| voices.iter().filter_map(|voice| voice.last()))
+            .map(|note| note.end)
+            .max()
+            .unwrap_or(0);
+
+        let mut out = "\\version \"2.18.2\"\n\n".to_string();
+        if let Some(title) = metadata.sequence_name() {
+            let _ = writeln!(out, "\\header {{\n  title = {}\n}}\n", string(title));
+        }
+        out.push_str("\\score {\n  <<\n");
+        for &(track, mean, ref voices) in &staves {
+            out.push_str("    \\new Staff ");
+            if let Some(name) = tracks[track].name() {
+                let _ = write!(out, "\\with {{ instrumentName = {} }} ", string(name));
+            }
+            out.push_str("{\n");
+            let _ = writeln!(out, "      \\clef {}", if mean < 60 { "bass" } else { "treble" });
+            let (letter, alter) = tonic(signature, kind);
+            let _ = writeln!(out, "      \\key {} \\{}", pitch_name(letter, alter),
+                             if kind == KeyKind::Minor { "minor" } else { "major" });
+            let _ = writeln!(out, "      \\time {}", meter(map.meter_at(0)));
+            let tempo = self.tempo_map().tempo_at(0);
+            let _ = writeln!(out, "      \\tempo 4 = {}",
+                             (60_000_000.0 / cmp::max(1, tempo) as f64).round());
+            if voices.len() == 1 {
+                music(&mut out, &voices[0], &map, end, signature, "      ");
+            } else {
+                out.push_str("      <<\n");
+                for (i, voice) in voices.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str("        \\\\\n");
+                    }
+                    out.push_str("        {\n");
+                    music(&mut out, voice, &map, end, signature, "          ");
+                    out.push_str("        }\n");
+                }
+                out.push_str("      >>\n");
+            }
+            out.push_str("    }\n");
+        }
+        out.push_str("  >>\n  \\layout { }\n}\n");
+        Some(out)
+    }
+}
+
+/// Writes a voice a bar to a line, with bar checks.
+fn music(out: &mut String, line: &[LineNote], map: &MeterMap, end: u64, signature: Key,
+         indent: &str) {
+    let ticks_per_quarter = map.ticks_per_quarter();
+    let mut current = map.meter_at(0);
+    for (measure, items) in bars(line, map, end) {
+        out.push_str(indent);
+        if measure.meter != current {
+            current = measure.meter;
+            let _ = write!(out, "\\time {} ", meter(current));
+        }
+        for item in items {
+            let name = match item.key {
+                Some(key) => {
+                    let pitch = spell(key, signature);
+                    let mut name = pitch_name(pitch.letter, pitch.alter);
+                    for _ in 3..pitch.octave {
+                        name.push('\'');
+                    }
+                    for _ in pitch.octave..3 {
+                        name.push(',');
+                    }
+                    name
+                }
+                None => "r".to_string(),
+            };
+            let values = note_values(item.end - item.start, ticks_per_quarter);
+            for (i, &(value, dots)) in values.iter().enumerate() {
+                let _ = write!(out, "{}{}", name, value);
+                for _ in 0..dots {
+                    out.push('.');
+                }
+                // Rests are never tied.
+                if item.key.is_some() && (i + 1 < values.len() || item.tied) {
+                    out.push('~');
+                }
+                out.push(' ');
+            }
+        }
+        out.push_str("|\n");
+    }
+}
+
+/// The Dutch note names LilyPond uses by default, like `fis` and `bes`.
+fn pitch_name(letter: char, alter: i8) -> String {
+    let letter = letter.to_ascii_lowercase();
+    match (letter, alter) {
+        ('e', -1) => "es".to_string(),
+        ('a', -1) => "as".to_string(),
+        (letter, 1) => format!("{}is", letter),
+        (letter, -1) => format!("{}es", letter),
+        (letter, _) => letter.to_string(),
+    }
+}
+
+fn meter(meter: Meter) -> String {
+    format!("{}/{}", meter.numerator, note_value(meter.denominator))
+}
+
+fn string(text: &[u8]) -> String {
+    let text = String::from_utf8_lossy(text);
+    format!("\"{}\"", text.trim().replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_lilypond() {
+    let ly = ::gen::scale(48, &::gen::MAJOR).to_lilypond().unwrap();
+    assert_eq!(ly, "\\version \"2.18.2\"\n\n\\score {\n  <<\n    \\new Staff {\n      \
+                    \\clef bass\n      \\key c \\major\n      \\time 4/4\n      \
+                    \\tempo 4 = 120\n      c4 d4 e4 f4 |\n      g4 a4 b4 c'4 |\n    }\n  \
+                    >>\n  \\layout { }\n}\n");
+
+    // The chords split into three voices, with the top note of each first.
+    let ly = ::gen::chords(&[60, 65]).to_lilypond().unwrap();
+    assert!(ly.contains("      <<\n        {\n          g'1 |\n          c''1 |\n        }\n\
+                         \x20       \\\\\n        {\n          e'1 |\n          a'1 |\n"),
+            "{}", ly);
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x23,
+        0x00, 0xFF, 0x03, 0x05, b'"', b'S', b'o', b'n', b'g',
+        0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08,
+        0x00, 0xFF, 0x59, 0x02, 0xFE, 0x00,
+        0x60, 0x90, 0x46, 0x40,
+        0x84, 0x40, 0x46, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let ly = ::parse_midi(&data).unwrap().to_lilypond().unwrap();
+    assert!(ly.contains("\\header {\n  title = \"\\\"Song\"\n}\n"), "{}", ly);
+    assert!(ly.contains("\\key bes \\major\n      \\time 3/4\n"), "{}", ly);
+    assert!(ly.contains("      r4 bes'2~ |\n      bes'2.~ |\n      bes'4 r2 |\n"), "{}", ly);
+}
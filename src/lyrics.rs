@@ -0,0 +1,177 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Event, MetaEvent, Midi, TextType};
+
+
+// Lyrics Extraction ///////////////////////////////////////////////////////////
+
+/// The timed lyrics of a file. Text is left as raw bytes, since the standard
+/// doesn't specify an encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lyrics<'a> {
+    /// The `@` header fields of a karaoke (.kar) file, without the `@`, such
+    /// as `T` for the title and artist, `L` for the language and `I` for
+    /// other information.
+    pub headers: Vec<(u8, &'a [u8])>,
+    pub syllables: Vec<Syllable<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Syllable<'a> {
+    pub tick: u64,
+    pub seconds: f64,
+    /// The text, without any line or paragraph markers.
+    pub text: &'a [u8],
+    pub starts_line: bool,
+    pub starts_paragraph: bool,
+}
+
+/// A whole line of lyrics, timed from its first syllable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub tick: u64,
+    pub seconds: f64,
+    pub text: String,
+    pub starts_paragraph: bool,
+}
+
+impl<'a> Lyrics<'a> {
+    /// The values of the header fields with the given name, in order.
+    pub fn header(&self, name: u8) -> Vec<&'a [u8]> {
+        self.headers.iter().filter(|&&(n, _)| n == name).map(|&(_, value)| value).collect()
+    }
+
+    /// Joins the syllables into lines, decoding the text as UTF-8 with any
+    /// invalid bytes replaced.
+    pub fn lines(&self) -> Vec<Line> {
+        let mut lines: Vec<Line> = Vec::new();
+        for syllable in &self.syllables {
+            let text = String::from_utf8_lossy(syllable.text);
+            match lines.last_mut() {
+                Some(ref mut line) if !syllable.starts_line => {
+                    line.text.push_str(&text);
+                    continue;
+                }
+                _ => {}
+            }
+            lines.push(Line {
+                tick: syllable.tick,
+                seconds: syllable.seconds,
+                text: text.into_owned(),
+                starts_paragraph: syllable.starts_paragraph,
+            });
+        }
+        lines
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// Collects the lyrics from the lyric events of every track. Files with
+    /// no lyric events are read as karaoke files, which put the lyrics in
+    /// text events instead, marking new lines with `/` and new paragraphs
+    /// with `\`.
+    ///
+    /// Lyric events ending in a carriage return or line feed also end the
+    /// line, which is the usual convention outside of karaoke files.
+    pub fn lyrics(&self) -> Lyrics<'a> {
+        let mut texts = Vec::new();
+        let mut karaoke = Vec::new();
+        for track in self.tracks() {
+            for (tick, event) in track.absolute_events() {
+                match *event {
+                    Event::Meta(_, MetaEvent::Text { kind: TextType::Lyric, text }) => {
+                        texts.push((tick, text));
+                    }
+                    Event::Meta(_, MetaEvent::Text { kind: TextType::Text, text }) => {
+                        karaoke.push((tick, text));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut headers = Vec::new();
+        if texts.is_empty() {
+            for (tick, text) in karaoke {
+                match text {
+                    [b'@', name, value @ ..] => headers.push((*name, value)),
+                    _ => texts.push((tick, text)),
+                }
+            }
+        }
+        // Stable, so syllables at the same tick keep the order of their
+        // tracks.
+        texts.sort_by_key(|&(tick, _)| tick);
+
+        let tempo_map = self.tempo_map();
+        let mut cursor = tempo_map.cursor();
+        let mut syllables = Vec::with_capacity(texts.len());
+        let mut starts_line = true;
+        let mut starts_paragraph = true;
+        for (tick, mut text) in texts {
+            match text.first() {
+                Some(&b'\\') => {
+                    starts_line = true;
+                    starts_paragraph = true;
+                    text = &text[1..];
+                }
+                Some(&b'/') => {
+                    starts_line = true;
+                    text = &text[1..];
+                }
+                _ => {}
+            }
+            let mut ends_line = false;
+            while let Some(&last) = text.last() {
+                if last != b'\r' && last != b'\n' {
+                    break;
+                }
+                ends_line = true;
+                text = &text[..text.len() - 1];
+            }
+            syllables.push(Syllable {
+                tick: tick,
+                seconds: cursor.seconds_at(tick),
+                text: text,
+                starts_line: starts_line,
+                starts_paragraph: starts_paragraph,
+            });
+            starts_line = ends_line;
+            starts_paragraph = false;
+        }
+        Lyrics {
+            headers: headers,
+            syllables: syllables,
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_lyrics() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x29,
+        0x00, 0xFF, 0x01, 0x06, b'@', b'T', b'S', b'o', b'n', b'g',
+        0x00, 0xFF, 0x01, 0x03, b'\\', b'L', b'a',
+        0x30, 0xFF, 0x01, 0x02, b'l', b'a',
+        0x30, 0xFF, 0x01, 0x03, b'/', b'L', b'i',
+        0x60, 0xFF, 0x01, 0x03, b'\\', b'L', b'o',
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let lyrics = midi.lyrics();
+    assert_eq!(lyrics.header(b'T'), [b"Song"]);
+    let lines: Vec<_> = lyrics.lines().into_iter()
+        .map(|line| (line.tick, line.text, line.starts_paragraph))
+        .collect();
+    assert_eq!(lines, [
+        (0x00, "Lala".to_string(), true),
+        (0x60, "Li".to_string(), false),
+        (0xC0, "Lo".to_string(), true),
+    ]);
+    assert_eq!(lyrics.syllables[2].seconds, 0.5);
+}
@@ -0,0 +1,117 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Chunk, Event, MetaEvent, Midi, TextType, TrackChunk};
+use tempo::TempoMap;
+
+
+// Markers and Cue Points //////////////////////////////////////////////////////
+
+/// A marker or cue point, with its name left as raw bytes since the standard
+/// doesn't specify an encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker<'a> {
+    /// The index of the track among the file's tracks.
+    pub track: usize,
+    pub tick: u64,
+    pub seconds: f64,
+    pub name: &'a [u8],
+}
+
+impl<'a> Midi<'a> {
+    /// The markers in every track, which name sections of the piece like
+    /// verses and choruses, sorted by time.
+    pub fn markers(&self) -> Vec<Marker<'a>> {
+        self.text_events(TextType::Marker)
+    }
+
+    /// The cue points in every track, which mark where something outside
+    /// the music happens, like a sound effect or a curtain rising, sorted by
+    /// time.
+    pub fn cue_points(&self) -> Vec<Marker<'a>> {
+        self.text_events(TextType::CuePoint)
+    }
+
+    /// Adds a marker to the first track, which is where the standard puts
+    /// them, adding a track if the file has none.
+    pub fn add_marker(&mut self, tick: u64, name: &'a [u8]) {
+        self.add_text(tick, TextType::Marker, name)
+    }
+
+    /// Adds a cue point to the first track, adding a track if the file has
+    /// none.
+    pub fn add_cue_point(&mut self, tick: u64, name: &'a [u8]) {
+        self.add_text(tick, TextType::CuePoint, name)
+    }
+
+    fn text_events(&self, kind: TextType) -> Vec<Marker<'a>> {
+        let tempo_map = TempoMap::new(self);
+        let mut markers = Vec::new();
+        for (index, track) in self.tracks().enumerate() {
+            for (tick, event) in track.absolute_events() {
+                match *event {
+                    Event::Meta(_, MetaEvent::Text { kind: k, text }) if k == kind => {
+                        markers.push(Marker {
+                            track: index,
+                            tick: tick,
+                            seconds: tempo_map.seconds_at(tick),
+                            name: text,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // The sort is stable, so markers on the same tick stay in track order.
+        markers.sort_by_key(|marker| marker.tick);
+        markers
+    }
+
+    fn add_text(&mut self, tick: u64, kind: TextType, text: &'a [u8]) {
+        let event = Event::Meta(0, MetaEvent::Text {
+            kind: kind,
+            text: text,
+        });
+        for chunk in &mut self.chunks {
+            if let Chunk::Track(ref mut track) = *chunk {
+                track.insert(tick, event);
+                return;
+            }
+        }
+        let mut track = TrackChunk { events: vec![Event::Meta(0, MetaEvent::EndOfTrack)].into() };
+        track.insert(tick, event);
+        self.chunks.insert(0, Chunk::Track(track));
+        self.header.tracks += 1;
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_markers() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0D,
+        0x00, 0xFF, 0x06, 0x05, b'V', b'e', b'r', b's', b'e',
+        0x60, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0A,
+        0x30, 0xFF, 0x07, 0x02, b'F', b'X',
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let mut midi = ::parse_midi(&data).unwrap();
+    let cues = midi.cue_points();
+    assert_eq!(cues.len(), 1);
+    assert_eq!((cues[0].track, cues[0].tick, cues[0].seconds, cues[0].name),
+               (1, 0x30, 0.25, &b"FX"[..]));
+
+    midi.add_marker(0xC0, b"Chorus");
+    midi.add_marker(0x30, b"Bridge");
+    let markers: Vec<_> = midi.markers().iter().map(|marker| (marker.tick, marker.name)).collect();
+    assert_eq!(markers, [(0, &b"Verse"[..]), (0x30, &b"Bridge"[..]), (0xC0, &b"Chorus"[..])]);
+    let track = midi.tracks().next().unwrap();
+    assert_eq!(track.duration(), 0xC0);
+    let reparsed = midi.to_bytes();
+    assert_eq!(::parse_midi(&reparsed).unwrap().markers().len(), 3);
+}
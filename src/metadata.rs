@@ -0,0 +1,166 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Event, Key, KeyKind, MetaEvent, Midi, MidiEvent, TextType, TrackChunk};
+use meter::Meter;
+use tempo::DEFAULT_TEMPO;
+
+
+// Metadata ////////////////////////////////////////////////////////////////////
+
+/// Descriptive information about a file. Text is left as raw bytes, since
+/// the standard doesn't specify an encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata<'a> {
+    pub tracks: Vec<TrackMetadata<'a>>,
+    /// The first copyright notice found in any track.
+    pub copyright: Option<&'a [u8]>,
+    /// Microseconds per quarter note at the start of the file.
+    pub initial_tempo: u32,
+    pub initial_time_signature: Option<Meter>,
+    pub initial_key_signature: Option<(Key, KeyKind)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TrackMetadata<'a> {
+    pub name: Option<&'a [u8]>,
+    pub instruments: Vec<&'a [u8]>,
+    /// The names of the programs the track selects, in order.
+    pub program_names: Vec<&'a [u8]>,
+    /// The first device the track names to play on.
+    pub device_name: Option<&'a [u8]>,
+}
+
+impl<'a> Metadata<'a> {
+    /// In format 0 and 1 files, the name of the first track names the whole
+    /// sequence.
+    pub fn sequence_name(&self) -> Option<&'a [u8]> {
+        self.tracks.first().and_then(|track| track.name)
+    }
+}
+
+impl<'a> TrackChunk<'a> {
+    fn first_text(&self, kind: TextType) -> Option<&'a [u8]> {
+        self.events().iter().filter_map(|event| match *event {
+            Event::Meta(_, MetaEvent::Text { kind: k, text }) if k == kind => Some(text),
+            _ => None,
+        }).next()
+    }
+
+    /// The track's first track name.
+    pub fn name(&self) -> Option<&'a [u8]> {
+        self.first_text(TextType::TrackName)
+    }
+
+    pub fn instrument_name(&self) -> Option<&'a [u8]> {
+        self.first_text(TextType::InstrumentName)
+    }
+
+    /// The channels, from 0 to 15, that the track has messages on, in order.
+    pub fn channels_used(&self) -> Vec<u8> {
+        let mut used = [false; 16];
+        for event in self.events() {
+            if let Some(channel) = event.channel() {
+                used[channel as usize & 0x0F] = true;
+            }
+        }
+        (0..16).filter(|&channel| used[channel as usize]).collect()
+    }
+
+    /// The channel and program of the track's first program change.
+    pub fn first_program(&self) -> Option<(u8, u8)> {
+        self.events().iter().filter_map(|event| match *event {
+            Event::Midi(_, MidiEvent::ProgramChange { channel, program_number }) => {
+                Some((channel, program_number))
+            }
+            _ => None,
+        }).next()
+    }
+}
+
+impl<'a> Midi<'a> {
+    pub fn metadata(&self) -> Metadata<'a> {
+        let mut metadata = Metadata {
+            tracks: Vec::new(),
+            copyright: None,
+            initial_tempo: DEFAULT_TEMPO,
+            initial_time_signature: None,
+            initial_key_signature: None,
+        };
+        for track in self.tracks() {
+            let mut info = TrackMetadata::default();
+            for (tick, event) in track.absolute_events() {
+                let meta = match *event {
+                    Event::Meta(_, ref meta) => meta,
+                    _ => continue,
+                };
+                match *meta {
+                    MetaEvent::Text { kind: TextType::TrackName, text } => {
+                        info.name = info.name.or(Some(text));
+                    }
+                    MetaEvent::Text { kind: TextType::InstrumentName, text } => {
+                        info.instruments.push(text);
+                    }
+                    MetaEvent::Text { kind: TextType::ProgramName, text } => {
+                        info.program_names.push(text);
+                    }
+                    MetaEvent::Text { kind: TextType::DeviceName, text } => {
+                        info.device_name = info.device_name.or(Some(text));
+                    }
+                    MetaEvent::Text { kind: TextType::Copyright, text } => {
+                        metadata.copyright = metadata.copyright.or(Some(text));
+                    }
+                    MetaEvent::SetTempo(tempo) if tick == 0 => {
+                        metadata.initial_tempo = tempo;
+                    }
+                    MetaEvent::TimeSignature { numerator, denominator, .. } if tick == 0 => {
+                        metadata.initial_time_signature = Some(Meter {
+                            numerator: numerator,
+                            denominator: denominator,
+                        });
+                    }
+                    MetaEvent::KeySignature { key, kind } if tick == 0 => {
+                        metadata.initial_key_signature = Some((key, kind));
+                    }
+                    _ => (),
+                }
+            }
+            metadata.tracks.push(info);
+        }
+        metadata
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_metadata() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x31,
+        0x00, 0xFF, 0x03, 0x04, b'S', b'o', b'n', b'g',
+        0x00, 0xFF, 0x04, 0x04, b'O', b'b', b'o', b'e',
+        0x00, 0xFF, 0x09, 0x04, b'S', b'C', b'-', b'8',
+        0x00, 0xFF, 0x08, 0x04, b'R', b'e', b'e', b'd',
+        0x00, 0xFF, 0x59, 0x02, 0xFE, 0x01,
+        0x00, 0xC2, 0x13,
+        0x00, 0x91, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let track = midi.tracks().next().unwrap();
+    assert_eq!(track.name(), Some(&b"Song"[..]));
+    assert_eq!(track.instrument_name(), Some(&b"Oboe"[..]));
+    assert_eq!(track.channels_used(), [1, 2]);
+    assert_eq!(track.first_program(), Some((2, 0x13)));
+    let metadata = midi.metadata();
+    assert_eq!(metadata.sequence_name(), Some(&b"Song"[..]));
+    assert_eq!(metadata.tracks[0].instruments, vec![&b"Oboe"[..]]);
+    assert_eq!(metadata.tracks[0].program_names, vec![&b"Reed"[..]]);
+    assert_eq!(metadata.tracks[0].device_name, Some(&b"SC-8"[..]));
+    assert_eq!(metadata.copyright, None);
+    assert_eq!(metadata.initial_tempo, DEFAULT_TEMPO);
+    assert_eq!(metadata.initial_key_signature, Some((Key::Flats(2), KeyKind::Minor)));
+}
@@ -0,0 +1,262 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+
+use {Division, Event, MetaEvent, Midi, TrackChunk};
+
+
+// Meter Map ///////////////////////////////////////////////////////////////////
+
+/// A time signature as it affects bar lines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Meter {
+    pub numerator: u8,
+    /// The denominator as a power of two, as it's stored in the file, so a
+    /// value of 2 means quarter notes and 3 means eighth notes.
+    pub denominator: u8,
+}
+
+impl Meter {
+    pub fn ticks_per_measure(&self, ticks_per_quarter: u16) -> u64 {
+        let whole_notes = ticks_per_quarter as u64 * 4 * self.numerator as u64;
+        whole_notes.checked_shr(self.denominator as u32).unwrap_or(0)
+    }
+}
+
+/// A stored time signature denominator written out as a note value, like 8
+/// for a value of 3, or as a power of two like `2^70` when it's too large
+/// for that.
+pub fn note_value(denominator: u8) -> String {
+    match 1u64.checked_shl(denominator as u32) {
+        Some(value) => value.to_string(),
+        None => format!("2^{}", denominator),
+    }
+}
+
+impl Default for Meter {
+    /// Files without a time signature are assumed to be in 4/4.
+    fn default() -> Meter {
+        Meter {
+            numerator: 4,
+            denominator: 2,
+        }
+    }
+}
+
+/// The time signatures of a file, sorted by the tick they take effect on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeterMap {
+    ticks_per_quarter: u16,
+    changes: Vec<(u64, Meter)>,
+}
+
+impl<'a> Midi<'a> {
+    /// Collects the time signatures from every track. Returns `None` for files
+    /// using SMPTE time, where ticks have no relationship to beats.
+    pub fn meter_map(&self) -> Option<MeterMap> {
+        MeterMap::new(self)
+    }
+}
+
+impl MeterMap {
+    pub fn new(midi: &Midi) -> Option<MeterMap> {
+        let ticks_per_quarter = match midi.header().division() {
+            Division::TicksPerQuarter(n) => n,
+            Division::Smpte { .. } => return None,
+        };
+        let mut changes = Vec::new();
+        for track in midi.tracks() {
+            changes.extend(track.time_signatures());
+        }
+        Some(MeterMap::from_changes(ticks_per_quarter, changes))
+    }
+
+    /// Builds a map from time signatures in any order. A 4/4 signature is
+    /// assumed until the first change, and when several changes land on the
+    /// same tick the last one wins.
+    pub fn from_changes(ticks_per_quarter: u16, mut changes: Vec<(u64, Meter)>) -> MeterMap {
+        changes.sort_by_key(|&(tick, _)| tick);
+        let mut deduped: Vec<(u64, Meter)> = Vec::with_capacity(changes.len() + 1);
+        deduped.push((0, Meter::default()));
+        for (tick, meter) in changes {
+            if deduped.last().map_or(false, |&(last, _)| last == tick) {
+                deduped.pop();
+            }
+            deduped.push((tick, meter));
+        }
+        MeterMap {
+            ticks_per_quarter: ticks_per_quarter,
+            changes: deduped,
+        }
+    }
+
+    pub fn ticks_per_quarter(&self) -> u16 {
+        self.ticks_per_quarter
+    }
+
+    /// The time signature changes, always starting with one at tick 0.
+    pub fn changes(&self) -> &[(u64, Meter)] {
+        &self.changes
+    }
+
+    pub fn meter_at(&self, tick: u64) -> Meter {
+        self.changes.iter()
+            .take_while(|&&(start, _)| start <= tick)
+            .last()
+            .map_or(Meter::default(), |&(_, meter)| meter)
+    }
+
+    /// An endless iterator over the measures of the map.
+    pub fn measures(&self) -> Measures {
+        Measures {
+            map: self,
+            next_change: 0,
+            index: 0,
+            start: 0,
+        }
+    }
+
+    pub fn measure_at(&self, tick: u64) -> Measure {
+        self.measures()
+            .find(|measure| tick < measure.end)
+            .expect("measures never run out")
+    }
+
+    /// Splits every track of `midi` into the measures that its events fall
+    /// in, stopping after the measure containing the last event in the file.
+    pub fn segment<'m, 'a>(&self, midi: &'m Midi<'a>) -> Vec<Segment<'m, 'a>> {
+        let tracks: Vec<&'m TrackChunk<'a>> = midi.tracks().collect();
+        let end = tracks.iter().map(|track| track.duration()).max().unwrap_or(0);
+        // The index of the next unassigned event in each track, along with
+        // the absolute time of the event before it.
+        let mut cursors = vec![(0, 0); tracks.len()];
+        let mut segments = Vec::new();
+        for measure in self.measures() {
+            if measure.start > end {
+                break;
+            }
+            let mut slices = Vec::with_capacity(tracks.len());
+            for (&track, cursor) in tracks.iter().zip(cursors.iter_mut()) {
+                let events = track.events();
+                let (ref mut pos, ref mut time) = *cursor;
+                let begin = *pos;
+                while *pos < events.len() {
+                    let next = *time + events[*pos].delta() as u64;
+                    if next >= measure.end {
+                        break;
+                    }
+                    *time = next;
+                    *pos += 1;
+                }
+                slices.push(&events[begin..*pos]);
+            }
+            segments.push(Segment {
+                measure: measure,
+                tracks: slices,
+            });
+        }
+        segments
+    }
+}
+
+impl<'a> TrackChunk<'a> {
+    /// The time signatures in this track alone, by absolute tick.
+    pub fn time_signatures(&self) -> Vec<(u64, Meter)> {
+        self.absolute_events()
+            .filter_map(|(tick, event)| match *event {
+                Event::Meta(_, MetaEvent::TimeSignature { numerator, denominator, .. }) =>
+                    Some((tick, Meter {
+                        numerator: numerator,
+                        denominator: denominator,
+                    })),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+
+// Measures ////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Measure {
+    /// The zero-based position of the measure in the piece.
+    pub index: usize,
+    pub start: u64,
+    /// The first tick after the measure. A time signature change that doesn't
+    /// fall on a bar line cuts the measure short.
+    pub end: u64,
+    pub meter: Meter,
+}
+
+pub struct Measures<'m> {
+    map: &'m MeterMap,
+    next_change: usize,
+    index: usize,
+    start: u64,
+}
+
+impl<'m> Iterator for Measures<'m> {
+    type Item = Measure;
+
+    fn next(&mut self) -> Option<Measure> {
+        let changes = &self.map.changes;
+        while self.next_change < changes.len() && changes[self.next_change].0 <= self.start {
+            self.next_change += 1;
+        }
+        let meter = changes[self.next_change - 1].1;
+        let length = cmp::max(1, meter.ticks_per_measure(self.map.ticks_per_quarter));
+        let mut end = self.start + length;
+        if let Some(&(tick, _)) = changes.get(self.next_change) {
+            end = cmp::min(end, tick);
+        }
+        let measure = Measure {
+            index: self.index,
+            start: self.start,
+            end: end,
+            meter: meter,
+        };
+        self.index += 1;
+        self.start = end;
+        Some(measure)
+    }
+}
+
+/// The events of each track that fall within one measure. The delta time of
+/// the first event in each slice is still relative to the event before it,
+/// which may be in an earlier measure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment<'m, 'a: 'm> {
+    pub measure: Measure,
+    pub tracks: Vec<&'m [Event<'a>]>,
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_segment() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1A,
+        0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08,
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x40, 0x80, 0x3C, 0x40,
+        0x81, 0x40, 0x90, 0x3E, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let map = midi.meter_map().unwrap();
+    assert_eq!(map.meter_at(1000), Meter { numerator: 3, denominator: 2 });
+    assert_eq!(note_value(3), "8");
+    assert_eq!(note_value(64), "2^64");
+
+    let segments = map.segment(&midi);
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].measure.end, 288);
+    assert_eq!(segments[0].tracks[0].len(), 3);
+    assert_eq!(segments[1].measure.start, 288);
+    assert_eq!(segments[1].tracks[0].len(), 2);
+}
@@ -0,0 +1,137 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+
+use {Chunk, Event, MetaEvent, Midi, MidiEvent, TextType, TrackChunk};
+use meter::MeterMap;
+
+
+// Click Tracks ////////////////////////////////////////////////////////////////
+
+/// The sounds of a click track. The default plays a high wood block on each
+/// downbeat and a low wood block on the other beats, on the General MIDI
+/// percussion channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClickOptions {
+    /// The channel, from 0 to 15, so 9 is channel 10.
+    pub channel: u8,
+    pub downbeat_key: u8,
+    pub downbeat_velocity: u8,
+    pub beat_key: u8,
+    pub beat_velocity: u8,
+}
+
+impl Default for ClickOptions {
+    fn default() -> ClickOptions {
+        ClickOptions {
+            channel: 9,
+            downbeat_key: 76,
+            downbeat_velocity: 127,
+            beat_key: 77,
+            beat_velocity: 80,
+        }
+    }
+}
+
+impl MeterMap {
+    /// A track with a click on every beat until `end`, where a beat is the
+    /// denominator of the time signature. Each click lasts half a beat.
+    pub fn click_track(&self, end: u64, options: &ClickOptions) -> TrackChunk<'static> {
+        let mut clicks = Vec::new();
+        for measure in self.measures().take_while(|measure| measure.start < end) {
+            let beat = cmp::max(1, (self.ticks_per_quarter() as u64 * 4)
+                .checked_shr(measure.meter.denominator as u32)
+                .unwrap_or(0));
+            let mut tick = measure.start;
+            while tick < measure.end && tick < end {
+                let (key, velocity) = if tick == measure.start {
+                    (options.downbeat_key, options.downbeat_velocity)
+                } else {
+                    (options.beat_key, options.beat_velocity)
+                };
+                clicks.push((tick, 1, MidiEvent::NoteOn {
+                    channel: options.channel,
+                    number: key,
+                    velocity: velocity,
+                }));
+                clicks.push((tick + cmp::max(1, beat / 2), 0, MidiEvent::NoteOff {
+                    channel: options.channel,
+                    number: key,
+                    velocity: 64,
+                }));
+                tick += beat;
+            }
+        }
+        // A click that runs into a short measure still ends before the next.
+        clicks.sort_by_key(|&(tick, order, _)| (tick, order));
+
+        let mut events = vec![Event::Meta(0, MetaEvent::Text {
+            kind: TextType::TrackName,
+            text: b"Click",
+        })];
+        let mut time = 0;
+        for (tick, _, event) in clicks {
+            events.push(Event::Midi((tick - time) as u32, event));
+            time = tick;
+        }
+        events.push(Event::Meta(end.saturating_sub(time) as u32, MetaEvent::EndOfTrack));
+        TrackChunk { events: events.into() }
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// Adds a click track that follows the file's time signatures to the end
+    /// of its longest track. A format 0 file becomes format 1, since it can
+    /// only have one track. Returns false, changing nothing, for files using
+    /// SMPTE time.
+    pub fn add_click_track(&mut self, options: &ClickOptions) -> bool {
+        let map = match self.meter_map() {
+            Some(map) => map,
+            None => return false,
+        };
+        let end = self.tracks().map(|track| track.duration()).max().unwrap_or(0);
+        self.chunks.push(Chunk::Track(map.click_track(end, options)));
+        self.header.tracks += 1;
+        if self.header.format == 0 && self.header.tracks > 1 {
+            self.header.format = 1;
+        }
+        true
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_click_track() {
+    use meter::Meter;
+
+    let meter = |numerator, denominator| Meter {
+        numerator: numerator,
+        denominator: denominator,
+    };
+    // A bar of 2/4, then a bar of 6/8.
+    let map = MeterMap::from_changes(4, vec![(0, meter(2, 2)), (8, meter(6, 3))]);
+    let track = map.click_track(20, &ClickOptions::default());
+    let ons: Vec<_> = track.absolute_events()
+        .filter_map(|(tick, event)| match *event {
+            Event::Midi(_, MidiEvent::NoteOn { number, .. }) => Some((tick, number)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ons, [(0, 76), (4, 77), (8, 76), (10, 77), (12, 77), (14, 77), (16, 77), (18, 77)]);
+    assert_eq!(track.duration(), 20);
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x05,
+        0x83, 0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let mut midi = ::parse_midi(&data).unwrap();
+    assert!(midi.add_click_track(&ClickOptions::default()));
+    assert_eq!((midi.header().format(), midi.header().tracks()), (1, 2));
+    let click = midi.tracks().nth(1).unwrap();
+    assert_eq!(click.events().len(), 2 + 2 * 4);
+}
@@ -0,0 +1,86 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::path::Path;
+
+use memmap::Mmap;
+use {parse_midi, Midi, ParseError};
+
+
+// Memory-Mapped Files /////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Midi(ParseError),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// A parsed file along with the memory map it borrows from.
+pub struct MappedMidi {
+    // Declared before the map so that it's dropped first.
+    midi: Midi<'static>,
+    map: Mmap,
+}
+
+/// Maps the file at `path` into memory and parses it, so that even huge
+/// files are never copied. As with any memory map, changing the file while
+/// it's mapped may change or crash the program.
+pub fn parse_midi_file<P: AsRef<Path>>(path: P) -> Result<MappedMidi, Error> {
+    let file = File::open(path)?;
+    let map = unsafe { Mmap::map(&file)? };
+    // The mapping stays at the same address when the `Mmap` is moved, and it
+    // outlives `midi`, so the borrow never dangles. The lifetime is
+    // shortened again before anything outside can see it.
+    let midi = {
+        let data: &'static [u8] = unsafe { mem::transmute::<&[u8], &'static [u8]>(&map) };
+        parse_midi(data).map_err(Error::Midi)?
+    };
+    Ok(MappedMidi {
+        midi: midi,
+        map: map,
+    })
+}
+
+impl MappedMidi {
+    pub fn midi<'m>(&'m self) -> &'m Midi<'m> {
+        &self.midi
+    }
+
+    /// The raw bytes of the file.
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_parse_midi_file() {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let path = env::temp_dir().join("midi-test-parse-midi-file.mid");
+    File::create(&path).and_then(|mut file| file.write_all(&data)).unwrap();
+    let mapped = parse_midi_file(&path).unwrap();
+    assert_eq!(mapped.bytes(), &data[..]);
+    assert_eq!(mapped.midi(), &parse_midi(&data).unwrap());
+    drop(mapped);
+    fs::remove_file(&path).unwrap();
+}
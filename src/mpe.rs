@@ -0,0 +1,232 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::ops::Range;
+
+use {ControlChange, Event, Midi, MidiEvent};
+
+
+// MIDI Polyphonic Expression //////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZoneKind {
+    /// Managed from channel 0, with members counting up from channel 1.
+    Lower,
+    /// Managed from channel 15, with members counting down from channel 14.
+    Upper,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Zone {
+    pub kind: ZoneKind,
+    /// The number of member channels, where 0 turns the zone off.
+    pub members: u8,
+}
+
+impl Zone {
+    pub fn manager(&self) -> u8 {
+        match self.kind {
+            ZoneKind::Lower => 0,
+            ZoneKind::Upper => 15,
+        }
+    }
+
+    pub fn member_channels(&self) -> Range<u8> {
+        match self.kind {
+            ZoneKind::Lower => 1..1 + self.members,
+            ZoneKind::Upper => 15 - self.members..15,
+        }
+    }
+
+    pub fn contains(&self, channel: u8) -> bool {
+        let channels = self.member_channels();
+        channel >= channels.start && channel < channels.end
+    }
+}
+
+/// A note played on a member channel, along with the expression sent on
+/// that channel while it sounded. Each stream starts with the value the
+/// channel already had when the note began, if one had been sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MpeNote {
+    pub zone: ZoneKind,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+    pub release_velocity: u8,
+    /// The absolute ticks when the note started and ended. Notes that are
+    /// never released end with the file.
+    pub start: u64,
+    pub end: u64,
+    /// Pitch bend, centered on 0.
+    pub pitch_bend: Vec<(u64, i16)>,
+    pub pressure: Vec<(u64, u8)>,
+    /// The third dimension of control, sent on CC 74.
+    pub timbre: Vec<(u64, u8)>,
+}
+
+/// The MPE interpretation of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mpe {
+    /// Every zone configuration message, by absolute tick.
+    pub zones: Vec<(u64, Zone)>,
+    /// The notes in member channels, sorted by their start.
+    pub notes: Vec<MpeNote>,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct ChannelState {
+    rpn: (u8, u8),
+    pitch_bend: Option<i16>,
+    pressure: Option<u8>,
+    timbre: Option<u8>,
+}
+
+impl<'a> Midi<'a> {
+    /// Reads the file as MPE, or `None` if it never configures an MPE zone
+    /// with the MPE Configuration Message (RPN 6).
+    pub fn mpe(&self) -> Option<Mpe> {
+        let mut events: Vec<(u64, MidiEvent)> = self.tracks()
+            .flat_map(|track| track.absolute_events())
+            .filter_map(|(tick, event)| match *event {
+                Event::Midi(_, event) => Some((tick, event)),
+                _ => None,
+            })
+            .collect();
+        events.sort_by_key(|&(tick, _)| tick);
+        let last_tick = events.last().map_or(0, |&(tick, _)| tick);
+
+        let mut zones = Vec::new();
+        let mut lower = Zone { kind: ZoneKind::Lower, members: 0 };
+        let mut upper = Zone { kind: ZoneKind::Upper, members: 0 };
+        let mut channels = [ChannelState { rpn: (0x7F, 0x7F), ..Default::default() }; 16];
+        let mut notes: Vec<MpeNote> = Vec::new();
+        // Indices into `notes` of the notes still sounding.
+        let mut sounding: Vec<usize> = Vec::new();
+        for (tick, event) in events {
+            use MidiEvent::*;
+            match event {
+                Control { channel, change: ControlChange::Raw(controller, value) } => {
+                    let state = &mut channels[channel as usize & 0x0F];
+                    match controller {
+                        101 => state.rpn.0 = value,
+                        100 => state.rpn.1 = value,
+                        6 if state.rpn == (0, 6) && (channel == 0 || channel == 15) => {
+                            // A zone can't take more than the channels the
+                            // other one leaves free, so it shrinks the other.
+                            let members = ::std::cmp::min(value, 15);
+                            let (zone, other) = if channel == 0 {
+                                (&mut lower, &mut upper)
+                            } else {
+                                (&mut upper, &mut lower)
+                            };
+                            zone.members = members;
+                            other.members =
+                                ::std::cmp::min(other.members, 14u8.saturating_sub(members));
+                            zones.push((tick, *zone));
+                        }
+                        74 => {
+                            state.timbre = Some(value);
+                            for &i in &sounding {
+                                if notes[i].channel == channel {
+                                    notes[i].timbre.push((tick, value));
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                PitchBend { channel, pitch } => {
+                    let bend = pitch as i16 - 0x2000;
+                    channels[channel as usize & 0x0F].pitch_bend = Some(bend);
+                    for &i in &sounding {
+                        if notes[i].channel == channel {
+                            notes[i].pitch_bend.push((tick, bend));
+                        }
+                    }
+                }
+                ChannelAftertouch { channel, pressure } => {
+                    channels[channel as usize & 0x0F].pressure = Some(pressure);
+                    for &i in &sounding {
+                        if notes[i].channel == channel {
+                            notes[i].pressure.push((tick, pressure));
+                        }
+                    }
+                }
+                NoteOn { channel, number, velocity } if velocity > 0 => {
+                    let zone = if lower.contains(channel) {
+                        ZoneKind::Lower
+                    } else if upper.contains(channel) {
+                        ZoneKind::Upper
+                    } else {
+                        continue;
+                    };
+                    let state = channels[channel as usize & 0x0F];
+                    sounding.push(notes.len());
+                    notes.push(MpeNote {
+                        zone: zone,
+                        channel: channel,
+                        key: number,
+                        velocity: velocity,
+                        release_velocity: 0,
+                        start: tick,
+                        end: last_tick,
+                        pitch_bend: state.pitch_bend.map(|b| (tick, b)).into_iter().collect(),
+                        pressure: state.pressure.map(|p| (tick, p)).into_iter().collect(),
+                        timbre: state.timbre.map(|t| (tick, t)).into_iter().collect(),
+                    });
+                }
+                NoteOn { channel, number, velocity } |
+                NoteOff { channel, number, velocity } => {
+                    let found = sounding.iter()
+                        .position(|&i| notes[i].channel == channel && notes[i].key == number);
+                    if let Some(pos) = found {
+                        let note = &mut notes[sounding.remove(pos)];
+                        note.end = tick;
+                        note.release_velocity = velocity;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if zones.is_empty() {
+            None
+        } else {
+            Some(Mpe {
+                zones: zones,
+                notes: notes,
+            })
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_mpe() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1F,
+        // Lower zone with three member channels.
+        0x00, 0xB0, 0x65, 0x00,
+        0x00, 0xB0, 0x64, 0x06,
+        0x00, 0xB0, 0x06, 0x03,
+        0x00, 0xE1, 0x00, 0x50,
+        0x00, 0x91, 0x3C, 0x40,
+        0x10, 0xD1, 0x20,
+        0x10, 0x81, 0x3C, 0x10,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let mpe = midi.mpe().unwrap();
+    assert_eq!(mpe.zones, vec![(0, Zone { kind: ZoneKind::Lower, members: 3 })]);
+    assert_eq!(mpe.zones[0].1.member_channels(), 1..4);
+    assert_eq!(mpe.notes.len(), 1);
+    let note = &mpe.notes[0];
+    assert_eq!((note.channel, note.key, note.start, note.end), (1, 0x3C, 0, 0x20));
+    assert_eq!(note.pitch_bend, vec![(0, 0x800)]);
+    assert_eq!(note.pressure, vec![(0x10, 0x20)]);
+    assert!(note.timbre.is_empty());
+}
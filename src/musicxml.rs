@@ -0,0 +1,296 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::fmt::Write;
+
+use {Key, KeyKind, Midi};
+use meter::{note_value, Meter};
+use notation::{bars, fifths, note_values, quantize, spell, voices, BarItem, LineNote};
+
+
+// MusicXML Export /////////////////////////////////////////////////////////////
+
+/// Settings for `to_musicxml_with`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MusicXmlOptions {
+    /// The shortest note kept, as a fraction of a whole note, so 16 rounds
+    /// every note to sixteenths.
+    pub shortest: u16,
+}
+
+impl Default for MusicXmlOptions {
+    fn default() -> MusicXmlOptions {
+        MusicXmlOptions { shortest: 16 }
+    }
+}
+
+impl<'a> Midi<'a> {
+    pub fn to_musicxml(&self) -> Option<String> {
+        self.to_musicxml_with(&MusicXmlOptions::default())
+    }
+
+    /// Writes the file as a partwise MusicXML score, with a part for each
+    /// track that has notes, split into voices where its notes overlap.
+    /// Measures follow the meter map, and durations are in ticks, rounded
+    /// to the nearest `options.shortest`. Lyrics go on the first voice of
+    /// the first part. Returns `None` for files using SMPTE time.
+    pub fn to_musicxml_with(&self, options: &MusicXmlOptions) -> Option<String> {
+        let map = self.meter_map()?;
+        let shortest = cmp::max(1, options.shortest) as u64;
+        let grid = cmp::max(1, map.ticks_per_quarter() as u64 * 4 / shortest);
+        let metadata = self.metadata();
+        let (signature, kind) = metadata.initial_key_signature
+            .unwrap_or((Key::OfC, KeyKind::Major));
+        let notes = self.notes();
+        let lyrics: Vec<(u64, String)> = self.lyrics()
+            .syllables
+            .iter()
+            .map(|syllable| {
+                let text = String::from_utf8_lossy(syllable.text).into_owned();
+                (quantize(syllable.tick, grid), text)
+            })
+            .collect();
+
+        let tracks: Vec<_> = self.tracks().collect();
+        let parts: Vec<_> = (0..tracks.len())
+            .map(|track| {
+                let notes: Vec<_> = notes.iter().filter(|note| note.track == track).cloned()
+                    .collect();
+                let mean = notes.iter().map(|note| note.key as u64).sum::<u64>() /
+                           cmp::max(1, notes.len() as u64);
+                (track, mean, voices(&notes, grid))
+            })
+            .filter(|&(_, _, ref voices)| !voices.is_empty())
+            .collect();
+        let end = parts.iter()
+            .flat_map(|&(_, _, ref voices)| voices.iter().filter_map(|voice| voice.last()))
+            .map(|note| note.end)
+            .max()
+            .unwrap_or(0);
+
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 3.1 \
+                      Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">\n");
+        out.push_str("<score-partwise version=\"3.1\">\n");
+        if let Some(title) = metadata.sequence_name() {
+            let _ = writeln!(out, "  <work><work-title>{}</work-title></work>", text(title));
+        }
+        out.push_str("  <part-list>\n");
+        for (i, &(track, _, _)) in parts.iter().enumerate() {
+            let name = tracks[track].name().map_or(format!("Track {}", track + 1), text);
+            let _ = writeln!(out, "    <score-part id=\"P{}\"><part-name>{}</part-name>\
+                                   </score-part>",
+                             i + 1, name);
+        }
+        out.push_str("  </part-list>\n");
+
+        let ticks_per_quarter = map.ticks_per_quarter();
+        for (i, &(_, mean, ref voices)) in parts.iter().enumerate() {
+            let _ = writeln!(out, "  <part id=\"P{}\">", i + 1);
+            let voice_bars: Vec<_> = voices.iter().map(|voice| bars(voice, &map, end)).collect();
+            let mut current = map.meter_at(0);
+            let mut hyphenated = false;
+            for (index, &(measure, _)) in voice_bars[0].iter().enumerate() {
+                let _ = writeln!(out, "    <measure number=\"{}\">", index + 1);
+                if index == 0 {
+                    out.push_str("      <attributes>\n");
+                    let _ = writeln!(out, "        <divisions>{}</divisions>", ticks_per_quarter);
+                    let _ = writeln!(out, "        <key><fifths>{}</fifths><mode>{}</mode></key>",
+                                     fifths(signature),
+                                     if kind == KeyKind::Minor { "minor" } else { "major" });
+                    let _ = writeln!(out, "        {}", time(current));
+                    let _ = writeln!(out, "        <clef>{}</clef>",
+                                     if mean < 60 {
+                                         "<sign>F</sign><line>4</line>"
+                                     } else {
+                                         "<sign>G</sign><line>2</line>"
+                                     });
+                    out.push_str("      </attributes>\n");
+                    if i == 0 {
+                        let tempo = self.tempo_map().tempo_at(0);
+                        let bpm = (60_000_000.0 / cmp::max(1, tempo) as f64).round();
+                        let _ = writeln!(out, "      <direction placement=\"above\">\
+                                               <direction-type><metronome>\
+                                               <beat-unit>quarter</beat-unit>\
+                                               <per-minute>{0}</per-minute></metronome>\
+                                               </direction-type><sound tempo=\"{0}\"/>\
+                                               </direction>",
+                                         bpm);
+                    }
+                } else if measure.meter != current {
+                    current = measure.meter;
+                    let _ = writeln!(out, "      <attributes>{}</attributes>", time(current));
+                }
+                for (voice, bars) in voice_bars.iter().enumerate() {
+                    if voice > 0 {
+                        let _ = writeln!(out, "      <backup><duration>{}</duration></backup>",
+                                         measure.end - measure.start);
+                    }
+                    for item in &bars[index].1 {
+                        let lyric = if i == 0 && voice == 0 && item.key.is_some() {
+                            lyrics.iter().find(|&&(tick, _)| tick == item.start)
+                        } else {
+                            None
+                        };
+                        let lyric = lyric.map(|&(_, ref syllable)| {
+                            let next = syllable.ends_with('-');
+                            let syllabic = match (hyphenated, next) {
+                                (false, false) => "single",
+                                (false, true) => "begin",
+                                (true, true) => "middle",
+                                (true, false) => "end",
+                            };
+                            hyphenated = next;
+                            (syllabic, syllable.trim_end_matches('-'))
+                        });
+                        note(&mut out, item, voice + 1, signature, ticks_per_quarter,
+                             continues(voices[voice].as_slice(), item), lyric);
+                    }
+                }
+                out.push_str("    </measure>\n");
+            }
+            out.push_str("  </part>\n");
+        }
+        out.push_str("</score-partwise>\n");
+        Some(out)
+    }
+}
+
+/// Whether a note item continues one tied over from the measure before.
+fn continues(line: &[LineNote], item: &BarItem) -> bool {
+    item.key.is_some() && line.iter().any(|note| note.start < item.start && note.end > item.start)
+}
+
+/// Writes a note or rest, as tied notes of each value it takes.
+fn note(out: &mut String, item: &BarItem, voice: usize, signature: Key, ticks_per_quarter: u16,
+        tied_from: bool, lyric: Option<(&str, &str)>) {
+    let ticks = item.end - item.start;
+    let values = note_values(ticks, ticks_per_quarter);
+    let mut left = ticks;
+    for (i, &(value, dots)) in values.iter().enumerate() {
+        let base = ticks_per_quarter as u64 * 4 / value as u64;
+        let mut duration = (0..dots as u32 + 1).map(|dot| base >> dot).sum::<u64>();
+        // Durations that don't come out even are made up in the last piece.
+        if i + 1 == values.len() {
+            duration = left;
+        }
+        left -= cmp::min(left, duration);
+        let stop = item.key.is_some() && (i > 0 || tied_from);
+        let start = item.key.is_some() && (i + 1 < values.len() || item.tied);
+
+        out.push_str("      <note>\n");
+        match item.key {
+            Some(key) => {
+                let pitch = spell(key, signature);
+                let _ = write!(out, "        <pitch><step>{}</step>", pitch.letter);
+                if pitch.alter != 0 {
+                    let _ = write!(out, "<alter>{}</alter>", pitch.alter);
+                }
+                let _ = writeln!(out, "<octave>{}</octave></pitch>", pitch.octave);
+            }
+            None => out.push_str("        <rest/>\n"),
+        }
+        let _ = writeln!(out, "        <duration>{}</duration>", duration);
+        if stop {
+            out.push_str("        <tie type=\"stop\"/>\n");
+        }
+        if start {
+            out.push_str("        <tie type=\"start\"/>\n");
+        }
+        let _ = writeln!(out, "        <voice>{}</voice>", voice);
+        let _ = writeln!(out, "        <type>{}</type>", type_name(value));
+        for _ in 0..dots {
+            out.push_str("        <dot/>\n");
+        }
+        if stop || start {
+            out.push_str("        <notations>");
+            if stop {
+                out.push_str("<tied type=\"stop\"/>");
+            }
+            if start {
+                out.push_str("<tied type=\"start\"/>");
+            }
+            out.push_str("</notations>\n");
+        }
+        if let (0, Some((syllabic, syllable))) = (i, lyric) {
+            let _ = writeln!(out, "        <lyric><syllabic>{}</syllabic><text>{}</text></lyric>",
+                             syllabic, text(syllable.as_bytes()));
+        }
+        out.push_str("      </note>\n");
+    }
+}
+
+fn type_name(value: u16) -> &'static str {
+    match value {
+        1 => "whole",
+        2 => "half",
+        4 => "quarter",
+        8 => "eighth",
+        16 => "16th",
+        32 => "32nd",
+        _ => "64th",
+    }
+}
+
+fn time(meter: Meter) -> String {
+    format!("<time><beats>{}</beats><beat-type>{}</beat-type></time>",
+            meter.numerator, note_value(meter.denominator))
+}
+
+/// Decodes text as UTF-8 and escapes it for XML.
+fn text(text: &[u8]) -> String {
+    let mut out = String::new();
+    for c in String::from_utf8_lossy(text).trim().chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_musicxml() {
+    let xml = ::gen::scale(60, &::gen::MAJOR).to_musicxml().unwrap();
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+    assert_eq!(xml.matches("<measure ").count(), 2);
+    assert_eq!(xml.matches("<note>").count(), 8);
+    assert!(xml.contains("<part-name>Track 1</part-name>"));
+    assert!(xml.contains("<divisions>96</divisions>"));
+    assert!(xml.contains("<key><fifths>0</fifths><mode>major</mode></key>"));
+    assert!(xml.contains("<time><beats>4</beats><beat-type>4</beat-type></time>"));
+    assert!(xml.contains("<pitch><step>C</step><octave>5</octave></pitch>\n        \
+                          <duration>96</duration>\n        <voice>1</voice>\n        \
+                          <type>quarter</type>\n"));
+    assert!(xml.ends_with("  </part>\n</score-partwise>\n"));
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1A,
+        0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08,
+        0x60, 0xFF, 0x05, 0x02, b'L', b'a',
+        0x00, 0x90, 0x46, 0x40,
+        0x84, 0x40, 0x46, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let xml = ::parse_midi(&data).unwrap().to_musicxml().unwrap();
+    assert_eq!(xml.matches("<measure ").count(), 3);
+    let held = "<pitch><step>A</step><alter>1</alter><octave>4</octave></pitch>\n        \
+                <duration>288</duration>\n        <tie type=\"stop\"/>\n        \
+                <tie type=\"start\"/>\n        <voice>1</voice>\n        \
+                <type>half</type>\n        <dot/>\n";
+    assert!(xml.contains(held), "{}", xml);
+    assert!(xml.contains("<lyric><syllabic>single</syllabic><text>La</text></lyric>"));
+    assert_eq!(xml.matches("<tied type=\"start\"/>").count(), 2);
+    assert_eq!(xml.matches("<tied type=\"stop\"/>").count(), 2);
+    assert_eq!(xml.matches("<rest/>").count(), 2);
+}
@@ -0,0 +1,287 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+
+use {Key, KeyKind};
+use meter::{Measure, MeterMap};
+use notes::Note;
+
+
+// Lines of Notes //////////////////////////////////////////////////////////////
+
+/// A note of a monophonic line, with its start and end on a grid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineNote {
+    pub start: u64,
+    pub end: u64,
+    pub key: u8,
+}
+
+/// Rounds a tick to the nearest multiple of `grid`.
+pub fn quantize(tick: u64, grid: u64) -> u64 {
+    let grid = cmp::max(1, grid);
+    (tick + grid / 2) / grid * grid
+}
+
+fn quantized(notes: &[Note], grid: u64) -> Vec<LineNote> {
+    let grid = cmp::max(1, grid);
+    let mut line: Vec<LineNote> = notes.iter()
+        .map(|note| {
+            let start = quantize(note.start, grid);
+            LineNote {
+                start: start,
+                end: cmp::max(start + grid, quantize(note.end, grid)),
+                key: note.key,
+            }
+        })
+        .collect();
+    line.sort_by_key(|note| (note.start, cmp::Reverse(note.key)));
+    line
+}
+
+/// The top line of some notes, on a grid: of the notes starting together
+/// the highest is kept, and each note is cut short where the next starts.
+pub fn melody(notes: &[Note], grid: u64) -> Vec<LineNote> {
+    let mut line: Vec<LineNote> = Vec::new();
+    for note in quantized(notes, grid) {
+        if let Some(last) = line.last_mut() {
+            if last.start == note.start {
+                continue;
+            }
+            last.end = cmp::min(last.end, note.start);
+        }
+        line.push(note);
+    }
+    line
+}
+
+/// Splits some notes, on a grid, into as few monophonic voices as they fit
+/// in. Each note goes in the first voice that's free when it starts, so the
+/// notes of a chord go from the top voice down.
+pub fn voices(notes: &[Note], grid: u64) -> Vec<Vec<LineNote>> {
+    let mut voices: Vec<Vec<LineNote>> = Vec::new();
+    for note in quantized(notes, grid) {
+        let free = voices.iter().position(|voice| voice.last().map_or(true, |last| {
+            last.end <= note.start
+        }));
+        match free {
+            Some(voice) => voices[voice].push(note),
+            None => voices.push(vec![note]),
+        }
+    }
+    voices
+}
+
+
+// Bars ////////////////////////////////////////////////////////////////////////
+
+/// A note or rest within one measure.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BarItem {
+    pub start: u64,
+    pub end: u64,
+    /// The key of a note, or `None` for a rest.
+    pub key: Option<u8>,
+    /// Whether the note carries on into the next measure, tied over.
+    pub tied: bool,
+}
+
+/// Lays a line out in measures, with rests filling the gaps, through the
+/// measure containing `end`. Notes that cross a bar line are split and
+/// tied.
+pub fn bars(line: &[LineNote], map: &MeterMap, end: u64) -> Vec<(Measure, Vec<BarItem>)> {
+    let mut bars = Vec::new();
+    let mut next = 0;
+    for measure in map.measures() {
+        if measure.start >= end && !bars.is_empty() {
+            break;
+        }
+        let mut items = Vec::new();
+        let mut time = measure.start;
+        while next < line.len() && line[next].start < measure.end {
+            let note = line[next];
+            if note.start > time {
+                items.push(rest(time, note.start));
+            }
+            let start = cmp::max(note.start, measure.start);
+            time = cmp::min(note.end, measure.end);
+            items.push(BarItem {
+                start: start,
+                end: time,
+                key: Some(note.key),
+                tied: note.end > measure.end,
+            });
+            if note.end > measure.end {
+                break;
+            }
+            next += 1;
+        }
+        if time < measure.end {
+            items.push(rest(time, measure.end));
+        }
+        bars.push((measure, items));
+    }
+    bars
+}
+
+fn rest(start: u64, end: u64) -> BarItem {
+    BarItem {
+        start: start,
+        end: end,
+        key: None,
+        tied: false,
+    }
+}
+
+/// Splits a length into note values that can be written without ties, as
+/// pairs of a value, where 4 is a quarter note and 1 is a whole note, and
+/// a number of dots. Lengths that aren't a whole number of 64th notes leave
+/// the remainder off.
+pub fn note_values(ticks: u64, ticks_per_quarter: u16) -> Vec<(u16, u8)> {
+    let sixty_fourth = cmp::max(1, ticks_per_quarter as u64 / 16);
+    let mut left = ticks / sixty_fourth;
+    let mut values = Vec::new();
+    // Whole notes first, and then the longest value that fits.
+    while left >= 64 {
+        values.push((1, 0));
+        left -= 64;
+    }
+    while left > 0 {
+        let mut size = 32;
+        let mut value = 2;
+        while size > left {
+            size /= 2;
+            value *= 2;
+        }
+        left -= size;
+        let mut dots = 0;
+        let mut dot = size / 2;
+        while dot > 0 && left >= dot && dots < 2 {
+            left -= dot;
+            dots += 1;
+            dot /= 2;
+        }
+        values.push((value, dots));
+    }
+    values
+}
+
+
+// Spelling ////////////////////////////////////////////////////////////////////
+
+/// A key number spelled as a letter with an alteration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pitch {
+    /// An uppercase letter from `A` to `G`.
+    pub letter: char,
+    /// Semitones up from the letter, so 1 is a sharp and -1 is a flat.
+    pub alter: i8,
+    /// The octave in scientific pitch, where middle C is C4.
+    pub octave: i32,
+}
+
+static SHARP_LETTERS: [(char, i8); 12] = [('C', 0), ('C', 1), ('D', 0), ('D', 1), ('E', 0),
+                                         ('F', 0), ('F', 1), ('G', 0), ('G', 1), ('A', 0),
+                                         ('A', 1), ('B', 0)];
+static FLAT_LETTERS: [(char, i8); 12] = [('C', 0), ('D', -1), ('D', 0), ('E', -1), ('E', 0),
+                                        ('F', 0), ('G', -1), ('G', 0), ('A', -1), ('A', 0),
+                                        ('B', -1), ('B', 0)];
+
+/// Spells a key with sharps, or with flats in a key signature with flats.
+pub fn spell(key: u8, signature: Key) -> Pitch {
+    let letters = match signature {
+        Key::Flats(_) => &FLAT_LETTERS,
+        _ => &SHARP_LETTERS,
+    };
+    let (letter, alter) = letters[key as usize % 12];
+    Pitch {
+        letter: letter,
+        alter: alter,
+        octave: key as i32 / 12 - 1,
+    }
+}
+
+/// Sharps as positive numbers and flats as negative, as MusicXML counts
+/// them.
+pub fn fifths(signature: Key) -> i8 {
+    match signature {
+        Key::Flats(n) => -(cmp::min(n, 7) as i8),
+        Key::OfC => 0,
+        Key::Sharps(n) => cmp::min(n, 7) as i8,
+    }
+}
+
+/// How a key signature alters a letter.
+pub fn signature_alter(signature: Key, letter: char) -> i8 {
+    let fifths = fifths(signature);
+    if fifths > 0 && "FCGDAEB"[..fifths as usize].contains(letter) {
+        1
+    } else if fifths < 0 && "BEADGCF"[..-fifths as usize].contains(letter) {
+        -1
+    } else {
+        0
+    }
+}
+
+/// The letter and alteration of the tonic of a key.
+pub fn tonic(signature: Key, kind: KeyKind) -> (char, i8) {
+    static MAJOR: [(char, i8); 15] = [('C', -1), ('G', -1), ('D', -1), ('A', -1), ('E', -1),
+                                      ('B', -1), ('F', 0), ('C', 0), ('G', 0), ('D', 0),
+                                      ('A', 0), ('E', 0), ('B', 0), ('F', 1), ('C', 1)];
+    static MINOR: [(char, i8); 15] = [('A', -1), ('E', -1), ('B', -1), ('F', 0), ('C', 0),
+                                      ('G', 0), ('D', 0), ('A', 0), ('E', 0), ('B', 0),
+                                      ('F', 1), ('C', 1), ('G', 1), ('D', 1), ('A', 1)];
+    let index = (fifths(signature) + 7) as usize;
+    match kind {
+        KeyKind::Major => MAJOR[index],
+        KeyKind::Minor => MINOR[index],
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_notation() {
+    let note = |start, end, key| Note {
+        track: 0,
+        channel: 0,
+        key: key,
+        velocity: 64,
+        start: start,
+        end: end,
+        start_seconds: 0.0,
+        duration_seconds: 0.0,
+    };
+    let line = |notes: &[LineNote]| -> Vec<_> {
+        notes.iter().map(|note| (note.start, note.end, note.key)).collect()
+    };
+    let notes = [note(0, 95, 60), note(0, 190, 64), note(97, 200, 67), note(200, 300, 65)];
+    assert_eq!(line(&melody(&notes, 24)), [(0, 96, 64), (96, 192, 67), (192, 312, 65)]);
+    let voices = voices(&notes, 24);
+    assert_eq!((line(&voices[0]), line(&voices[1])),
+               (vec![(0, 192, 64), (192, 312, 65)], vec![(0, 96, 60), (96, 192, 67)]));
+
+    let map = MeterMap::from_changes(96, vec![]);
+    let held = [LineNote { start: 96, end: 480, key: 60 }];
+    let bars = bars(&held, &map, 480);
+    assert_eq!(bars.len(), 2);
+    let items: Vec<_> = bars[0].1.iter().map(|item| (item.end - item.start, item.key, item.tied))
+        .collect();
+    assert_eq!(items, [(96, None, false), (288, Some(60), true)]);
+    assert_eq!(bars[1].1[0], BarItem { start: 384, end: 480, key: Some(60), tied: false });
+
+    assert_eq!(note_values(96, 96), [(4, 0)]);
+    assert_eq!(note_values(96 * 3 / 2, 96), [(4, 1)]);
+    assert_eq!(note_values(96 * 5 / 4, 96), [(4, 0), (16, 0)]);
+    assert_eq!(note_values(96 * 6, 96), [(1, 0), (2, 0)]);
+
+    assert_eq!(spell(61, Key::OfC), Pitch { letter: 'C', alter: 1, octave: 4 });
+    assert_eq!(spell(70, Key::Flats(2)), Pitch { letter: 'B', alter: -1, octave: 4 });
+    assert_eq!(signature_alter(Key::Sharps(2), 'C'), 1);
+    assert_eq!(signature_alter(Key::Sharps(2), 'G'), 0);
+    assert_eq!(signature_alter(Key::Flats(1), 'B'), -1);
+    assert_eq!(tonic(Key::Flats(3), KeyKind::Major), ('E', -1));
+    assert_eq!(tonic(Key::Sharps(1), KeyKind::Minor), ('E', 0));
+}
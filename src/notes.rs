@@ -0,0 +1,287 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::ops::Range;
+
+use {ControlChange, Event, Midi, MidiEvent};
+use tempo::TempoMap;
+
+
+// Piano Roll //////////////////////////////////////////////////////////////////
+
+/// A note with its on and off events paired up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Note {
+    /// The index of the track among the file's tracks.
+    pub track: usize,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+    /// The absolute ticks of the note on and note off.
+    pub start: u64,
+    pub end: u64,
+    pub start_seconds: f64,
+    pub duration_seconds: f64,
+}
+
+/// Settings for how notes are paired up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct NoteOptions {
+    /// Keep notes sounding while the sustain pedal (controller 64) is down,
+    /// so that a note released with the pedal down ends when the pedal is
+    /// released, or when the same key is struck again.
+    pub sustain: bool,
+}
+
+impl<'a> Midi<'a> {
+    /// Every note in the file, sorted by start time and then by track,
+    /// channel and key. When the same key is struck again before it's
+    /// released, each note off ends the earliest note still held, and notes
+    /// that are never released end with their track.
+    pub fn notes(&self) -> Vec<Note> {
+        self.notes_with(&NoteOptions::default())
+    }
+
+    pub fn notes_with(&self, options: &NoteOptions) -> Vec<Note> {
+        let tempo_map = TempoMap::new(self);
+        let mut notes: Vec<Note> = Vec::new();
+        for (index, track) in self.tracks().enumerate() {
+            // Indices into `notes` of the notes still held in this track,
+            // and of the ones released but kept sounding by the pedal.
+            let mut held: Vec<usize> = Vec::new();
+            let mut sustained: Vec<usize> = Vec::new();
+            let mut pedals = [false; 16];
+            for (tick, event) in track.absolute_events() {
+                match *event {
+                    Event::Midi(_, MidiEvent::NoteOn { channel, number, velocity })
+                        if velocity > 0 => {
+                        let found = sustained.iter()
+                            .position(|&i| notes[i].channel == channel && notes[i].key == number);
+                        if let Some(pos) = found {
+                            notes[sustained.remove(pos)].end = tick;
+                        }
+                        held.push(notes.len());
+                        notes.push(Note {
+                            track: index,
+                            channel: channel,
+                            key: number,
+                            velocity: velocity,
+                            start: tick,
+                            end: tick,
+                            start_seconds: 0.0,
+                            duration_seconds: 0.0,
+                        });
+                    }
+                    Event::Midi(_, MidiEvent::NoteOn { channel, number, .. }) |
+                    Event::Midi(_, MidiEvent::NoteOff { channel, number, .. }) => {
+                        let found = held.iter()
+                            .position(|&i| notes[i].channel == channel && notes[i].key == number);
+                        if let Some(pos) = found {
+                            let note = held.remove(pos);
+                            notes[note].end = tick;
+                            if options.sustain && pedals[channel as usize & 0x0F] {
+                                sustained.push(note);
+                            }
+                        }
+                    }
+                    Event::Midi(_, MidiEvent::Control {
+                        channel,
+                        change: ControlChange::Raw(64, value),
+                    }) => {
+                        let down = value >= 64;
+                        pedals[channel as usize & 0x0F] = down;
+                        if !down {
+                            sustained.retain(|&i| {
+                                if notes[i].channel != channel {
+                                    return true;
+                                }
+                                notes[i].end = tick;
+                                false
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            let duration = track.duration();
+            for i in held.into_iter().chain(sustained) {
+                notes[i].end = duration;
+            }
+        }
+        for note in &mut notes {
+            note.start_seconds = tempo_map.seconds_at(note.start);
+            note.duration_seconds = tempo_map.seconds_at(note.end) - note.start_seconds;
+        }
+        notes.sort_by(|a, b| {
+            (a.start, a.track, a.channel, a.key).cmp(&(b.start, b.track, b.channel, b.key))
+        });
+        notes
+    }
+}
+
+
+// Note Index //////////////////////////////////////////////////////////////////
+
+/// Notes arranged for finding the ones that sound at a tick or over a range
+/// of ticks in logarithmic time, rather than by looking at every note. A
+/// note sounds from its start up to but not including its end.
+///
+/// The notes are kept sorted by start, and the sorted list is read as a
+/// balanced binary tree with the middle note at the root, where each node
+/// knows the latest end of any note under it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteIndex {
+    notes: Vec<Note>,
+    /// For the node at each index, the latest end in its subtree.
+    max_end: Vec<u64>,
+}
+
+impl<'a> Midi<'a> {
+    pub fn note_index(&self) -> NoteIndex {
+        NoteIndex::new(self.notes())
+    }
+}
+
+impl NoteIndex {
+    pub fn new(mut notes: Vec<Note>) -> NoteIndex {
+        notes.sort_by_key(|note| note.start);
+        let mut max_end = vec![0; notes.len()];
+        fill_max_end(&notes, &mut max_end, 0, notes.len());
+        NoteIndex {
+            notes: notes,
+            max_end: max_end,
+        }
+    }
+
+    /// Every note, sorted by start.
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// The notes sounding at a tick, sorted by start.
+    pub fn at(&self, tick: u64) -> Vec<&Note> {
+        self.in_range(tick..tick.saturating_add(1))
+    }
+
+    /// The notes sounding at any point in a range of ticks, sorted by start.
+    pub fn in_range(&self, range: Range<u64>) -> Vec<&Note> {
+        let mut found = Vec::new();
+        if range.start < range.end {
+            self.search(&range, 0, self.notes.len(), &mut found);
+        }
+        found
+    }
+
+    fn search<'i>(&'i self, range: &Range<u64>, lo: usize, hi: usize, found: &mut Vec<&'i Note>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        // Everything under this node has ended by the start of the range.
+        if self.max_end[mid] <= range.start {
+            return;
+        }
+        self.search(range, lo, mid, found);
+        let note = &self.notes[mid];
+        // Everything after this node starts after the range.
+        if note.start >= range.end {
+            return;
+        }
+        if note.end > range.start {
+            found.push(note);
+        }
+        self.search(range, mid + 1, hi, found);
+    }
+}
+
+fn fill_max_end(notes: &[Note], max_end: &mut [u64], lo: usize, hi: usize) -> u64 {
+    if lo >= hi {
+        return 0;
+    }
+    let mid = lo + (hi - lo) / 2;
+    let left = fill_max_end(notes, max_end, lo, mid);
+    let right = fill_max_end(notes, max_end, mid + 1, hi);
+    max_end[mid] = cmp::max(notes[mid].end, cmp::max(left, right));
+    max_end[mid]
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_notes() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1B,
+        0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x90, 0x40, 0x50,
+        0x60, 0x90, 0x3C, 0x00,
+        0x00, 0x91, 0x3C, 0x20,
+        0x60, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let notes = midi.notes();
+    assert_eq!(notes.len(), 3);
+    assert_eq!((notes[0].key, notes[0].start, notes[0].end), (0x3C, 0, 0xC0));
+    assert_eq!(notes[0].duration_seconds, 2.0);
+    assert_eq!((notes[1].key, notes[1].velocity), (0x40, 0x50));
+    assert_eq!(notes[1].start_seconds, 1.0);
+    // The last two notes are never released.
+    assert_eq!((notes[1].end, notes[2].end), (0x120, 0x120));
+    assert_eq!(notes[2].channel, 1);
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1C,
+        0x00, 0xB0, 0x40, 0x7F,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0x90, 0x3E, 0x40,
+        0x10, 0x80, 0x3C, 0x00,
+        0x10, 0x80, 0x3E, 0x00,
+        0x10, 0xB0, 0x40, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let ends: Vec<_> = midi.notes().iter().map(|note| note.end).collect();
+    assert_eq!(ends, [0x10, 0x20]);
+    let ends: Vec<_> = midi.notes_with(&NoteOptions { sustain: true })
+        .iter()
+        .map(|note| note.end)
+        .collect();
+    assert_eq!(ends, [0x30, 0x30]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_note_index() {
+    let note = |key, start, end| Note {
+        track: 0,
+        channel: 0,
+        key: key,
+        velocity: 64,
+        start: start,
+        end: end,
+        start_seconds: 0.0,
+        duration_seconds: 0.0,
+    };
+    let notes: Vec<_> = (0..100).map(|i| note(i as u8, i * 10, i * 10 + 25)).collect();
+    let mut shuffled = notes.clone();
+    shuffled.reverse();
+    shuffled.push(note(127, 0, 2000));
+    let index = NoteIndex::new(shuffled);
+    let keys = |found: Vec<&Note>| found.iter().map(|note| note.key).collect::<Vec<_>>();
+    assert_eq!(keys(index.at(500)), [127, 48, 49, 50]);
+    assert_eq!(keys(index.at(25)), [127, 1, 2]);
+    assert!(index.at(u64::max_value()).is_empty());
+    assert_eq!(keys(index.in_range(980..1500)), [127, 96, 97, 98, 99]);
+    assert_eq!(keys(index.in_range(2000..3000)), []);
+    assert_eq!(index.in_range(10..10), Vec::<&Note>::new());
+    for tick in 0..1100 {
+        let expected = index.notes().iter().filter(|n| n.start <= tick && tick < n.end).count();
+        assert_eq!(index.at(tick).len(), expected);
+    }
+}
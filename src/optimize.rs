@@ -0,0 +1,360 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::slice;
+
+use {Chunk, Event, Key, KeyKind, MetaEvent, Midi, MidiEvent};
+use write::write_var_length;
+
+
+// File Optimization ///////////////////////////////////////////////////////////
+
+/// The smallest encoding `optimize` found for a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Optimized {
+    /// A plain Standard MIDI File, even if the file came in an RMID
+    /// container.
+    pub bytes: Vec<u8>,
+    pub report: OptimizeReport,
+}
+
+/// What `optimize` did to a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct OptimizeReport {
+    /// The size of the file as `to_bytes` writes it, without its container.
+    /// The file it was parsed from may have been bigger still.
+    pub before: usize,
+    pub after: usize,
+    /// Events that changed nothing, like a program change to the program
+    /// the channel already has.
+    pub events_removed: usize,
+    pub tracks_removed: usize,
+    /// Sysex packets folded into the message they continue.
+    pub sysex_merged: usize,
+}
+
+impl OptimizeReport {
+    pub fn saved(&self) -> usize {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// Writes the file as small as it will go without changing how it
+    /// plays. Redundant events and empty tracks are dropped, running status
+    /// is used wherever it can be, with note offs sent as note ons of
+    /// velocity zero when their velocity is the standard 64, and sysex
+    /// messages split into packets with no time between them are sent
+    /// whole.
+    pub fn optimize(&self) -> Optimized {
+        let mut report = OptimizeReport::default();
+        let mut tracks: Vec<Vec<(u64, Event<'a>)>> = self.tracks()
+            .map(|track| {
+                track.absolute_events().map(|(tick, event)| (tick, event.clone())).collect()
+            })
+            .collect();
+        report.events_removed = remove_redundant(&mut tracks, self.header.format == 2);
+
+        // A track with only an end of track still matters if it's what makes
+        // the file as long as it is.
+        let ends: Vec<u64> = tracks.iter().map(|track| track.last().map_or(0, |e| e.0)).collect();
+        let mut keep: Vec<bool> = tracks.iter().map(|track| track.len() > 1).collect();
+        let end = (0..tracks.len()).filter(|&i| keep[i]).map(|i| ends[i]).max();
+        let longest = (0..tracks.len()).rev().max_by_key(|&i| ends[i]);
+        if let Some(longest) = longest {
+            if end.map_or(true, |end| ends[longest] > end) {
+                keep[longest] = true;
+            }
+        }
+        report.tracks_removed = keep.iter().filter(|&&kept| !kept).count();
+
+        let mut tracks = tracks.into_iter().zip(keep);
+        let mut out = b"MThd\0\0\0\x06".to_vec();
+        write_u16(&mut out, self.header.format);
+        write_u16(&mut out, (self.tracks().count() - report.tracks_removed) as u16);
+        write_u16(&mut out, self.header.division);
+        let mut before = out.len();
+        for chunk in &self.chunks {
+            let mut original = Vec::new();
+            chunk.write_to(&mut original);
+            before += original.len();
+            match *chunk {
+                Chunk::Track(_) => {
+                    let (events, kept) = tracks.next().unwrap();
+                    if kept {
+                        let body = write_track(&events, &mut report.sysex_merged);
+                        out.extend_from_slice(b"MTrk");
+                        write_u32(&mut out, body.len() as u32);
+                        out.extend_from_slice(&body);
+                    }
+                }
+                Chunk::Other { .. } => out.extend_from_slice(&original),
+            }
+        }
+        report.before = before;
+        report.after = out.len();
+        Optimized {
+            bytes: out,
+            report: report,
+        }
+    }
+}
+
+/// The state of the file that an event sets, so that an event setting it
+/// to what it already is can go.
+struct State {
+    tempo: Option<u32>,
+    time_signature: Option<(u8, u8, u8, u8)>,
+    key_signature: Option<(Key, KeyKind)>,
+    programs: [Option<u8>; 16],
+    controllers: Vec<[Option<u8>; 128]>,
+    bends: [Option<u16>; 16],
+    pressures: [Option<u8>; 16],
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            tempo: None,
+            time_signature: None,
+            key_signature: None,
+            programs: [None; 16],
+            controllers: vec![[None; 128]; 16],
+            bends: [None; 16],
+            pressures: [None; 16],
+        }
+    }
+
+    /// Applies an event, returning whether it changed anything.
+    fn apply(&mut self, event: &Event) -> bool {
+        fn set<T: PartialEq>(slot: &mut Option<T>, value: T) -> bool {
+            let changed = slot.as_ref() != Some(&value);
+            *slot = Some(value);
+            changed
+        }
+        match *event {
+            Event::Meta(_, MetaEvent::SetTempo(tempo)) => set(&mut self.tempo, tempo),
+            Event::Meta(_, MetaEvent::TimeSignature { numerator, denominator,
+                                                      clocks_per_metronome,
+                                                      notated_divisions }) => {
+                set(&mut self.time_signature,
+                    (numerator, denominator, clocks_per_metronome, notated_divisions))
+            }
+            Event::Meta(_, MetaEvent::KeySignature { key, kind }) => {
+                set(&mut self.key_signature, (key, kind))
+            }
+            // A sysex message could reset anything.
+            Event::Sysex(..) => {
+                let (tempo, time, key) = (self.tempo, self.time_signature, self.key_signature);
+                *self = State::new();
+                self.tempo = tempo;
+                self.time_signature = time;
+                self.key_signature = key;
+                true
+            }
+            Event::Midi(_, MidiEvent::ProgramChange { channel, program_number }) => {
+                set(&mut self.programs[channel as usize & 0x0F], program_number)
+            }
+            Event::Midi(_, MidiEvent::PitchBend { channel, pitch }) => {
+                set(&mut self.bends[channel as usize & 0x0F], pitch)
+            }
+            Event::Midi(_, MidiEvent::ChannelAftertouch { channel, pressure }) => {
+                set(&mut self.pressures[channel as usize & 0x0F], pressure)
+            }
+            Event::Midi(_, MidiEvent::Control { channel, change }) => {
+                let channel = channel as usize & 0x0F;
+                match change.parts() {
+                    (121, _) => {
+                        self.controllers[channel] = [None; 128];
+                        self.bends[channel] = None;
+                        self.pressures[channel] = None;
+                        true
+                    }
+                    // Data entry and parameter numbers act each time they're
+                    // sent, and so do the channel mode messages.
+                    (6, _) | (38, _) | (96...101, _) | (120...127, _) => true,
+                    (controller, value) => {
+                        let slot = &mut self.controllers[channel][controller as usize & 0x7F];
+                        let changed = set(slot, value);
+                        // A program change after a new bank selects a new patch.
+                        if changed && (controller == 0 || controller == 32) {
+                            self.programs[channel] = None;
+                        }
+                        changed
+                    }
+                }
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Drops events that set the file's state to what it already is, following
+/// every track together in order of time and then of track. When the tracks
+/// are `independent`, as in a format 2 file, each one starts from nothing.
+fn remove_redundant(tracks: &mut [Vec<(u64, Event)>], independent: bool) -> usize {
+    if independent {
+        return tracks.iter_mut()
+            .map(|track| remove_redundant(slice::from_mut(track), false))
+            .sum();
+    }
+    let mut order: Vec<(u64, usize, usize)> = Vec::new();
+    for (track, events) in tracks.iter().enumerate() {
+        order.extend(events.iter().enumerate().map(|(index, &(tick, _))| (tick, track, index)));
+    }
+    order.sort();
+    let mut state = State::new();
+    let mut redundant: Vec<Vec<bool>> = tracks.iter().map(|events| vec![false; events.len()])
+        .collect();
+    for (_, track, index) in order {
+        redundant[track][index] = !state.apply(&tracks[track][index].1);
+    }
+    let mut removed = 0;
+    for (events, redundant) in tracks.iter_mut().zip(redundant) {
+        removed += redundant.iter().filter(|&&redundant| redundant).count();
+        let mut flags = redundant.into_iter();
+        events.retain(|_| !flags.next().unwrap());
+    }
+    removed
+}
+
+/// Writes a track's events, at absolute times, with running status.
+fn write_track(events: &[(u64, Event)], sysex_merged: &mut usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut running_status = None;
+    let mut time = 0;
+    let mut i = 0;
+    while i < events.len() {
+        let (tick, ref event) = events[i];
+        write_var_length(&mut out, (tick - time) as u32);
+        time = tick;
+        i += 1;
+        match *event {
+            Event::Midi(_, ref midi) => {
+                let mut bytes = Vec::with_capacity(3);
+                match *midi {
+                    MidiEvent::NoteOff { channel, number, velocity: 64 }
+                        if running_status == Some(0x90 | channel & 0x0F) => {
+                        bytes.extend_from_slice(&[0x90 | channel & 0x0F, number, 0]);
+                    }
+                    _ => midi.write_to(&mut bytes),
+                }
+                if let MidiEvent::Previous(..) = *midi {
+                    out.extend_from_slice(&bytes);
+                    continue;
+                }
+                if running_status == Some(bytes[0]) {
+                    out.extend_from_slice(&bytes[1..]);
+                } else {
+                    out.extend_from_slice(&bytes);
+                }
+                running_status = Some(bytes[0]);
+            }
+            Event::Sysex(_, ref sysex) => {
+                running_status = None;
+                let mut data = sysex.data.to_vec();
+                if sysex.start {
+                    while !ends_sysex(&events[i - 1].1) && i < events.len() &&
+                          events[i].0 == tick {
+                        match events[i].1 {
+                            Event::Sysex(_, ref next) if !next.start => {
+                                data.extend_from_slice(next.data);
+                                *sysex_merged += 1;
+                                i += 1;
+                            }
+                            _ => break,
+                        }
+                    }
+                }
+                out.push(if sysex.start { 0xF0 } else { 0xF7 });
+                write_var_length(&mut out, data.len() as u32);
+                out.extend_from_slice(&data);
+            }
+            Event::Meta(_, ref meta) => {
+                running_status = None;
+                meta.write_to(&mut out);
+            }
+        }
+    }
+    out
+}
+
+fn ends_sysex(event: &Event) -> bool {
+    match *event {
+        Event::Sysex(_, ref sysex) => sysex.end,
+        _ => false,
+    }
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+                            value as u8]);
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_optimize() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x03, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x12,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x25,
+        0x00, 0xC0, 0x05,
+        0x00, 0xC0, 0x05,
+        0x00, 0xF0, 0x03, 0x43, 0x10, 0x4C,
+        0x00, 0xF7, 0x02, 0x00, 0xF7,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x00, 0x90, 0x3E, 0x40,
+        0x60, 0x80, 0x3E, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let optimized = midi.optimize();
+    assert_eq!(optimized.report, OptimizeReport {
+        before: data.len(),
+        after: 69,
+        events_removed: 2,
+        tracks_removed: 1,
+        sysex_merged: 1,
+    });
+    assert_eq!(optimized.report.saved(), 28);
+    assert_eq!(optimized.bytes.len(), 69);
+    let smaller = ::parse_midi(&optimized.bytes).unwrap();
+    assert_eq!(smaller.header().tracks(), 2);
+    assert_eq!(smaller.notes(), midi.notes());
+    assert_eq!(smaller.tempo_map(), midi.tempo_map());
+    let sysex: Vec<_> = smaller.tracks().nth(1).unwrap().events().iter()
+        .filter_map(|event| match *event {
+            Event::Sysex(_, ref sysex) => Some((sysex.start, sysex.end, sysex.data)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(sysex, [(true, true, &[0x43, 0x10, 0x4C, 0x00, 0xF7][..])]);
+
+    // Each pattern of a format 2 file sets up its own program.
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x02, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0xC0, 0x05,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x07,
+        0x00, 0xC0, 0x05,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let optimized = ::parse_midi(&data).unwrap().optimize();
+    assert_eq!(optimized.report.events_removed, 0);
+    assert_eq!(optimized.bytes, &data[..]);
+}
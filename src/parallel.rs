@@ -0,0 +1,57 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use nom::Err;
+use rayon::prelude::*;
+
+use {locate_error, track_body, Chunk, ErrorKind, Midi, ParseError, ParseOptions};
+use lazy::scan_midi;
+
+
+// Parallel Track Parsing //////////////////////////////////////////////////////
+
+/// Parses a Standard MIDI File like `parse_midi`, but parses the tracks on
+/// rayon's thread pool once the chunk boundaries are known. This only pays
+/// off for files with many large tracks.
+pub fn parse_midi_parallel(input: &[u8]) -> Result<Midi, ParseError> {
+    let lazy = scan_midi(input).map_err(|e| locate_error(input, e, &ParseOptions::default()))?;
+    let chunks = lazy.chunks()
+        .par_iter()
+        .map(|chunk| {
+            if !chunk.is_track() {
+                return Ok(Chunk::Other {
+                    kind: chunk.kind,
+                    data: chunk.data,
+                });
+            }
+            match track_body(chunk.data) {
+                Ok((_, track)) => Ok(Chunk::Track(track)),
+                Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(e),
+                Err(Err::Incomplete(_)) => Err(ErrorKind::Eof),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| locate_error(input, e, &ParseOptions::default()))?;
+    Ok(Midi {
+        header: lazy.header().clone(),
+        chunks: chunks,
+        rmid: None,
+    })
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_parse_parallel() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    assert_eq!(parse_midi_parallel(&data), ::parse_midi(&data));
+}
@@ -0,0 +1,138 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {ControlChange, Event, Midi, MidiEvent};
+
+
+// Patch Tracking //////////////////////////////////////////////////////////////
+
+/// The sound a channel is playing, as chosen by bank select and program
+/// change messages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub bank_msb: u8,
+    pub bank_lsb: u8,
+    pub program: u8,
+}
+
+/// Follows the bank selects and program changes on every channel. A bank
+/// select only takes effect with the next program change on its channel.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PatchTracker {
+    /// The bank selected on each channel, as (MSB, LSB).
+    banks: [(u8, u8); 16],
+    patches: [Option<Patch>; 16],
+}
+
+impl PatchTracker {
+    pub fn new() -> PatchTracker {
+        PatchTracker::default()
+    }
+
+    /// Updates the state of the channel the event is on, returning the new
+    /// patch if the event was a program change.
+    pub fn update(&mut self, event: &MidiEvent) -> Option<Patch> {
+        match *event {
+            MidiEvent::Control { channel, change: ControlChange::Raw(0, value) } => {
+                self.banks[channel as usize & 0x0F].0 = value;
+            }
+            MidiEvent::Control { channel, change: ControlChange::Raw(32, value) } => {
+                self.banks[channel as usize & 0x0F].1 = value;
+            }
+            MidiEvent::ProgramChange { channel, program_number } => {
+                let (msb, lsb) = self.banks[channel as usize & 0x0F];
+                let patch = Patch {
+                    bank_msb: msb,
+                    bank_lsb: lsb,
+                    program: program_number,
+                };
+                self.patches[channel as usize & 0x0F] = Some(patch);
+                return Some(patch);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// The patch on a channel, or `None` before its first program change.
+    pub fn patch(&self, channel: u8) -> Option<Patch> {
+        self.patches[channel as usize & 0x0F]
+    }
+}
+
+/// The patch changes on each channel of a file, sorted by tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchMap {
+    channels: Vec<Vec<(u64, Patch)>>,
+}
+
+impl<'a> Midi<'a> {
+    pub fn patch_map(&self) -> PatchMap {
+        PatchMap::new(self)
+    }
+}
+
+impl PatchMap {
+    /// Follows every track of the file together, so a bank select in one
+    /// track applies to a later program change in another.
+    pub fn new(midi: &Midi) -> PatchMap {
+        let mut events = Vec::new();
+        for track in midi.tracks() {
+            events.extend(track.absolute_events().filter_map(|(tick, event)| match *event {
+                Event::Midi(_, ref event) => Some((tick, event)),
+                _ => None,
+            }));
+        }
+        // The sort is stable, so events on the same tick stay in track order.
+        events.sort_by_key(|&(tick, _)| tick);
+        let mut tracker = PatchTracker::new();
+        let mut channels = vec![Vec::new(); 16];
+        for (tick, event) in events {
+            if let Some(patch) = tracker.update(event) {
+                channels[event.channel().unwrap_or(0) as usize & 0x0F].push((tick, patch));
+            }
+        }
+        PatchMap { channels: channels }
+    }
+
+    pub fn changes(&self, channel: u8) -> &[(u64, Patch)] {
+        &self.channels[channel as usize & 0x0F]
+    }
+
+    /// The patch playing on a channel at a tick, including any change on
+    /// that tick, or `None` if the channel hasn't had a program change yet.
+    pub fn patch_at(&self, channel: u8, tick: u64) -> Option<Patch> {
+        self.changes(channel)
+            .iter()
+            .take_while(|&&(start, _)| start <= tick)
+            .last()
+            .map(|&(_, patch)| patch)
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_patch_map() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0xB1, 0x00, 0x79,
+        0x00, 0xB1, 0x20, 0x01,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0E,
+        0x10, 0xC1, 0x05,
+        0x10, 0xB1, 0x00, 0x00,
+        0x10, 0xC1, 0x06,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let map = ::parse_midi(&data).unwrap().patch_map();
+    assert_eq!(map.patch_at(1, 0x0F), None);
+    assert_eq!(map.patch_at(1, 0x10), Some(Patch { bank_msb: 0x79, bank_lsb: 1, program: 5 }));
+    // The bank select alone doesn't change the patch.
+    assert_eq!(map.patch_at(1, 0x2F).map(|patch| patch.bank_msb), Some(0x79));
+    assert_eq!(map.patch_at(1, 0x30), Some(Patch { bank_msb: 0, bank_lsb: 1, program: 6 }));
+    assert!(map.changes(0).is_empty());
+}
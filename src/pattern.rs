@@ -0,0 +1,161 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Chunk, Division, Event, Header, MetaEvent, Midi, TextType, TrackChunk, Tracks};
+use meter::MeterMap;
+use tempo::TempoMap;
+
+
+// Format 2 Patterns ///////////////////////////////////////////////////////////
+
+/// A track viewed as an independent sequence, the way format 2 files treat
+/// them. Each pattern starts at tick 0 with the default tempo and meter, and
+/// only its own meta events apply to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pattern<'m, 'a: 'm> {
+    index: usize,
+    division: Division,
+    track: &'m TrackChunk<'a>,
+}
+
+impl<'a> Midi<'a> {
+    /// Iterates over the tracks as independent patterns. This is only the
+    /// intended reading of format 2 files, since in the other formats the
+    /// tracks play together.
+    pub fn patterns<'m>(&'m self) -> Patterns<'m, 'a> {
+        Patterns {
+            index: 0,
+            division: self.header().division(),
+            tracks: self.tracks(),
+        }
+    }
+
+    /// Whether the tracks are independent patterns rather than parts of one
+    /// sequence.
+    pub fn is_patterns(&self) -> bool {
+        self.header().format() == 2
+    }
+}
+
+impl<'m, 'a> Pattern<'m, 'a> {
+    /// The pattern's position among the tracks.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn track(&self) -> &'m TrackChunk<'a> {
+        self.track
+    }
+
+    /// The number songs use to refer to the pattern, which defaults to its
+    /// position in the file when there's no SequenceNumber event.
+    pub fn sequence_number(&self) -> u16 {
+        self.track.events().iter()
+            .filter_map(|event| match *event {
+                Event::Meta(_, MetaEvent::SequenceNumber(n)) => Some(n),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(self.index as u16)
+    }
+
+    pub fn name(&self) -> Option<&'a [u8]> {
+        self.track.events().iter()
+            .filter_map(|event| match *event {
+                Event::Meta(_, MetaEvent::Text { kind: TextType::TrackName, text }) => Some(text),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The tempo map of this pattern alone.
+    pub fn tempo_map(&self) -> TempoMap {
+        let changes = self.track.absolute_events()
+            .filter_map(|(tick, event)| match *event {
+                Event::Meta(_, MetaEvent::SetTempo(tempo)) => Some((tick, tempo)),
+                _ => None,
+            })
+            .collect();
+        TempoMap::from_changes(self.division, changes)
+    }
+
+    /// The time signatures of this pattern alone, or `None` for SMPTE time.
+    pub fn meter_map(&self) -> Option<MeterMap> {
+        match self.division {
+            Division::TicksPerQuarter(n) => {
+                Some(MeterMap::from_changes(n, self.track.time_signatures()))
+            }
+            Division::Smpte { .. } => None,
+        }
+    }
+
+    /// Copies the pattern out into a standalone format 0 file with the same
+    /// division.
+    pub fn to_midi(&self) -> Midi<'a> {
+        let division = match self.division {
+            Division::TicksPerQuarter(n) => n,
+            Division::Smpte { frames_per_second, ticks_per_frame } => {
+                (frames_per_second.wrapping_neg() as u16) << 8 | ticks_per_frame as u16
+            }
+        };
+        Midi {
+            header: Header {
+                len: 6,
+                format: 0,
+                tracks: 1,
+                division: division,
+            },
+            chunks: vec![Chunk::Track(self.track.clone())],
+            rmid: None,
+        }
+    }
+}
+
+pub struct Patterns<'m, 'a: 'm> {
+    index: usize,
+    division: Division,
+    tracks: Tracks<'m, 'a>,
+}
+
+impl<'m, 'a> Iterator for Patterns<'m, 'a> {
+    type Item = Pattern<'m, 'a>;
+
+    fn next(&mut self) -> Option<Pattern<'m, 'a>> {
+        self.tracks.next().map(|track| {
+            self.index += 1;
+            Pattern {
+                index: self.index - 1,
+                division: self.division,
+                track: track,
+            }
+        })
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_patterns() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x02, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0A,
+        0x00, 0xFF, 0x00, 0x02, 0x00, 0x07,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    assert!(midi.is_patterns());
+    let patterns: Vec<_> = midi.patterns().collect();
+    assert_eq!(patterns[0].tempo_map().tempo_at(0), 1_000_000);
+    assert_eq!(patterns[0].sequence_number(), 0);
+    assert_eq!(patterns[1].tempo_map().tempo_at(0), ::tempo::DEFAULT_TEMPO);
+    assert_eq!(patterns[1].sequence_number(), 7);
+
+    let single = patterns[1].to_midi();
+    assert_eq!(single.header().format(), 0);
+    assert_eq!(single.tracks().next(), Some(patterns[1].track()));
+}
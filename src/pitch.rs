@@ -0,0 +1,158 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use bend::semitones;
+
+
+// Note Names //////////////////////////////////////////////////////////////////
+
+/// Which octave number middle C (key 60) gets. Both are in wide use.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Octaves {
+    /// Middle C is C4, as in scientific pitch notation.
+    MiddleC4,
+    /// Middle C is C3, as Yamaha and many sequencers number it.
+    MiddleC3,
+}
+
+/// How the black keys are spelled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Accidentals {
+    Sharps,
+    Flats,
+}
+
+/// A convention for turning key numbers into note names like C#4 and back.
+/// The default is scientific pitch with sharps, so key 61 is C#4. The text
+/// format, the explained dump and midi-dump can each be given one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NoteNames {
+    pub octaves: Octaves,
+    pub accidentals: Accidentals,
+}
+
+impl Default for NoteNames {
+    fn default() -> NoteNames {
+        NoteNames {
+            octaves: Octaves::MiddleC4,
+            accidentals: Accidentals::Sharps,
+        }
+    }
+}
+
+static SHARP_NAMES: [&'static str; 12] =
+    ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+static FLAT_NAMES: [&'static str; 12] =
+    ["C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B"];
+
+impl NoteNames {
+    /// The octave number of key 0.
+    fn lowest_octave(&self) -> i32 {
+        match self.octaves {
+            Octaves::MiddleC4 => -1,
+            Octaves::MiddleC3 => -2,
+        }
+    }
+
+    pub fn name(&self, key: u8) -> String {
+        let names = match self.accidentals {
+            Accidentals::Sharps => &SHARP_NAMES,
+            Accidentals::Flats => &FLAT_NAMES,
+        };
+        format!("{}{}", names[key as usize % 12], key as i32 / 12 + self.lowest_octave())
+    }
+
+    /// Reads a note name like C4, F#2 or Bb-1, accepting either accidental
+    /// whatever the style, and ♯ and ♭ as well as # and b.
+    pub fn parse(&self, name: &str) -> Option<u8> {
+        let mut chars = name.chars();
+        let base = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+        let rest = chars.as_str();
+        let (accidental, octave) = match rest.chars().next() {
+            Some(c @ '#') | Some(c @ '♯') => (1, &rest[c.len_utf8()..]),
+            Some(c @ 'b') | Some(c @ '♭') => (-1, &rest[c.len_utf8()..]),
+            _ => (0, rest),
+        };
+        let octave = octave.parse::<i32>().ok()?.checked_sub(self.lowest_octave())?;
+        let key = octave.checked_mul(12)?.checked_add(base + accidental)?;
+        if key >= 0 && key < 0x80 { Some(key as u8) } else { None }
+    }
+}
+
+/// Names a key in scientific pitch with sharps, where 60 is C4.
+pub fn note_name(key: u8) -> String {
+    NoteNames::default().name(key)
+}
+
+/// Reads a note name in scientific pitch, where C4 is 60.
+pub fn parse_note_name(name: &str) -> Option<u8> {
+    NoteNames::default().parse(name)
+}
+
+
+// Frequencies /////////////////////////////////////////////////////////////////
+
+/// Concert pitch, the usual tuning of A4.
+pub const A4_HZ: f64 = 440.0;
+
+/// The frequency in Hz of a key in equal temperament with A4 (key 69) tuned
+/// to `a4_hz`. Keys can be fractional, for notes between the keys.
+pub fn key_to_freq(key: f64, a4_hz: f64) -> f64 {
+    a4_hz * 2f64.powf((key - 69.0) / 12.0)
+}
+
+/// The fractional key that sounds at a frequency, the inverse of
+/// `key_to_freq`.
+pub fn freq_to_key(freq: f64, a4_hz: f64) -> f64 {
+    69.0 + 12.0 * (freq / a4_hz).log2()
+}
+
+/// The fractional key that a key sounds at under a pitch bend, from 0 to
+/// 0x3FFF with 0x2000 in the middle, when a full bend moves it by
+/// `range` semitones. The range is 2 semitones unless changed with RPN 0,
+/// which `BendMap` follows.
+pub fn bent_key(key: u8, pitch: u16, range: f64) -> f64 {
+    key as f64 + semitones(pitch, range)
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_note_names() {
+    assert_eq!(note_name(60), "C4");
+    assert_eq!(note_name(0), "C-1");
+    assert_eq!(note_name(127), "G9");
+    let yamaha = NoteNames {
+        octaves: Octaves::MiddleC3,
+        accidentals: Accidentals::Flats,
+    };
+    assert_eq!(yamaha.name(61), "Db3");
+    assert_eq!(yamaha.parse("C3"), Some(60));
+    assert_eq!(yamaha.parse("C-2"), Some(0));
+    for key in 0..128 {
+        assert_eq!(parse_note_name(&note_name(key)), Some(key));
+        assert_eq!(yamaha.parse(&yamaha.name(key)), Some(key));
+    }
+    assert_eq!(parse_note_name("B♭3"), Some(58));
+    assert_eq!(parse_note_name("Cb-1"), None);
+    assert_eq!(parse_note_name("H2"), None);
+    assert_eq!(parse_note_name("C999999999"), None);
+    assert_eq!(yamaha.parse("C-2147483648"), None);
+
+    assert_eq!(key_to_freq(69.0, A4_HZ), 440.0);
+    assert_eq!(key_to_freq(81.0, 415.0), 830.0);
+    assert!((key_to_freq(60.0, A4_HZ) - 261.6256).abs() < 1e-4);
+    assert!((freq_to_key(261.6256, A4_HZ) - 60.0).abs() < 1e-4);
+    assert_eq!(bent_key(60, 0x3000, 2.0), 61.0);
+    assert_eq!(bent_key(60, 0, 12.0), 48.0);
+}
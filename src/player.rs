@@ -0,0 +1,140 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::time::Duration;
+
+use {Event, Midi};
+use tempo::TempoMap;
+
+
+// Playback Scheduling /////////////////////////////////////////////////////////
+
+/// The events of every track merged into one list and timed with the tempo
+/// map, ready to be played. Playing one back to a device is a loop over
+/// `play`:
+///
+/// ```ignore
+/// for (wait, events) in midi.playback().play() {
+///     thread::sleep(wait);
+///     for event in events {
+///         output.send(event);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playback<'a> {
+    /// Sorted by tick, and by track on the same tick.
+    events: Vec<Event<'a>>,
+    /// Where each run of events on the same tick starts in `events`, and
+    /// its time in nanoseconds from the start of the file.
+    batches: Vec<(usize, u64)>,
+}
+
+impl<'a> Midi<'a> {
+    pub fn playback(&self) -> Playback<'a> {
+        Playback::new(self)
+    }
+}
+
+impl<'a> Playback<'a> {
+    pub fn new(midi: &Midi<'a>) -> Playback<'a> {
+        let mut timed = Vec::new();
+        for track in midi.tracks() {
+            timed.extend(track.absolute_events());
+        }
+        // The sort is stable, so events on the same tick stay in track order.
+        timed.sort_by_key(|&(tick, _)| tick);
+
+        let tempo_map = TempoMap::new(midi);
+        let mut cursor = tempo_map.cursor();
+        let mut events = Vec::with_capacity(timed.len());
+        let mut batches = Vec::new();
+        let mut last = None;
+        for (tick, event) in timed {
+            if last != Some(tick) {
+                let nanos = (cursor.seconds_at(tick) * 1e9).round() as u64;
+                batches.push((events.len(), nanos));
+                last = Some(tick);
+            }
+            events.push(event.clone());
+        }
+        Playback {
+            events: events,
+            batches: batches,
+        }
+    }
+
+    /// Every event, in the order it's played. Each keeps the delta time it
+    /// had in its track.
+    pub fn events(&self) -> &[Event<'a>] {
+        &self.events
+    }
+
+    /// How long the whole file takes, up to its last event.
+    pub fn duration(&self) -> Duration {
+        Duration::from_nanos(self.batches.last().map_or(0, |&(_, nanos)| nanos))
+    }
+
+    /// Each run of events on the same tick, with how long to wait after the
+    /// run before it. The times are rounded from the start of the file, so
+    /// rounding errors don't add up over a long file.
+    pub fn play<'p>(&'p self) -> Player<'p, 'a> {
+        Player {
+            playback: self,
+            batch: 0,
+            time: 0,
+        }
+    }
+}
+
+/// The iterator returned by `Playback::play`.
+#[derive(Debug, Clone)]
+pub struct Player<'p, 'a: 'p> {
+    playback: &'p Playback<'a>,
+    batch: usize,
+    /// The time of the last batch, in nanoseconds.
+    time: u64,
+}
+
+impl<'p, 'a> Iterator for Player<'p, 'a> {
+    type Item = (Duration, &'p [Event<'a>]);
+
+    fn next(&mut self) -> Option<(Duration, &'p [Event<'a>])> {
+        let batches = &self.playback.batches;
+        let (start, time) = *batches.get(self.batch)?;
+        let end = batches.get(self.batch + 1).map_or(self.playback.events.len(), |&(end, _)| end);
+        let wait = Duration::from_nanos(time - self.time);
+        self.batch += 1;
+        self.time = time;
+        Some((wait, &self.playback.events[start..end]))
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_playback() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x10,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x80, 0x3C, 0x40,
+        0x60, 0x90, 0x3E, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let playback = ::parse_midi(&data).unwrap().playback();
+    let batches: Vec<_> = playback.play().map(|(wait, events)| (wait, events.len())).collect();
+    // Half a second at the default tempo, then a second after the change.
+    assert_eq!(batches, [
+        (Duration::from_secs(0), 1),
+        (Duration::from_millis(500), 3),
+        (Duration::from_secs(1), 2),
+    ]);
+    assert_eq!(playback.duration(), Duration::from_millis(1500));
+    assert_eq!(playback.events().len(), 6);
+}
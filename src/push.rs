@@ -0,0 +1,242 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::mem;
+
+use nom::Err;
+
+use {event, header, var_length, ErrorKind, Event, Header};
+
+
+// Push Parsing ////////////////////////////////////////////////////////////////
+
+/// A piece of a file handed back by `PushParser::feed`. Items own their
+/// data, since the parser's buffer moves on as more bytes are fed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParsedItem {
+    Header(Header),
+    TrackStart {
+        len: u32,
+    },
+    Event(RawEvent),
+    TrackEnd,
+    /// A whole chunk that isn't a track.
+    Chunk {
+        kind: [u8; 4],
+        data: Vec<u8>,
+    },
+    /// The file is malformed. Nothing fed after this is parsed.
+    Error(ErrorKind),
+}
+
+/// The bytes of one event of a track, from its delta time on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEvent {
+    bytes: Vec<u8>,
+    /// The running status going into the event, which it needs if it
+    /// leaves out its status byte.
+    running_status: Option<u8>,
+}
+
+impl RawEvent {
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn event(&self) -> Event {
+        let mut running_status = self.running_status;
+        match event(&self.bytes, &mut running_status) {
+            Ok((_, event)) => event,
+            // Only events that parsed are kept.
+            _ => panic!("a raw event no longer parses"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Header,
+    Chunks,
+    Track {
+        left: usize,
+    },
+    Failed(ErrorKind),
+}
+
+/// Parses a Standard MIDI File from bytes pushed in as they arrive, say
+/// from a socket or a pipe, in pieces of any size. Whenever the parsers
+/// come back `Incomplete`, the bytes are kept until more are fed, and
+/// everything that's complete is handed back right away.
+#[derive(Debug, Clone)]
+pub struct PushParser {
+    buffer: Vec<u8>,
+    state: State,
+    running_status: Option<u8>,
+}
+
+impl Default for PushParser {
+    fn default() -> PushParser {
+        PushParser::new()
+    }
+}
+
+impl PushParser {
+    pub fn new() -> PushParser {
+        PushParser {
+            buffer: Vec::new(),
+            state: State::Header,
+            running_status: None,
+        }
+    }
+
+    /// Feeds the next bytes of the file, returning every item they
+    /// completed.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<ParsedItem> {
+        if let State::Failed(_) = self.state {
+            return Vec::new();
+        }
+        let mut buffer = mem::replace(&mut self.buffer, Vec::new());
+        buffer.extend_from_slice(bytes);
+        let mut items = Vec::new();
+        let mut pos = 0;
+        loop {
+            match self.next(&buffer[pos..]) {
+                Ok(Some((len, item))) => {
+                    pos += len;
+                    items.push(item);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    self.state = State::Failed(e.clone());
+                    items.push(ParsedItem::Error(e));
+                    break;
+                }
+            }
+        }
+        buffer.drain(..pos);
+        self.buffer = buffer;
+        items
+    }
+
+    /// Checks that the file ended between chunks, once there's nothing
+    /// left to feed.
+    pub fn finish(&self) -> Result<(), ErrorKind> {
+        match self.state {
+            State::Chunks if self.buffer.is_empty() => Ok(()),
+            State::Failed(ref e) => Err(e.clone()),
+            _ => Err(ErrorKind::Eof),
+        }
+    }
+
+    /// Parses the next item at the start of `input`, returning how many
+    /// bytes it took, or `None` if more are needed.
+    fn next(&mut self, input: &[u8]) -> Result<Option<(usize, ParsedItem)>, ErrorKind> {
+        match self.state {
+            State::Header => match header(input) {
+                Ok((rest, header)) => {
+                    self.state = State::Chunks;
+                    Ok(Some((input.len() - rest.len(), ParsedItem::Header(header))))
+                }
+                Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(e),
+                Err(Err::Incomplete(_)) => Ok(None),
+            },
+            State::Chunks => {
+                if input.len() < 8 {
+                    return Ok(None);
+                }
+                let kind = [input[0], input[1], input[2], input[3]];
+                let len = (input[4] as u32) << 24 | (input[5] as u32) << 16 |
+                          (input[6] as u32) << 8 | input[7] as u32;
+                if &kind == b"MTrk" {
+                    self.state = State::Track { left: len as usize };
+                    self.running_status = None;
+                    Ok(Some((8, ParsedItem::TrackStart { len: len })))
+                } else if input.len() - 8 < len as usize {
+                    Ok(None)
+                } else {
+                    let data = input[8..8 + len as usize].to_vec();
+                    Ok(Some((8 + len as usize, ParsedItem::Chunk {
+                        kind: kind,
+                        data: data,
+                    })))
+                }
+            }
+            State::Track { left: 0 } => {
+                self.state = State::Chunks;
+                Ok(Some((0, ParsedItem::TrackEnd)))
+            }
+            State::Track { left } => {
+                let data = &input[..cmp::min(left, input.len())];
+                let mut running_status = self.running_status;
+                // The event parser treats a cut off delta time as an error,
+                // so that's checked for first.
+                let result = var_length(data).and_then(|_| event(data, &mut running_status));
+                match result {
+                    Ok((rest, _)) => {
+                        let len = data.len() - rest.len();
+                        let raw = RawEvent {
+                            bytes: data[..len].to_vec(),
+                            running_status: self.running_status,
+                        };
+                        self.running_status = running_status;
+                        self.state = State::Track { left: left - len };
+                        Ok(Some((len, ParsedItem::Event(raw))))
+                    }
+                    Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(e),
+                    Err(Err::Incomplete(_)) if data.len() < left => Ok(None),
+                    // The event runs past the end of the track.
+                    Err(Err::Incomplete(_)) => Err(ErrorKind::Eof),
+                }
+            }
+            State::Failed(_) => Ok(None),
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_push_parser() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x14,
+        0x00, 0xFF, 0x03, 0x04, b'L', b'e', b'a', b'd',
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x40, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x58, 0x41, 0x42, 0x43, 0x00, 0x00, 0x00, 0x01, 0x07,
+    ];
+    let mut parser = PushParser::new();
+    let mut items = Vec::new();
+    for byte in data.chunks(1) {
+        items.extend(parser.feed(byte));
+    }
+    assert!(parser.finish().is_ok());
+    assert_eq!(items.len(), 8);
+    assert_eq!(items[1], ParsedItem::TrackStart { len: 0x14 });
+    let events: Vec<_> = items.iter()
+        .filter_map(|item| match *item {
+            ParsedItem::Event(ref raw) => Some(raw.event()),
+            _ => None,
+        })
+        .collect();
+    let midi = ::parse_midi(&data).unwrap();
+    assert_eq!(&events[..], midi.tracks().next().unwrap().events());
+    assert_eq!(items[7], ParsedItem::Chunk {
+        kind: *b"XABC",
+        data: vec![0x07],
+    });
+
+    let mut parser = PushParser::new();
+    assert_eq!(parser.feed(&data[..30]).len(), 3);
+    assert_eq!(parser.finish(), Err(ErrorKind::Eof));
+    let items = parser.feed(&[0x00, 0xF1]);
+    assert!(match items[..] {
+        [ParsedItem::Error(_)] => true,
+        _ => false,
+    });
+    assert!(parser.feed(&data[32..]).is_empty() && parser.finish().is_err());
+}
@@ -0,0 +1,128 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use {parse_midi, Division, Event, Midi};
+
+
+// Python Bindings /////////////////////////////////////////////////////////////
+
+/// A parsed file. Python objects can't borrow, so this keeps the bytes of
+/// the file and parses them again whenever they're looked at.
+#[pyclass(name = "Midi", module = "nommidi")]
+pub struct PyMidi {
+    bytes: Vec<u8>,
+}
+
+impl PyMidi {
+    fn new(midi: &Midi) -> PyMidi {
+        PyMidi { bytes: midi.to_bytes() }
+    }
+
+    fn midi(&self) -> Midi {
+        parse_midi(&self.bytes).expect("checked when the object was made")
+    }
+}
+
+fn value_error<E: ::std::fmt::Debug>(error: E) -> PyErr {
+    PyValueError::new_err(format!("{:?}", error))
+}
+
+#[pymethods]
+impl PyMidi {
+    #[getter]
+    fn format(&self) -> u16 {
+        self.midi().header().format()
+    }
+
+    /// The ticks per quarter note, or None for SMPTE timing.
+    #[getter]
+    fn ticks_per_quarter(&self) -> Option<u16> {
+        match self.midi().header().division() {
+            Division::TicksPerQuarter(n) => Some(n),
+            Division::Smpte { .. } => None,
+        }
+    }
+
+    #[getter]
+    fn track_count(&self) -> usize {
+        self.midi().tracks().count()
+    }
+
+    /// Every event as a `(track, tick, bytes)` tuple in track order, where
+    /// the bytes are the event as written in a file without its delta time
+    /// or running status.
+    fn events(&self, py: Python) -> Vec<(usize, u64, PyObject)> {
+        let midi = self.midi();
+        let mut events = Vec::new();
+        let mut bytes = Vec::new();
+        for (index, track) in midi.tracks().enumerate() {
+            for (tick, event) in track.absolute_events() {
+                bytes.clear();
+                match *event {
+                    Event::Midi(_, ref event) => event.write_to(&mut bytes),
+                    Event::Meta(_, ref event) => event.write_to(&mut bytes),
+                    Event::Sysex(_, ref event) => event.write_to(&mut bytes),
+                }
+                events.push((index, tick, PyBytes::new(py, &bytes).to_object(py)));
+            }
+        }
+        events
+    }
+
+    /// Every note as a `(track, channel, key, velocity, start, end,
+    /// start_seconds, duration_seconds)` tuple, sorted by start time.
+    fn notes(&self) -> Vec<(usize, u8, u8, u8, u64, u64, f64, f64)> {
+        self.midi().notes().into_iter().map(|note| {
+            (note.track, note.channel, note.key, note.velocity, note.start, note.end,
+             note.start_seconds, note.duration_seconds)
+        }).collect()
+    }
+
+    fn to_format_0(&self) -> PyMidi {
+        PyMidi::new(&self.midi().to_format_0())
+    }
+
+    fn to_format_1(&self) -> PyMidi {
+        PyMidi::new(&self.midi().to_format_1())
+    }
+
+    fn rescale(&self, ticks_per_quarter: u16) -> PyResult<PyMidi> {
+        match self.midi().rescale(ticks_per_quarter) {
+            Some(midi) => Ok(PyMidi::new(&midi)),
+            None => Err(PyValueError::new_err("files with SMPTE timing can't be rescaled")),
+        }
+    }
+
+    fn to_bytes(&self, py: Python) -> PyObject {
+        PyBytes::new(py, &self.bytes).to_object(py)
+    }
+
+    fn to_json(&self) -> String {
+        self.midi().to_json()
+    }
+}
+
+/// Parses a Standard MIDI File or RMID file, raising ValueError if it
+/// doesn't parse.
+#[pyfunction]
+fn parse(data: &[u8]) -> PyResult<PyMidi> {
+    parse_midi(data).map(|midi| PyMidi::new(&midi)).map_err(value_error)
+}
+
+/// Reads a file back from the JSON that `Midi.to_json` writes.
+#[pyfunction]
+fn from_json(json: &str) -> PyResult<PyMidi> {
+    let bytes = ::json::from_json(json).map_err(value_error)?;
+    parse(&bytes)
+}
+
+#[pymodule]
+fn nommidi(_py: Python, module: &PyModule) -> PyResult<()> {
+    module.add_class::<PyMidi>()?;
+    module.add_function(wrap_pyfunction!(parse, module)?)?;
+    module.add_function(wrap_pyfunction!(from_json, module)?)?;
+    Ok(())
+}
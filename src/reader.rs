@@ -0,0 +1,330 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+use std::io::{self, Read};
+
+use nom::Err;
+
+use {event, event_len, header, var_length, ErrorKind, Event, Header, Malformed};
+
+
+// Streaming File Reader ///////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Midi(ErrorKind),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// A piece of a file, borrowing from the reader's buffer until the next one
+/// is read.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Item<'r> {
+    Header(Header),
+    TrackStart {
+        len: u32,
+    },
+    Event(Event<'r>),
+    /// The start of a sysex or meta event too big for the buffer, whose data
+    /// follows in `Data` items.
+    LargeEvent {
+        delta: u32,
+        kind: LargeKind,
+        len: u32,
+    },
+    TrackEnd,
+    /// The start of a chunk that isn't a track, whose data follows in `Data`
+    /// items.
+    Chunk {
+        kind: [u8; 4],
+        len: u32,
+    },
+    Data(&'r [u8]),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LargeKind {
+    Sysex {
+        start: bool,
+    },
+    Meta(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Header,
+    Chunks,
+    Track,
+    /// Passing along the data of a large event or another kind of chunk.
+    Data {
+        left: usize,
+        in_track: bool,
+    },
+    Done,
+}
+
+/// Reads a Standard MIDI File from any `io::Read` through a fixed-size
+/// buffer, handing back each piece of the file as soon as it's complete.
+/// Memory use stays the same however large the file or its tracks are,
+/// which makes it suitable for huge sysex dumps.
+pub struct Reader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+    state: State,
+    /// The bytes of the current track not yet taken out of the buffer.
+    track_left: usize,
+    running_status: Option<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    /// A reader with a 64 KiB buffer.
+    pub fn new(reader: R) -> Reader<R> {
+        Reader::with_buffer_size(reader, 64 * 1024)
+    }
+
+    /// A reader with a buffer of the given size, which is raised to at least
+    /// 16 bytes so that any header fits. Events bigger than the buffer come
+    /// back as `LargeEvent`s.
+    pub fn with_buffer_size(reader: R, size: usize) -> Reader<R> {
+        Reader {
+            reader: reader,
+            buffer: vec![0; cmp::max(size, 16)],
+            start: 0,
+            end: 0,
+            state: State::Header,
+            track_left: 0,
+            running_status: None,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// How many bytes have been read from the underlying reader but not yet
+    /// handed back in an item.
+    pub fn buffered(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Reads the next piece of the file, or `None` at the end of the file.
+    /// After an I/O error, such as `WouldBlock` from a nonblocking reader,
+    /// nothing is lost and the call can be tried again.
+    pub fn next_item(&mut self) -> Result<Option<Item>, Error> {
+        match self.state {
+            State::Header => {
+                self.fill_to(14)?;
+                let header = match header(&self.buffer[self.start..self.end]) {
+                    Ok((_, header)) => header,
+                    Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(Error::Midi(e)),
+                    Err(Err::Incomplete(_)) => return Err(Error::Midi(ErrorKind::Eof)),
+                };
+                self.start += 14;
+                self.state = State::Chunks;
+                Ok(Some(Item::Header(header)))
+            }
+            State::Chunks => {
+                if self.start == self.end && self.fill()? == 0 {
+                    self.state = State::Done;
+                    return Ok(None);
+                }
+                self.fill_to(8)?;
+                let bytes = &self.buffer[self.start..self.start + 8];
+                let kind = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                let len = (bytes[4] as u32) << 24 | (bytes[5] as u32) << 16 |
+                          (bytes[6] as u32) << 8 | bytes[7] as u32;
+                self.start += 8;
+                if &kind == b"MTrk" {
+                    self.state = State::Track;
+                    self.track_left = len as usize;
+                    self.running_status = None;
+                    Ok(Some(Item::TrackStart { len: len }))
+                } else {
+                    self.state = State::Data {
+                        left: len as usize,
+                        in_track: false,
+                    };
+                    Ok(Some(Item::Chunk {
+                        kind: kind,
+                        len: len,
+                    }))
+                }
+            }
+            State::Track => {
+                if self.track_left == 0 {
+                    self.state = State::Chunks;
+                    return Ok(Some(Item::TrackEnd));
+                }
+                self.next_event()
+            }
+            State::Data { left, in_track } => {
+                if left == 0 {
+                    self.state = if in_track { State::Track } else { State::Chunks };
+                    return self.next_item();
+                }
+                if self.start == self.end && self.fill()? == 0 {
+                    return Err(Error::Midi(ErrorKind::Eof));
+                }
+                let len = cmp::min(left, self.end - self.start);
+                let start = self.start;
+                self.start += len;
+                if in_track {
+                    self.track_left -= len;
+                }
+                self.state = State::Data {
+                    left: left - len,
+                    in_track: in_track,
+                };
+                Ok(Some(Item::Data(&self.buffer[start..start + len])))
+            }
+            State::Done => Ok(None),
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Item>, Error> {
+        let wanted = cmp::min(self.track_left, self.buffer.len());
+        let len = loop {
+            let available = cmp::min(self.end - self.start, self.track_left);
+            let data = &self.buffer[self.start..self.start + available];
+            let mut running_status = self.running_status;
+            if let Some(len) = event_len(data, &mut running_status) {
+                break len;
+            }
+            if available < wanted {
+                if self.fill()? == 0 {
+                    return Err(Error::Midi(ErrorKind::Eof));
+                }
+            } else if available < self.track_left {
+                return self.large_event();
+            } else {
+                // The whole rest of the track is here, so it's malformed.
+                return Err(Error::Midi(match event(data, &mut running_status) {
+                    Err(Err::Error(e)) | Err(Err::Failure(e)) => e,
+                    _ => ErrorKind::Eof,
+                }));
+            }
+        };
+        let start = self.start;
+        self.start += len;
+        self.track_left -= len;
+        match event(&self.buffer[start..start + len], &mut self.running_status) {
+            Ok((_, event)) => Ok(Some(Item::Event(event))),
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(Error::Midi(e)),
+            Err(Err::Incomplete(_)) => Err(Error::Midi(ErrorKind::Eof)),
+        }
+    }
+
+    /// Starts an event that doesn't fit in the full buffer, which can only
+    /// be a sysex or meta event.
+    fn large_event(&mut self) -> Result<Option<Item>, Error> {
+        let data = &self.buffer[self.start..self.end];
+        let (rest, delta) = match var_length(data) {
+            Ok((rest, delta)) => (rest, delta),
+            _ => return Err(Error::Midi(ErrorKind::from(Malformed::VarLengthOverflow))),
+        };
+        let (rest, kind) = match rest {
+            [0xFF, kind, rest @ ..] => (rest, LargeKind::Meta(*kind)),
+            [status @ 0xF0, rest @ ..] | [status @ 0xF7, rest @ ..] => {
+                (rest, LargeKind::Sysex { start: *status == 0xF0 })
+            }
+            _ => return Err(Error::Midi(ErrorKind::from(Malformed::BadStatusByte))),
+        };
+        let (rest, len) = match var_length(rest) {
+            Ok((rest, len)) => (rest, len),
+            _ => return Err(Error::Midi(ErrorKind::from(Malformed::VarLengthOverflow))),
+        };
+        let header_len = data.len() - rest.len();
+        if len as usize > self.track_left - header_len {
+            return Err(Error::Midi(ErrorKind::Eof));
+        }
+        self.start += header_len;
+        self.track_left -= header_len;
+        self.running_status = None;
+        self.state = State::Data {
+            left: len as usize,
+            in_track: true,
+        };
+        Ok(Some(Item::LargeEvent {
+            delta: delta,
+            kind: kind,
+            len: len,
+        }))
+    }
+
+    /// Moves what's left in the buffer to the front and reads more after it,
+    /// returning how many bytes were read.
+    fn fill(&mut self) -> io::Result<usize> {
+        if self.start > 0 {
+            for i in self.start..self.end {
+                self.buffer[i - self.start] = self.buffer[i];
+            }
+            self.end -= self.start;
+            self.start = 0;
+        }
+        loop {
+            match self.reader.read(&mut self.buffer[self.end..]) {
+                Ok(n) => {
+                    self.end += n;
+                    return Ok(n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn fill_to(&mut self, len: usize) -> Result<(), Error> {
+        while self.end - self.start < len {
+            if self.fill()? == 0 {
+                return Err(Error::Midi(ErrorKind::Eof));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_reader() {
+    let mut data = vec![
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x2E,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x3C, 0x00,
+        0x00, 0xF0, 0x20,
+    ];
+    data.extend_from_slice(&[0x11; 31]);
+    data.extend_from_slice(&[0xF7, 0x00, 0xFF, 0x2F, 0x00]);
+
+    let mut reader = Reader::with_buffer_size(&data[..], 16);
+    let mut items = Vec::new();
+    while let Some(item) = reader.next_item().unwrap() {
+        items.push(match item {
+            Item::Data(data) => format!("Data({})", data.len()),
+            Item::Event(_) => "Event".to_string(),
+            item => format!("{:?}", item),
+        });
+    }
+    assert_eq!(items[1], "TrackStart { len: 46 }");
+    assert_eq!(&items[2..4], ["Event", "Event"]);
+    assert_eq!(items[4], "LargeEvent { delta: 0, kind: Sysex { start: true }, len: 32 }");
+    assert_eq!(&items[5..9], ["Data(13)", "Data(16)", "Data(3)", "Event"]);
+    assert_eq!(items[9], "TrackEnd");
+    assert_eq!(items.len(), 10);
+}
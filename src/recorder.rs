@@ -0,0 +1,160 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Chunk, Event, Header, MetaEvent, Midi, MidiEvent, SysexEvent, TrackChunk};
+use stream::{Message, Parser};
+use write::{spacer, MAX_DELTA};
+
+
+// Live Recording //////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Recorded {
+    Channel(MidiEvent),
+    /// The body of a sysex message with its closing F7.
+    Sysex(Vec<u8>),
+}
+
+/// Records what arrives from a live MIDI input into a track. Bytes come in
+/// with the time they arrived, and each message is placed on the nearest
+/// tick at a fixed tempo. System real-time and common messages, like clocks
+/// and active sensing, aren't recorded.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    parser: Parser,
+    ticks_per_quarter: u16,
+    /// Microseconds per quarter note.
+    tempo: u32,
+    /// The timestamp of tick 0, which is when the first message arrived
+    /// unless it's set with `start_at`.
+    start: Option<u64>,
+    events: Vec<(u64, Recorded)>,
+}
+
+impl Recorder {
+    /// A recorder at the given resolution and tempo, in microseconds per
+    /// quarter note.
+    pub fn new(ticks_per_quarter: u16, tempo: u32) -> Recorder {
+        Recorder {
+            parser: Parser::new(),
+            ticks_per_quarter: ::std::cmp::max(1, ticks_per_quarter),
+            tempo: ::std::cmp::max(1, tempo),
+            start: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Sets the timestamp, in microseconds, that tick 0 falls on.
+    pub fn start_at(&mut self, micros: u64) {
+        self.start = Some(micros);
+    }
+
+    /// Records bytes that arrived at a timestamp in microseconds, as input
+    /// ports report them. A message split across calls is placed at the
+    /// time its last byte arrived. Timestamps before the start, or before
+    /// the last message, are moved up to it.
+    pub fn record(&mut self, micros: u64, bytes: &[u8]) {
+        for &byte in bytes {
+            let recorded = match self.parser.feed(byte) {
+                Some(Message::Channel(event)) => Recorded::Channel(event),
+                Some(Message::Sysex(body)) => {
+                    let mut data = body.to_vec();
+                    data.push(0xF7);
+                    Recorded::Sysex(data)
+                }
+                Some(Message::System(_)) | None => continue,
+            };
+            let start = *self.start.get_or_insert(micros);
+            let micros = micros.saturating_sub(start) as u128;
+            let tick = (micros * self.ticks_per_quarter as u128 + self.tempo as u128 / 2) /
+                       self.tempo as u128;
+            let last = self.events.last().map_or(0, |&(last, _)| last);
+            self.events.push((::std::cmp::max(tick as u64, last), recorded));
+        }
+    }
+
+    /// The number of messages recorded so far.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The recording as a track, starting with its tempo and ending on the
+    /// last message. Pauses too long for one delta are carried by empty text
+    /// events.
+    pub fn track(&self) -> TrackChunk {
+        let mut events = vec![Event::Meta(0, MetaEvent::SetTempo(self.tempo))];
+        let mut time = 0;
+        for &(tick, ref recorded) in &self.events {
+            let mut gap = tick - time;
+            while gap > MAX_DELTA as u64 {
+                events.push(spacer(MAX_DELTA));
+                gap -= MAX_DELTA as u64;
+            }
+            let delta = gap as u32;
+            events.push(match *recorded {
+                Recorded::Channel(event) => Event::Midi(delta, event),
+                Recorded::Sysex(ref data) => Event::Sysex(delta, SysexEvent {
+                    start: true,
+                    end: true,
+                    data: data,
+                }),
+            });
+            time = tick;
+        }
+        events.push(Event::Meta(0, MetaEvent::EndOfTrack));
+        TrackChunk { events: events.into() }
+    }
+
+    /// The recording as a format 0 file, ready to write.
+    pub fn to_midi(&self) -> Midi {
+        Midi {
+            header: Header {
+                len: 6,
+                format: 0,
+                tracks: 1,
+                division: self.ticks_per_quarter & 0x7FFF,
+            },
+            chunks: vec![Chunk::Track(self.track())],
+            rmid: None,
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_recorder() {
+    let mut recorder = Recorder::new(96, 500_000);
+    recorder.record(1_000_000, &[0x90, 0x3C]);
+    recorder.record(1_000_100, &[0x40, 0xF8]);
+    // A quarter note later, give or take a few milliseconds.
+    recorder.record(1_503_000, &[0x3C, 0x00]);
+    recorder.record(2_000_000, &[0xF0, 0x7E, 0x7F, 0xF7, 0xFE]);
+    assert_eq!(recorder.len(), 3);
+    let midi = recorder.to_midi();
+    assert!(midi.conformance().conforms());
+    let track = midi.tracks().next().unwrap();
+    let ticks: Vec<_> = track.absolute_events().map(|(tick, _)| tick).collect();
+    assert_eq!(ticks, [0, 0, 97, 192, 192]);
+    assert_eq!(track.events()[2], Event::Midi(97, MidiEvent::NoteOn {
+        channel: 0,
+        number: 0x3C,
+        velocity: 0,
+    }));
+    let bytes = midi.to_bytes();
+    assert_eq!(&::parse_midi(&bytes).unwrap(), &midi);
+
+    // Almost 300 million ticks between the notes.
+    let mut recorder = Recorder::new(96, 1);
+    recorder.record(0, &[0x90, 0x3C, 0x40]);
+    recorder.record(3_000_000, &[0x3C, 0x00]);
+    let track = recorder.track();
+    let ticks: Vec<_> = track.absolute_events().map(|(tick, _)| tick).collect();
+    assert_eq!(ticks, [0, 0, 0x0FFF_FFFF, 288_000_000, 288_000_000]);
+    assert_eq!(track.events()[2], spacer(MAX_DELTA));
+}
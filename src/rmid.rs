@@ -0,0 +1,118 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {parse_smf, ErrorKind, Malformed, Midi, ParseError, ParseOptions};
+
+
+// RIFF MIDI Container /////////////////////////////////////////////////////////
+
+/// The parts of an RMID file other than the MIDI data itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rmid<'a> {
+    /// Every chunk of the RIFF form other than the `data` chunk, in order.
+    pub chunks: Vec<RiffChunk<'a>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RiffChunk<'a> {
+    pub id: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> Rmid<'a> {
+    /// Whether the file embeds a Downloadable Sounds bank to play it with.
+    pub fn has_dls(&self) -> bool {
+        self.dls().is_some()
+    }
+
+    /// The DLS bank, which is either a plain `DLS ` chunk or a nested `RIFF`
+    /// chunk with that form type.
+    pub fn dls(&self) -> Option<&RiffChunk<'a>> {
+        self.chunks.iter().find(|chunk| {
+            chunk.id == b"DLS " || (chunk.id == b"RIFF" && chunk.data.starts_with(b"DLS "))
+        })
+    }
+
+    /// The `LIST` chunk holding the INFO metadata, if present.
+    pub fn info(&self) -> Option<&RiffChunk<'a>> {
+        self.chunks.iter().find(|chunk| chunk.id == b"LIST" && chunk.data.starts_with(b"INFO"))
+    }
+}
+
+pub fn parse_rmid(input: &[u8]) -> Result<Midi, ParseError> {
+    parse_rmid_with(input, &ParseOptions::default())
+}
+
+pub fn parse_rmid_with<'a>(input: &'a [u8], options: &ParseOptions)
+                           -> Result<Midi<'a>, ParseError> {
+    let error = |kind| ParseError {
+        kind: kind,
+        offset: 0,
+        chunk: None,
+        event: None,
+    };
+    if input.len() < 12 || &input[..4] != b"RIFF" || &input[8..12] != b"RMID" {
+        return Err(error(ErrorKind::Tag));
+    }
+    // The form size is often wrong in the wild, so trust the chunks instead.
+    let mut rest = &input[12..];
+    let mut data = None;
+    let mut chunks = Vec::new();
+    while rest.len() >= 8 {
+        let id = &rest[..4];
+        let len = le_u32(&rest[4..8]) as usize;
+        let body = &rest[8..];
+        // Chunks are padded to an even length.
+        let padded = len + (len & 1);
+        let (chunk, next) = if padded <= body.len() {
+            (&body[..len], &body[padded..])
+        } else {
+            (&body[..::std::cmp::min(len, body.len())], &body[body.len()..])
+        };
+        if id == b"data" && data.is_none() {
+            data = Some(chunk);
+        } else {
+            chunks.push(RiffChunk {
+                id: id,
+                data: chunk,
+            });
+        }
+        rest = next;
+    }
+    let data = match data {
+        Some(data) => data,
+        None => return Err(error(ErrorKind::from(Malformed::MissingRmidData))),
+    };
+    // Report offsets within the whole RIFF file rather than the data chunk.
+    let start = data.as_ptr() as usize - input.as_ptr() as usize;
+    let mut midi = parse_smf(data, options).map_err(|mut e| {
+        e.offset += start;
+        e
+    })?;
+    midi.rmid = Some(Rmid { chunks: chunks });
+    Ok(midi)
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    (bytes[3] as u32) << 24 | (bytes[2] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[0] as u32
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_rmid() {
+    let mut data = b"RIFF\x00\x00\x00\x00RMIDdata\x1A\x00\x00\x00".to_vec();
+    data.extend_from_slice(&[
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xFF, 0x2F, 0x00,
+    ]);
+    data.extend_from_slice(b"DLS \x03\x00\x00\x00abc\x00");
+    let midi = ::parse_midi(&data).unwrap();
+    assert_eq!(midi.header().tracks(), 1);
+    let rmid = midi.rmid().unwrap();
+    assert!(rmid.has_dls());
+    assert_eq!(rmid.dls().unwrap().data, b"abc");
+}
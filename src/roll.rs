@@ -0,0 +1,144 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt;
+
+use Midi;
+
+
+// Piano Roll Matrices /////////////////////////////////////////////////////////
+
+/// What a cell of a piano roll holds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cells {
+    /// The velocity of the loudest note sounding, or 0 for silence.
+    Velocity,
+    /// 1 if any note is sounding, and 0 otherwise.
+    Binary,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RollOptions {
+    /// How many ticks each time step covers.
+    pub ticks_per_step: u64,
+    pub cells: Cells,
+}
+
+impl Default for RollOptions {
+    /// Sixteenth notes at 96 ticks per quarter note, with velocities.
+    fn default() -> RollOptions {
+        RollOptions {
+            ticks_per_step: 24,
+            cells: Cells::Velocity,
+        }
+    }
+}
+
+/// A file as a dense 128 × steps array, one row for each key and one column
+/// for each time step. A note fills every step it sounds during any part of,
+/// so even a very short note shows up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PianoRoll {
+    pub ticks_per_step: u64,
+    pub steps: usize,
+    /// The rows one after another, from key 0 up.
+    cells: Vec<u8>,
+}
+
+impl<'a> Midi<'a> {
+    /// Returns `None` if `ticks_per_step` is zero.
+    pub fn piano_roll(&self, options: &RollOptions) -> Option<PianoRoll> {
+        let step = options.ticks_per_step;
+        if step == 0 {
+            return None;
+        }
+        let end = self.tracks().map(|track| track.duration()).max().unwrap_or(0);
+        let steps = ((end + step - 1) / step) as usize;
+        let mut cells = vec![0; 128 * steps];
+        for note in self.notes() {
+            let first = (note.start / step) as usize;
+            let last = ((note.end + step - 1) / step) as usize;
+            let value = match options.cells {
+                Cells::Velocity => note.velocity,
+                Cells::Binary => 1,
+            };
+            let row = note.key as usize & 0x7F;
+            for cell in &mut cells[row * steps..][first..last.max(first + 1).min(steps)] {
+                *cell = (*cell).max(value);
+            }
+        }
+        Some(PianoRoll {
+            ticks_per_step: step,
+            steps: steps,
+            cells: cells,
+        })
+    }
+}
+
+impl PianoRoll {
+    pub fn get(&self, key: u8, step: usize) -> u8 {
+        self.row(key)[step]
+    }
+
+    pub fn row(&self, key: u8) -> &[u8] {
+        let start = (key as usize & 0x7F) * self.steps;
+        &self.cells[start..start + self.steps]
+    }
+
+    /// Every cell, row by row from key 0 up.
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+}
+
+/// Draws the rows from the highest key played down to the lowest, with a
+/// `#` for each sounding cell.
+impl fmt::Display for PianoRoll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let played: Vec<u8> = (0..128)
+            .filter(|&key| self.row(key).iter().any(|&cell| cell > 0))
+            .collect();
+        let (low, high) = match (played.first(), played.last()) {
+            (Some(&low), Some(&high)) => (low, high),
+            _ => return Ok(()),
+        };
+        for key in (low..high + 1).rev() {
+            write!(f, "{:3} ", key)?;
+            for &cell in self.row(key) {
+                f.write_str(if cell > 0 { "#" } else { "." })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_piano_roll() {
+    let midi = ::gen::arpeggio(&[60, 64, 67], 1);
+    let roll = midi.piano_roll(&RollOptions::default()).unwrap();
+    assert_eq!(roll.steps, 4);
+    assert_eq!(roll.cells().len(), 128 * 4);
+    assert_eq!(roll.row(64), [0, 100, 0, 100]);
+    assert_eq!(roll.get(60, 0), 100);
+    let options = RollOptions {
+        ticks_per_step: 48,
+        cells: Cells::Binary,
+    };
+    let roll = midi.piano_roll(&options).unwrap();
+    assert_eq!(roll.row(64), [1, 1]);
+    assert_eq!(roll.to_string(), "
+ 67 .#
+ 66 ..
+ 65 ..
+ 64 ##
+ 63 ..
+ 62 ..
+ 61 ..
+ 60 #.
+"[1..]);
+    assert!(midi.piano_roll(&RollOptions { ticks_per_step: 0, ..options }).is_none());
+}
@@ -0,0 +1,253 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use nom::Err;
+
+use {midi_event, var_length, ErrorKind, Malformed, MidiEvent, SysexEvent, SystemMessage};
+
+
+// RTP-MIDI Payloads ///////////////////////////////////////////////////////////
+
+/// An RTP packet carrying MIDI, as described in RFC 6295.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload: Payload<'a>,
+}
+
+/// The MIDI command section of a packet, along with the recovery journal
+/// header if the packet has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payload<'a> {
+    /// Each command with the delta time before it, in RTP timestamp units.
+    pub commands: Vec<(u32, Command<'a>)>,
+    /// Set when the first command's status byte was only implied by running
+    /// status in the original stream.
+    pub phantom: bool,
+    pub journal: Option<Journal<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command<'a> {
+    Channel(MidiEvent),
+    System(SystemMessage),
+    /// A whole sysex command or one segment of it. Segments that continue
+    /// an earlier one aren't starts, and ones that are continued later
+    /// aren't ends.
+    Sysex(SysexEvent<'a>),
+    /// Ends a segmented sysex command without completing it.
+    SysexCancel,
+}
+
+/// The recovery journal header. The journals themselves are left undecoded
+/// in `data`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Journal<'a> {
+    /// Set when the journal covers only the single packet before this one.
+    pub single_packet_loss: bool,
+    /// Whether chapter C uses the enhanced encoding.
+    pub enhanced: bool,
+    /// The sequence number of the checkpoint packet.
+    pub checkpoint: u16,
+    /// Whether a system journal follows the header.
+    pub system: bool,
+    /// The number of channel journals after the system journal.
+    pub channels: u8,
+    pub data: &'a [u8],
+}
+
+/// Parses a whole RTP packet, skipping any CSRC list, header extension or
+/// padding.
+pub fn parse_packet(input: &[u8]) -> Result<Packet, ErrorKind> {
+    if input.len() < 12 {
+        return Err(ErrorKind::Eof);
+    }
+    if input[0] >> 6 != 2 {
+        return Err(ErrorKind::Tag);
+    }
+    let mut end = input.len();
+    if input[0] & 0x20 != 0 {
+        end = end.checked_sub(input[end - 1] as usize).ok_or(ErrorKind::Eof)?;
+    }
+    let mut start = 12 + (input[0] & 0x0F) as usize * 4;
+    if input[0] & 0x10 != 0 {
+        let len = input.get(start + 2..start + 4).ok_or(ErrorKind::Eof)?;
+        start += 4 + (u16_at(len) as usize) * 4;
+    }
+    if start > end {
+        return Err(ErrorKind::Eof);
+    }
+    Ok(Packet {
+        marker: input[1] & 0x80 != 0,
+        payload_type: input[1] & 0x7F,
+        sequence_number: u16_at(&input[2..]),
+        timestamp: u32_at(&input[4..]),
+        ssrc: u32_at(&input[8..]),
+        payload: parse_payload(&input[start..end])?,
+    })
+}
+
+/// Parses the payload of an RTP-MIDI packet, after the RTP header.
+pub fn parse_payload(input: &[u8]) -> Result<Payload, ErrorKind> {
+    let flags = *input.first().ok_or(ErrorKind::Eof)?;
+    let (len, list) = if flags & 0x80 != 0 {
+        let low = *input.get(1).ok_or(ErrorKind::Eof)?;
+        (((flags & 0x0F) as usize) << 8 | low as usize, &input[2..])
+    } else {
+        ((flags & 0x0F) as usize, &input[1..])
+    };
+    if list.len() < len {
+        return Err(ErrorKind::Eof);
+    }
+    let journal = if flags & 0x40 != 0 {
+        Some(journal(&list[len..])?)
+    } else {
+        None
+    };
+    Ok(Payload {
+        commands: commands(&list[..len], flags & 0x20 != 0)?,
+        phantom: flags & 0x10 != 0,
+        journal: journal,
+    })
+}
+
+fn commands(mut input: &[u8], first_delta: bool) -> Result<Vec<(u32, Command)>, ErrorKind> {
+    let mut commands = Vec::new();
+    let mut running_status = None;
+    while !input.is_empty() {
+        let delta = if first_delta || !commands.is_empty() {
+            match var_length(input) {
+                Ok((rest, delta)) => {
+                    input = rest;
+                    delta
+                }
+                Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(e),
+                Err(Err::Incomplete(_)) => return Err(ErrorKind::Eof),
+            }
+        } else {
+            0
+        };
+        let status = match input.first() {
+            Some(&status @ 0x80...0xFF) => {
+                input = &input[1..];
+                status
+            }
+            Some(_) => running_status.ok_or(Malformed::NoRunningStatus)?,
+            None => return Err(ErrorKind::Eof),
+        };
+        let command = match status {
+            0x80...0xEF => {
+                running_status = Some(status);
+                match midi_event(input, status) {
+                    Ok((rest, event)) => {
+                        input = rest;
+                        Command::Channel(event)
+                    }
+                    Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(e),
+                    Err(Err::Incomplete(_)) => return Err(ErrorKind::Eof),
+                }
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let end = input.iter()
+                    .position(|&b| b == 0xF0 || b == 0xF7 || b == 0xF4)
+                    .ok_or(ErrorKind::Eof)?;
+                let command = match input[end] {
+                    0xF4 if status == 0xF7 && end == 0 => Command::SysexCancel,
+                    0xF4 => return Err(ErrorKind::from(Malformed::BadStatusByte)),
+                    last => Command::Sysex(SysexEvent {
+                        start: status == 0xF0,
+                        end: last == 0xF7,
+                        data: if last == 0xF7 { &input[..end + 1] } else { &input[..end] },
+                    }),
+                };
+                input = &input[end + 1..];
+                command
+            }
+            _ => {
+                let len = SystemMessage::data_len(status);
+                if input.len() < len || input[..len].iter().any(|&b| b >= 0x80) {
+                    return Err(ErrorKind::Eof);
+                }
+                if status < 0xF8 {
+                    running_status = None;
+                }
+                let data1 = if len > 0 { input[0] } else { 0 };
+                let data2 = if len > 1 { input[1] } else { 0 };
+                input = &input[len..];
+                Command::System(SystemMessage::from_parts(status, data1, data2)
+                    .ok_or(ErrorKind::from(Malformed::BadStatusByte))?)
+            }
+        };
+        commands.push((delta, command));
+    }
+    Ok(commands)
+}
+
+fn journal(input: &[u8]) -> Result<Journal, ErrorKind> {
+    if input.len() < 3 {
+        return Err(ErrorKind::Eof);
+    }
+    Ok(Journal {
+        single_packet_loss: input[0] & 0x80 != 0,
+        enhanced: input[0] & 0x10 != 0,
+        checkpoint: u16_at(&input[1..]),
+        system: input[0] & 0x40 != 0,
+        channels: if input[0] & 0x20 != 0 { (input[0] & 0x0F) + 1 } else { 0 },
+        data: &input[3..],
+    })
+}
+
+fn u16_at(data: &[u8]) -> u16 {
+    (data[0] as u16) << 8 | data[1] as u16
+}
+
+fn u32_at(data: &[u8]) -> u32 {
+    (u16_at(data) as u32) << 16 | u16_at(&data[2..]) as u32
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_rtp_payload() {
+    let packet = [
+        0x80, 0x61, 0x12, 0x34, 0x00, 0x00, 0x01, 0x00, 0xDE, 0xAD, 0xBE, 0xEF,
+        // Journal and delta time on the first command, twelve bytes long.
+        0x6C,
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x00, 0x3E, 0x40,
+        0x00, 0xF0, 0x7E, 0xF0,
+        // Journal header, with two channel journals.
+        0x21, 0x12, 0x30, 0xAA,
+    ];
+    let packet = parse_packet(&packet).unwrap();
+    assert_eq!(packet.sequence_number, 0x1234);
+    assert_eq!(packet.payload_type, 0x61);
+    let payload = packet.payload;
+    assert_eq!(payload.commands.len(), 3);
+    assert_eq!(payload.commands[1], (0x80, Command::Channel(MidiEvent::NoteOn {
+        channel: 0,
+        number: 0x3E,
+        velocity: 0x40,
+    })));
+    match payload.commands[2].1 {
+        Command::Sysex(ref sysex) => {
+            assert!(sysex.is_start() && !sysex.is_end());
+            assert_eq!(sysex.data(), &[0x7E]);
+        }
+        ref command => panic!("expected a sysex segment, got {:?}", command),
+    }
+    let journal = payload.journal.unwrap();
+    assert_eq!(journal.channels, 2);
+    assert_eq!(journal.checkpoint, 0x1230);
+    assert_eq!(journal.data, &[0xAA]);
+
+    // Running status can't carry into the first command.
+    assert_eq!(parse_payload(&[0x02, 0x3C, 0x40]),
+               Err(ErrorKind::from(Malformed::NoRunningStatus)));
+}
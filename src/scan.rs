@@ -0,0 +1,148 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use nom::Err;
+
+use {event, header, ErrorKind, Event, MidiEvent};
+
+
+// Allocation-Free Scanning ////////////////////////////////////////////////////
+
+/// Walks the events of a Standard MIDI File one at a time without building
+/// a `Midi`, yielding each event with the index of its track and its
+/// absolute tick within the track. Nothing is allocated along the way, so
+/// this is the fastest way to answer questions about many files.
+///
+/// Once an error is returned, the iterator ends.
+#[derive(Debug, Clone)]
+pub struct Scan<'a> {
+    rest: &'a [u8],
+    track: &'a [u8],
+    in_track: bool,
+    index: usize,
+    tick: u64,
+    running_status: Option<u8>,
+    failed: bool,
+}
+
+/// Starts scanning a file, checking only its header up front.
+pub fn scan(input: &[u8]) -> Result<Scan, ErrorKind> {
+    match header(input) {
+        Ok((rest, _)) => Ok(Scan {
+            rest: rest,
+            track: &[],
+            in_track: false,
+            index: 0,
+            tick: 0,
+            running_status: None,
+            failed: false,
+        }),
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(e),
+        Err(Err::Incomplete(_)) => Err(ErrorKind::Eof),
+    }
+}
+
+impl<'a> Iterator for Scan<'a> {
+    type Item = Result<(usize, u64, Event<'a>), ErrorKind>;
+
+    fn next(&mut self) -> Option<Result<(usize, u64, Event<'a>), ErrorKind>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            if self.in_track {
+                if self.track.is_empty() {
+                    self.in_track = false;
+                    self.index += 1;
+                    continue;
+                }
+                let error = match event(self.track, &mut self.running_status) {
+                    Ok((rest, event)) => {
+                        self.track = rest;
+                        self.tick += event.delta() as u64;
+                        return Some(Ok((self.index, self.tick, event)));
+                    }
+                    Err(Err::Error(e)) | Err(Err::Failure(e)) => e,
+                    Err(Err::Incomplete(_)) => ErrorKind::Eof,
+                };
+                self.failed = true;
+                return Some(Err(error));
+            }
+            if self.rest.is_empty() {
+                return None;
+            }
+            let len = match self.rest.get(4..8) {
+                Some(len) => {
+                    (len[0] as usize) << 24 | (len[1] as usize) << 16 | (len[2] as usize) << 8 |
+                    len[3] as usize
+                }
+                None => 0,
+            };
+            if self.rest.len() < 8 || self.rest.len() - 8 < len {
+                self.failed = true;
+                return Some(Err(ErrorKind::Eof));
+            }
+            if &self.rest[..4] == b"MTrk" {
+                self.track = &self.rest[8..8 + len];
+                self.in_track = true;
+                self.tick = 0;
+                self.running_status = None;
+            }
+            self.rest = &self.rest[8 + len..];
+        }
+    }
+}
+
+/// Counts the note on events in a file, not counting ones with a velocity of
+/// 0 that really end a note.
+pub fn count_notes(input: &[u8]) -> Result<usize, ErrorKind> {
+    let mut count = 0;
+    for item in scan(input)? {
+        if let (_, _, Event::Midi(_, MidiEvent::NoteOn { velocity, .. })) = item? {
+            if velocity > 0 {
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Whether any channel message in the file is on `channel`, counting from 0,
+/// so the General MIDI drum channel is 9. Stops at the first one found.
+pub fn uses_channel(input: &[u8], channel: u8) -> Result<bool, ErrorKind> {
+    for item in scan(input)? {
+        if let (_, _, Event::Midi(_, event)) = item? {
+            if event.channel() == Some(channel) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_scan() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0x90, 0x3C, 0x40,
+        0x60, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x58, 0x46, 0x49, 0x48, 0x00, 0x00, 0x00, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x08,
+        0x10, 0x99, 0x24, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let events: Vec<_> = scan(&data).unwrap().map(|item| item.unwrap()).collect();
+    assert_eq!(events.len(), 5);
+    assert_eq!((events[1].0, events[1].1), (0, 0x60));
+    assert_eq!((events[3].0, events[3].1), (1, 0x10));
+    assert_eq!(count_notes(&data), Ok(2));
+    assert_eq!(uses_channel(&data, 9), Ok(true));
+    assert_eq!(uses_channel(&data, 1), Ok(false));
+    assert_eq!(count_notes(&data[..30]), Err(ErrorKind::Eof));
+}
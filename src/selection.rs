@@ -0,0 +1,139 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::ops::Range;
+
+use {Event, MidiEvent, Midi};
+use meter::MeterMap;
+
+
+// Selections //////////////////////////////////////////////////////////////////
+
+/// Which events to pick out of a file. The default selects every event, and
+/// each field narrows it down, so all the note events on channel 2 in bars 9
+/// through 16 are
+///
+/// ```ignore
+/// Selection {
+///     channel: Some(1),
+///     notes_only: true,
+///     ..Selection::measures(&meter_map, 8..16)
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Selection {
+    /// The first tick selected.
+    pub start: u64,
+    /// The first tick after the selection, or `None` to select to the end.
+    pub end: Option<u64>,
+    /// The index of a track among the file's tracks.
+    pub track: Option<usize>,
+    /// A channel from 0 to 15. Only channel messages are on a channel.
+    pub channel: Option<u8>,
+    /// Only note ons and note offs.
+    pub notes_only: bool,
+}
+
+/// An event picked out by a selection, found by its track among the file's
+/// tracks and its index in that track. A handle stays valid until the file
+/// is changed, and `EditSession` takes a whole list of them in one edit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventHandle {
+    pub track: usize,
+    pub index: usize,
+}
+
+impl Selection {
+    /// Selects the measures in a range of zero-based measure indices, as in
+    /// `Measure::index`.
+    pub fn measures(map: &MeterMap, measures: Range<usize>) -> Selection {
+        let mut bars = map.measures().skip(measures.start);
+        let start = bars.next().map_or(0, |measure| measure.start);
+        let end = if measures.end > measures.start {
+            bars.nth(measures.end - measures.start - 1).map(|measure| measure.start)
+        } else {
+            Some(start)
+        };
+        Selection {
+            start: start,
+            end: end,
+            ..Selection::default()
+        }
+    }
+
+    pub fn matches(&self, track: usize, tick: u64, event: &Event) -> bool {
+        if tick < self.start || self.end.map_or(false, |end| tick >= end) {
+            return false;
+        }
+        if self.track.map_or(false, |t| t != track) {
+            return false;
+        }
+        let midi = match *event {
+            Event::Midi(_, ref event) => Some(event),
+            _ => None,
+        };
+        if let Some(channel) = self.channel {
+            if midi.and_then(MidiEvent::channel) != Some(channel) {
+                return false;
+            }
+        }
+        match midi {
+            Some(&MidiEvent::NoteOn { .. }) | Some(&MidiEvent::NoteOff { .. }) => true,
+            _ => !self.notes_only,
+        }
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// The events that a selection picks out, in order of track and then
+    /// of index.
+    pub fn select(&self, selection: &Selection) -> Vec<EventHandle> {
+        let mut handles = Vec::new();
+        for (track, chunk) in self.tracks().enumerate() {
+            for (index, (tick, event)) in chunk.absolute_events().enumerate() {
+                if selection.matches(track, tick, event) {
+                    handles.push(EventHandle {
+                        track: track,
+                        index: index,
+                    });
+                }
+            }
+        }
+        handles
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_selection() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0D,
+        0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08,
+        0x83, 0x60, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x15,
+        0x00, 0x91, 0x3C, 0x40,
+        0x83, 0x60, 0x81, 0x3C, 0x40,
+        0x00, 0xB1, 0x07, 0x64,
+        0x00, 0x92, 0x3E, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let map = midi.meter_map().unwrap();
+    // In 3/4, the second measure starts at 0x120.
+    let second = Selection::measures(&map, 1..2);
+    assert_eq!((second.start, second.end), (0x120, Some(0x240)));
+    let handle = |track, index| EventHandle { track: track, index: index };
+    assert_eq!(midi.select(&second),
+               [handle(0, 1), handle(1, 1), handle(1, 2), handle(1, 3), handle(1, 4)]);
+    let notes = Selection {
+        channel: Some(1),
+        notes_only: true,
+        ..second
+    };
+    assert_eq!(midi.select(&notes), [handle(1, 1)]);
+    assert_eq!(midi.select(&Selection { track: Some(0), ..Selection::default() }).len(), 2);
+}
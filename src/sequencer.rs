@@ -0,0 +1,121 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use MetaEvent;
+use sysex::manufacturer::ManufacturerId;
+use xf::{self, XfMeta};
+
+
+// Sequencer-Specific Events ///////////////////////////////////////////////////
+
+/// A decoded sequencer-specific meta event.
+#[derive(Debug)]
+pub enum Sequencer<'a> {
+    Xf(XfMeta<'a>),
+    /// Whatever a decoder installed by the user returned, for them to
+    /// downcast.
+    Custom(Box<Any>),
+    /// An event from a manufacturer with no decoder, or that its decoder
+    /// didn't recognize. The data follows the manufacturer ID.
+    Unknown {
+        manufacturer: ManufacturerId,
+        data: &'a [u8],
+    },
+}
+
+/// Decodes the whole data of a sequencer-specific event, including the
+/// manufacturer ID at its start, or returns `None` if it doesn't recognize it.
+pub type Decoder = for<'a> fn(&'a [u8]) -> Option<Sequencer<'a>>;
+
+/// Decoders for sequencer-specific events, keyed by the manufacturer ID that
+/// starts each event.
+#[derive(Clone)]
+pub struct Registry {
+    decoders: BTreeMap<ManufacturerId, Decoder>,
+}
+
+impl Registry {
+    /// A registry with the built-in decoders, which currently handle Yamaha's
+    /// XF events.
+    pub fn new() -> Registry {
+        let mut registry = Registry::empty();
+        registry.install(ManufacturerId::Short(0x43), decode_yamaha);
+        registry
+    }
+
+    pub fn empty() -> Registry {
+        Registry { decoders: BTreeMap::new() }
+    }
+
+    /// Installs a decoder for a manufacturer, replacing any decoder it had.
+    pub fn install(&mut self, manufacturer: ManufacturerId, decoder: Decoder) {
+        self.decoders.insert(manufacturer, decoder);
+    }
+
+    pub fn remove(&mut self, manufacturer: ManufacturerId) {
+        self.decoders.remove(&manufacturer);
+    }
+
+    /// Decodes the data of a sequencer-specific event, or returns `None` if
+    /// it doesn't start with a manufacturer ID.
+    pub fn decode<'a>(&self, data: &'a [u8]) -> Option<Sequencer<'a>> {
+        let (manufacturer, rest) = ManufacturerId::from_bytes(data)?;
+        let decoded = self.decoders.get(&manufacturer).and_then(|decode| decode(data));
+        Some(decoded.unwrap_or(Sequencer::Unknown {
+            manufacturer: manufacturer,
+            data: rest,
+        }))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+impl<'a> MetaEvent<'a> {
+    /// Decodes a sequencer-specific event with the decoders in `registry`.
+    pub fn sequencer_specific(&self, registry: &Registry) -> Option<Sequencer<'a>> {
+        match *self {
+            MetaEvent::SequencerSpecific { data } => registry.decode(data),
+            _ => None,
+        }
+    }
+}
+
+fn decode_yamaha(data: &[u8]) -> Option<Sequencer> {
+    xf::decode(data).map(Sequencer::Xf)
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_registry() {
+    fn decode_length(data: &[u8]) -> Option<Sequencer> {
+        Some(Sequencer::Custom(Box::new(data.len())))
+    }
+
+    let mut registry = Registry::new();
+    match registry.decode(&[0x43, 0x7B, 0x00, b'X', b'F', b'0', b'2', 0x00, 0x1B]) {
+        Some(Sequencer::Xf(XfMeta::Version { version, .. })) => assert_eq!(&version, b"02"),
+        other => panic!("expected an XF version, got {:?}", other),
+    }
+    match registry.decode(&[0x00, 0x00, 0x41, 0x01]) {
+        Some(Sequencer::Unknown { manufacturer, data }) => {
+            assert_eq!(manufacturer, ManufacturerId::Extended(0x00, 0x41));
+            assert_eq!(data, &[0x01]);
+        }
+        other => panic!("expected an unknown event, got {:?}", other),
+    }
+    registry.install(ManufacturerId::Extended(0x00, 0x41), decode_length);
+    match registry.decode(&[0x00, 0x00, 0x41, 0x01]) {
+        Some(Sequencer::Custom(value)) => assert_eq!(value.downcast_ref(), Some(&4usize)),
+        other => panic!("expected a custom event, got {:?}", other),
+    }
+    assert!(registry.decode(&[0x7F]).is_none());
+}
@@ -0,0 +1,414 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Chunk, ControlChange, Division, ErrorKind, Event, Header, Malformed, MetaEvent, Midi,
+     MidiEvent, SysexEvent, TrackChunk};
+use ump::{self, DataStatus, Midi2Event, Packet, Utility};
+
+
+// MIDI Clip Files /////////////////////////////////////////////////////////////
+
+/// A MIDI 2.0 clip file, which holds a single sequence of Universal MIDI
+/// Packets, each of them timed by the delta clockstamps before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clip {
+    pub ticks_per_quarter: u16,
+    /// Configuration packets from the clip header, other than the ticks per
+    /// quarter note declaration.
+    pub header: Vec<(u32, Packet)>,
+    /// Packets between the start and end of clip messages, paired with the
+    /// number of ticks since the packet before them. The delta clockstamps
+    /// that time them in the file are folded into those gaps, so none are
+    /// kept here, and writing the clip splits gaps too long for one.
+    pub events: Vec<(u32, Packet)>,
+}
+
+const START_OF_CLIP: u16 = 0x20;
+const END_OF_CLIP: u16 = 0x21;
+const MAX_CLOCKSTAMP: u64 = 0x000F_FFFF;
+
+/// The status of a UMP stream message, if the packet is one.
+fn stream_status(packet: &Packet) -> Option<u16> {
+    match *packet {
+        Packet::Raw { message_type: 0xF, words } => Some((words[0] >> 16) as u16 & 0x03FF),
+        _ => None,
+    }
+}
+
+pub fn parse_clip(input: &[u8]) -> Result<Clip, ErrorKind> {
+    if !input.starts_with(b"SMF2CLIP") {
+        return Err(ErrorKind::Tag);
+    }
+    let words = ump::words_from_bytes(&input[8..]);
+    let mut ticks_per_quarter = None;
+    let mut header = Vec::new();
+    let mut events = Vec::new();
+    let mut in_clip = false;
+    let mut finished = false;
+    let mut delta = 0u32;
+    let mut packets = ump::Packets::new(&words);
+    for packet in &mut packets {
+        match (packet, stream_status(&packet)) {
+            (Packet::Utility(Utility::DeltaClockstamp(ticks)), _) => {
+                delta = delta.saturating_add(ticks);
+                continue;
+            }
+            (Packet::Utility(Utility::DeltaClockstampTpq(tpq)), _) if !in_clip => {
+                ticks_per_quarter = Some(tpq);
+            }
+            (_, Some(START_OF_CLIP)) => in_clip = true,
+            (_, Some(END_OF_CLIP)) if in_clip => {
+                finished = true;
+                break;
+            }
+            (packet, _) if in_clip => events.push((delta, packet)),
+            (packet, _) => header.push((delta, packet)),
+        }
+        delta = 0;
+    }
+    match ticks_per_quarter {
+        Some(tpq) if finished => Ok(Clip {
+            ticks_per_quarter: tpq,
+            header: header,
+            events: events,
+        }),
+        _ => Err(ErrorKind::from(Malformed::IncompleteClip)),
+    }
+}
+
+
+impl Clip {
+    /// Writes the clip as a MIDI Clip File, with a delta clockstamp before
+    /// every packet.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words = Vec::new();
+        let write = |words: &mut Vec<u32>, delta: u32, packet: Packet| {
+            let mut delta = delta as u64;
+            while delta > MAX_CLOCKSTAMP {
+                Packet::Utility(Utility::DeltaClockstamp(MAX_CLOCKSTAMP as u32))
+                    .write_to(words);
+                delta -= MAX_CLOCKSTAMP;
+            }
+            Packet::Utility(Utility::DeltaClockstamp(delta as u32)).write_to(words);
+            packet.write_to(words);
+        };
+        let tpq = Packet::Utility(Utility::DeltaClockstampTpq(self.ticks_per_quarter));
+        write(&mut words, 0, tpq);
+        for &(delta, packet) in &self.header {
+            write(&mut words, delta, packet);
+        }
+        write(&mut words, 0, stream(START_OF_CLIP));
+        for &(delta, packet) in &self.events {
+            write(&mut words, delta, packet);
+        }
+        write(&mut words, 0, stream(END_OF_CLIP));
+        let mut out = b"SMF2CLIP".to_vec();
+        out.extend_from_slice(&ump::bytes_from_words(&words));
+        out
+    }
+}
+
+/// A UMP stream message with no data.
+fn stream(status: u16) -> Packet {
+    Packet::Raw {
+        message_type: 0xF,
+        words: [0xF000_0000 | (status as u32) << 16, 0, 0, 0],
+    }
+}
+
+
+// Conversion //////////////////////////////////////////////////////////////////
+
+static SYSEX_END: [u8; 1] = [0xF7];
+
+impl Clip {
+    /// Converts the clip into a single track format 0 file. MIDI 2.0 channel
+    /// voice messages are scaled down to MIDI 1.0 resolution, tempo and time
+    /// signature flex data become meta events, and packets with no classic
+    /// equivalent are dropped. Sysex packets become sysex continuation
+    /// events, borrowing their data from the clip.
+    pub fn to_midi(&self) -> Midi {
+        let mut events = Vec::with_capacity(self.events.len() + 1);
+        let mut delta = 0u32;
+        for &(dt, ref packet) in &self.events {
+            delta = delta.saturating_add(dt);
+            let converted = to_events(packet);
+            for (i, event) in converted.into_iter().enumerate() {
+                events.push(with_delta(event, if i == 0 { delta } else { 0 }));
+                delta = 0;
+            }
+        }
+        events.push(Event::Meta(delta, MetaEvent::EndOfTrack));
+        Midi {
+            header: Header {
+                len: 6,
+                format: 0,
+                tracks: 1,
+                division: self.ticks_per_quarter & 0x7FFF,
+            },
+            chunks: vec![Chunk::Track(TrackChunk { events: events.into() })],
+            rmid: None,
+        }
+    }
+
+    /// Merges every track of a file into one clip, carrying channel voice
+    /// messages over as MIDI 1.0 packets. Returns `None` for SMPTE timed
+    /// files, which clips can't represent.
+    pub fn from_midi(midi: &Midi) -> Option<Clip> {
+        let ticks_per_quarter = match midi.header().division() {
+            Division::TicksPerQuarter(n) => n,
+            Division::Smpte { .. } => return None,
+        };
+        let mut timed = Vec::new();
+        for track in midi.tracks() {
+            timed.extend(track.absolute_events());
+        }
+        // The sort is stable, so simultaneous events keep their track order.
+        timed.sort_by_key(|&(tick, _)| tick);
+        let mut events = Vec::new();
+        let mut last = 0;
+        for (tick, event) in timed {
+            let packets = from_event(event);
+            for (i, packet) in packets.into_iter().enumerate() {
+                let delta = if i == 0 { tick - last } else { 0 };
+                events.push((::std::cmp::min(delta, u32::max_value() as u64) as u32, packet));
+                last = tick;
+            }
+        }
+        Some(Clip {
+            ticks_per_quarter: ticks_per_quarter,
+            header: Vec::new(),
+            events: events,
+        })
+    }
+}
+
+fn with_delta(event: Event, delta: u32) -> Event {
+    match event {
+        Event::Midi(_, e) => Event::Midi(delta, e),
+        Event::Meta(_, e) => Event::Meta(delta, e),
+        Event::Sysex(_, e) => Event::Sysex(delta, e),
+    }
+}
+
+fn to_events(packet: &Packet) -> Vec<Event> {
+    match *packet {
+        Packet::Midi1 { event, .. } => vec![Event::Midi(0, event)],
+        Packet::Midi2 { event, .. } => midi2_to_midi1(event)
+            .into_iter()
+            .map(|event| Event::Midi(0, event))
+            .collect(),
+        Packet::Sysex7 { status, .. } => {
+            let start = match status {
+                DataStatus::Complete | DataStatus::Start => true,
+                _ => false,
+            };
+            let mut events = vec![Event::Sysex(0, SysexEvent {
+                start: start,
+                end: false,
+                data: packet.data(),
+            })];
+            if let DataStatus::Complete | DataStatus::End = status {
+                events.push(Event::Sysex(0, SysexEvent {
+                    start: false,
+                    end: true,
+                    data: &SYSEX_END,
+                }));
+            }
+            events
+        }
+        Packet::Raw { message_type: 0xD, words } => flex_to_meta(words)
+            .into_iter()
+            .map(|meta| Event::Meta(0, meta))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn flex_to_meta<'a>(words: [u32; 4]) -> Option<MetaEvent<'a>> {
+    let bank = (words[0] >> 8) as u8;
+    let status = words[0] as u8;
+    match (bank, status) {
+        // Tempo is given in units of 10 nanoseconds per quarter note.
+        (0x00, 0x00) => Some(MetaEvent::SetTempo(::std::cmp::min(words[1] / 100, 0x00FF_FFFF))),
+        (0x00, 0x01) => Some(MetaEvent::TimeSignature {
+            numerator: (words[1] >> 24) as u8,
+            denominator: (words[1] >> 16) as u8,
+            clocks_per_metronome: 24,
+            notated_divisions: (words[1] >> 8) as u8,
+        }),
+        _ => None,
+    }
+}
+
+fn scale_down(value: u32, bits: u32) -> u8 {
+    (value >> (32 - bits)) as u8
+}
+
+fn midi2_to_midi1(event: Midi2Event) -> Vec<MidiEvent> {
+    match event {
+        Midi2Event::NoteOn { channel, note, velocity, .. } => vec![MidiEvent::NoteOn {
+            channel: channel,
+            number: note & 0x7F,
+            // A MIDI 2.0 note on with a tiny velocity is still a note on.
+            velocity: ::std::cmp::max(1, (velocity >> 9) as u8),
+        }],
+        Midi2Event::NoteOff { channel, note, velocity, .. } => vec![MidiEvent::NoteOff {
+            channel: channel,
+            number: note & 0x7F,
+            velocity: (velocity >> 9) as u8,
+        }],
+        Midi2Event::PolyPressure { channel, note, pressure } => {
+            vec![MidiEvent::PolyphonicAftertouch {
+                     channel: channel,
+                     number: note & 0x7F,
+                     pressure: scale_down(pressure, 7),
+                 }]
+        }
+        Midi2Event::ControlChange { channel, index, value } => vec![MidiEvent::Control {
+            channel: channel,
+            change: ControlChange::from_parts(index & 0x7F, scale_down(value, 7)),
+        }],
+        Midi2Event::ProgramChange { channel, program, bank } => {
+            let mut events = Vec::new();
+            if let Some((msb, lsb)) = bank {
+                events.push(MidiEvent::Control {
+                    channel: channel,
+                    change: ControlChange::Raw(0, msb & 0x7F),
+                });
+                events.push(MidiEvent::Control {
+                    channel: channel,
+                    change: ControlChange::Raw(32, lsb & 0x7F),
+                });
+            }
+            events.push(MidiEvent::ProgramChange {
+                channel: channel,
+                program_number: program & 0x7F,
+            });
+            events
+        }
+        Midi2Event::ChannelPressure { channel, pressure } => vec![MidiEvent::ChannelAftertouch {
+            channel: channel,
+            pressure: scale_down(pressure, 7),
+        }],
+        Midi2Event::PitchBend { channel, value } => vec![MidiEvent::PitchBend {
+            channel: channel,
+            pitch: (value >> 18) as u16,
+        }],
+        _ => Vec::new(),
+    }
+}
+
+fn from_event(event: &Event) -> Vec<Packet> {
+    match *event {
+        Event::Midi(_, event) => vec![Packet::Midi1 {
+            group: 0,
+            event: event,
+        }],
+        Event::Meta(_, MetaEvent::SetTempo(tempo)) => {
+            vec![flex(0x00, [tempo.saturating_mul(100), 0, 0])]
+        }
+        Event::Meta(_, MetaEvent::TimeSignature { numerator, denominator, notated_divisions, .. }) => {
+            let word = (numerator as u32) << 24 | (denominator as u32) << 16 |
+                       (notated_divisions as u32) << 8;
+            vec![flex(0x01, [word, 0, 0])]
+        }
+        Event::Sysex(_, ref sysex) if sysex.is_start() && sysex.is_end() => {
+            let payload = sysex.payload();
+            let count = ::std::cmp::max(1, (payload.len() + 5) / 6);
+            payload.chunks(6).chain(if payload.is_empty() { Some(&[][..]) } else { None })
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let status = match (i == 0, i + 1 == count) {
+                        (true, true) => DataStatus::Complete,
+                        (true, false) => DataStatus::Start,
+                        (false, false) => DataStatus::Continue,
+                        (false, true) => DataStatus::End,
+                    };
+                    let mut data = [0; 6];
+                    data[..chunk.len()].copy_from_slice(chunk);
+                    Packet::Sysex7 {
+                        group: 0,
+                        status: status,
+                        len: chunk.len() as u8,
+                        data: data,
+                    }
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// A flex data packet addressed to the whole group, in the setup and
+/// performance status bank.
+fn flex(status: u8, data: [u32; 3]) -> Packet {
+    Packet::Raw {
+        message_type: 0xD,
+        words: [0xD010_0000 | status as u32, data[0], data[1], data[2]],
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_parse_clip() {
+    let words: &[u32] = &[
+        0x0040_0000, 0x0030_0060,
+        0x0040_0000, 0xF020_0000, 0x0000_0000, 0x0000_0000, 0x0000_0000,
+        0x0040_0000, 0x2090_3C40,
+        0x0040_0060, 0x4080_3C00, 0x0000_0000,
+        0x0040_0000, 0xF021_0000, 0x0000_0000, 0x0000_0000, 0x0000_0000,
+    ];
+    let mut bytes = b"SMF2CLIP".to_vec();
+    for &word in words {
+        bytes.extend_from_slice(&[(word >> 24) as u8, (word >> 16) as u8,
+                                  (word >> 8) as u8, word as u8]);
+    }
+    let clip = parse_clip(&bytes).unwrap();
+    assert_eq!(clip.to_bytes(), bytes);
+    assert_eq!(clip.ticks_per_quarter, 0x60);
+    assert_eq!(clip.events.len(), 2);
+    assert_eq!(clip.events[1].0, 0x60);
+
+    let midi = clip.to_midi();
+    let track = midi.tracks().next().unwrap();
+    assert_eq!(track.events(), &[
+        Event::Midi(0, MidiEvent::NoteOn {
+            channel: 0,
+            number: 0x3C,
+            velocity: 0x40,
+        }),
+        Event::Midi(0x60, MidiEvent::NoteOff {
+            channel: 0,
+            number: 0x3C,
+            velocity: 0,
+        }),
+        Event::Meta(0, MetaEvent::EndOfTrack),
+    ][..]);
+
+    let round_trip = Clip::from_midi(&midi).unwrap();
+    assert_eq!(round_trip.events[0].1, clip.events[0].1);
+
+    // A gap too long for one clockstamp keeps its length.
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0F,
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x80, 0x80, 0x00, 0x80, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let clip = Clip::from_midi(&::parse_midi(&data).unwrap()).unwrap();
+    assert_eq!(clip.events.len(), 2);
+    assert_eq!(clip.events[1].0, 0x20_0000);
+    let bytes = clip.to_bytes();
+    let words = ump::words_from_bytes(&bytes[8..]);
+    let clockstamp = Packet::Utility(Utility::DeltaClockstamp(0x000F_FFFF));
+    let packets: Vec<_> = ump::Packets::new(&words).collect();
+    assert_eq!(&packets[6..8], &[clockstamp, clockstamp]);
+    assert_eq!(parse_clip(&bytes), Ok(clip.clone()));
+    let midi = clip.to_midi();
+    assert_eq!(midi.tracks().next().unwrap().events()[1].delta(), 0x20_0000);
+}
@@ -0,0 +1,159 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Midi, ParseError};
+
+
+// File Sniffing ///////////////////////////////////////////////////////////////
+
+/// The kinds of MIDI file that `sniff` recognizes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// A Standard MIDI File, starting with an MThd chunk.
+    Smf,
+    /// A RIFF MIDI file, wrapping a Standard MIDI File.
+    Rmid,
+}
+
+/// Guesses what kind of MIDI file some bytes are from their first few bytes
+/// alone, without reading any tracks. The header has to be plausible too,
+/// with a known format, some tracks and a nonzero division, so a file that
+/// just happens to start with the right letters isn't taken for MIDI. A file
+/// that sniffs as MIDI can still fail to parse.
+pub fn sniff(input: &[u8]) -> Option<Kind> {
+    if input.starts_with(b"RIFF") {
+        if input.len() >= 12 && &input[8..12] == b"RMID" {
+            return Some(Kind::Rmid);
+        }
+        return None;
+    }
+    if input.len() < 14 || &input[..4] != b"MThd" {
+        return None;
+    }
+    let len = be_u32(&input[4..8]);
+    let format = be_u16(&input[8..10]);
+    let tracks = be_u16(&input[10..12]);
+    let division = be_u16(&input[12..14]);
+    // Leave some room for headers that are longer than they should be.
+    if len < 6 || len > 0xFF || format > 2 || tracks == 0 || division == 0 {
+        return None;
+    }
+    Some(Kind::Smf)
+}
+
+/// Whether some bytes look like any kind of MIDI file.
+pub fn is_midi(input: &[u8]) -> bool {
+    sniff(input).is_some()
+}
+
+/// A Standard MIDI File found inside other data by `find_embedded`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Embedded<'a> {
+    /// Where the file starts in the data searched.
+    pub offset: usize,
+    /// The bytes of the file, from its header to the end of its last track.
+    pub data: &'a [u8],
+}
+
+impl<'a> Embedded<'a> {
+    pub fn parse(&self) -> Result<Midi<'a>, ParseError> {
+        ::parse_midi(self.data)
+    }
+}
+
+/// Searches any data, such as a game ROM or a resource archive, for the
+/// Standard MIDI Files inside it. A file is an MThd header that sniffs as
+/// plausible followed by at least one whole track chunk, and it runs until
+/// it has as many tracks as the header says, or until the data stops looking
+/// like chunks. Files are returned in order and never overlap.
+pub fn find_embedded(input: &[u8]) -> Vec<Embedded> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(at) = find(&input[offset..], b"MThd") {
+        let start = offset + at;
+        match embedded_len(&input[start..]) {
+            Some(len) => {
+                found.push(Embedded {
+                    offset: start,
+                    data: &input[start..start + len],
+                });
+                offset = start + len;
+            }
+            None => offset = start + 1,
+        }
+    }
+    found
+}
+
+/// How long the file at the start of some data is, if it has a plausible
+/// header and a whole track.
+fn embedded_len(input: &[u8]) -> Option<usize> {
+    if sniff(input) != Some(Kind::Smf) {
+        return None;
+    }
+    let tracks = be_u16(&input[10..12]);
+    let mut end = 8 + be_u32(&input[4..8]) as usize;
+    let mut found = 0;
+    while found < tracks && end + 8 <= input.len() {
+        let id = &input[end..end + 4];
+        let chunk_like = id.iter().all(|&b| b.is_ascii_alphanumeric() || b == b' ');
+        if !chunk_like || id == b"MThd" {
+            break;
+        }
+        let len = be_u32(&input[end + 4..end + 8]) as usize;
+        if len > input.len() - end - 8 {
+            break;
+        }
+        if id == b"MTrk" {
+            found += 1;
+        }
+        end += 8 + len;
+    }
+    if found > 0 { Some(end) } else { None }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) << 8 | bytes[1] as u16
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    (be_u16(&bytes[..2]) as u32) << 16 | be_u16(&bytes[2..4]) as u32
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_sniff() {
+    let smf = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+    ];
+    assert_eq!(sniff(&smf), Some(Kind::Smf));
+    assert_eq!(sniff(&smf[..13]), None);
+    let mut bad = smf;
+    bad[9] = 7;
+    assert_eq!(sniff(&bad), None);
+    assert_eq!(sniff(b"RIFF\x24\0\0\0RMIDdata"), Some(Kind::Rmid));
+    assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "), None);
+    assert!(is_midi(&::gen::scale(60, &::gen::MAJOR).to_bytes()));
+    assert!(!is_midi(b"MThd, but not really a MIDI file"));
+
+    let song = ::gen::scale(60, &::gen::MAJOR).to_bytes();
+    let chords = ::gen::chords(&[60, 65]).to_bytes();
+    let mut rom = b"\x00\x01MThd\x00\x00\x00\x06junk".to_vec();
+    rom.extend_from_slice(&song);
+    rom.extend_from_slice(b"\xFF\xFE");
+    rom.extend_from_slice(&smf);
+    rom.extend_from_slice(&chords);
+    let found = find_embedded(&rom);
+    let offsets: Vec<_> = found.iter().map(|embedded| embedded.offset).collect();
+    let second = rom.len() - chords.len();
+    assert_eq!(offsets, [14, second]);
+    assert_eq!(found[0].data, &song[..]);
+    assert_eq!(found[1].parse().unwrap(), ::gen::chords(&[60, 65]));
+}
@@ -0,0 +1,136 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {event_len, parse_midi, var_length, ParseError};
+
+
+// Byte Spans //////////////////////////////////////////////////////////////////
+
+/// The bytes that each part of a file was parsed from. The chunks and their
+/// events line up with the chunks and events of the parsed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spans<'a> {
+    /// The MThd chunk, including its type and length.
+    pub header: &'a [u8],
+    pub chunks: Vec<ChunkSpan<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSpan<'a> {
+    /// Where the chunk starts, counting from the start of the input.
+    pub offset: usize,
+    /// The chunk's type and length.
+    pub header: &'a [u8],
+    pub data: &'a [u8],
+    /// The events of a track chunk, or nothing for other chunks.
+    pub events: Vec<EventSpan<'a>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EventSpan<'a> {
+    /// Where the event starts, counting from the start of the input.
+    pub offset: usize,
+    /// The whole event, including its delta time.
+    pub bytes: &'a [u8],
+    delta_len: usize,
+}
+
+impl<'a> EventSpan<'a> {
+    pub fn delta(&self) -> &'a [u8] {
+        &self.bytes[..self.delta_len]
+    }
+
+    /// The status and data bytes. Under running status there's no status
+    /// byte, so this is only the data.
+    pub fn message(&self) -> &'a [u8] {
+        &self.bytes[self.delta_len..]
+    }
+
+    pub fn is_running_status(&self) -> bool {
+        self.message().first().map_or(false, |&b| b < 0x80)
+    }
+}
+
+impl<'a> ChunkSpan<'a> {
+    pub fn is_track(&self) -> bool {
+        &self.header[..4] == b"MTrk"
+    }
+}
+
+/// Finds the bytes behind every chunk and event of a file, failing the same
+/// way `parse_midi` does for files that don't parse. Offsets in an RMID file
+/// count from the start of the RIFF container.
+pub fn spans(input: &[u8]) -> Result<Spans, ParseError> {
+    parse_midi(input)?;
+    let start = if input.starts_with(b"RIFF") {
+        input.windows(4).position(|window| window == b"MThd").unwrap_or(0)
+    } else {
+        0
+    };
+    let mut spans = Spans {
+        header: &input[start..start + 14],
+        chunks: Vec::new(),
+    };
+    let mut offset = start + 14;
+    while offset + 8 <= input.len() {
+        let header = &input[offset..offset + 8];
+        let len = (header[4] as usize) << 24 | (header[5] as usize) << 16 |
+                  (header[6] as usize) << 8 | header[7] as usize;
+        if offset + 8 + len > input.len() {
+            break;
+        }
+        let data = &input[offset + 8..offset + 8 + len];
+        let mut chunk = ChunkSpan {
+            offset: offset,
+            header: header,
+            data: data,
+            events: Vec::new(),
+        };
+        if chunk.is_track() {
+            let mut pos = 0;
+            let mut running_status = None;
+            while let Some(len) = event_len(&data[pos..], &mut running_status) {
+                let bytes = &data[pos..pos + len];
+                let delta_len = match var_length(bytes) {
+                    Ok((rest, _)) => bytes.len() - rest.len(),
+                    _ => 0,
+                };
+                chunk.events.push(EventSpan {
+                    offset: offset + 8 + pos,
+                    bytes: bytes,
+                    delta_len: delta_len,
+                });
+                pos += len;
+            }
+        }
+        spans.chunks.push(chunk);
+        offset += 8 + len;
+    }
+    Ok(spans)
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_spans() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x00, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let found = spans(&data).unwrap();
+    assert_eq!(found.header, &data[..14]);
+    let track = &found.chunks[0];
+    assert_eq!((track.offset, track.header.len(), track.data.len()), (14, 8, 12));
+    assert_eq!(track.events.len(), 3);
+    let event = track.events[1];
+    assert_eq!(event.offset, 26);
+    assert_eq!((event.delta(), event.message()), (&[0x81, 0x00][..], &[0x3C, 0x00][..]));
+    assert!(event.is_running_status());
+    assert!(!track.events[2].is_running_status());
+    assert!(spans(&data[..30]).is_err());
+}
@@ -0,0 +1,233 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut, Range, RangeBounds};
+use std::slice;
+use std::sync::Arc;
+use std::vec;
+
+use {Event, MetaEvent};
+
+
+// Event Lists /////////////////////////////////////////////////////////////////
+
+/// How many events a track can hold in place with `Storage::Inline`.
+pub const INLINE_EVENTS: usize = 8;
+
+/// The events of a track. They're usually in a `Vec` of their own, but
+/// short tracks can keep them in place and the tracks of a file can share
+/// one arena, depending on the `Storage` they were parsed with. Either way
+/// they read as a slice, and anything that changes the number of events
+/// moves them into a `Vec` first.
+#[derive(Clone)]
+pub struct EventList<'a> {
+    repr: Repr<'a>,
+}
+
+#[derive(Clone)]
+enum Repr<'a> {
+    Heap(Vec<Event<'a>>),
+    Inline(usize, [Event<'a>; INLINE_EVENTS]),
+    Arena(Arc<Vec<Event<'a>>>, Range<usize>),
+}
+
+impl<'a> EventList<'a> {
+    pub fn new() -> EventList<'a> {
+        EventList { repr: Repr::Heap(Vec::new()) }
+    }
+
+    pub fn with_capacity(capacity: usize) -> EventList<'a> {
+        EventList { repr: Repr::Heap(Vec::with_capacity(capacity)) }
+    }
+
+    /// An empty list that holds its first `INLINE_EVENTS` events in place.
+    pub fn inline() -> EventList<'a> {
+        let pad = || Event::Meta(0, MetaEvent::EndOfTrack);
+        EventList {
+            repr: Repr::Inline(0, [pad(), pad(), pad(), pad(), pad(), pad(), pad(), pad()]),
+        }
+    }
+
+    /// The events in `range` of an arena shared with other lists.
+    pub fn from_arena(arena: Arc<Vec<Event<'a>>>, range: Range<usize>) -> EventList<'a> {
+        assert!(range.start <= range.end && range.end <= arena.len());
+        EventList { repr: Repr::Arena(arena, range) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        match self.repr {
+            Repr::Heap(ref events) => events.capacity(),
+            Repr::Inline(..) => INLINE_EVENTS,
+            Repr::Arena(_, ref range) => range.len(),
+        }
+    }
+
+    /// Whether the events are kept in place, without an allocation.
+    pub fn is_inline(&self) -> bool {
+        match self.repr {
+            Repr::Inline(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the events are part of an arena shared by several lists.
+    pub fn is_shared(&self) -> bool {
+        match self.repr {
+            Repr::Arena(..) => true,
+            _ => false,
+        }
+    }
+
+    /// The events as a `Vec`, moving them into one if they aren't already.
+    pub fn to_mut(&mut self) -> &mut Vec<Event<'a>> {
+        let events = match self.repr {
+            Repr::Heap(_) => None,
+            _ => Some(self.to_vec()),
+        };
+        if let Some(events) = events {
+            self.repr = Repr::Heap(events);
+        }
+        match self.repr {
+            Repr::Heap(ref mut events) => events,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<Event<'a>> {
+        match self.repr {
+            Repr::Heap(events) => events,
+            _ => self.to_vec(),
+        }
+    }
+
+    pub fn push(&mut self, event: Event<'a>) {
+        if let Repr::Inline(ref mut len, ref mut events) = self.repr {
+            if *len < INLINE_EVENTS {
+                events[*len] = event;
+                *len += 1;
+                return;
+            }
+        }
+        self.to_mut().push(event);
+    }
+
+    pub fn pop(&mut self) -> Option<Event<'a>> {
+        self.to_mut().pop()
+    }
+
+    pub fn insert(&mut self, index: usize, event: Event<'a>) {
+        self.to_mut().insert(index, event);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Event<'a> {
+        self.to_mut().remove(index)
+    }
+
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> vec::Drain<Event<'a>> {
+        self.to_mut().drain(range)
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        if let Repr::Heap(ref mut events) = self.repr {
+            events.shrink_to_fit();
+        }
+    }
+}
+
+impl<'a> Default for EventList<'a> {
+    fn default() -> EventList<'a> {
+        EventList::new()
+    }
+}
+
+impl<'a> Deref for EventList<'a> {
+    type Target = [Event<'a>];
+
+    fn deref(&self) -> &[Event<'a>] {
+        match self.repr {
+            Repr::Heap(ref events) => events,
+            Repr::Inline(len, ref events) => &events[..len],
+            Repr::Arena(ref arena, ref range) => &arena[range.clone()],
+        }
+    }
+}
+
+impl<'a> DerefMut for EventList<'a> {
+    /// Changing events in an arena copies them out of it, since the arena is
+    /// shared.
+    fn deref_mut(&mut self) -> &mut [Event<'a>] {
+        if let Repr::Arena(..) = self.repr {
+            self.to_mut();
+        }
+        match self.repr {
+            Repr::Heap(ref mut events) => events,
+            Repr::Inline(len, ref mut events) => &mut events[..len],
+            Repr::Arena(..) => unreachable!(),
+        }
+    }
+}
+
+impl<'a> From<Vec<Event<'a>>> for EventList<'a> {
+    fn from(events: Vec<Event<'a>>) -> EventList<'a> {
+        EventList { repr: Repr::Heap(events) }
+    }
+}
+
+impl<'a> FromIterator<Event<'a>> for EventList<'a> {
+    fn from_iter<I: IntoIterator<Item = Event<'a>>>(events: I) -> EventList<'a> {
+        EventList::from(events.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<'m, 'a> IntoIterator for &'m EventList<'a> {
+    type Item = &'m Event<'a>;
+    type IntoIter = slice::Iter<'m, Event<'a>>;
+
+    fn into_iter(self) -> slice::Iter<'m, Event<'a>> {
+        self.iter()
+    }
+}
+
+impl<'a> PartialEq for EventList<'a> {
+    fn eq(&self, other: &EventList<'a>) -> bool {
+        **self == **other
+    }
+}
+
+impl<'a> Eq for EventList<'a> {}
+
+impl<'a> fmt::Debug for EventList<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_event_list() {
+    let event = |delta| Event::Meta(delta, MetaEvent::EndOfTrack);
+    let mut inline = EventList::inline();
+    for delta in 0..INLINE_EVENTS as u32 {
+        inline.push(event(delta));
+    }
+    assert!(inline.is_inline());
+    assert_eq!(inline.len(), INLINE_EVENTS);
+    inline[0] = event(10);
+    assert_eq!(inline[0], event(10));
+    inline.push(event(8));
+    assert!(!inline.is_inline());
+    assert_eq!(inline.last(), Some(&event(8)));
+
+    let arena = Arc::new((0..6).map(event).collect::<Vec<_>>());
+    let mut first = EventList::from_arena(arena.clone(), 0..2);
+    let second = EventList::from_arena(arena.clone(), 2..6);
+    assert_eq!(first, EventList::from(vec![event(0), event(1)]));
+    assert_eq!(second.len(), 4);
+    first[1] = event(7);
+    assert!(!first.is_shared() && second.is_shared());
+    assert_eq!((&first[..], arena[1].clone()), (&[event(0), event(7)][..], event(1)));
+}
@@ -0,0 +1,132 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {midi_event, MidiEvent, SystemMessage};
+
+
+// Live Byte Streams ///////////////////////////////////////////////////////////
+
+/// A complete message read off the wire.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Message<'a> {
+    Channel(MidiEvent),
+    System(SystemMessage),
+    /// The body of a system exclusive message, without the F0 and F7.
+    Sysex(&'a [u8]),
+}
+
+/// Parses a serial or USB MIDI byte stream as it arrives, one byte at a
+/// time. Unlike in files, real-time messages can show up in the middle of
+/// any other message, and running status carries across messages until a
+/// system common message or sysex clears it.
+#[derive(Debug, Clone, Default)]
+pub struct Parser {
+    status: Option<u8>,
+    data: [u8; 2],
+    len: usize,
+    in_sysex: bool,
+    sysex: Vec<u8>,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser::default()
+    }
+
+    /// Feeds the next byte of the stream, returning a message if the byte
+    /// completed one. Stray data bytes with no status to apply to are
+    /// dropped, as are sysex messages cut off by another status byte.
+    pub fn feed(&mut self, byte: u8) -> Option<Message> {
+        match byte {
+            0xF8...0xFF => return SystemMessage::from_parts(byte, 0, 0).map(Message::System),
+            0xF0 => {
+                self.status = None;
+                self.in_sysex = true;
+                self.sysex.clear();
+                return None;
+            }
+            0xF7 => {
+                self.status = None;
+                if self.in_sysex {
+                    self.in_sysex = false;
+                    return Some(Message::Sysex(&self.sysex));
+                }
+                return None;
+            }
+            0x80...0xF6 => {
+                self.in_sysex = false;
+                self.status = Some(byte);
+                self.len = 0;
+            }
+            _ if self.in_sysex => {
+                self.sysex.push(byte);
+                return None;
+            }
+            _ => match self.status {
+                Some(_) => {
+                    self.data[self.len] = byte;
+                    self.len += 1;
+                }
+                None => return None,
+            },
+        }
+        let status = self.status?;
+        if self.len < data_len(status) {
+            return None;
+        }
+        self.len = 0;
+        if status >= 0xF0 {
+            // System common messages cancel running status.
+            self.status = None;
+            SystemMessage::from_parts(status, self.data[0], self.data[1]).map(Message::System)
+        } else {
+            match midi_event(&self.data[..data_len(status)], status) {
+                Ok((_, event)) => Some(Message::Channel(event)),
+                _ => None,
+            }
+        }
+    }
+
+    /// Whether the parser is partway through a message.
+    pub fn is_pending(&self) -> bool {
+        self.in_sysex || self.len > 0
+    }
+}
+
+fn data_len(status: u8) -> usize {
+    match status {
+        0xC0...0xDF => 1,
+        0x80...0xEF => 2,
+        _ => SystemMessage::data_len(status),
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_stream() {
+    let note = |number| {
+        Some(Message::Channel(MidiEvent::NoteOn {
+            channel: 0,
+            number: number,
+            velocity: 0x40,
+        }))
+    };
+    let mut parser = Parser::new();
+    assert_eq!(parser.feed(0x90), None);
+    assert_eq!(parser.feed(0x3C), None);
+    assert_eq!(parser.feed(0xF8), Some(Message::System(SystemMessage::TimingClock)));
+    assert_eq!(parser.feed(0x40), note(0x3C));
+    assert_eq!(parser.feed(0x3E), None);
+    assert_eq!(parser.feed(0x40), note(0x3E));
+    assert_eq!(parser.feed(0xF0), None);
+    assert_eq!(parser.feed(0x7E), None);
+    assert_eq!(parser.feed(0xFE), Some(Message::System(SystemMessage::ActiveSensing)));
+    assert_eq!(parser.feed(0x09), None);
+    assert_eq!(parser.feed(0xF7), Some(Message::Sysex(&[0x7E, 0x09])));
+    // Sysex cancels running status.
+    assert_eq!(parser.feed(0x40), None);
+    assert_eq!(parser.feed(0xF6), Some(Message::System(SystemMessage::TuneRequest)));
+    assert!(!parser.is_pending());
+}
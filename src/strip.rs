@@ -0,0 +1,202 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Chunk, Event, MetaEvent, Midi, TextType, TrackChunk};
+use write::{spacer, MAX_DELTA};
+
+
+// Stripping Metadata //////////////////////////////////////////////////////////
+
+/// What to do with one category of text events.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Keep,
+    Remove,
+    /// Keeps the event but empties its text, so a track is still named,
+    /// just not by anything.
+    Redact,
+}
+
+/// Which kinds of events `strip_metadata` takes out of a file. The default
+/// removes everything but lyrics and sysex messages, which can matter to how
+/// a file sounds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StripOptions {
+    /// Plain text, markers, cue points and unknown kinds of text.
+    pub text: Action,
+    pub copyright: Action,
+    /// Track, instrument, program and device names.
+    pub names: Action,
+    pub lyrics: Action,
+    pub sequencer_specific: bool,
+    /// Every sysex message, including F7 escapes.
+    pub sysex: bool,
+    /// The chunks of an RMID container other than a DLS bank, such as its
+    /// INFO list.
+    pub container: bool,
+}
+
+impl Default for StripOptions {
+    fn default() -> StripOptions {
+        StripOptions {
+            text: Action::Remove,
+            copyright: Action::Remove,
+            names: Action::Remove,
+            lyrics: Action::Keep,
+            sequencer_specific: true,
+            sysex: false,
+            container: true,
+        }
+    }
+}
+
+impl StripOptions {
+    fn text_action(&self, kind: TextType) -> Action {
+        match kind {
+            TextType::Copyright => self.copyright,
+            TextType::TrackName | TextType::InstrumentName | TextType::ProgramName |
+            TextType::DeviceName => self.names,
+            TextType::Lyric => self.lyrics,
+            TextType::Text | TextType::Marker | TextType::CuePoint | TextType::Other(_) => {
+                self.text
+            }
+        }
+    }
+}
+
+impl<'a> TrackChunk<'a> {
+    /// Strips the track's metadata, moving each removed event's delta onto
+    /// the next event so everything else keeps its tick. Where that's more
+    /// time than one delta holds, an empty text event is left to carry it.
+    /// Returns how many events were removed or redacted.
+    pub fn strip_metadata(&mut self, options: &StripOptions) -> usize {
+        let mut changed = 0;
+        let mut carry = 0u32;
+        let mut events = Vec::with_capacity(self.events.len());
+        for mut event in self.events.drain(..) {
+            let action = match event {
+                Event::Meta(_, MetaEvent::Text { kind, .. }) => options.text_action(kind),
+                Event::Meta(_, MetaEvent::SequencerSpecific { .. }) => {
+                    if options.sequencer_specific { Action::Remove } else { Action::Keep }
+                }
+                Event::Sysex(..) if options.sysex => Action::Remove,
+                _ => Action::Keep,
+            };
+            match action {
+                Action::Keep => {}
+                Action::Remove => {
+                    if carry + event.delta() > MAX_DELTA {
+                        events.push(spacer(carry));
+                        carry = 0;
+                    }
+                    carry += event.delta();
+                    changed += 1;
+                    continue;
+                }
+                Action::Redact => {
+                    if let Event::Meta(_, MetaEvent::Text { ref mut text, .. }) = event {
+                        if !text.is_empty() {
+                            *text = b"";
+                            changed += 1;
+                        }
+                    }
+                }
+            }
+            if carry + event.delta() > MAX_DELTA {
+                events.push(spacer(carry));
+            } else if carry > 0 {
+                let delta = event.delta() + carry;
+                event.set_delta(delta);
+            }
+            carry = 0;
+            events.push(event);
+        }
+        self.events = events.into();
+        changed
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// Strips metadata from every track, for publishing a file without its
+    /// names, comments or the sequencer it was made with. Returns how many
+    /// events were removed or redacted.
+    pub fn strip_metadata(&mut self, options: &StripOptions) -> usize {
+        let mut changed = 0;
+        for chunk in &mut self.chunks {
+            if let Chunk::Track(ref mut track) = *chunk {
+                changed += track.strip_metadata(options);
+            }
+        }
+        if options.container {
+            if let Some(ref mut rmid) = self.rmid {
+                rmid.chunks.retain(|chunk| {
+                    chunk.id == b"DLS " || (chunk.id == b"RIFF" && chunk.data.starts_with(b"DLS "))
+                });
+            }
+        }
+        changed
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_strip_metadata() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x2A,
+        0x00, 0xFF, 0x03, 0x03, 0x41, 0x42, 0x43,
+        0x00, 0xFF, 0x02, 0x02, 0x28, 0x63,
+        0x00, 0x90, 0x3C, 0x40,
+        0x10, 0xFF, 0x7F, 0x02, 0x00, 0x41,
+        0x10, 0xFF, 0x05, 0x02, 0x6C, 0x61,
+        0x10, 0x80, 0x3C, 0x40,
+        0x00, 0xF0, 0x02, 0x7E, 0xF7,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let original = ::parse_midi(&data).unwrap();
+    let mut midi = ::parse_midi(&data).unwrap();
+    assert_eq!(midi.strip_metadata(&StripOptions::default()), 3);
+    {
+        let track = midi.tracks().next().unwrap();
+        assert_eq!(track.events().len(), 5);
+        assert_eq!(track.duration(), original.tracks().next().unwrap().duration());
+        assert_eq!(midi.notes(), original.notes());
+        let ticks: Vec<_> = track.absolute_events().map(|(tick, _)| tick).collect();
+        assert_eq!(ticks, [0x00, 0x20, 0x30, 0x30, 0x30]);
+    }
+
+    let mut midi = ::parse_midi(&data).unwrap();
+    let options = StripOptions {
+        names: Action::Redact,
+        copyright: Action::Keep,
+        sysex: true,
+        ..StripOptions::default()
+    };
+    assert_eq!(midi.strip_metadata(&options), 3);
+    let track = midi.tracks().next().unwrap();
+    assert_eq!(track.events()[0], Event::Meta(0, MetaEvent::Text {
+        kind: TextType::TrackName,
+        text: b"",
+    }));
+    assert_eq!(track.events().len(), 6);
+
+    // Gaps too long for one delta are left to empty text events.
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x18,
+        0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0x03, 0x01, 0x41,
+        0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0x7F, 0x01, 0x00,
+        0x01, 0x90, 0x3C, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let mut midi = ::parse_midi(&data).unwrap();
+    assert_eq!(midi.strip_metadata(&StripOptions::default()), 2);
+    let track = midi.tracks().next().unwrap();
+    assert_eq!(track.events()[..2], [::write::spacer(MAX_DELTA), ::write::spacer(MAX_DELTA)]);
+    let ticks: Vec<_> = track.absolute_events().map(|(tick, _)| tick).collect();
+    assert_eq!(ticks, [0x0FFF_FFFF, 0x1FFF_FFFE, 0x1FFF_FFFF, 0x1FFF_FFFF]);
+}
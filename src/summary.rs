@@ -0,0 +1,200 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+
+use {Event, Midi, MidiEvent, TrackChunk};
+
+
+// Summaries ///////////////////////////////////////////////////////////////////
+
+/// How many events of each kind there are.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct EventCounts {
+    pub note_on: usize,
+    /// Note offs, including note ons with a velocity of zero.
+    pub note_off: usize,
+    pub polyphonic_aftertouch: usize,
+    pub channel_aftertouch: usize,
+    pub control: usize,
+    pub program_change: usize,
+    pub pitch_bend: usize,
+    /// Messages without a status byte of their own.
+    pub previous: usize,
+    pub meta: usize,
+    pub sysex: usize,
+}
+
+impl EventCounts {
+    pub fn total(&self) -> usize {
+        self.note_on + self.note_off + self.polyphonic_aftertouch + self.channel_aftertouch +
+        self.control + self.program_change + self.pitch_bend + self.previous + self.meta +
+        self.sysex
+    }
+
+    fn add(&mut self, other: &EventCounts) {
+        self.note_on += other.note_on;
+        self.note_off += other.note_off;
+        self.polyphonic_aftertouch += other.polyphonic_aftertouch;
+        self.channel_aftertouch += other.channel_aftertouch;
+        self.control += other.control;
+        self.program_change += other.program_change;
+        self.pitch_bend += other.pitch_bend;
+        self.previous += other.previous;
+        self.meta += other.meta;
+        self.sysex += other.sysex;
+    }
+}
+
+/// Statistics about a track, or about a whole file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackSummary {
+    pub events: EventCounts,
+    /// The absolute ticks of the first and last channel messages, which is
+    /// where the music starts and ends, or `None` without any.
+    pub first_tick: Option<u64>,
+    pub last_tick: Option<u64>,
+    /// The absolute tick of the end of the track.
+    pub duration: u64,
+    /// The size of the track chunk as `to_bytes` writes it, header
+    /// included.
+    pub bytes: usize,
+    /// The channels with messages on them, in order.
+    pub channels: Vec<u8>,
+    /// The programs chosen, as pairs of a channel and a program, in order.
+    pub programs: Vec<(u8, u8)>,
+}
+
+/// The summary of each track in a file, and of all of them together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileSummary {
+    pub tracks: Vec<TrackSummary>,
+    pub total: TrackSummary,
+}
+
+impl<'a> TrackChunk<'a> {
+    pub fn summary(&self) -> TrackSummary {
+        let mut summary = TrackSummary {
+            bytes: self.to_bytes().len() + 8,
+            ..TrackSummary::default()
+        };
+        for (tick, event) in self.absolute_events() {
+            summary.duration = tick;
+            let counts = &mut summary.events;
+            let midi = match *event {
+                Event::Midi(_, ref midi) => midi,
+                Event::Meta(..) => {
+                    counts.meta += 1;
+                    continue;
+                }
+                Event::Sysex(..) => {
+                    counts.sysex += 1;
+                    continue;
+                }
+            };
+            match *midi {
+                MidiEvent::NoteOn { .. } if event.is_note_on() => counts.note_on += 1,
+                MidiEvent::NoteOn { .. } | MidiEvent::NoteOff { .. } => counts.note_off += 1,
+                MidiEvent::PolyphonicAftertouch { .. } => counts.polyphonic_aftertouch += 1,
+                MidiEvent::ChannelAftertouch { .. } => counts.channel_aftertouch += 1,
+                MidiEvent::Control { .. } => counts.control += 1,
+                MidiEvent::ProgramChange { channel, program_number } => {
+                    counts.program_change += 1;
+                    summary.programs.push((channel, program_number));
+                }
+                MidiEvent::PitchBend { .. } => counts.pitch_bend += 1,
+                MidiEvent::Previous(..) => counts.previous += 1,
+            }
+            if let Some(channel) = midi.channel() {
+                summary.channels.push(channel);
+            }
+            summary.first_tick = summary.first_tick.or(Some(tick));
+            summary.last_tick = Some(tick);
+        }
+        summary.channels.sort();
+        summary.channels.dedup();
+        summary.programs.sort();
+        summary.programs.dedup();
+        summary
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// Summarizes each track, and rolls them up into a total for the file,
+    /// where the duration is that of the longest track.
+    pub fn summary(&self) -> FileSummary {
+        let tracks: Vec<_> = self.tracks().map(TrackChunk::summary).collect();
+        let mut total = TrackSummary::default();
+        for track in &tracks {
+            total.events.add(&track.events);
+            total.first_tick = match (total.first_tick, track.first_tick) {
+                (Some(a), Some(b)) => Some(cmp::min(a, b)),
+                (a, b) => a.or(b),
+            };
+            total.last_tick = cmp::max(total.last_tick, track.last_tick);
+            total.duration = cmp::max(total.duration, track.duration);
+            total.bytes += track.bytes;
+            total.channels.extend_from_slice(&track.channels);
+            total.programs.extend_from_slice(&track.programs);
+        }
+        total.channels.sort();
+        total.channels.dedup();
+        total.programs.sort();
+        total.programs.dedup();
+        FileSummary {
+            tracks: tracks,
+            total: total,
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_summary() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1A,
+        0x00, 0xC1, 0x18,
+        0x10, 0x91, 0x3C, 0x40,
+        0x60, 0x3C, 0x00,
+        0x00, 0x92, 0x40, 0x40,
+        0x20, 0x82, 0x40, 0x40,
+        0x00, 0xE1, 0x00, 0x40,
+        0x10, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let summary = midi.summary();
+    assert_eq!(summary.tracks[0].events, EventCounts { meta: 2, ..EventCounts::default() });
+    assert_eq!((summary.tracks[0].first_tick, summary.tracks[0].duration), (None, 0));
+
+    let track = &summary.tracks[1];
+    assert_eq!(track.events, EventCounts {
+        note_on: 2,
+        note_off: 2,
+        program_change: 1,
+        pitch_bend: 1,
+        meta: 1,
+        ..EventCounts::default()
+    });
+    assert_eq!(track.events.total(), 7);
+    assert_eq!((track.first_tick, track.last_tick, track.duration), (Some(0), Some(144), 160));
+    assert_eq!(track.channels, [1, 2]);
+    assert_eq!(track.programs, [(1, 0x18)]);
+    // Written without running status, one byte longer than it was read.
+    assert_eq!(track.bytes, 8 + 0x1B);
+
+    assert_eq!(summary.total.events.total(), 9);
+    assert_eq!(summary.total.first_tick, Some(0));
+    assert_eq!(summary.total.duration, 160);
+    assert_eq!(summary.total.bytes, 19 + 35);
+
+    #[cfg(feature = "json")]
+    assert!(summary.to_json()
+        .contains(r#""channels":[1,2],"programs":[{"channel":1,"program":24}]"#));
+}
@@ -0,0 +1,262 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use SysexEvent;
+use sysex::u14;
+use sysex::manufacturer::ManufacturerId;
+
+
+// MIDI Capability Inquiry /////////////////////////////////////////////////////
+
+/// The MUID that addresses every device.
+pub const BROADCAST_MUID: u32 = 0x0FFF_FFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CiMessage<'a> {
+    /// 7F addresses the whole function block rather than a single channel.
+    pub device: u8,
+    pub version: u8,
+    pub source: u32,
+    pub destination: u32,
+    pub command: CiCommand<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CiCommand<'a> {
+    Discovery(DeviceInfo),
+    DiscoveryReply(DeviceInfo),
+    InvalidateMuid {
+        target: u32,
+    },
+    ProfileInquiry,
+    ProfileInquiryReply {
+        enabled: Vec<ProfileId>,
+        disabled: Vec<ProfileId>,
+    },
+    SetProfileOn(ProfileId),
+    SetProfileOff(ProfileId),
+    ProfileEnabled(ProfileId),
+    ProfileDisabled(ProfileId),
+    PropertyCapabilities {
+        simultaneous_requests: u8,
+    },
+    PropertyCapabilitiesReply {
+        simultaneous_requests: u8,
+    },
+    PropertyExchange {
+        kind: PropertyKind,
+        chunk: PropertyChunk<'a>,
+    },
+    /// A message whose sub-ID isn't decoded, with the data after the MUIDs.
+    Other {
+        sub_id: u8,
+        data: &'a [u8],
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub manufacturer: ManufacturerId,
+    pub family: u16,
+    pub model: u16,
+    pub revision: [u8; 4],
+    /// A bitmask of the supported inquiry categories: bit 2 is profile
+    /// configuration, bit 3 property exchange and bit 4 process inquiry.
+    pub categories: u8,
+    pub max_sysex_size: u32,
+}
+
+/// The five byte ID of a profile. Standard profiles start with 7E, while
+/// the rest start with a manufacturer ID.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ProfileId(pub [u8; 5]);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PropertyKind {
+    Get,
+    GetReply,
+    Set,
+    SetReply,
+    Subscription,
+    SubscriptionReply,
+    Notify,
+}
+
+/// One chunk of a property exchange message. The header is JSON text, and
+/// the property data may be split across several chunks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PropertyChunk<'a> {
+    pub request_id: u8,
+    pub header: &'a [u8],
+    pub chunks: u16,
+    pub chunk: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn ci(&self) -> Option<CiMessage<'a>> {
+        if self.is_start() {
+            decode(self.payload())
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes the body of a sysex message, without the surrounding F0 and F7.
+pub fn decode(payload: &[u8]) -> Option<CiMessage> {
+    use self::CiCommand::*;
+    let (device, sub_id, version, data) = match payload {
+        [0x7E, device, 0x0D, sub_id, version, data @ ..] if data.len() >= 8 => {
+            (*device, *sub_id, *version, data)
+        }
+        _ => return None,
+    };
+    let (source, destination, data) = (u28(&data[..4]), u28(&data[4..8]), &data[8..]);
+    let command = match (sub_id, data) {
+        (0x70, _) => Discovery(device_info(data)?),
+        (0x71, _) => DiscoveryReply(device_info(data)?),
+        (0x7E, [a, b, c, d]) => InvalidateMuid { target: u28(&[*a, *b, *c, *d]) },
+        (0x20, []) => ProfileInquiry,
+        (0x21, _) => {
+            let (enabled, rest) = profile_list(data)?;
+            let (disabled, _) = profile_list(rest)?;
+            ProfileInquiryReply {
+                enabled: enabled,
+                disabled: disabled,
+            }
+        }
+        (0x22...0x25, _) if data.len() >= 5 => {
+            let id = ProfileId([data[0], data[1], data[2], data[3], data[4]]);
+            match sub_id {
+                0x22 => SetProfileOn(id),
+                0x23 => SetProfileOff(id),
+                0x24 => ProfileEnabled(id),
+                _ => ProfileDisabled(id),
+            }
+        }
+        (0x30, [n, ..]) => PropertyCapabilities { simultaneous_requests: *n },
+        (0x31, [n, ..]) => PropertyCapabilitiesReply { simultaneous_requests: *n },
+        (0x34...0x39, _) | (0x3F, _) => PropertyExchange {
+            kind: match sub_id {
+                0x34 => PropertyKind::Get,
+                0x35 => PropertyKind::GetReply,
+                0x36 => PropertyKind::Set,
+                0x37 => PropertyKind::SetReply,
+                0x38 => PropertyKind::Subscription,
+                0x39 => PropertyKind::SubscriptionReply,
+                _ => PropertyKind::Notify,
+            },
+            chunk: property_chunk(data)?,
+        },
+        _ => Other {
+            sub_id: sub_id,
+            data: data,
+        },
+    };
+    Some(CiMessage {
+        device: device,
+        version: version,
+        source: source,
+        destination: destination,
+        command: command,
+    })
+}
+
+/// Decodes a 28-bit value sent as four 7-bit bytes, least significant first.
+fn u28(bytes: &[u8]) -> u32 {
+    bytes.iter().rev().fold(0, |acc, &b| acc << 7 | (b as u32 & 0x7F))
+}
+
+fn device_info(data: &[u8]) -> Option<DeviceInfo> {
+    if data.len() < 16 {
+        return None;
+    }
+    // The ID is always three bytes here, with single byte IDs zero padded.
+    let manufacturer = match data[0] {
+        0x00 => ManufacturerId::Extended(data[1], data[2]),
+        id => ManufacturerId::Short(id),
+    };
+    Some(DeviceInfo {
+        manufacturer: manufacturer,
+        family: u14(data[3], data[4]),
+        model: u14(data[5], data[6]),
+        revision: [data[7], data[8], data[9], data[10]],
+        categories: data[11],
+        max_sysex_size: u28(&data[12..16]),
+    })
+}
+
+fn profile_list(data: &[u8]) -> Option<(Vec<ProfileId>, &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let len = u14(data[0], data[1]) as usize * 5;
+    if data.len() < 2 + len {
+        return None;
+    }
+    let (ids, rest) = data[2..].split_at(len);
+    let ids = ids.chunks(5)
+        .map(|id| ProfileId([id[0], id[1], id[2], id[3], id[4]]))
+        .collect();
+    Some((ids, rest))
+}
+
+fn property_chunk(data: &[u8]) -> Option<PropertyChunk> {
+    if data.len() < 3 {
+        return None;
+    }
+    let header_len = u14(data[1], data[2]) as usize;
+    let rest = &data[3..];
+    if rest.len() < header_len + 6 {
+        return None;
+    }
+    let (header, rest) = rest.split_at(header_len);
+    let data_len = u14(rest[4], rest[5]) as usize;
+    if rest.len() < data_len + 6 {
+        return None;
+    }
+    Some(PropertyChunk {
+        request_id: data[0],
+        header: header,
+        chunks: u14(rest[0], rest[1]),
+        chunk: u14(rest[2], rest[3]),
+        data: &rest[6..6 + data_len],
+    })
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_ci() {
+    let discovery = [0x7E, 0x7F, 0x0D, 0x70, 0x02,
+                     0x01, 0x02, 0x03, 0x04, 0x7F, 0x7F, 0x7F, 0x7F,
+                     0x43, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+                     0x1C, 0x00, 0x02, 0x00, 0x00, 0x00];
+    let message = decode(&discovery).unwrap();
+    assert_eq!(message.source, 0x04 << 21 | 0x03 << 14 | 0x02 << 7 | 0x01);
+    assert_eq!(message.destination, BROADCAST_MUID);
+    assert_eq!(message.command, CiCommand::Discovery(DeviceInfo {
+        manufacturer: ManufacturerId::Short(0x43),
+        family: 1,
+        model: 2,
+        revision: [0, 0, 0, 1],
+        categories: 0x1C,
+        max_sysex_size: 0x100,
+    }));
+
+    let get = [0x7E, 0x7F, 0x0D, 0x34, 0x02,
+               0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+               0x01, 0x02, 0x00, b'{', b'}', 0x01, 0x00, 0x01, 0x00, 0x00, 0x00];
+    assert_eq!(decode(&get).unwrap().command, CiCommand::PropertyExchange {
+        kind: PropertyKind::Get,
+        chunk: PropertyChunk {
+            request_id: 1,
+            header: b"{}",
+            chunks: 1,
+            chunk: 1,
+            data: &[],
+        },
+    });
+}
@@ -0,0 +1,219 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt;
+
+use SysexEvent;
+
+
+// Manufacturer IDs ////////////////////////////////////////////////////////////
+
+/// The ID at the start of a manufacturer-specific sysex message. IDs 01 to
+/// 7D are a single byte, while 00 is followed by two more bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ManufacturerId {
+    Short(u8),
+    Extended(u8, u8),
+}
+
+impl ManufacturerId {
+    /// Reads the ID from the front of a sysex body, returning the remaining
+    /// bytes. The universal IDs 7E and 7F aren't manufacturers.
+    pub fn from_bytes(bytes: &[u8]) -> Option<(ManufacturerId, &[u8])> {
+        match bytes {
+            [0x00, a, b, rest @ ..] => Some((ManufacturerId::Extended(*a, *b), rest)),
+            [0x7E, ..] | [0x7F, ..] => None,
+            [id, rest @ ..] if *id < 0x80 => Some((ManufacturerId::Short(*id), rest)),
+            _ => None,
+        }
+    }
+
+    /// The name of the manufacturer, if it's one that's known.
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            ManufacturerId::Short(id) => SHORT_IDS
+                .binary_search_by_key(&id, |&(id, _)| id)
+                .ok()
+                .map(|i| SHORT_IDS[i].1),
+            ManufacturerId::Extended(a, b) => EXTENDED_IDS
+                .binary_search_by_key(&(a, b), |&(a, b, _)| (a, b))
+                .ok()
+                .map(|i| EXTENDED_IDS[i].2),
+        }
+    }
+}
+
+impl fmt::Display for ManufacturerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.name(), *self) {
+            (Some(name), _) => f.write_str(name),
+            (None, ManufacturerId::Short(id)) => write!(f, "{:02X}", id),
+            (None, ManufacturerId::Extended(a, b)) => write!(f, "00 {:02X} {:02X}", a, b),
+        }
+    }
+}
+
+impl<'a> SysexEvent<'a> {
+    /// The manufacturer a message is addressed to. Only the first packet of
+    /// a message carries the ID, and universal messages don't have one.
+    pub fn manufacturer(&self) -> Option<ManufacturerId> {
+        if self.is_start() {
+            ManufacturerId::from_bytes(self.payload()).map(|(id, _)| id)
+        } else {
+            None
+        }
+    }
+}
+
+/// Single byte manufacturer IDs, sorted by ID.
+static SHORT_IDS: &'static [(u8, &'static str)] = &[
+    (0x01, "Sequential Circuits"),
+    (0x02, "Big Briar"),
+    (0x03, "Octave / Plateau"),
+    (0x04, "Moog"),
+    (0x05, "Passport Designs"),
+    (0x06, "Lexicon"),
+    (0x07, "Kurzweil"),
+    (0x08, "Fender"),
+    (0x09, "Gulbransen"),
+    (0x0A, "AKG Acoustics"),
+    (0x0B, "Voyce Music"),
+    (0x0C, "Waveframe"),
+    (0x0D, "ADA Signal Processors"),
+    (0x0E, "Garfield Electronics"),
+    (0x0F, "Ensoniq"),
+    (0x10, "Oberheim"),
+    (0x11, "Apple"),
+    (0x12, "Grey Matter Response"),
+    (0x13, "Digidesign"),
+    (0x14, "Palmtree Instruments"),
+    (0x15, "JLCooper Electronics"),
+    (0x16, "Lowrey"),
+    (0x17, "Adams-Smith"),
+    (0x18, "E-mu"),
+    (0x19, "Harmony Systems"),
+    (0x1A, "ART"),
+    (0x1B, "Baldwin"),
+    (0x1C, "Eventide"),
+    (0x1D, "Inventronics"),
+    (0x1F, "Clarity"),
+    (0x20, "Passac"),
+    (0x21, "SIEL"),
+    (0x22, "Synthaxe"),
+    (0x24, "Hohner"),
+    (0x25, "Twister"),
+    (0x26, "Solton"),
+    (0x27, "Jellinghaus MS"),
+    (0x28, "Southworth Music Systems"),
+    (0x29, "PPG"),
+    (0x2A, "JEN"),
+    (0x2B, "Solid State Logic"),
+    (0x2C, "Audio Veritrieb"),
+    (0x2F, "Elka"),
+    (0x30, "Dynacord"),
+    (0x31, "Viscount"),
+    (0x33, "Clavia"),
+    (0x34, "Audio Architecture"),
+    (0x35, "GeneralMusic"),
+    (0x39, "Soundcraft"),
+    (0x3B, "Wersi"),
+    (0x3C, "AVAB"),
+    (0x3D, "Digigram"),
+    (0x3E, "Waldorf"),
+    (0x3F, "Quasimidi"),
+    (0x40, "Kawai"),
+    (0x41, "Roland"),
+    (0x42, "Korg"),
+    (0x43, "Yamaha"),
+    (0x44, "Casio"),
+    (0x46, "Kamiya Studio"),
+    (0x47, "Akai"),
+    (0x48, "Victor"),
+    (0x4B, "Fujitsu"),
+    (0x4C, "Sony"),
+    (0x4E, "Teac"),
+    (0x50, "Matsushita Electric"),
+    (0x51, "Fostex"),
+    (0x52, "Zoom"),
+    (0x54, "Matsushita Communication"),
+    (0x55, "Suzuki"),
+    (0x56, "Fuji Sound"),
+    (0x57, "Acoustic Technical Laboratory"),
+    (0x7D, "Non-commercial"),
+];
+
+/// Three byte manufacturer IDs without the leading zero, sorted by ID.
+static EXTENDED_IDS: &'static [(u8, u8, &'static str)] = &[
+    (0x00, 0x01, "Time Warner Interactive"),
+    (0x00, 0x07, "Digital Music"),
+    (0x00, 0x0E, "Alesis"),
+    (0x00, 0x15, "KAT"),
+    (0x00, 0x16, "Opcode"),
+    (0x00, 0x1A, "Allen & Heath"),
+    (0x00, 0x1B, "Peavey"),
+    (0x00, 0x1C, "360 Systems"),
+    (0x00, 0x1D, "Spectrum Design and Development"),
+    (0x00, 0x1E, "Marquis Music"),
+    (0x00, 0x1F, "Zeta Systems"),
+    (0x00, 0x20, "Axxes"),
+    (0x00, 0x21, "Orban"),
+    (0x00, 0x24, "KTI"),
+    (0x00, 0x25, "Breakaway Technologies"),
+    (0x00, 0x26, "CAE"),
+    (0x00, 0x29, "Rocktron"),
+    (0x00, 0x2A, "PianoDisc"),
+    (0x00, 0x2B, "Cannon Research Group"),
+    (0x00, 0x2D, "Rodgers Instrument"),
+    (0x00, 0x2E, "Blue Sky Logic"),
+    (0x00, 0x2F, "Encore Electronics"),
+    (0x00, 0x30, "Uptown"),
+    (0x00, 0x31, "Voce"),
+    (0x00, 0x32, "CTI Audio"),
+    (0x00, 0x33, "S3"),
+    (0x00, 0x34, "Broderbund"),
+    (0x00, 0x35, "Allen Organ"),
+    (0x00, 0x37, "Music Quest"),
+    (0x00, 0x38, "Aphex"),
+    (0x00, 0x39, "Gallien Krueger"),
+    (0x00, 0x3A, "IBM"),
+    (0x00, 0x3B, "Mark of the Unicorn"),
+    (0x00, 0x3C, "Hotz Instruments"),
+    (0x00, 0x3D, "ETA Lighting"),
+    (0x00, 0x3E, "NSI"),
+    (0x00, 0x3F, "Ad Lib"),
+    (0x00, 0x40, "Richmond Sound Design"),
+    (0x00, 0x41, "Microsoft"),
+    (0x00, 0x42, "The Software Toolworks"),
+    (0x00, 0x43, "Niche / RJMG"),
+    (0x00, 0x44, "Intone"),
+    (0x00, 0x47, "GT Electronics"),
+    (0x01, 0x05, "M-Audio"),
+    (0x01, 0x0C, "Line 6"),
+    (0x20, 0x1F, "TC Electronic"),
+    (0x20, 0x29, "Focusrite / Novation"),
+    (0x20, 0x32, "Behringer"),
+    (0x20, 0x33, "Access Music"),
+    (0x20, 0x3C, "Elektron"),
+    (0x20, 0x6B, "Arturia"),
+    (0x21, 0x09, "Native Instruments"),
+];
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_manufacturer() {
+    assert_eq!(ManufacturerId::Short(0x41).to_string(), "Roland");
+    assert_eq!(ManufacturerId::Extended(0x20, 0x29).name(), Some("Focusrite / Novation"));
+    assert_eq!(ManufacturerId::Short(0x6E).to_string(), "6E");
+    assert_eq!(ManufacturerId::from_bytes(&[0x00, 0x00, 0x0E, 0x01]),
+               Some((ManufacturerId::Extended(0x00, 0x0E), &[0x01][..])));
+    assert_eq!(ManufacturerId::from_bytes(&[0x7E, 0x00]), None);
+
+    for pair in SHORT_IDS.windows(2) {
+        assert!(pair[0].0 < pair[1].0);
+    }
+    for pair in EXTENDED_IDS.windows(2) {
+        assert!((pair[0].0, pair[0].1) < (pair[1].0, pair[1].1));
+    }
+}
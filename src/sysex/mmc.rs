@@ -0,0 +1,141 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use SysexEvent;
+use sysex::{time_code, TimeCode};
+
+
+// MIDI Machine Control ////////////////////////////////////////////////////////
+
+/// A universal real-time MMC command message, which may hold several
+/// commands for the same device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmcMessage<'a> {
+    pub device: u8,
+    pub commands: Vec<MmcCommand<'a>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MmcCommand<'a> {
+    Stop,
+    Play,
+    DeferredPlay,
+    FastForward,
+    Rewind,
+    /// Also known as punch in.
+    RecordStrobe,
+    /// Also known as punch out.
+    RecordExit,
+    RecordPause,
+    Pause,
+    Eject,
+    Chase,
+    CommandErrorReset,
+    MmcReset,
+    Locate(Locate),
+    Other {
+        command: u8,
+        data: &'a [u8],
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Locate {
+    /// Move to the time stored in one of the device's information fields.
+    Field(u8),
+    Target(TimeCode),
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn mmc(&self) -> Option<MmcMessage<'a>> {
+        if self.is_start() {
+            decode(self.payload())
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes the body of a sysex message, without the surrounding F0 and F7.
+pub fn decode(payload: &[u8]) -> Option<MmcMessage> {
+    match payload {
+        [0x7F, device, 0x06, commands @ ..] => decode_commands(commands).map(|commands| {
+            MmcMessage {
+                device: *device,
+                commands: commands,
+            }
+        }),
+        _ => None,
+    }
+}
+
+fn decode_commands(mut data: &[u8]) -> Option<Vec<MmcCommand>> {
+    let mut commands = Vec::new();
+    while let Some((&command, rest)) = data.split_first() {
+        // Commands from 0x40 up are followed by a count of their data bytes.
+        let (args, rest) = if command >= 0x40 && command < 0x78 {
+            let (&count, rest) = rest.split_first()?;
+            if rest.len() < count as usize {
+                return None;
+            }
+            rest.split_at(count as usize)
+        } else {
+            (&rest[..0], rest)
+        };
+        commands.push(command_from_parts(command, args));
+        data = rest;
+    }
+    Some(commands)
+}
+
+fn command_from_parts(command: u8, args: &[u8]) -> MmcCommand {
+    use self::MmcCommand::*;
+    match (command, args) {
+        (0x01, _) => Stop,
+        (0x02, _) => Play,
+        (0x03, _) => DeferredPlay,
+        (0x04, _) => FastForward,
+        (0x05, _) => Rewind,
+        (0x06, _) => RecordStrobe,
+        (0x07, _) => RecordExit,
+        (0x08, _) => RecordPause,
+        (0x09, _) => Pause,
+        (0x0A, _) => Eject,
+        (0x0B, _) => Chase,
+        (0x0C, _) => CommandErrorReset,
+        (0x0D, _) => MmcReset,
+        (0x44, [0x00, field]) => Locate(self::Locate::Field(*field)),
+        (0x44, [0x01, time @ ..]) if time.len() == 5 => Locate(self::Locate::Target(time_code(time))),
+        (command, data) => Other {
+            command: command,
+            data: data,
+        },
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+use sysex::FrameRate;
+
+#[cfg(test)]
+#[test]
+fn test_mmc() {
+    let locate = [0x7F, 0x7F, 0x06, 0x44, 0x06, 0x01, 0x21, 0x02, 0x03, 0x04, 0x00];
+    assert_eq!(decode(&locate), Some(MmcMessage {
+        device: 0x7F,
+        commands: vec![MmcCommand::Locate(Locate::Target(TimeCode {
+            rate: FrameRate::Fps25,
+            hours: 1,
+            minutes: 2,
+            seconds: 3,
+            frames: 4,
+            subframes: 0,
+        }))],
+    }));
+    assert_eq!(decode(&[0x7F, 0x00, 0x06, 0x01, 0x02]), Some(MmcMessage {
+        device: 0,
+        commands: vec![MmcCommand::Stop, MmcCommand::Play],
+    }));
+    assert_eq!(decode(&[0x7F, 0x00, 0x06, 0x44, 0x06, 0x01]), None);
+}
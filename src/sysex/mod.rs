@@ -0,0 +1,131 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Event, Midi, SysexEvent};
+
+pub mod ci;
+pub mod manufacturer;
+pub mod mmc;
+pub mod msc;
+pub mod roland;
+pub mod mts;
+pub mod sds;
+pub mod universal;
+
+
+// Synth Standards /////////////////////////////////////////////////////////////
+
+/// The synth compatibility modes that a file can request with a reset
+/// message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Standard {
+    GeneralMidi,
+    GeneralMidi2,
+    RolandGs,
+    YamahaXg,
+}
+
+impl<'a> SysexEvent<'a> {
+    /// Recognizes GM System On, GM2 System On, GS Reset and XG System On
+    /// messages, accepting any device ID.
+    pub fn standard(&self) -> Option<Standard> {
+        if !self.is_start() || !self.is_end() {
+            return None;
+        }
+        match self.payload() {
+            [0x7E, _, 0x09, 0x01] => Some(Standard::GeneralMidi),
+            [0x7E, _, 0x09, 0x03] => Some(Standard::GeneralMidi2),
+            [0x41, _, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41] => Some(Standard::RolandGs),
+            [0x43, n, 0x4C, 0x00, 0x00, 0x7E, 0x00] if n & 0xF0 == 0x10 => Some(Standard::YamahaXg),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Midi<'a> {
+    /// The standard the file targets, judged from its reset messages. Files
+    /// commonly send a GM reset before a GS or XG one, so the vendor
+    /// extensions take precedence over GM.
+    pub fn standard(&self) -> Option<Standard> {
+        let mut found = None;
+        for track in self.tracks() {
+            for event in track.events() {
+                let standard = match *event {
+                    Event::Sysex(_, ref sysex) => sysex.standard(),
+                    _ => None,
+                };
+                found = match (found, standard) {
+                    (_, None) => found,
+                    (Some(Standard::RolandGs), Some(Standard::GeneralMidi)) |
+                    (Some(Standard::RolandGs), Some(Standard::GeneralMidi2)) |
+                    (Some(Standard::YamahaXg), Some(Standard::GeneralMidi)) |
+                    (Some(Standard::YamahaXg), Some(Standard::GeneralMidi2)) => found,
+                    (_, standard) => standard,
+                };
+            }
+        }
+        found
+    }
+}
+
+
+// Time Code ///////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameRate {
+    Fps24,
+    Fps25,
+    Fps30DropFrame,
+    Fps30,
+}
+
+/// The standard time code used by MMC and MSC messages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeCode {
+    pub rate: FrameRate,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub subframes: u8,
+}
+
+/// Decodes a five byte time code, where the frame rate is packed in above the
+/// hours and the last byte is subframes.
+fn time_code(bytes: &[u8]) -> TimeCode {
+    TimeCode {
+        rate: match (bytes[0] >> 5) & 0x03 {
+            0 => FrameRate::Fps24,
+            1 => FrameRate::Fps25,
+            2 => FrameRate::Fps30DropFrame,
+            _ => FrameRate::Fps30,
+        },
+        hours: bytes[0] & 0x1F,
+        minutes: bytes[1] & 0x3F,
+        seconds: bytes[2] & 0x3F,
+        frames: bytes[3] & 0x1F,
+        subframes: bytes[4] & 0x7F,
+    }
+}
+
+/// Fourteen bit values in sysex data are sent least significant byte first.
+fn u14(lsb: u8, msb: u8) -> u16 {
+    (msb as u16 & 0x7F) << 7 | (lsb as u16 & 0x7F)
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_standard() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x17,
+        0x00, 0xF0, 0x05, 0x7E, 0x7F, 0x09, 0x01, 0xF7,
+        0x00, 0xF0, 0x08, 0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    assert_eq!(midi.standard(), Some(Standard::YamahaXg));
+}
@@ -0,0 +1,214 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use SysexEvent;
+use sysex::{time_code, u14, TimeCode};
+
+
+// MIDI Show Control ///////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MscMessage<'a> {
+    pub device: u8,
+    pub format: CommandFormat,
+    pub command: MscCommand<'a>,
+}
+
+/// The kind of equipment a message is addressed to. The high nibble selects
+/// a category and the low nibble a specific type of device within it, with
+/// zero or one being the general case.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CommandFormat(pub u8);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Category {
+    Lighting,
+    Sound,
+    Machinery,
+    Video,
+    Projection,
+    ProcessControl,
+    Pyro,
+    AllTypes,
+    Other,
+}
+
+impl CommandFormat {
+    pub fn category(&self) -> Category {
+        match self.0 {
+            0x7F => Category::AllTypes,
+            0x01...0x0F => Category::Lighting,
+            0x10...0x1F => Category::Sound,
+            0x20...0x2F => Category::Machinery,
+            0x30...0x3F => Category::Video,
+            0x40...0x4F => Category::Projection,
+            0x50...0x5F => Category::ProcessControl,
+            0x60...0x6F => Category::Pyro,
+            _ => Category::Other,
+        }
+    }
+}
+
+/// Identifies a cue by its number, and optionally the list and path that
+/// contain it. Each part is an ASCII number such as `b"12.5"`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Cue<'a> {
+    pub number: Option<&'a [u8]>,
+    pub list: Option<&'a [u8]>,
+    pub path: Option<&'a [u8]>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MscCommand<'a> {
+    Go(Cue<'a>),
+    Stop(Cue<'a>),
+    Resume(Cue<'a>),
+    TimedGo {
+        time: TimeCode,
+        cue: Cue<'a>,
+    },
+    Load(Cue<'a>),
+    Set {
+        control: u16,
+        value: u16,
+        time: Option<TimeCode>,
+    },
+    Fire(u8),
+    AllOff,
+    Restore,
+    Reset,
+    GoOff(Cue<'a>),
+    GoJamClock(Cue<'a>),
+    StandbyPlus(Option<&'a [u8]>),
+    StandbyMinus(Option<&'a [u8]>),
+    SequencePlus(Option<&'a [u8]>),
+    SequenceMinus(Option<&'a [u8]>),
+    StartClock(Option<&'a [u8]>),
+    StopClock(Option<&'a [u8]>),
+    ZeroClock(Option<&'a [u8]>),
+    SetClock {
+        time: TimeCode,
+        list: Option<&'a [u8]>,
+    },
+    MtcChaseOn(Option<&'a [u8]>),
+    MtcChaseOff(Option<&'a [u8]>),
+    OpenCueList(&'a [u8]),
+    CloseCueList(&'a [u8]),
+    OpenCuePath(&'a [u8]),
+    CloseCuePath(&'a [u8]),
+    Other {
+        command: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn msc(&self) -> Option<MscMessage<'a>> {
+        if self.is_start() {
+            decode(self.payload())
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes the body of a sysex message, without the surrounding F0 and F7.
+pub fn decode(payload: &[u8]) -> Option<MscMessage> {
+    match payload {
+        [0x7F, device, 0x02, format, command, data @ ..] => Some(MscMessage {
+            device: *device,
+            format: CommandFormat(*format),
+            command: decode_command(*command, data)?,
+        }),
+        _ => None,
+    }
+}
+
+fn decode_command(command: u8, data: &[u8]) -> Option<MscCommand> {
+    use self::MscCommand::*;
+    Some(match command {
+        0x01 => Go(cue(data)),
+        0x02 => Stop(cue(data)),
+        0x03 => Resume(cue(data)),
+        0x04 if data.len() >= 5 => TimedGo {
+            time: time_code(&data[..5]),
+            cue: cue(&data[5..]),
+        },
+        0x05 => Load(cue(data)),
+        0x06 if data.len() >= 4 => Set {
+            control: u14(data[0], data[1]),
+            value: u14(data[2], data[3]),
+            time: if data.len() >= 9 { Some(time_code(&data[4..9])) } else { None },
+        },
+        0x07 if data.len() >= 1 => Fire(data[0]),
+        0x08 => AllOff,
+        0x09 => Restore,
+        0x0A => Reset,
+        0x0B => GoOff(cue(data)),
+        0x10 => GoJamClock(cue(data)),
+        0x11 => StandbyPlus(number(data)),
+        0x12 => StandbyMinus(number(data)),
+        0x13 => SequencePlus(number(data)),
+        0x14 => SequenceMinus(number(data)),
+        0x15 => StartClock(number(data)),
+        0x16 => StopClock(number(data)),
+        0x17 => ZeroClock(number(data)),
+        0x18 if data.len() >= 5 => SetClock {
+            time: time_code(&data[..5]),
+            list: number(&data[5..]),
+        },
+        0x19 => MtcChaseOn(number(data)),
+        0x1A => MtcChaseOff(number(data)),
+        0x1B => OpenCueList(number(data)?),
+        0x1C => CloseCueList(number(data)?),
+        0x1D => OpenCuePath(number(data)?),
+        0x1E => CloseCuePath(number(data)?),
+        0x04 | 0x06 | 0x07 | 0x18 => return None,
+        command => Other {
+            command: command,
+            data: data,
+        },
+    })
+}
+
+/// Splits the cue number, list and path, which are separated by zero bytes.
+fn cue(data: &[u8]) -> Cue {
+    let mut parts = data.split(|&b| b == 0x00).map(|part| number(part));
+    Cue {
+        number: parts.next().and_then(|x| x),
+        list: parts.next().and_then(|x| x),
+        path: parts.next().and_then(|x| x),
+    }
+}
+
+fn number(data: &[u8]) -> Option<&[u8]> {
+    // Some senders terminate the final number with a zero byte anyway.
+    let data = match data.split_last() {
+        Some((&0x00, rest)) => rest,
+        _ => data,
+    };
+    if data.is_empty() { None } else { Some(data) }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_msc() {
+    let go = [0x7F, 0x01, 0x02, 0x01, 0x01, b'2', b'.', b'5', 0x00, b'3'];
+    let message = decode(&go).unwrap();
+    assert_eq!(message.format.category(), Category::Lighting);
+    assert_eq!(message.command, MscCommand::Go(Cue {
+        number: Some(&b"2.5"[..]),
+        list: Some(&b"3"[..]),
+        path: None,
+    }));
+
+    let set = [0x7F, 0x01, 0x02, 0x10, 0x06, 0x05, 0x00, 0x7F, 0x01];
+    assert_eq!(decode(&set).unwrap().command, MscCommand::Set {
+        control: 5,
+        value: 0xFF,
+        time: None,
+    });
+    assert_eq!(decode(&[0x7F, 0x01, 0x02, 0x10, 0x1B]), None);
+}
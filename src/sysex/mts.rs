@@ -0,0 +1,362 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::collections::HashMap;
+
+use {Event, Midi, MidiEvent, SysexEvent};
+use pitch::{key_to_freq, A4_HZ};
+
+
+// MIDI Tuning Standard ////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtsMessage<'a> {
+    pub device: u8,
+    pub realtime: bool,
+    pub command: MtsCommand<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MtsCommand<'a> {
+    BulkDumpRequest {
+        bank: Option<u8>,
+        program: u8,
+    },
+    /// A complete tuning program, with an entry for each of the 128 keys.
+    /// Keys left as `None` keep their existing tuning.
+    BulkDump {
+        bank: Option<u8>,
+        program: u8,
+        name: &'a [u8],
+        tunings: Vec<Option<NoteTuning>>,
+        checksum_valid: bool,
+    },
+    SingleNoteChange {
+        bank: Option<u8>,
+        program: u8,
+        changes: Vec<(u8, Option<NoteTuning>)>,
+    },
+    /// Detunes every octave identically, for the channels set in the bitmask
+    /// where bit 0 is the first channel.
+    ScaleOctave {
+        channels: u16,
+        cents: [f64; 12],
+    },
+}
+
+/// A key's pitch as the equal-tempered semitone at or below it, plus a
+/// fraction of a semitone in units of 1/16384.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NoteTuning {
+    pub semitone: u8,
+    pub fraction: u16,
+}
+
+impl NoteTuning {
+    /// The pitch as a fractional key number, where 69.0 is A4.
+    pub fn key(&self) -> f64 {
+        self.semitone as f64 + self.fraction as f64 / 16384.0
+    }
+
+    /// The frequency in Hz, with A4 tuned to 440 Hz.
+    pub fn frequency(&self) -> f64 {
+        key_to_freq(self.key(), A4_HZ)
+    }
+}
+
+/// Fills in a complete frequency table from a bulk dump, using equal
+/// temperament for any keys that the dump leaves unchanged.
+pub fn frequencies(tunings: &[Option<NoteTuning>]) -> Vec<f64> {
+    (0..128).map(|key| {
+        let equal = NoteTuning {
+            semitone: key as u8,
+            fraction: 0,
+        };
+        tunings.get(key).and_then(|&tuning| tuning).unwrap_or(equal).frequency()
+    }).collect()
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn mts(&self) -> Option<MtsMessage<'a>> {
+        if self.is_start() {
+            decode(self.payload())
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes the body of a sysex message, without the surrounding F0 and F7.
+pub fn decode(payload: &[u8]) -> Option<MtsMessage> {
+    use self::MtsCommand::*;
+    let (realtime, device, sub_id, data) = match payload {
+        [0x7E, device, 0x08, sub_id, data @ ..] => (false, *device, *sub_id, data),
+        [0x7F, device, 0x08, sub_id, data @ ..] => (true, *device, *sub_id, data),
+        _ => return None,
+    };
+    let command = match (sub_id, data) {
+        (0x00, [program]) => BulkDumpRequest {
+            bank: None,
+            program: *program,
+        },
+        (0x03, [bank, program]) => BulkDumpRequest {
+            bank: Some(*bank),
+            program: *program,
+        },
+        (0x01, [program, rest @ ..]) => bulk_dump(payload, None, *program, rest)?,
+        (0x04, [bank, program, rest @ ..]) => bulk_dump(payload, Some(*bank), *program, rest)?,
+        (0x02, [program, count, rest @ ..]) => SingleNoteChange {
+            bank: None,
+            program: *program,
+            changes: note_changes(*count, rest)?,
+        },
+        (0x07, [bank, program, count, rest @ ..]) => SingleNoteChange {
+            bank: Some(*bank),
+            program: *program,
+            changes: note_changes(*count, rest)?,
+        },
+        (0x08, [ff, gg, hh, offsets @ ..]) if offsets.len() == 12 => {
+            let mut cents = [0.0; 12];
+            for (cent, &offset) in cents.iter_mut().zip(offsets) {
+                *cent = offset as f64 - 64.0;
+            }
+            ScaleOctave {
+                channels: channel_mask(*ff, *gg, *hh),
+                cents: cents,
+            }
+        }
+        (0x09, [ff, gg, hh, offsets @ ..]) if offsets.len() == 24 => {
+            let mut cents = [0.0; 12];
+            for (cent, pair) in cents.iter_mut().zip(offsets.chunks(2)) {
+                let value = (pair[0] as u16 & 0x7F) << 7 | (pair[1] as u16 & 0x7F);
+                *cent = (value as f64 - 8192.0) * 100.0 / 8192.0;
+            }
+            ScaleOctave {
+                channels: channel_mask(*ff, *gg, *hh),
+                cents: cents,
+            }
+        }
+        _ => return None,
+    };
+    Some(MtsMessage {
+        device: device,
+        realtime: realtime,
+        command: command,
+    })
+}
+
+fn bulk_dump<'a>(payload: &[u8], bank: Option<u8>, program: u8, rest: &'a [u8])
+                 -> Option<MtsCommand<'a>> {
+    if rest.len() != 16 + 128 * 3 + 1 {
+        return None;
+    }
+    let (name, rest) = rest.split_at(16);
+    let (data, checksum) = rest.split_at(128 * 3);
+    let computed = payload[..payload.len() - 1].iter().fold(0, |acc, &b| acc ^ b) & 0x7F;
+    Some(MtsCommand::BulkDump {
+        bank: bank,
+        program: program,
+        name: name,
+        tunings: data.chunks(3).map(note_tuning).collect(),
+        checksum_valid: checksum[0] == computed,
+    })
+}
+
+fn note_changes(count: u8, data: &[u8]) -> Option<Vec<(u8, Option<NoteTuning>)>> {
+    if data.len() != count as usize * 4 {
+        return None;
+    }
+    Some(data.chunks(4).map(|change| (change[0], note_tuning(&change[1..]))).collect())
+}
+
+/// Decodes the three byte frequency format, where 7F 7F 7F means no change.
+fn note_tuning(bytes: &[u8]) -> Option<NoteTuning> {
+    match bytes {
+        [0x7F, 0x7F, 0x7F] => None,
+        [semitone, msb, lsb] => Some(NoteTuning {
+            semitone: *semitone & 0x7F,
+            fraction: (*msb as u16 & 0x7F) << 7 | (*lsb as u16 & 0x7F),
+        }),
+        _ => None,
+    }
+}
+
+fn channel_mask(ff: u8, gg: u8, hh: u8) -> u16 {
+    (ff as u16 & 0x03) << 14 | (gg as u16 & 0x7F) << 7 | (hh as u16 & 0x7F)
+}
+
+
+// Tuning Map //////////////////////////////////////////////////////////////////
+
+/// The pitch of every key on every channel over the course of a file, from
+/// its tuning messages.
+///
+/// Bulk dumps and single note changes retune a tuning program, which
+/// channels choose with RPN 3 (tuning program select) and RPN 4 (tuning bank
+/// select), starting on program 0 of bank 0. Scale/octave tunings detune
+/// channels directly, on top of their program. Programs start out in equal
+/// temperament.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuningMap {
+    /// For each channel, the fractional key that every key sounds at from
+    /// each tick on, always starting at tick 0.
+    channels: Vec<Vec<(u64, Vec<f64>)>>,
+}
+
+impl<'a> Midi<'a> {
+    pub fn tuning_map(&self) -> TuningMap {
+        TuningMap::new(self)
+    }
+}
+
+impl TuningMap {
+    pub fn new(midi: &Midi) -> TuningMap {
+        let mut events = Vec::new();
+        for track in midi.tracks() {
+            events.extend(track.absolute_events());
+        }
+        // The sort is stable, so events on the same tick stay in track order.
+        events.sort_by_key(|&(tick, _)| tick);
+
+        let equal: Vec<f64> = (0..128).map(|key| key as f64).collect();
+        let mut programs: HashMap<(u8, u8), Vec<f64>> = HashMap::new();
+        // The (bank, program) each channel plays, the RPN it has selected,
+        // and its scale/octave detuning in cents.
+        let mut selected = [(0u8, 0u8); 16];
+        let mut rpns = [(0x7Fu8, 0x7Fu8); 16];
+        let mut octaves = [[0.0f64; 12]; 16];
+        let mut channels: Vec<Vec<(u64, Vec<f64>)>> = vec![vec![(0, equal.clone())]; 16];
+        for (tick, event) in events {
+            match *event {
+                Event::Midi(_, MidiEvent::Control { channel, change }) => {
+                    let channel = channel as usize & 0x0F;
+                    match change.parts() {
+                        (101, value) => rpns[channel].0 = value,
+                        (100, value) => rpns[channel].1 = value,
+                        (6, value) if rpns[channel] == (0, 3) => selected[channel].1 = value,
+                        (6, value) if rpns[channel] == (0, 4) => selected[channel].0 = value,
+                        _ => {}
+                    }
+                    // Only data entry can choose another tuning program.
+                    if change.parts().0 != 6 {
+                        continue;
+                    }
+                }
+                Event::Sysex(_, ref sysex) => match sysex.mts().map(|message| message.command) {
+                    Some(MtsCommand::BulkDump { bank, program, tunings, .. }) => {
+                        let table = programs.entry((bank.unwrap_or(0), program))
+                            .or_insert_with(|| equal.clone());
+                        for (key, tuning) in tunings.into_iter().enumerate() {
+                            if let Some(tuning) = tuning {
+                                table[key] = tuning.key();
+                            }
+                        }
+                    }
+                    Some(MtsCommand::SingleNoteChange { bank, program, changes }) => {
+                        let table = programs.entry((bank.unwrap_or(0), program))
+                            .or_insert_with(|| equal.clone());
+                        for (key, tuning) in changes {
+                            if let Some(tuning) = tuning {
+                                table[key as usize & 0x7F] = tuning.key();
+                            }
+                        }
+                    }
+                    Some(MtsCommand::ScaleOctave { channels: mask, cents }) => {
+                        for channel in 0..16 {
+                            if mask & 1 << channel != 0 {
+                                octaves[channel] = cents;
+                            }
+                        }
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            }
+            // Something changed, so note down any channel that now sounds
+            // different.
+            for channel in 0..16 {
+                let program = programs.get(&selected[channel]).unwrap_or(&equal);
+                let keys: Vec<f64> = program.iter()
+                    .enumerate()
+                    .map(|(key, &pitch)| pitch + octaves[channel][key % 12] / 100.0)
+                    .collect();
+                let changes = &mut channels[channel];
+                if changes.len() > 1 && changes.last().map_or(false, |&(last, _)| last == tick) {
+                    changes.pop();
+                }
+                if changes.last().map_or(true, |&(_, ref last)| *last != keys) {
+                    changes.push((tick, keys));
+                }
+            }
+        }
+        TuningMap { channels: channels }
+    }
+
+    /// The fractional key that a key on a channel sounds at on a tick,
+    /// including any retuning on that tick.
+    pub fn key_at(&self, channel: u8, key: u8, tick: u64) -> f64 {
+        let changes = &self.channels[channel as usize & 0x0F];
+        let index = changes.iter().take_while(|&&(start, _)| start <= tick).count();
+        changes[index.saturating_sub(1)].1[key as usize & 0x7F]
+    }
+
+    /// The frequency in Hz of a key on a channel on a tick, with A4 tuned to
+    /// 440 Hz.
+    pub fn frequency_at(&self, channel: u8, key: u8, tick: u64) -> f64 {
+        key_to_freq(self.key_at(channel, key, tick), A4_HZ)
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_single_note_change() {
+    let payload = [0x7F, 0x7F, 0x08, 0x02, 0x00, 0x02,
+                   0x45, 0x45, 0x00, 0x00,
+                   0x46, 0x7F, 0x7F, 0x7F];
+    let message = decode(&payload).unwrap();
+    let a4 = NoteTuning {
+        semitone: 0x45,
+        fraction: 0,
+    };
+    assert_eq!(message.command, MtsCommand::SingleNoteChange {
+        bank: None,
+        program: 0,
+        changes: vec![(0x45, Some(a4)), (0x46, None)],
+    });
+    assert_eq!(a4.frequency(), 440.0);
+    let quarter_tone = NoteTuning {
+        semitone: 0x45,
+        fraction: 0x2000,
+    };
+    assert_eq!(quarter_tone.key(), 69.5);
+}
+
+#[cfg(test)]
+#[test]
+fn test_tuning_map() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x35,
+        // Tune A4 a quarter tone sharp in program 1.
+        0x00, 0xF0, 0x0B, 0x7F, 0x7F, 0x08, 0x02, 0x01, 0x01, 0x45, 0x45, 0x40, 0x00, 0xF7,
+        // Select program 1 on channel 2.
+        0x10, 0xB2, 0x65, 0x00,
+        0x00, 0xB2, 0x64, 0x03,
+        0x00, 0xB2, 0x06, 0x01,
+        // Flatten every E on channel 0 by 14 cents.
+        0x10, 0xF0, 0x14, 0x7F, 0x7F, 0x08, 0x08, 0x00, 0x00, 0x01,
+        0x40, 0x40, 0x40, 0x40, 0x32, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0xF7,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let map = ::parse_midi(&data).unwrap().tuning_map();
+    assert_eq!(map.key_at(2, 0x45, 0x0F), 69.0);
+    assert_eq!(map.key_at(2, 0x45, 0x10), 69.5);
+    assert_eq!(map.key_at(0, 0x45, 0x10), 69.0);
+    assert_eq!(map.key_at(0, 0x40, 0x1F), 64.0);
+    assert_eq!(map.key_at(0, 0x34, 0x20), 51.86);
+    assert_eq!(map.key_at(2, 0x40, 0x20), 64.0);
+    assert_eq!(map.frequency_at(0, 0x45, 0x20), 440.0);
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use SysexEvent;
+
+
+// Roland Address-Mapped Messages //////////////////////////////////////////////
+
+/// A Roland data set (DT1) or data request (RQ1) message, which read and
+/// write a synth's parameters by address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RolandMessage<'a> {
+    pub device: u8,
+    /// The model ID, which newer models extend with leading zeros.
+    pub model: &'a [u8],
+    pub command: RolandCommand<'a>,
+    /// The checksum that was sent.
+    pub checksum: u8,
+    /// The checksum that the address and data add up to.
+    pub expected_checksum: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RolandCommand<'a> {
+    /// Writes the data at the address.
+    DataSet {
+        address: u32,
+        data: &'a [u8],
+    },
+    /// Asks for this many bytes from the address.
+    Request {
+        address: u32,
+        size: u32,
+    },
+}
+
+impl<'a> RolandMessage<'a> {
+    pub fn checksum_ok(&self) -> bool {
+        self.checksum == self.expected_checksum
+    }
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn roland(&self) -> Option<RolandMessage<'a>> {
+        if self.is_start() && self.is_end() {
+            decode(self.payload())
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes the body of a sysex message, without the surrounding F0 and F7.
+/// The message doesn't say how long its address is, so models with one byte
+/// IDs are taken to use three byte addresses like the GS synths, and those
+/// with longer IDs four byte addresses.
+pub fn decode(payload: &[u8]) -> Option<RolandMessage> {
+    let (device, rest) = match payload {
+        [0x41, device, rest @ ..] if *device < 0x80 => (*device, rest),
+        _ => return None,
+    };
+    let model_len = rest.iter().position(|&b| b != 0)? + 1;
+    let (model, rest) = rest.split_at(model_len);
+    let address_len = if model_len == 1 { 3 } else { 4 };
+    let (&command, rest) = rest.split_first()?;
+    let (&checksum, body) = rest.split_last()?;
+    if body.len() < address_len || body.iter().any(|&b| b >= 0x80) {
+        return None;
+    }
+    let (address, data) = body.split_at(address_len);
+    let command = match command {
+        0x12 => RolandCommand::DataSet {
+            address: from_bytes(address),
+            data: data,
+        },
+        0x11 if data.len() == address_len => RolandCommand::Request {
+            address: from_bytes(address),
+            size: from_bytes(data),
+        },
+        _ => return None,
+    };
+    Some(RolandMessage {
+        device: device,
+        model: model,
+        command: command,
+        checksum: checksum,
+        expected_checksum: roland_checksum(body),
+    })
+}
+
+/// The byte that makes the address, data and checksum add up to a multiple
+/// of 128.
+pub fn roland_checksum(bytes: &[u8]) -> u8 {
+    let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) & 0x7F;
+    (0x80 - sum) & 0x7F
+}
+
+/// Addresses and sizes are sent seven bits at a time, most significant first.
+fn from_bytes(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |value, &b| value << 7 | b as u32)
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_roland() {
+    // The GS reset, which sets address 40 00 7F to 0.
+    let message = decode(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41]).unwrap();
+    assert_eq!(message.model, &[0x42][..]);
+    assert_eq!(message.command, RolandCommand::DataSet {
+        address: 0x40 << 14 | 0x7F,
+        data: &[0x00],
+    });
+    assert!(message.checksum_ok());
+
+    let message = decode(&[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x40]).unwrap();
+    assert_eq!((message.checksum, message.expected_checksum), (0x40, 0x41));
+
+    let request = [0x41, 0x10, 0x00, 0x6A, 0x11, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                   0x40, 0x3F];
+    let message = decode(&request).unwrap();
+    assert_eq!(message.model, &[0x00, 0x6A][..]);
+    assert_eq!(message.command, RolandCommand::Request {
+        address: 1 << 21,
+        size: 0x40,
+    });
+    assert!(message.checksum_ok());
+    assert_eq!(decode(&[0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00]), None);
+}
@@ -0,0 +1,226 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Event, Midi, SysexEvent};
+use sysex::u14;
+
+
+// Sample Dump Standard ////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SdsMessage<'a> {
+    pub device: u8,
+    pub packet: Packet<'a>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    DumpHeader(DumpHeader),
+    Data(DataPacket<'a>),
+    DumpRequest {
+        sample: u16,
+    },
+    // The handshake messages carry the number of the packet they refer to.
+    Ack(u8),
+    Nak(u8),
+    Cancel(u8),
+    Wait(u8),
+    EndOfFile(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DumpHeader {
+    pub sample: u16,
+    /// The number of significant bits in each sample word.
+    pub bits: u8,
+    pub period_ns: u32,
+    /// The length of the sample in words.
+    pub length: u32,
+    pub loop_start: u32,
+    pub loop_end: u32,
+    pub loop_type: LoopType,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoopType {
+    Forward,
+    Alternating,
+    Off,
+    Other(u8),
+}
+
+/// A packet of 120 bytes of sample data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DataPacket<'a> {
+    /// A running count of packets, wrapping at 128.
+    pub number: u8,
+    pub data: &'a [u8],
+    pub checksum: u8,
+    /// Whether the checksum matched the one computed over the packet.
+    pub valid: bool,
+}
+
+impl<'a> DataPacket<'a> {
+    /// Unpacks the sample words in the packet. Each word is left-justified
+    /// across as many 7-bit bytes as it needs, and is unsigned with the
+    /// midpoint of the range as zero.
+    pub fn words(&self, bits: u8) -> Vec<u32> {
+        let bits = match bits {
+            0 => return Vec::new(),
+            n if n > 28 => 28,
+            n => n,
+        };
+        let bytes_per_word = (bits as usize + 6) / 7;
+        self.data.chunks(bytes_per_word)
+            .filter(|chunk| chunk.len() == bytes_per_word)
+            .map(|chunk| {
+                let raw = chunk.iter().fold(0u32, |acc, &b| acc << 7 | (b & 0x7F) as u32);
+                raw >> (bytes_per_word as u32 * 7 - bits as u32)
+            })
+            .collect()
+    }
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn sds(&self) -> Option<SdsMessage<'a>> {
+        if self.is_start() {
+            decode(self.payload())
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes the body of a sysex message, without the surrounding F0 and F7.
+pub fn decode(payload: &[u8]) -> Option<SdsMessage> {
+    let (device, sub_id, data) = match payload {
+        [0x7E, device, sub_id, data @ ..] => (*device, *sub_id, data),
+        _ => return None,
+    };
+    let packet = match (sub_id, data) {
+        (0x01, header) if header.len() == 16 => Packet::DumpHeader(DumpHeader {
+            sample: u14(header[0], header[1]),
+            bits: header[2],
+            period_ns: u21(&header[3..6]),
+            length: u21(&header[6..9]),
+            loop_start: u21(&header[9..12]),
+            loop_end: u21(&header[12..15]),
+            loop_type: match header[15] {
+                0x00 => LoopType::Forward,
+                0x01 => LoopType::Alternating,
+                0x7F => LoopType::Off,
+                n => LoopType::Other(n),
+            },
+        }),
+        (0x02, packet) if packet.len() == 122 => {
+            let checksum = packet[121];
+            let computed = payload[..payload.len() - 1].iter().fold(0, |acc, &b| acc ^ b) & 0x7F;
+            Packet::Data(DataPacket {
+                number: packet[0],
+                data: &packet[1..121],
+                checksum: checksum,
+                valid: checksum == computed,
+            })
+        }
+        (0x03, [lsb, msb]) => Packet::DumpRequest { sample: u14(*lsb, *msb) },
+        (0x7B, [n]) => Packet::EndOfFile(*n),
+        (0x7C, [n]) => Packet::Wait(*n),
+        (0x7D, [n]) => Packet::Cancel(*n),
+        (0x7E, [n]) => Packet::Nak(*n),
+        (0x7F, [n]) => Packet::Ack(*n),
+        _ => return None,
+    };
+    Some(SdsMessage {
+        device: device,
+        packet: packet,
+    })
+}
+
+fn u21(bytes: &[u8]) -> u32 {
+    (bytes[2] as u32 & 0x7F) << 14 | (bytes[1] as u32 & 0x7F) << 7 | (bytes[0] as u32 & 0x7F)
+}
+
+
+// Sample Extraction ///////////////////////////////////////////////////////////
+
+/// A sample reassembled from a dump header and the data packets after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleDump {
+    pub device: u8,
+    pub header: DumpHeader,
+    pub words: Vec<u32>,
+    /// The numbers of any packets whose checksum didn't match. Their data is
+    /// still included in the sample.
+    pub bad_packets: Vec<u8>,
+}
+
+/// Collects every sample dumped in the file, in the order of their headers
+/// within each track.
+pub fn extract(midi: &Midi) -> Vec<SampleDump> {
+    let mut dumps = Vec::new();
+    for track in midi.tracks() {
+        let mut current: Option<SampleDump> = None;
+        for event in track.events() {
+            let message = match *event {
+                Event::Sysex(_, ref sysex) => match sysex.sds() {
+                    Some(message) => message,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            match message.packet {
+                Packet::DumpHeader(header) => {
+                    dumps.extend(current.take());
+                    current = Some(SampleDump {
+                        device: message.device,
+                        header: header,
+                        words: Vec::with_capacity(header.length as usize),
+                        bad_packets: Vec::new(),
+                    });
+                }
+                Packet::Data(packet) => if let Some(ref mut dump) = current {
+                    if !packet.valid {
+                        dump.bad_packets.push(packet.number);
+                    }
+                    dump.words.extend(packet.words(dump.header.bits));
+                    // The last packet is padded out to the full 120 bytes.
+                    dump.words.truncate(dump.header.length as usize);
+                },
+                Packet::Cancel(_) => current = None,
+                _ => (),
+            }
+        }
+        dumps.extend(current);
+    }
+    dumps
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_data_packet() {
+    let mut payload = vec![0x7E, 0x00, 0x02, 0x05];
+    // Two 14-bit words of two bytes each, 0x3FFF and 0x0001.
+    payload.extend_from_slice(&[0x7F, 0x7F, 0x00, 0x01]);
+    payload.extend(::std::iter::repeat(0).take(116));
+    let checksum = payload.iter().fold(0, |acc, &b| acc ^ b) & 0x7F;
+    payload.push(checksum);
+
+    let message = decode(&payload).unwrap();
+    match message.packet {
+        Packet::Data(packet) => {
+            assert!(packet.valid);
+            assert_eq!(packet.number, 5);
+            assert_eq!(&packet.words(14)[..2], &[0x3FFF, 0x0001]);
+        }
+        other => panic!("expected a data packet, got {:?}", other),
+    }
+
+    let last = payload.len() - 1;
+    payload[last] ^= 1;
+    match decode(&payload).unwrap().packet {
+        Packet::Data(packet) => assert!(!packet.valid),
+        other => panic!("expected a data packet, got {:?}", other),
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use SysexEvent;
+use sysex::u14;
+use sysex::ci::{self, CiMessage};
+use sysex::manufacturer::ManufacturerId;
+use sysex::mmc::{self, MmcMessage};
+use sysex::msc::{self, MscMessage};
+use sysex::mts::{self, MtsMessage};
+use sysex::sds::{self, SdsMessage};
+
+
+// Universal System Exclusive //////////////////////////////////////////////////
+
+/// A message using one of the universal sysex IDs, 7E for non-real-time or
+/// 7F for real-time, rather than a manufacturer ID.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Universal<'a> {
+    MasterVolume {
+        device: u8,
+        volume: u16,
+    },
+    /// Centered at 0x2000.
+    MasterBalance {
+        device: u8,
+        balance: u16,
+    },
+    IdentityRequest {
+        device: u8,
+    },
+    IdentityReply {
+        device: u8,
+        manufacturer: ManufacturerId,
+        family: u16,
+        member: u16,
+        revision: [u8; 4],
+    },
+    GeneralMidi {
+        device: u8,
+        mode: GeneralMidiMode,
+    },
+    MachineControl(MmcMessage<'a>),
+    ShowControl(MscMessage<'a>),
+    SampleDump(SdsMessage<'a>),
+    Tuning(MtsMessage<'a>),
+    CapabilityInquiry(CiMessage<'a>),
+    /// A message whose sub-ID isn't decoded. The data follows the first
+    /// sub-ID, so it includes the second sub-ID if the message has one.
+    Unknown {
+        realtime: bool,
+        device: u8,
+        sub_id: u8,
+        data: &'a [u8],
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GeneralMidiMode {
+    On,
+    Off,
+    Gm2On,
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn universal(&self) -> Option<Universal<'a>> {
+        if self.is_start() {
+            decode(self.payload())
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes the body of a sysex message, without the surrounding F0 and F7.
+pub fn decode(payload: &[u8]) -> Option<Universal> {
+    use self::Universal::*;
+    let (realtime, device, sub_id, data) = match payload {
+        [0x7E, device, sub_id, data @ ..] => (false, *device, *sub_id, data),
+        [0x7F, device, sub_id, data @ ..] => (true, *device, *sub_id, data),
+        _ => return None,
+    };
+    let unknown = Unknown {
+        realtime: realtime,
+        device: device,
+        sub_id: sub_id,
+        data: data,
+    };
+    Some(match (realtime, sub_id, data) {
+        (true, 0x04, [0x01, lsb, msb]) => MasterVolume {
+            device: device,
+            volume: u14(*lsb, *msb),
+        },
+        (true, 0x04, [0x02, lsb, msb]) => MasterBalance {
+            device: device,
+            balance: u14(*lsb, *msb),
+        },
+        (true, 0x06, _) => mmc::decode(payload).map_or(unknown, MachineControl),
+        (true, 0x02, _) => msc::decode(payload).map_or(unknown, ShowControl),
+        (false, 0x01...0x03, _) | (false, 0x7B...0x7F, _) =>
+            sds::decode(payload).map_or(unknown, SampleDump),
+        (_, 0x08, _) => mts::decode(payload).map_or(unknown, Tuning),
+        (false, 0x0D, _) => ci::decode(payload).map_or(unknown, CapabilityInquiry),
+        (false, 0x06, [0x01]) => IdentityRequest { device: device },
+        (false, 0x06, [0x02, reply @ ..]) => identity_reply(device, reply).unwrap_or(unknown),
+        (false, 0x09, [0x01]) => GeneralMidi {
+            device: device,
+            mode: GeneralMidiMode::On,
+        },
+        (false, 0x09, [0x02]) => GeneralMidi {
+            device: device,
+            mode: GeneralMidiMode::Off,
+        },
+        (false, 0x09, [0x03]) => GeneralMidi {
+            device: device,
+            mode: GeneralMidiMode::Gm2On,
+        },
+        _ => unknown,
+    })
+}
+
+fn identity_reply(device: u8, data: &[u8]) -> Option<Universal> {
+    let (manufacturer, rest) = ManufacturerId::from_bytes(data)?;
+    if rest.len() != 8 {
+        return None;
+    }
+    Some(Universal::IdentityReply {
+        device: device,
+        manufacturer: manufacturer,
+        family: u14(rest[0], rest[1]),
+        member: u14(rest[2], rest[3]),
+        revision: [rest[4], rest[5], rest[6], rest[7]],
+    })
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_universal() {
+    assert_eq!(decode(&[0x7F, 0x7F, 0x04, 0x01, 0x00, 0x40]), Some(Universal::MasterVolume {
+        device: 0x7F,
+        volume: 0x2000,
+    }));
+    assert_eq!(decode(&[0x7E, 0x10, 0x06, 0x02, 0x43, 0x00, 0x41, 0x02, 0x04, 0x00, 0x00, 0x00, 0x01]),
+               Some(Universal::IdentityReply {
+                   device: 0x10,
+                   manufacturer: ManufacturerId::Short(0x43),
+                   family: 0x2080,
+                   member: 0x0202,
+                   revision: [0x00, 0x00, 0x00, 0x01],
+               }));
+    assert_eq!(decode(&[0x7E, 0x7F, 0x0A, 0x01]), Some(Universal::Unknown {
+        realtime: false,
+        device: 0x7F,
+        sub_id: 0x0A,
+        data: &[0x01],
+    }));
+}
@@ -0,0 +1,434 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::cmp;
+
+use {Chunk, Division, Event, MetaEvent, Midi, MidiEvent};
+
+/// The tempo assumed until the first SetTempo event, 120 beats per minute.
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+
+// Tempo Map ///////////////////////////////////////////////////////////////////
+
+/// The tempo in effect over a file, used to convert ticks into seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TempoMap {
+    division: Division,
+    /// Pairs of (tick, microseconds per quarter note), always starting at 0.
+    changes: Vec<(u64, u32)>,
+    /// The time at each change in microseconds times the ticks per quarter
+    /// note, which keeps it exact. Conversions are then a binary search and
+    /// a little arithmetic.
+    elapsed: Vec<u128>,
+}
+
+impl TempoMap {
+    pub fn new(midi: &Midi) -> TempoMap {
+        let changes = set_tempo_events(midi)
+            .into_iter()
+            .map(|(tick, _, tempo)| (tick, tempo))
+            .collect();
+        TempoMap::from_changes(midi.header().division(), changes)
+    }
+
+    /// Builds a map from tempo changes in any order. The default tempo is
+    /// assumed until the first change, and when several changes land on the
+    /// same tick the last one wins.
+    pub fn from_changes(division: Division, mut changes: Vec<(u64, u32)>) -> TempoMap {
+        changes.sort_by_key(|&(tick, _)| tick);
+        let mut deduped: Vec<(u64, u32)> = Vec::with_capacity(changes.len() + 1);
+        deduped.push((0, DEFAULT_TEMPO));
+        for (tick, tempo) in changes {
+            if deduped.last().map_or(false, |&(last, _)| last == tick) {
+                deduped.pop();
+            }
+            deduped.push((tick, tempo));
+        }
+        let mut elapsed = Vec::with_capacity(deduped.len());
+        let mut total = 0;
+        for (i, &(tick, _)) in deduped.iter().enumerate() {
+            if i > 0 {
+                let (start, tempo) = deduped[i - 1];
+                total += (tick - start) as u128 * tempo as u128;
+            }
+            elapsed.push(total);
+        }
+        TempoMap {
+            division: division,
+            changes: deduped,
+            elapsed: elapsed,
+        }
+    }
+
+    pub fn division(&self) -> Division {
+        self.division
+    }
+
+    pub fn changes(&self) -> &[(u64, u32)] {
+        &self.changes
+    }
+
+    /// The tempo in microseconds per quarter note at `tick`.
+    pub fn tempo_at(&self, tick: u64) -> u32 {
+        self.changes[self.index_at(tick)].1
+    }
+
+    /// The wall-clock time of `tick` in seconds from the start of the file.
+    pub fn seconds_at(&self, tick: u64) -> f64 {
+        self.seconds_in(self.index_at(tick), tick)
+    }
+
+    /// The nearest tick to a wall-clock time in seconds from the start of
+    /// the file, undoing `seconds_at`.
+    pub fn tick_at(&self, seconds: f64) -> u64 {
+        let seconds = seconds.max(0.0);
+        let ticks_per_quarter = match self.division {
+            Division::TicksPerQuarter(n) => cmp::max(1, n) as f64,
+            Division::Smpte { frames_per_second, ticks_per_frame } => {
+                let ticks_per_second = frame_rate(frames_per_second) * ticks_per_frame as f64;
+                return (seconds * ticks_per_second).round() as u64;
+            }
+        };
+        // In the same units as `elapsed`.
+        let elapsed = seconds * 1_000_000.0 * ticks_per_quarter;
+        let index = match self.elapsed.binary_search_by(|&start| {
+            (start as f64).partial_cmp(&elapsed).unwrap_or(cmp::Ordering::Less)
+        }) {
+            Ok(i) => i,
+            // The first change is always at time 0, so this is never 0.
+            Err(i) => i - 1,
+        };
+        let (start, tempo) = self.changes[index];
+        let since = elapsed - self.elapsed[index] as f64;
+        start + (since / cmp::max(1, tempo) as f64).round() as u64
+    }
+
+    /// A cursor for converting ticks that mostly increase, which avoids the
+    /// binary search while the ticks keep moving forward.
+    pub fn cursor(&self) -> TempoCursor {
+        TempoCursor {
+            map: self,
+            index: 0,
+        }
+    }
+
+    /// The index of the change in effect at `tick`.
+    fn index_at(&self, tick: u64) -> usize {
+        match self.changes.binary_search_by_key(&tick, |&(start, _)| start) {
+            Ok(i) => i,
+            // The first change is always at tick 0, so this is never 0.
+            Err(i) => i - 1,
+        }
+    }
+
+    /// Converts `tick` knowing that the change at `index` is in effect.
+    fn seconds_in(&self, index: usize, tick: u64) -> f64 {
+        let ticks_per_quarter = match self.division {
+            Division::TicksPerQuarter(n) => cmp::max(1, n) as f64,
+            Division::Smpte { frames_per_second, ticks_per_frame } => {
+                let ticks_per_second = frame_rate(frames_per_second) * ticks_per_frame as f64;
+                return tick as f64 / ticks_per_second;
+            }
+        };
+        let (start, tempo) = self.changes[index];
+        let elapsed = self.elapsed[index] + (tick - start) as u128 * tempo as u128;
+        elapsed as f64 / ticks_per_quarter / 1_000_000.0
+    }
+}
+
+/// Converts ticks to seconds in a tempo map, remembering where the last tick
+/// was found.
+#[derive(Debug, Clone)]
+pub struct TempoCursor<'m> {
+    map: &'m TempoMap,
+    index: usize,
+}
+
+impl<'m> TempoCursor<'m> {
+    /// Converts `tick` like `TempoMap::seconds_at`. Going backwards falls
+    /// back to a binary search.
+    pub fn seconds_at(&mut self, tick: u64) -> f64 {
+        let changes = &self.map.changes;
+        if tick < changes[self.index].0 {
+            self.index = self.map.index_at(tick);
+        }
+        while self.index + 1 < changes.len() && changes[self.index + 1].0 <= tick {
+            self.index += 1;
+        }
+        self.map.seconds_in(self.index, tick)
+    }
+}
+
+/// The -29 SMPTE rate is really 29.97 drop-frame.
+fn frame_rate(frames_per_second: u8) -> f64 {
+    match frames_per_second {
+        29 => 30_000.0 / 1001.0,
+        0 => 1.0,
+        n => n as f64,
+    }
+}
+
+pub fn bpm(micros_per_quarter: u32) -> f64 {
+    60_000_000.0 / cmp::max(1, micros_per_quarter) as f64
+}
+
+/// Every SetTempo event in the file as (tick, track index, tempo), sorted by
+/// tick.
+fn set_tempo_events(midi: &Midi) -> Vec<(u64, usize, u32)> {
+    let mut events = Vec::new();
+    for (index, track) in midi.tracks().enumerate() {
+        for (tick, event) in track.absolute_events() {
+            if let Event::Meta(_, MetaEvent::SetTempo(tempo)) = *event {
+                events.push((tick, index, tempo));
+            }
+        }
+    }
+    events.sort_by_key(|&(tick, _, _)| tick);
+    events
+}
+
+
+// Tempo Change Listing ////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TempoChange {
+    pub tick: u64,
+    pub track: usize,
+    pub seconds: f64,
+    pub micros_per_quarter: u32,
+    pub bpm: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoChanges {
+    pub changes: Vec<TempoChange>,
+    /// The tempo at the start of the file, which is the default tempo unless
+    /// a tempo event appears on tick 0.
+    pub initial_bpm: f64,
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+}
+
+impl<'a> Midi<'a> {
+    pub fn tempo_map(&self) -> TempoMap {
+        TempoMap::new(self)
+    }
+
+    /// Lists every tempo event in the file along with when it happens,
+    /// summarizing the range of tempos in effect.
+    pub fn tempo_changes(&self) -> TempoChanges {
+        let map = self.tempo_map();
+        let changes: Vec<TempoChange> = set_tempo_events(self)
+            .into_iter()
+            .map(|(tick, track, tempo)| TempoChange {
+                tick: tick,
+                track: track,
+                seconds: map.seconds_at(tick),
+                micros_per_quarter: tempo,
+                bpm: bpm(tempo),
+            })
+            .collect();
+        let effective: Vec<f64> = map.changes().iter().map(|&(_, tempo)| bpm(tempo)).collect();
+        TempoChanges {
+            changes: changes,
+            initial_bpm: bpm(map.tempo_at(0)),
+            min_bpm: effective.iter().cloned().fold(::std::f64::INFINITY, f64::min),
+            max_bpm: effective.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+
+// Tempo Inference /////////////////////////////////////////////////////////////
+
+/// A guess at a file's beat from the spacing of its notes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TempoEstimate {
+    /// How many ticks a beat seems to take.
+    pub ticks_per_beat: u64,
+    /// How fast those beats go at the file's tempo.
+    pub bpm: f64,
+    /// The share of note onsets from 0 to 1 that fall on the beat grid.
+    pub confidence: f64,
+}
+
+impl<'a> Midi<'a> {
+    /// Guesses the beat from the intervals between note onsets, for files
+    /// whose ticks don't line up with their quarter notes, like many that
+    /// were ripped from games. The beat is taken to be between 50 and 200
+    /// beats per minute, preferring beats close to 120. Returns `None` for
+    /// files using SMPTE time, with a tempo change after the start, or with
+    /// too few notes to tell.
+    pub fn estimate_tempo(&self) -> Option<TempoEstimate> {
+        if let Division::Smpte { .. } = self.header().division() {
+            return None;
+        }
+        let map = self.tempo_map();
+        if map.changes().len() > 1 {
+            return None;
+        }
+        let mut onsets: Vec<u64> = Vec::new();
+        for track in self.tracks() {
+            onsets.extend(track.absolute_events().filter_map(|(tick, event)| match *event {
+                Event::Midi(_, MidiEvent::NoteOn { velocity, .. }) if velocity > 0 => {
+                    Some(tick)
+                }
+                _ => None,
+            }));
+        }
+        onsets.sort();
+        onsets.dedup();
+        if onsets.len() < 3 {
+            return None;
+        }
+        // The beat is usually an interval between onsets, or a few of them
+        // added up when the notes subdivide it.
+        let mut candidates = Vec::new();
+        for i in 0..onsets.len() {
+            for span in 1..5 {
+                if let Some(&end) = onsets.get(i + span) {
+                    candidates.push(end - onsets[i]);
+                }
+            }
+        }
+        candidates.sort();
+        candidates.dedup();
+        let seconds_per_tick = map.seconds_at(1);
+        let first = onsets[0];
+        let mut scored: Vec<(u64, f64, f64)> = candidates.into_iter()
+            .filter(|&ticks| ticks <= 0x7FFF)
+            .map(|ticks| (ticks, 60.0 / (ticks as f64 * seconds_per_tick)))
+            .filter(|&(_, bpm)| bpm >= 50.0 && bpm <= 200.0)
+            .map(|(ticks, bpm)| {
+                let tolerance = ticks / 16;
+                let on_grid = onsets.iter()
+                    .filter(|&&onset| {
+                        let offset = (onset - first) % ticks;
+                        offset <= tolerance || ticks - offset <= tolerance
+                    })
+                    .count();
+                (ticks, bpm, on_grid as f64 / onsets.len() as f64)
+            })
+            .collect();
+        let best = scored.iter().map(|&(_, _, score)| score).fold(0.0, f64::max);
+        // A slower beat that fits almost as well is just as likely, so pick
+        // the most ordinary tempo among the good fits.
+        scored.retain(|&(_, _, score)| score >= best * 0.9);
+        scored.sort_by(|a, b| {
+            (a.1 - 120.0).abs().partial_cmp(&(b.1 - 120.0).abs()).unwrap_or(cmp::Ordering::Equal)
+        });
+        scored.first().map(|&(ticks, bpm, score)| TempoEstimate {
+            ticks_per_beat: ticks,
+            bpm: bpm,
+            confidence: score,
+        })
+    }
+
+    /// Makes the estimated beat the file's quarter note without changing how
+    /// it sounds, by setting the division to the beat and writing a tempo at
+    /// the start of the first track in place of any others, as near as a
+    /// SetTempo event can hold. Returns false, changing nothing, if the file
+    /// has no tracks or uses SMPTE time, or the beat isn't a division.
+    pub fn apply_tempo_estimate(&mut self, estimate: &TempoEstimate) -> bool {
+        let old = match self.header().division() {
+            Division::TicksPerQuarter(n) if n > 0 => n as u64,
+            _ => return false,
+        };
+        if self.tracks().next().is_none() || estimate.ticks_per_beat == 0 ||
+           estimate.ticks_per_beat > 0x7FFF {
+            return false;
+        }
+        let tempo = self.tempo_map().tempo_at(0) as u64 * estimate.ticks_per_beat / old;
+        let tempo = cmp::max(1, cmp::min(tempo, 0xFF_FFFF)) as u32;
+        let mut first = true;
+        for chunk in &mut self.chunks {
+            if let Chunk::Track(ref mut track) = *chunk {
+                let mut carry = 0;
+                let mut events = Vec::with_capacity(track.events.len() + 1);
+                for mut event in track.events.drain(..) {
+                    if let Event::Meta(delta, MetaEvent::SetTempo(_)) = event {
+                        carry += delta;
+                        continue;
+                    }
+                    let delta = event.delta() + carry;
+                    event.set_delta(delta);
+                    carry = 0;
+                    events.push(event);
+                }
+                track.events = events.into();
+                if first {
+                    track.insert(0, Event::Meta(0, MetaEvent::SetTempo(tempo)));
+                    first = false;
+                }
+            }
+        }
+        self.header.division = estimate.ticks_per_beat as u16;
+        true
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_tempo_changes() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x14,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x81, 0x40, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90,
+        0x81, 0x40, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    let map = midi.tempo_map();
+    assert_eq!(map.seconds_at(192), 1.0);
+    assert_eq!(map.seconds_at(384), 1.5);
+    assert_eq!(map.tempo_at(191), 500_000);
+    assert_eq!(map.tempo_at(192), 250_000);
+    let mut cursor = map.cursor();
+    let seconds: Vec<f64> = [0, 96, 192, 288, 96].iter().map(|&t| cursor.seconds_at(t)).collect();
+    assert_eq!(seconds, [0.0, 0.5, 1.0, 1.25, 0.5]);
+    let ticks: Vec<u64> = seconds.iter().map(|&s| map.tick_at(s)).collect();
+    assert_eq!(ticks, [0, 96, 192, 288, 96]);
+
+    let tempos = midi.tempo_changes();
+    assert_eq!(tempos.changes.len(), 2);
+    assert_eq!(tempos.changes[1].micros_per_quarter, 250_000);
+    assert_eq!(tempos.changes[1].seconds, 1.0);
+    assert_eq!(tempos.initial_bpm, 120.0);
+    assert_eq!(tempos.min_bpm, 120.0);
+    assert_eq!(tempos.max_bpm, 240.0);
+}
+
+#[cfg(test)]
+#[test]
+fn test_estimate_tempo() {
+    // A beat of 100 ticks at 96 ticks per quarter note, with some eighths.
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1D,
+        0x00, 0x90, 0x3C, 0x40,
+        0x64, 0x3E, 0x40,
+        0x32, 0x40, 0x40,
+        0x32, 0x41, 0x40,
+        0x64, 0x43, 0x40,
+        0x32, 0x45, 0x40,
+        0x32, 0x47, 0x40,
+        0x64, 0x48, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let mut midi = ::parse_midi(&data).unwrap();
+    let estimate = midi.estimate_tempo().unwrap();
+    assert_eq!(estimate.ticks_per_beat, 100);
+    assert!((estimate.bpm - 115.2).abs() < 1e-9);
+    assert!(midi.apply_tempo_estimate(&estimate));
+    assert_eq!(midi.header().division(), Division::TicksPerQuarter(100));
+    assert_eq!(midi.tempo_map().tempo_at(0), 520_833);
+    assert!((midi.tempo_map().seconds_at(100) - 0.520833).abs() < 1e-9);
+    let slow = TempoEstimate { ticks_per_beat: 0x7FFF, ..estimate };
+    assert!(midi.apply_tempo_estimate(&slow));
+    assert_eq!(midi.tempo_map().tempo_at(0), 0xFF_FFFF);
+}
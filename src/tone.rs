@@ -0,0 +1,145 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use Midi;
+use notation::melody;
+use pitch::{key_to_freq, A4_HZ};
+use tempo::TempoMap;
+
+
+// Tones ///////////////////////////////////////////////////////////////////////
+
+/// The frequency of each key in whole Hz, with A4 at 440 Hz, for a tone API
+/// that takes integers.
+pub const KEY_HZ: [u16; 128] = [
+    8, 9, 9, 10, 10, 11, 12, 12, 13, 14, 15, 15,
+    16, 17, 18, 19, 21, 22, 23, 24, 26, 28, 29, 31,
+    33, 35, 37, 39, 41, 44, 46, 49, 52, 55, 58, 62,
+    65, 69, 73, 78, 82, 87, 92, 98, 104, 110, 117, 123,
+    131, 139, 147, 156, 165, 175, 185, 196, 208, 220, 233, 247,
+    262, 277, 294, 311, 330, 349, 370, 392, 415, 440, 466, 494,
+    523, 554, 587, 622, 659, 698, 740, 784, 831, 880, 932, 988,
+    1047, 1109, 1175, 1245, 1319, 1397, 1480, 1568, 1661, 1760, 1865, 1976,
+    2093, 2217, 2349, 2489, 2637, 2794, 2960, 3136, 3322, 3520, 3729, 3951,
+    4186, 4435, 4699, 4978, 5274, 5588, 5920, 6272, 6645, 7040, 7459, 7902,
+    8372, 8870, 9397, 9956, 10548, 11175, 11840, 12544,
+];
+
+/// Settings for `tones_with`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneOptions {
+    /// The index of the track to play, or `None` for every track mixed.
+    pub track: Option<usize>,
+    pub a4_hz: f64,
+}
+
+impl Default for ToneOptions {
+    fn default() -> ToneOptions {
+        ToneOptions {
+            track: None,
+            a4_hz: A4_HZ,
+        }
+    }
+}
+
+impl<'a> Midi<'a> {
+    pub fn tones(&self) -> Vec<(u16, u32)> {
+        self.tones_with(&ToneOptions::default())
+    }
+
+    /// Flattens the file into one line of tones for a buzzer, as pairs of a
+    /// frequency in Hz and a duration in milliseconds, where a frequency of
+    /// 0 is a rest. Where notes overlap the highest of those starting
+    /// together is played, until the next note starts. Durations follow the
+    /// tempo map and are rounded so they add up to the length of the file.
+    pub fn tones_with(&self, options: &ToneOptions) -> Vec<(u16, u32)> {
+        let mut tones = Vec::new();
+        self.each_tone(options, |tone| tones.push(tone));
+        tones
+    }
+
+    /// Writes the tones of `tones_with` into `out`, without allocating for
+    /// them, and gives how many tones there are, which is more than fit if
+    /// `out` is too short. The file is still parsed and read with `std`, so
+    /// this is for filling a fixed table that's then copied to a
+    /// microcontroller, not for running on one.
+    pub fn tones_into(&self, options: &ToneOptions, out: &mut [(u16, u32)]) -> usize {
+        let mut count = 0;
+        self.each_tone(options, |tone| {
+            if let Some(slot) = out.get_mut(count) {
+                *slot = tone;
+            }
+            count += 1;
+        });
+        count
+    }
+
+    fn each_tone<F: FnMut((u16, u32))>(&self, options: &ToneOptions, mut f: F) {
+        let notes: Vec<_> = self.notes()
+            .into_iter()
+            .filter(|note| options.track.map_or(true, |track| note.track == track))
+            .collect();
+        let tempo_map = TempoMap::new(self);
+        let ms = |tick| (tempo_map.seconds_at(tick) * 1000.0).round() as u64;
+
+        let mut time = 0;
+        let mut push = |frequency, end| {
+            if end > time {
+                f((frequency, (end - time) as u32));
+                time = end;
+            }
+        };
+        for note in melody(&notes, 1) {
+            let frequency = if options.a4_hz == A4_HZ {
+                KEY_HZ[note.key as usize & 0x7F]
+            } else {
+                key_to_freq(note.key as f64, options.a4_hz).round() as u16
+            };
+            push(0, ms(note.start));
+            push(frequency, ms(note.end));
+        }
+        let end = self.tracks()
+            .enumerate()
+            .filter(|&(index, _)| options.track.map_or(true, |track| index == track))
+            .map(|(_, track)| track.absolute_events().last().map_or(0, |(tick, _)| tick))
+            .max()
+            .unwrap_or(0);
+        push(0, ms(end));
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_tones() {
+    let tones = ::gen::scale(69, &::gen::MAJOR).tones();
+    assert_eq!(&tones[..3], &[(440, 500), (494, 500), (554, 500)]);
+    assert_eq!(tones.len(), 8);
+
+    // Two tracks at 120 and then 60 bpm, with the lower line overlapping.
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x19,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40,
+        0x00, 0x90, 0x45, 0x40,
+        0x60, 0x45, 0x00,
+        0x60, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x00, 0x91, 0x39, 0x40,
+        0x81, 0x40, 0x39, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    assert_eq!(midi.tones(), [(220, 500), (440, 1000), (0, 1000)]);
+    let bass = midi.tones_with(&ToneOptions { track: Some(1), ..ToneOptions::default() });
+    assert_eq!(bass, [(220, 1500)]);
+    let mut out = [(0, 0); 2];
+    assert_eq!(midi.tones_into(&ToneOptions::default(), &mut out), 3);
+    assert_eq!(out, [(220, 500), (440, 1000)]);
+    for key in 0..128 {
+        assert_eq!(KEY_HZ[key], key_to_freq(key as f64, A4_HZ).round() as u16);
+    }
+}
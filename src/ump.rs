@@ -0,0 +1,589 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {midi_event, MidiEvent, SystemMessage};
+
+
+// Universal MIDI Packets //////////////////////////////////////////////////////
+
+/// A MIDI 2.0 Universal MIDI Packet. The group is the upper nibble of the
+/// first byte of every packet, addressing one of 16 groups of 16 channels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Utility(Utility),
+    System {
+        group: u8,
+        message: SystemMessage,
+    },
+    Midi1 {
+        group: u8,
+        event: MidiEvent,
+    },
+    Sysex7 {
+        group: u8,
+        status: DataStatus,
+        len: u8,
+        data: [u8; 6],
+    },
+    Midi2 {
+        group: u8,
+        event: Midi2Event,
+    },
+    Sysex8 {
+        group: u8,
+        status: DataStatus,
+        stream: u8,
+        len: u8,
+        data: [u8; 13],
+    },
+    /// Any packet that isn't decoded, including flex data, UMP stream and
+    /// reserved message types. Only the first `packet_len` words are used.
+    Raw {
+        message_type: u8,
+        words: [u32; 4],
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Utility {
+    Noop,
+    JrClock(u16),
+    JrTimestamp(u16),
+    DeltaClockstampTpq(u16),
+    DeltaClockstamp(u32),
+    Other {
+        status: u8,
+        data: u32,
+    },
+}
+
+/// Where a packet falls within a sysex message split across packets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataStatus {
+    Complete,
+    Start,
+    Continue,
+    End,
+    Other(u8),
+}
+
+/// MIDI 2.0 channel voice messages, with higher resolution values than their
+/// MIDI 1.0 equivalents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Midi2Event {
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u16,
+        attribute_type: u8,
+        attribute: u16,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u16,
+        attribute_type: u8,
+        attribute: u16,
+    },
+    PolyPressure {
+        channel: u8,
+        note: u8,
+        pressure: u32,
+    },
+    ControlChange {
+        channel: u8,
+        index: u8,
+        value: u32,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+        /// The bank MSB and LSB, if the bank valid flag is set.
+        bank: Option<(u8, u8)>,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u32,
+    },
+    /// Centered at 0x80000000.
+    PitchBend {
+        channel: u8,
+        value: u32,
+    },
+    PerNotePitchBend {
+        channel: u8,
+        note: u8,
+        value: u32,
+    },
+    RegisteredController {
+        channel: u8,
+        bank: u8,
+        index: u8,
+        value: u32,
+    },
+    AssignableController {
+        channel: u8,
+        bank: u8,
+        index: u8,
+        value: u32,
+    },
+    RelativeRegisteredController {
+        channel: u8,
+        bank: u8,
+        index: u8,
+        value: i32,
+    },
+    RelativeAssignableController {
+        channel: u8,
+        bank: u8,
+        index: u8,
+        value: i32,
+    },
+    RegisteredPerNoteController {
+        channel: u8,
+        note: u8,
+        index: u8,
+        value: u32,
+    },
+    AssignablePerNoteController {
+        channel: u8,
+        note: u8,
+        index: u8,
+        value: u32,
+    },
+    PerNoteManagement {
+        channel: u8,
+        note: u8,
+        detach: bool,
+        reset: bool,
+    },
+}
+
+impl Packet {
+    pub fn group(&self) -> Option<u8> {
+        match *self {
+            Packet::Utility(_) => None,
+            Packet::System { group, .. } |
+            Packet::Midi1 { group, .. } |
+            Packet::Sysex7 { group, .. } |
+            Packet::Midi2 { group, .. } |
+            Packet::Sysex8 { group, .. } => Some(group),
+            Packet::Raw { words, .. } => Some((words[0] >> 24) as u8 & 0x0F),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match *self {
+            Packet::Sysex7 { len, ref data, .. } => &data[..::std::cmp::min(len as usize, 6)],
+            Packet::Sysex8 { len, ref data, .. } => &data[..::std::cmp::min(len as usize, 13)],
+            _ => &[],
+        }
+    }
+
+    /// Writes the packet's words, the inverse of `parse_packet`.
+    pub fn write_to(&self, out: &mut Vec<u32>) {
+        let word = |message_type: u32, group: u8, b1: u8, b2: u8, b3: u8| {
+            message_type << 28 | (group as u32 & 0x0F) << 24 | (b1 as u32) << 16 |
+            (b2 as u32) << 8 | b3 as u32
+        };
+        match *self {
+            Packet::Utility(utility) => {
+                let (status, data) = match utility {
+                    Utility::Noop => (0x0, 0),
+                    Utility::JrClock(time) => (0x1, time as u32),
+                    Utility::JrTimestamp(time) => (0x2, time as u32),
+                    Utility::DeltaClockstampTpq(tpq) => (0x3, tpq as u32),
+                    Utility::DeltaClockstamp(ticks) => (0x4, ticks),
+                    Utility::Other { status, data } => (status, data),
+                };
+                out.push((status as u32 & 0x0F) << 20 | data & 0x000F_FFFF);
+            }
+            Packet::System { group, message } => {
+                let (status, data1, data2) = message.parts();
+                out.push(word(0x1, group, status, data1, data2));
+            }
+            Packet::Midi1 { group, event } => {
+                let mut bytes = Vec::with_capacity(3);
+                event.write_to(&mut bytes);
+                bytes.resize(3, 0);
+                out.push(word(0x2, group, bytes[0], bytes[1], bytes[2]));
+            }
+            Packet::Sysex7 { group, status, len, data } => {
+                let b1 = status_nibble(status) << 4 | len & 0x0F;
+                out.push(word(0x3, group, b1, data[0], data[1]));
+                out.push(from_be_bytes(&data[2..6]));
+            }
+            Packet::Midi2 { group, event } => {
+                let (status, index1, index2, data) = midi2_parts(event);
+                out.push(word(0x4, group, status, index1, index2));
+                out.push(data);
+            }
+            Packet::Sysex8 { group, status, stream, len, data } => {
+                let b1 = status_nibble(status) << 4 | len.saturating_add(1) & 0x0F;
+                out.push(word(0x5, group, b1, stream, data[0]));
+                out.extend(data[1..].chunks(4).map(from_be_bytes));
+            }
+            Packet::Raw { words, .. } => out.extend_from_slice(&words[..packet_len(words[0])]),
+        }
+    }
+}
+
+/// The number of 32-bit words in a packet, which is fixed by its message
+/// type even for types that aren't defined yet.
+pub fn packet_len(first_word: u32) -> usize {
+    match first_word >> 28 {
+        0x0 | 0x1 | 0x2 | 0x6 | 0x7 => 1,
+        0x3 | 0x4 | 0x8 | 0x9 | 0xA => 2,
+        0xB | 0xC => 3,
+        _ => 4,
+    }
+}
+
+/// Parses the packet at the start of `words`, returning it along with the
+/// words after it, or `None` if the packet is cut off.
+pub fn parse_packet(words: &[u32]) -> Option<(Packet, &[u32])> {
+    let first = *words.first()?;
+    let len = packet_len(first);
+    if words.len() < len {
+        return None;
+    }
+    let (packet, rest) = words.split_at(len);
+    let message_type = (first >> 28) as u8;
+    let group = (first >> 24) as u8 & 0x0F;
+    let [b1, b2, b3] = [(first >> 16) as u8, (first >> 8) as u8, first as u8];
+    let decoded = match message_type {
+        0x0 => Some(Packet::Utility(utility(first))),
+        0x1 => SystemMessage::from_parts(b1, b2, b3).map(|message| Packet::System {
+            group: group,
+            message: message,
+        }),
+        0x2 => match midi_event(&[b2, b3], b1) {
+            Ok((_, event)) => Some(Packet::Midi1 {
+                group: group,
+                event: event,
+            }),
+            _ => None,
+        },
+        0x3 => {
+            let mut data = [0; 6];
+            data[0] = b2;
+            data[1] = b3;
+            data[2..].copy_from_slice(&be_bytes(packet[1]));
+            Some(Packet::Sysex7 {
+                group: group,
+                status: data_status(b1 >> 4),
+                len: b1 & 0x0F,
+                data: data,
+            })
+        }
+        0x4 => midi2_event(b1, b2, b3, packet[1]).map(|event| Packet::Midi2 {
+            group: group,
+            event: event,
+        }),
+        0x5 if b1 >> 4 <= 3 => {
+            let mut data = [0; 13];
+            data[0] = b3;
+            for (i, &word) in packet[1..].iter().enumerate() {
+                data[1 + i * 4..5 + i * 4].copy_from_slice(&be_bytes(word));
+            }
+            Some(Packet::Sysex8 {
+                group: group,
+                status: data_status(b1 >> 4),
+                // The stream ID is counted as one of the bytes.
+                len: (b1 & 0x0F).saturating_sub(1),
+                stream: b2,
+                data: data,
+            })
+        }
+        _ => None,
+    };
+    let decoded = decoded.unwrap_or_else(|| {
+        let mut raw = [0; 4];
+        raw[..len].copy_from_slice(packet);
+        Packet::Raw {
+            message_type: message_type,
+            words: raw,
+        }
+    });
+    Some((decoded, rest))
+}
+
+fn utility(word: u32) -> Utility {
+    let status = (word >> 20) as u8 & 0x0F;
+    match status {
+        0x0 => Utility::Noop,
+        0x1 => Utility::JrClock(word as u16),
+        0x2 => Utility::JrTimestamp(word as u16),
+        0x3 => Utility::DeltaClockstampTpq(word as u16),
+        0x4 => Utility::DeltaClockstamp(word & 0x000F_FFFF),
+        _ => Utility::Other {
+            status: status,
+            data: word & 0x000F_FFFF,
+        },
+    }
+}
+
+fn data_status(status: u8) -> DataStatus {
+    match status {
+        0x0 => DataStatus::Complete,
+        0x1 => DataStatus::Start,
+        0x2 => DataStatus::Continue,
+        0x3 => DataStatus::End,
+        n => DataStatus::Other(n),
+    }
+}
+
+fn status_nibble(status: DataStatus) -> u8 {
+    match status {
+        DataStatus::Complete => 0x0,
+        DataStatus::Start => 0x1,
+        DataStatus::Continue => 0x2,
+        DataStatus::End => 0x3,
+        DataStatus::Other(n) => n & 0x0F,
+    }
+}
+
+fn midi2_event(status: u8, index1: u8, index2: u8, data: u32) -> Option<Midi2Event> {
+    use self::Midi2Event::*;
+    let channel = status & 0x0F;
+    Some(match status >> 4 {
+        0x0 => RegisteredPerNoteController {
+            channel: channel,
+            note: index1,
+            index: index2,
+            value: data,
+        },
+        0x1 => AssignablePerNoteController {
+            channel: channel,
+            note: index1,
+            index: index2,
+            value: data,
+        },
+        0x2 => RegisteredController {
+            channel: channel,
+            bank: index1,
+            index: index2,
+            value: data,
+        },
+        0x3 => AssignableController {
+            channel: channel,
+            bank: index1,
+            index: index2,
+            value: data,
+        },
+        0x4 => RelativeRegisteredController {
+            channel: channel,
+            bank: index1,
+            index: index2,
+            value: data as i32,
+        },
+        0x5 => RelativeAssignableController {
+            channel: channel,
+            bank: index1,
+            index: index2,
+            value: data as i32,
+        },
+        0x6 => PerNotePitchBend {
+            channel: channel,
+            note: index1,
+            value: data,
+        },
+        0x8 => NoteOff {
+            channel: channel,
+            note: index1,
+            velocity: (data >> 16) as u16,
+            attribute_type: index2,
+            attribute: data as u16,
+        },
+        0x9 => NoteOn {
+            channel: channel,
+            note: index1,
+            velocity: (data >> 16) as u16,
+            attribute_type: index2,
+            attribute: data as u16,
+        },
+        0xA => PolyPressure {
+            channel: channel,
+            note: index1,
+            pressure: data,
+        },
+        0xB => ControlChange {
+            channel: channel,
+            index: index1,
+            value: data,
+        },
+        0xC => ProgramChange {
+            channel: channel,
+            program: (data >> 24) as u8,
+            bank: if index2 & 0x01 != 0 {
+                Some(((data >> 8) as u8, data as u8))
+            } else {
+                None
+            },
+        },
+        0xD => ChannelPressure {
+            channel: channel,
+            pressure: data,
+        },
+        0xE => PitchBend {
+            channel: channel,
+            value: data,
+        },
+        0xF => PerNoteManagement {
+            channel: channel,
+            note: index1,
+            detach: index2 & 0x02 != 0,
+            reset: index2 & 0x01 != 0,
+        },
+        _ => return None,
+    })
+}
+
+/// The status byte, two index bytes and data word of a message, the inverse
+/// of `midi2_event`.
+fn midi2_parts(event: Midi2Event) -> (u8, u8, u8, u32) {
+    use self::Midi2Event::*;
+    let (kind, channel, index1, index2, data) = match event {
+        RegisteredPerNoteController { channel, note, index, value } => {
+            (0x0, channel, note, index, value)
+        }
+        AssignablePerNoteController { channel, note, index, value } => {
+            (0x1, channel, note, index, value)
+        }
+        RegisteredController { channel, bank, index, value } => (0x2, channel, bank, index, value),
+        AssignableController { channel, bank, index, value } => (0x3, channel, bank, index, value),
+        RelativeRegisteredController { channel, bank, index, value } => {
+            (0x4, channel, bank, index, value as u32)
+        }
+        RelativeAssignableController { channel, bank, index, value } => {
+            (0x5, channel, bank, index, value as u32)
+        }
+        PerNotePitchBend { channel, note, value } => (0x6, channel, note, 0, value),
+        NoteOff { channel, note, velocity, attribute_type, attribute } => {
+            (0x8, channel, note, attribute_type, (velocity as u32) << 16 | attribute as u32)
+        }
+        NoteOn { channel, note, velocity, attribute_type, attribute } => {
+            (0x9, channel, note, attribute_type, (velocity as u32) << 16 | attribute as u32)
+        }
+        PolyPressure { channel, note, pressure } => (0xA, channel, note, 0, pressure),
+        ControlChange { channel, index, value } => (0xB, channel, index, 0, value),
+        ProgramChange { channel, program, bank } => {
+            let (msb, lsb) = bank.unwrap_or((0, 0));
+            let data = (program as u32) << 24 | (msb as u32) << 8 | lsb as u32;
+            (0xC, channel, 0, bank.is_some() as u8, data)
+        }
+        ChannelPressure { channel, pressure } => (0xD, channel, 0, 0, pressure),
+        PitchBend { channel, value } => (0xE, channel, 0, 0, value),
+        PerNoteManagement { channel, note, detach, reset } => {
+            (0xF, channel, note, (detach as u8) << 1 | reset as u8, 0)
+        }
+    };
+    (kind << 4 | channel & 0x0F, index1, index2, data)
+}
+
+fn be_bytes(word: u32) -> [u8; 4] {
+    [(word >> 24) as u8, (word >> 16) as u8, (word >> 8) as u8, word as u8]
+}
+
+fn from_be_bytes(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+/// Groups big-endian bytes into words, ignoring any incomplete final word.
+pub fn words_from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes.chunks(4).filter(|chunk| chunk.len() == 4).map(from_be_bytes).collect()
+}
+
+/// Splits words into big-endian bytes, the inverse of `words_from_bytes`.
+pub fn bytes_from_words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|&word| be_bytes(word).to_vec()).collect()
+}
+
+/// Iterates over the packets in a stream of words, stopping at the first
+/// packet that's cut off.
+pub struct Packets<'a> {
+    words: &'a [u32],
+}
+
+impl<'a> Packets<'a> {
+    pub fn new(words: &'a [u32]) -> Packets<'a> {
+        Packets { words: words }
+    }
+
+    /// The words that haven't been parsed yet.
+    pub fn remainder(&self) -> &'a [u32] {
+        self.words
+    }
+}
+
+impl<'a> Iterator for Packets<'a> {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Packet> {
+        parse_packet(self.words).map(|(packet, rest)| {
+            self.words = rest;
+            packet
+        })
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_parse_packets() {
+    let words = [
+        0x0030_0060,
+        0x2391_3C40,
+        0x4391_3C00, 0x8000_0000,
+        0x40C2_0001, 0x0500_0102,
+        0x1FF8_0000,
+        0x0000,
+    ];
+    let packets: Vec<Packet> = Packets::new(&words).collect();
+    assert_eq!(packets, vec![
+        Packet::Utility(Utility::DeltaClockstampTpq(0x60)),
+        Packet::Midi1 {
+            group: 3,
+            event: MidiEvent::NoteOn {
+                channel: 1,
+                number: 0x3C,
+                velocity: 0x40,
+            },
+        },
+        Packet::Midi2 {
+            group: 3,
+            event: Midi2Event::NoteOn {
+                channel: 1,
+                note: 0x3C,
+                velocity: 0x8000,
+                attribute_type: 0,
+                attribute: 0,
+            },
+        },
+        Packet::Midi2 {
+            group: 0,
+            event: Midi2Event::ProgramChange {
+                channel: 2,
+                program: 5,
+                bank: Some((1, 2)),
+            },
+        },
+        Packet::System {
+            group: 0xF,
+            message: SystemMessage::TimingClock,
+        },
+        Packet::Utility(Utility::Noop),
+    ]);
+
+    let mut written = Vec::new();
+    for packet in &packets {
+        packet.write_to(&mut written);
+    }
+    assert_eq!(written, words);
+
+    let cut_off = [0x4391_3C00];
+    let mut packets = Packets::new(&cut_off);
+    assert_eq!(packets.next(), None);
+    assert_eq!(packets.remainder().len(), 1);
+}
@@ -0,0 +1,458 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use std::fmt;
+
+use nom::Err;
+
+use {event, event_len, header, parse_midi, ErrorKind, Event, Malformed, MetaEvent, Midi,
+     MidiEvent};
+use spans::spans;
+
+
+// File Validation /////////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something worth knowing about that isn't wrong, like an event that
+    /// has no effect.
+    Info,
+    /// Something players will usually cope with, but that breaks the
+    /// standard or suggests the file is damaged.
+    Warning,
+    /// Something that stops the file from parsing.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A problem found in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// Where in the file the problem is.
+    pub offset: usize,
+    /// The index of the track among the file's tracks, if the problem is
+    /// inside one.
+    pub track: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#08X}: {}: ", self.offset, self.severity)?;
+        if let Some(track) = self.track {
+            write!(f, "track {}: ", track)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Checks a Standard MIDI File against the standard, returning every problem
+/// found in the order they appear. A file with no issues of `Error`
+/// severity parses.
+pub fn validate(input: &[u8]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    {
+        let mut issue = |severity, offset, track, message: String| {
+            issues.push(Issue {
+                severity: severity,
+                offset: offset,
+                track: track,
+                message: message,
+            })
+        };
+        let header = match header(input) {
+            Ok((_, header)) => header,
+            _ => {
+                issue(Severity::Error, 0, None, "missing or truncated MThd header".to_string());
+                return issues;
+            }
+        };
+        if header.len != 6 {
+            issue(Severity::Warning, 4, None,
+                  format!("header length is {} instead of 6", header.len));
+        }
+        if header.format > 2 {
+            issue(Severity::Warning, 8, None, format!("unknown format {}", header.format));
+        }
+
+        let mut offset = 14;
+        let mut tracks = 0;
+        while offset < input.len() {
+            let rest = &input[offset..];
+            if rest.len() < 8 {
+                issue(Severity::Error, offset, None, "truncated chunk header".to_string());
+                break;
+            }
+            let len = (rest[4] as usize) << 24 | (rest[5] as usize) << 16 |
+                      (rest[6] as usize) << 8 | rest[7] as usize;
+            if rest.len() - 8 < len {
+                issue(Severity::Error, offset, None,
+                      format!("chunk claims {} bytes but only {} remain", len, rest.len() - 8));
+                break;
+            }
+            if &rest[..4] == b"MTrk" {
+                validate_track(&rest[8..8 + len], offset + 8, tracks, &mut issue);
+                tracks += 1;
+            } else if !rest[..4].iter().all(|&b| b >= 0x20 && b < 0x7F) {
+                issue(Severity::Warning, offset, None,
+                      "chunk type isn't printable ASCII, so this may be garbage".to_string());
+            }
+            offset += 8 + len;
+        }
+
+        if tracks != header.tracks as usize {
+            issue(Severity::Warning, 10, None,
+                  format!("header says {} tracks but there are {}", header.tracks, tracks));
+        }
+        if header.format == 0 && tracks > 1 {
+            issue(Severity::Warning, 8, None,
+                  format!("format 0 file has {} tracks instead of 1", tracks));
+        }
+    }
+    // Anything the checks above missed that the parser still rejects.
+    if !issues.iter().any(|issue| issue.severity == Severity::Error) {
+        if let Err(e) = parse_midi(input) {
+            issues.push(Issue {
+                severity: Severity::Error,
+                offset: e.offset,
+                track: None,
+                message: format!("the parser rejected the file: {}", describe(&e.kind)),
+            });
+        }
+    }
+    issues
+}
+
+fn validate_track<F>(data: &[u8], base: usize, track: usize, issue: &mut F)
+    where F: FnMut(Severity, usize, Option<usize>, String)
+{
+    let track = Some(track);
+    let mut pos = 0;
+    let mut running_status = None;
+    let mut ended = false;
+    let mut held = [[0u8; 128]; 16];
+    while pos < data.len() {
+        let len = match event_len(&data[pos..], &mut running_status.clone()) {
+            Some(len) => len,
+            None => {
+                let message = match event(&data[pos..], &mut running_status.clone()) {
+                    Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+                        format!("malformed event: {}", describe(&e))
+                    }
+                    _ => "truncated event".to_string(),
+                };
+                issue(Severity::Error, base + pos, track, message);
+                return;
+            }
+        };
+        let event = match event(&data[pos..pos + len], &mut running_status) {
+            Ok((_, event)) => event,
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+                issue(Severity::Error, base + pos, track,
+                      format!("malformed event: {}", describe(&e)));
+                return;
+            }
+            Err(Err::Incomplete(_)) => {
+                issue(Severity::Error, base + pos, track, "truncated event".to_string());
+                return;
+            }
+        };
+        if ended {
+            issue(Severity::Warning, base + pos, track, "event after end of track".to_string());
+        }
+        match event {
+            Event::Meta(_, MetaEvent::EndOfTrack) => ended = true,
+            Event::Midi(_, MidiEvent::NoteOn { channel, number, velocity }) if velocity > 0 => {
+                let count = &mut held[channel as usize & 0x0F][number as usize & 0x7F];
+                *count = count.saturating_add(1);
+            }
+            Event::Midi(_, MidiEvent::NoteOn { channel, number, .. }) |
+            Event::Midi(_, MidiEvent::NoteOff { channel, number, .. }) => {
+                let count = &mut held[channel as usize & 0x0F][number as usize & 0x7F];
+                *count = count.saturating_sub(1);
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+    if !ended {
+        issue(Severity::Warning, base + data.len(), track,
+              "track doesn't end with an end of track event".to_string());
+    }
+    let stuck = held.iter().flat_map(|keys| keys.iter()).filter(|&&count| count > 0).count();
+    if stuck > 0 {
+        issue(Severity::Warning, base + data.len(), track,
+              format!("{} note{} never released", stuck,
+                      if stuck == 1 { " is" } else { "s are" }));
+    }
+}
+
+fn describe(kind: &ErrorKind) -> String {
+    match Malformed::from_kind(kind) {
+        Some(malformed) => format!("{:?}", malformed),
+        None => format!("{:?}", kind),
+    }
+}
+
+
+// Semantic Checks /////////////////////////////////////////////////////////////
+
+/// A problem found in a file that parsed, located by event rather than by
+/// byte offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub track: usize,
+    /// The index of the event in its track. Problems with a whole track,
+    /// like a missing end of track, are placed just after its last event.
+    pub event: usize,
+    pub tick: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "track {}, event {} (tick {}): {}: {}",
+               self.track, self.event, self.tick, self.severity, self.message)
+    }
+}
+
+/// Everything `validate_midi` found, in track order.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// The most severe finding's severity, or `None` for a clean file.
+    pub fn worst(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|finding| finding.severity == severity).count()
+    }
+}
+
+/// Checks a parsed file against the rules that parsing doesn't enforce:
+/// where end of track events go, that tempo and meter changes are in the
+/// conductor track of a format 1 file, that channel prefixes are used for
+/// something, that note offs end notes that were started, and that Roland
+/// sysex messages have the right checksums.
+pub fn validate_midi(midi: &Midi) -> Report {
+    let mut report = Report::default();
+    let format = midi.header().format();
+    for (index, track) in midi.tracks().enumerate() {
+        let mut finding = |severity, event, tick, message: String| {
+            report.findings.push(Finding {
+                severity: severity,
+                track: index,
+                event: event,
+                tick: tick,
+                message: message,
+            })
+        };
+        // How many times each key of each channel is held down.
+        let mut held = [[0u32; 128]; 16];
+        // A channel prefix that hasn't been followed by a meta or sysex
+        // event yet, along with where it was.
+        let mut prefix = None;
+        let mut ended = None;
+        let mut tick = 0;
+        for (i, (at, event)) in track.absolute_events().enumerate() {
+            tick = at;
+            if let Some(end) = ended {
+                finding(Severity::Error, i, tick,
+                        format!("event after the end of track at event {}", end));
+            }
+            match *event {
+                Event::Midi(_, ref message) => {
+                    if let Some((p, t)) = prefix.take() {
+                        finding(Severity::Info, p, t,
+                                "channel prefix doesn't apply to any event".to_string());
+                    }
+                    match *message {
+                        MidiEvent::NoteOn { channel, number, velocity } if velocity > 0 => {
+                            held[channel as usize & 0x0F][number as usize & 0x7F] += 1;
+                        }
+                        MidiEvent::NoteOn { channel, number, .. } |
+                        MidiEvent::NoteOff { channel, number, .. } => {
+                            let count = &mut held[channel as usize & 0x0F][number as usize & 0x7F];
+                            if *count == 0 {
+                                finding(Severity::Warning, i, tick,
+                                        format!("note off for key {} on channel {} that isn't \
+                                                 held", number, channel));
+                            }
+                            *count = count.saturating_sub(1);
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Meta(_, MetaEvent::ChannelPrefix(channel)) => {
+                    if channel > 15 {
+                        finding(Severity::Error, i, tick,
+                                format!("channel prefix for channel {}", channel));
+                    }
+                    if let Some((p, t)) = ::std::mem::replace(&mut prefix, Some((i, tick))) {
+                        finding(Severity::Info, p, t,
+                                "channel prefix doesn't apply to any event".to_string());
+                    }
+                }
+                Event::Meta(_, MetaEvent::EndOfTrack) => ended = Some(i),
+                Event::Meta(_, ref meta) => {
+                    prefix = None;
+                    let conductor = match *meta {
+                        MetaEvent::SetTempo(_) => Some("tempo change"),
+                        MetaEvent::TimeSignature { .. } => Some("time signature"),
+                        _ => None,
+                    };
+                    if let Some(what) = conductor {
+                        if format == 1 && index > 0 {
+                            finding(Severity::Warning, i, tick,
+                                    format!("{} outside the conductor track", what));
+                        }
+                    }
+                }
+                Event::Sysex(_, ref sysex) => {
+                    prefix = None;
+                    match sysex.roland() {
+                        Some(ref roland) if !roland.checksum_ok() => {
+                            finding(Severity::Warning, i, tick,
+                                    format!("Roland checksum is {:02X} instead of {:02X}",
+                                            roland.checksum, roland.expected_checksum));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        let len = track.events().len();
+        if ended.is_none() {
+            finding(Severity::Warning, len, tick, "missing end of track".to_string());
+        }
+        let stuck = held.iter().flat_map(|keys| keys.iter()).filter(|&&count| count > 0).count();
+        if stuck > 0 {
+            finding(Severity::Warning, len, tick,
+                    format!("{} note{} never released", stuck,
+                            if stuck == 1 { " is" } else { "s are" }));
+        }
+    }
+    report
+}
+
+
+// Strict Validation ///////////////////////////////////////////////////////////
+
+/// Runs `validate`, and `validate_midi` too for a file that parses, placing
+/// each finding at the byte offset of its event. Some things are checked by
+/// both, so findings where `validate` already has an issue are left out.
+pub fn validate_strict(input: &[u8]) -> Vec<Issue> {
+    let mut issues = validate(input);
+    let (midi, spans) = match (parse_midi(input), spans(input)) {
+        (Ok(midi), Ok(spans)) => (midi, spans),
+        _ => return issues,
+    };
+    let tracks: Vec<_> = spans.chunks.iter().filter(|chunk| chunk.is_track()).collect();
+    for finding in validate_midi(&midi).findings {
+        let chunk = tracks[finding.track];
+        let offset = chunk.events
+            .get(finding.event)
+            .map_or(chunk.offset + 8 + chunk.data.len(), |event| event.offset);
+        let track = Some(finding.track);
+        if !issues.iter().any(|issue| issue.offset == offset && issue.track == track) {
+            issues.push(Issue {
+                severity: finding.severity,
+                offset: offset,
+                track: track,
+                message: finding.message,
+            });
+        }
+    }
+    issues
+}
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_validate() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x90, 0x3C, 0x40,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x90, 0x3C, 0x80,
+    ];
+    let issues = validate(&data);
+    let found: Vec<_> = issues.iter().map(|i| (i.severity, i.offset, i.track)).collect();
+    assert_eq!(found, [
+        (Severity::Warning, 26, Some(0)),
+        (Severity::Warning, 26, Some(0)),
+        (Severity::Error, 34, Some(1)),
+        (Severity::Warning, 8, None),
+    ]);
+    assert_eq!(issues[2].to_string(), "0x000022: error: track 1: malformed event: Switch");
+    assert!(validate(&data[..22]).iter().any(|i| i.severity == Severity::Error));
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0B,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x18,
+        0x00, 0xFF, 0x20, 0x01, 0x02,
+        0x00, 0x91, 0x3C, 0x40,
+        0x10, 0x81, 0x3E, 0x00,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let report = validate_midi(&parse_midi(&data).unwrap());
+    let found: Vec<_> = report.findings.iter()
+        .map(|f| (f.severity, f.track, f.event, f.tick))
+        .collect();
+    assert_eq!(found, [
+        (Severity::Info, 1, 0, 0),
+        (Severity::Warning, 1, 2, 16),
+        (Severity::Warning, 1, 3, 16),
+        (Severity::Warning, 1, 5, 16),
+    ]);
+    assert_eq!(report.worst(), Some(Severity::Warning));
+    assert_eq!(report.findings[3].to_string(),
+               "track 1, event 5 (tick 16): warning: 1 note is never released");
+
+    let issues = validate_strict(&data);
+    let found: Vec<_> = issues.iter().map(|i| (i.severity, i.offset, i.track)).collect();
+    // The stuck note is found by both.
+    assert_eq!(found, [
+        (Severity::Warning, 65, Some(1)),
+        (Severity::Info, 41, Some(1)),
+        (Severity::Warning, 50, Some(1)),
+        (Severity::Warning, 54, Some(1)),
+    ]);
+
+    // Events built in code can have channels and keys out of range.
+    let note = |velocity| MidiEvent::NoteOn { channel: 200, number: 200, velocity: velocity };
+    let midi = Midi {
+        header: ::Header { len: 6, format: 0, tracks: 1, division: 96 },
+        chunks: vec![::Chunk::Track(::TrackChunk {
+            events: vec![
+                Event::Midi(0, note(0x40)),
+                Event::Midi(0, note(0)),
+                Event::Meta(0, MetaEvent::EndOfTrack),
+            ].into(),
+        })],
+        rmid: None,
+    };
+    assert_eq!(validate_midi(&midi).findings, []);
+}
@@ -0,0 +1,180 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {ControlChange, Event, Key, KeyKind, MetaEvent, Midi, MidiEvent, SysexEvent, TextType};
+
+
+// Event Visitors //////////////////////////////////////////////////////////////
+
+/// Where an event happens: the index of its track among the file's tracks,
+/// and its absolute time in ticks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub track: usize,
+    pub tick: u64,
+}
+
+/// Receives the events of a file from `Midi::accept`. Every method does
+/// nothing by default, so a visitor only implements what it cares about.
+/// `visit_midi` and `visit_meta` hand each event to its more specific method,
+/// and overriding them sees every event of that kind instead.
+#[allow(unused_variables)]
+pub trait EventVisitor<'a> {
+    fn visit_midi(&mut self, at: Position, event: &MidiEvent) {
+        match *event {
+            MidiEvent::NoteOn { channel, number, velocity: 0 } => {
+                self.visit_note_off(at, channel, number, 64)
+            }
+            MidiEvent::NoteOn { channel, number, velocity } => {
+                self.visit_note_on(at, channel, number, velocity)
+            }
+            MidiEvent::NoteOff { channel, number, velocity } => {
+                self.visit_note_off(at, channel, number, velocity)
+            }
+            MidiEvent::PolyphonicAftertouch { channel, number, pressure } => {
+                self.visit_polyphonic_aftertouch(at, channel, number, pressure)
+            }
+            MidiEvent::ChannelAftertouch { channel, pressure } => {
+                self.visit_channel_aftertouch(at, channel, pressure)
+            }
+            MidiEvent::Control { channel, change } => self.visit_control(at, channel, change),
+            MidiEvent::ProgramChange { channel, program_number } => {
+                self.visit_program_change(at, channel, program_number)
+            }
+            MidiEvent::PitchBend { channel, pitch } => self.visit_pitch_bend(at, channel, pitch),
+            MidiEvent::Previous(first, second) => self.visit_previous(at, first, second),
+        }
+    }
+
+    /// A note on with a velocity of 0 is passed to `visit_note_off` instead.
+    fn visit_note_on(&mut self, at: Position, channel: u8, key: u8, velocity: u8) {}
+    fn visit_note_off(&mut self, at: Position, channel: u8, key: u8, velocity: u8) {}
+    fn visit_polyphonic_aftertouch(&mut self, at: Position, channel: u8, key: u8, pressure: u8) {}
+    fn visit_channel_aftertouch(&mut self, at: Position, channel: u8, pressure: u8) {}
+    fn visit_control(&mut self, at: Position, channel: u8, change: ControlChange) {}
+    fn visit_program_change(&mut self, at: Position, channel: u8, program: u8) {}
+    fn visit_pitch_bend(&mut self, at: Position, channel: u8, pitch: u16) {}
+    /// The data bytes of a message sent with running status, whose status is
+    /// that of the message before it.
+    fn visit_previous(&mut self, at: Position, first: u8, second: u8) {}
+
+    fn visit_meta(&mut self, at: Position, event: &MetaEvent<'a>) {
+        match *event {
+            MetaEvent::SequenceNumber(number) => self.visit_sequence_number(at, number),
+            MetaEvent::Text { kind, text } => self.visit_text(at, kind, text),
+            MetaEvent::ChannelPrefix(channel) => self.visit_channel_prefix(at, channel),
+            MetaEvent::EndOfTrack => self.visit_end_of_track(at),
+            MetaEvent::SetTempo(tempo) => self.visit_tempo(at, tempo),
+            MetaEvent::SmpteOffset { hours, minutes, seconds, frames, fractional_frames } => {
+                self.visit_smpte_offset(at, hours, minutes, seconds, frames, fractional_frames)
+            }
+            MetaEvent::TimeSignature { numerator, denominator, .. } => {
+                self.visit_time_signature(at, numerator, denominator)
+            }
+            MetaEvent::KeySignature { key, kind } => self.visit_key_signature(at, key, kind),
+            MetaEvent::SequencerSpecific { data } => self.visit_sequencer_specific(at, data),
+            MetaEvent::Other { kind, data } => self.visit_other_meta(at, kind, data),
+        }
+    }
+
+    fn visit_sequence_number(&mut self, at: Position, number: u16) {}
+    fn visit_text(&mut self, at: Position, kind: TextType, text: &'a [u8]) {}
+    fn visit_channel_prefix(&mut self, at: Position, channel: u8) {}
+    fn visit_end_of_track(&mut self, at: Position) {}
+    /// The tempo is in microseconds per quarter note.
+    fn visit_tempo(&mut self, at: Position, tempo: u32) {}
+    /// The fractional frames are in hundredths of a frame.
+    fn visit_smpte_offset(&mut self, at: Position, hours: u8, minutes: u8, seconds: u8,
+                          frames: u8, fractional_frames: u8) {}
+    /// The denominator is a power of two, as stored in the file.
+    fn visit_time_signature(&mut self, at: Position, numerator: u8, denominator: u8) {}
+    fn visit_key_signature(&mut self, at: Position, key: Key, kind: KeyKind) {}
+    fn visit_sequencer_specific(&mut self, at: Position, data: &'a [u8]) {}
+    /// A meta event of a kind the standard doesn't define.
+    fn visit_other_meta(&mut self, at: Position, kind: u8, data: &'a [u8]) {}
+
+    fn visit_sysex(&mut self, at: Position, event: &SysexEvent<'a>) {}
+}
+
+impl<'a> Midi<'a> {
+    /// Passes every event of every track to the visitor, in order of time.
+    /// Events on the same tick are visited in track order.
+    pub fn accept<V: EventVisitor<'a>>(&self, visitor: &mut V) {
+        let mut events = Vec::new();
+        for (index, track) in self.tracks().enumerate() {
+            events.extend(track.absolute_events().map(|(tick, event)| {
+                (Position { track: index, tick: tick }, event)
+            }));
+        }
+        // The sort is stable, so events on the same tick stay in track order.
+        events.sort_by_key(|&(at, _)| at.tick);
+        for (at, event) in events {
+            match *event {
+                Event::Midi(_, ref event) => visitor.visit_midi(at, event),
+                Event::Meta(_, ref event) => visitor.visit_meta(at, event),
+                Event::Sysex(_, ref event) => visitor.visit_sysex(at, event),
+            }
+        }
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_visitor() {
+    #[derive(Default)]
+    struct Log {
+        notes: Vec<(Position, u8)>,
+        tempos: Vec<u64>,
+        pressures: Vec<u8>,
+        keys: Vec<Key>,
+        ends: usize,
+    }
+
+    impl<'a> EventVisitor<'a> for Log {
+        fn visit_note_on(&mut self, at: Position, _: u8, key: u8, _: u8) {
+            self.notes.push((at, key));
+        }
+
+        fn visit_tempo(&mut self, at: Position, _: u32) {
+            self.tempos.push(at.tick);
+        }
+
+        fn visit_channel_aftertouch(&mut self, _: Position, _: u8, pressure: u8) {
+            self.pressures.push(pressure);
+        }
+
+        fn visit_key_signature(&mut self, _: Position, key: Key, _: KeyKind) {
+            self.keys.push(key);
+        }
+
+        fn visit_end_of_track(&mut self, _: Position) {
+            self.ends += 1;
+        }
+    }
+
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x18,
+        0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+        0x00, 0xFF, 0x59, 0x02, 0x02, 0x00,
+        0x20, 0x90, 0x3C, 0x40,
+        0x00, 0xD0, 0x30,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x0C,
+        0x10, 0x90, 0x40, 0x40,
+        0x10, 0x90, 0x40, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let mut log = Log::default();
+    ::parse_midi(&data).unwrap().accept(&mut log);
+    assert_eq!(log.notes, vec![
+        (Position { track: 1, tick: 0x10 }, 0x40),
+        (Position { track: 0, tick: 0x20 }, 0x3C),
+    ]);
+    assert_eq!(log.tempos, vec![0]);
+    assert_eq!((log.pressures, log.keys), (vec![0x30], vec![Key::Sharps(2)]));
+    assert_eq!(log.ends, 2);
+}
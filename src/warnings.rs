@@ -0,0 +1,439 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use nom::Err;
+
+use {chunk, event, header, locate_error, parse_midi, parse_midi_with, track_body_with, Chunk,
+     ErrorKind, Event, Header, Malformed, MetaEvent, Midi, MidiEvent, ParseError, ParseOptions,
+     TrackChunk};
+
+
+// Parse Warnings //////////////////////////////////////////////////////////////
+
+/// Something odd about a file that parsed anyway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The last chunk is a track that claims more bytes than the file has
+    /// left, so it was parsed up to the end of the file instead.
+    TruncatedTrack {
+        chunk: usize,
+        declared: u32,
+        actual: u32,
+    },
+    /// The header's track count doesn't match the tracks in the file.
+    TrackCount {
+        declared: u16,
+        actual: usize,
+    },
+    MissingEndOfTrack {
+        track: usize,
+    },
+    /// A note on that no note off in its track ends.
+    UnreleasedNote {
+        track: usize,
+        tick: u64,
+        channel: u8,
+        key: u8,
+    },
+    /// A meta event of a type the standard doesn't define.
+    UnknownMeta {
+        track: usize,
+        tick: u64,
+        kind: u8,
+    },
+    /// Bytes of a track that couldn't be parsed as events, which lenient
+    /// parsing skipped. The offset counts from the start of the input.
+    Skipped {
+        track: usize,
+        offset: usize,
+        len: usize,
+    },
+}
+
+/// Parses a file like `parse_midi_with`, also collecting anything odd about
+/// it that doesn't stop it from parsing. A last track cut off by the end of
+/// the file is a warning here rather than an error.
+pub fn parse_midi_with_warnings<'a>(input: &'a [u8], options: &ParseOptions)
+                                    -> Result<(Midi<'a>, Vec<Warning>), ParseError> {
+    parse_checked(input, options, false)
+}
+
+/// Parses a file like `parse_midi_with_warnings`, but when an event in a
+/// track is malformed, skips ahead to the next event that parses instead of
+/// failing, recording what was skipped. This keeps one corrupt event from
+/// losing the rest of its track.
+pub fn parse_midi_lenient<'a>(input: &'a [u8], options: &ParseOptions)
+                              -> Result<(Midi<'a>, Vec<Warning>), ParseError> {
+    parse_checked(input, options, true)
+}
+
+fn parse_checked<'a>(input: &'a [u8], options: &ParseOptions, lenient: bool)
+                     -> Result<(Midi<'a>, Vec<Warning>), ParseError> {
+    let mut warnings = Vec::new();
+    let midi = if input.starts_with(b"RIFF") {
+        parse_midi_with(input, options)?
+    } else {
+        parse_smf(input, options, lenient, &mut warnings)?
+    };
+
+    let tracks = midi.tracks().count();
+    if tracks != midi.header.tracks as usize {
+        warnings.push(Warning::TrackCount {
+            declared: midi.header.tracks,
+            actual: tracks,
+        });
+    }
+    for (index, track) in midi.tracks().enumerate() {
+        // The ticks of the notes still held on each channel and key.
+        let mut held: Vec<(u8, u8, u64)> = Vec::new();
+        let mut ended = false;
+        for (tick, event) in track.absolute_events() {
+            match *event {
+                Event::Midi(_, MidiEvent::NoteOn { channel, number, velocity })
+                    if velocity > 0 => held.push((channel, number, tick)),
+                Event::Midi(_, MidiEvent::NoteOn { channel, number, .. }) |
+                Event::Midi(_, MidiEvent::NoteOff { channel, number, .. }) => {
+                    let note = held.iter().position(|&(c, k, _)| (c, k) == (channel, number));
+                    if let Some(i) = note {
+                        held.remove(i);
+                    }
+                }
+                Event::Meta(_, MetaEvent::EndOfTrack) => ended = true,
+                // MIDI port, which is common though it was never standardized.
+                Event::Meta(_, MetaEvent::Other { kind: 0x21, .. }) => {}
+                Event::Meta(_, MetaEvent::Other { kind, .. }) => {
+                    warnings.push(Warning::UnknownMeta {
+                        track: index,
+                        tick: tick,
+                        kind: kind,
+                    });
+                }
+                _ => {}
+            }
+        }
+        if !ended {
+            warnings.push(Warning::MissingEndOfTrack { track: index });
+        }
+        for (channel, key, tick) in held {
+            warnings.push(Warning::UnreleasedNote {
+                track: index,
+                tick: tick,
+                channel: channel,
+                key: key,
+            });
+        }
+    }
+    Ok((midi, warnings))
+}
+
+/// Parses a Standard MIDI File, letting the last track run short.
+fn parse_smf<'a>(input: &'a [u8], options: &ParseOptions, lenient: bool,
+                 warnings: &mut Vec<Warning>)
+                 -> Result<Midi<'a>, ParseError> {
+    let (mut rest, header) = match header(input) {
+        Ok((rest, header)) => (rest, header),
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(locate_error(input, e, options)),
+        Err(Err::Incomplete(_)) => return Err(locate_error(input, ErrorKind::Eof, options)),
+    };
+    let mut chunks = Vec::new();
+    let mut tracks = 0;
+    while !rest.is_empty() {
+        if lenient && rest.len() >= 8 && &rest[..4] == b"MTrk" {
+            let declared = (rest[4] as usize) << 24 | (rest[5] as usize) << 16 |
+                           (rest[6] as usize) << 8 | rest[7] as usize;
+            let len = ::std::cmp::min(declared, rest.len() - 8);
+            if len < declared {
+                warnings.push(Warning::TruncatedTrack {
+                    chunk: chunks.len(),
+                    declared: declared as u32,
+                    actual: len as u32,
+                });
+            }
+            let offset = input.len() - rest.len();
+            if declared > options.limits.max_track_len as usize {
+                return Err(ParseError {
+                    kind: ErrorKind::from(Malformed::TrackTooLong),
+                    offset: offset,
+                    chunk: Some(chunks.len()),
+                    event: None,
+                });
+            }
+            let body = &rest[8..8 + len];
+            let mut skips = Vec::new();
+            let track = recovering_track(body, offset + 8, options, &mut skips)
+                .map_err(|mut e| {
+                    e.chunk = Some(chunks.len());
+                    e
+                })?;
+            warnings.extend(skips.into_iter().map(|skip| {
+                Warning::Skipped {
+                    track: tracks,
+                    offset: skip.offset,
+                    len: skip.len,
+                }
+            }));
+            chunks.push(Chunk::Track(track));
+            tracks += 1;
+            rest = &rest[8 + len..];
+            continue;
+        }
+        match chunk(rest, options) {
+            Ok((next, chunk)) => {
+                if let Chunk::Track(_) = chunk {
+                    tracks += 1;
+                }
+                chunks.push(chunk);
+                rest = next;
+                continue;
+            }
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+                return Err(locate_error(input, e, options))
+            }
+            Err(Err::Incomplete(_)) => {}
+        }
+        // Only a track header that's all there can be cut off.
+        if rest.len() < 8 || &rest[..4] != b"MTrk" {
+            return Err(locate_error(input, ErrorKind::Eof, options));
+        }
+        let data = &rest[8..];
+        let declared = (rest[4] as u32) << 24 | (rest[5] as u32) << 16 | (rest[6] as u32) << 8 |
+                       rest[7] as u32;
+        let track = match track_body_with(data, options) {
+            Ok((_, track)) => track,
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+                return Err(ParseError {
+                    kind: e,
+                    offset: input.len() - rest.len(),
+                    chunk: Some(chunks.len()),
+                    event: None,
+                })
+            }
+            Err(Err::Incomplete(_)) => return Err(locate_error(input, ErrorKind::Eof, options)),
+        };
+        warnings.push(Warning::TruncatedTrack {
+            chunk: chunks.len(),
+            declared: declared,
+            actual: data.len() as u32,
+        });
+        chunks.push(Chunk::Track(track));
+        break;
+    }
+    Ok(Midi {
+        header: header,
+        chunks: chunks,
+        rmid: None,
+    })
+}
+
+/// Parses a file as far as possible, never giving up on it. Whatever could
+/// be recovered is returned along with everything that went wrong, so that
+/// viewers and repair tools can open broken files. A header that's missing
+/// or broken is replaced with one for an empty format 1 file.
+pub fn parse_midi_partial(input: &[u8]) -> (Midi, Vec<ParseError>) {
+    let mut errors = Vec::new();
+    let error = |kind, offset, chunk, event| ParseError {
+        kind: kind,
+        offset: offset,
+        chunk: chunk,
+        event: event,
+    };
+    if input.starts_with(b"RIFF") {
+        match parse_midi(input) {
+            Ok(midi) => return (midi, errors),
+            Err(e) => errors.push(e),
+        }
+    }
+    let start = find(input, b"MThd").unwrap_or(0);
+    let (mut rest, header) = match header(&input[start..]) {
+        Ok((rest, header)) => (rest, header),
+        _ => {
+            if errors.is_empty() {
+                errors.push(error(ErrorKind::Tag, start, None, None));
+            }
+            let header = Header {
+                len: 6,
+                format: 1,
+                tracks: 0,
+                division: 96,
+            };
+            (&input[find(input, b"MTrk").unwrap_or(input.len())..], header)
+        }
+    };
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        let offset = input.len() - rest.len();
+        if rest.len() < 8 {
+            errors.push(error(ErrorKind::Eof, offset, Some(chunks.len()), None));
+            break;
+        }
+        // A chunk type that isn't text means the chunks before it lied about
+        // their lengths, so look for the next track to pick up from.
+        if !rest[..4].iter().all(|&b| b >= 0x20 && b < 0x7F) {
+            errors.push(error(ErrorKind::Tag, offset, Some(chunks.len()), None));
+            match find(&rest[1..], b"MTrk") {
+                Some(i) => rest = &rest[1 + i..],
+                None => break,
+            }
+            continue;
+        }
+        let declared = (rest[4] as usize) << 24 | (rest[5] as usize) << 16 |
+                       (rest[6] as usize) << 8 | rest[7] as usize;
+        let len = ::std::cmp::min(declared, rest.len() - 8);
+        if len < declared {
+            errors.push(error(ErrorKind::Eof, offset, Some(chunks.len()), None));
+        }
+        let body = &rest[8..8 + len];
+        if &rest[..4] == b"MTrk" {
+            let mut skips = Vec::new();
+            let track = recovering_track(body, offset + 8, &ParseOptions::default(), &mut skips)
+                .expect("there are no limits to exceed");
+            for skip in skips {
+                errors.push(error(skip.kind, skip.offset, Some(chunks.len()), Some(skip.event)));
+            }
+            chunks.push(Chunk::Track(track));
+        } else {
+            chunks.push(Chunk::Other {
+                kind: &rest[..4],
+                data: body,
+            });
+        }
+        rest = &rest[8 + len..];
+    }
+    let midi = Midi {
+        header: header,
+        chunks: chunks,
+        rmid: None,
+    };
+    (midi, errors)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Bytes that `recovering_track` skipped, with why the event there didn't
+/// parse and how many events came before it.
+struct Skip {
+    offset: usize,
+    len: usize,
+    kind: ErrorKind,
+    event: usize,
+}
+
+/// Parses the events of a track body, skipping over anything malformed.
+/// `offset` is where the body starts in the input.
+fn recovering_track<'a>(data: &'a [u8], offset: usize, options: &ParseOptions,
+                        skips: &mut Vec<Skip>)
+                        -> Result<TrackChunk<'a>, ParseError> {
+    let mut events = Vec::new();
+    let mut running_status = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        let kind = match event(&data[pos..], &mut running_status) {
+            Ok((rest, event)) => {
+                if let Some(malformed) = options.limits.check(&event, events.len()) {
+                    return Err(ParseError {
+                        kind: ErrorKind::from(malformed),
+                        offset: offset + pos,
+                        chunk: None,
+                        event: Some(events.len()),
+                    });
+                }
+                events.push(options.finish(event));
+                pos = data.len() - rest.len();
+                continue;
+            }
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => e,
+            Err(Err::Incomplete(_)) => ErrorKind::Eof,
+        };
+        // Look for a one byte delta time followed by a status byte that
+        // starts an event that parses. System common and real-time statuses
+        // never appear in files, so they aren't plausible.
+        let start = pos;
+        pos = data.len();
+        for p in start + 1..data.len() {
+            let plausible = match data[p] {
+                0x80...0xF0 | 0xF7 | 0xFF => data[p - 1] < 0x80,
+                _ => false,
+            };
+            if !plausible {
+                continue;
+            }
+            let mut status = None;
+            if event(&data[p - 1..], &mut status).is_ok() {
+                pos = p - 1;
+                break;
+            }
+        }
+        skips.push(Skip {
+            offset: offset + start,
+            len: pos - start,
+            kind: kind,
+            event: events.len(),
+        });
+        running_status = None;
+    }
+    Ok(TrackChunk { events: events.into() })
+}
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_warnings() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0xFF, 0x60, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x10,
+        0x00, 0x90, 0x3C, 0x40,
+        0x10, 0x3E, 0x40,
+        0x10, 0x3E, 0x00,
+    ];
+    assert!(::parse_midi(&data).is_err());
+    let (midi, warnings) = parse_midi_with_warnings(&data, &ParseOptions::default()).unwrap();
+    assert_eq!(midi.tracks().count(), 2);
+    assert_eq!(warnings, [
+        Warning::TruncatedTrack { chunk: 1, declared: 16, actual: 10 },
+        Warning::TrackCount { declared: 1, actual: 2 },
+        Warning::UnknownMeta { track: 0, tick: 0, kind: 0x60 },
+        Warning::MissingEndOfTrack { track: 1 },
+        Warning::UnreleasedNote { track: 1, tick: 0, channel: 0, key: 0x3C },
+    ]);
+
+    let corrupt = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x14,
+        0x00, 0x90, 0x3C, 0x40,
+        0x00, 0xFF, 0x01, 0x00,
+        0x10, 0x12, 0x34,
+        0x10, 0x80, 0x3C, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+        0x00,
+    ];
+    assert!(parse_midi_with_warnings(&corrupt, &ParseOptions::default()).is_err());
+    let (midi, warnings) = parse_midi_lenient(&corrupt, &ParseOptions::default()).unwrap();
+    assert_eq!(midi.tracks().next().unwrap().events().len(), 4);
+    assert_eq!(warnings, [
+        Warning::Skipped { track: 0, offset: 30, len: 3 },
+        Warning::Skipped { track: 0, offset: 41, len: 1 },
+    ]);
+
+    let (midi, errors) = parse_midi_partial(&corrupt[..36]);
+    assert_eq!(midi.tracks().next().unwrap().events().len(), 2);
+    assert_eq!(errors, [
+        ParseError { kind: ErrorKind::Eof, offset: 14, chunk: Some(0), event: None },
+        ParseError {
+            kind: ErrorKind::from(Malformed::NoRunningStatus),
+            offset: 30,
+            chunk: Some(0),
+            event: Some(2),
+        },
+    ]);
+    let (midi, errors) = parse_midi_partial(&corrupt[14..]);
+    assert_eq!(midi.header().format(), 1);
+    assert_eq!(midi.tracks().next().unwrap().events().len(), 4);
+    assert_eq!(errors.len(), 3);
+}
@@ -0,0 +1,25 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use js_sys::JSON;
+use wasm_bindgen::prelude::*;
+
+use json::from_json;
+
+
+// WebAssembly Bindings ////////////////////////////////////////////////////////
+
+/// Parses a file into the object described by `Midi::to_json`, throwing a
+/// string describing the problem if it doesn't parse.
+#[wasm_bindgen(js_name = parseMidi)]
+pub fn parse_midi(bytes: &[u8]) -> Result<JsValue, JsValue> {
+    let midi = ::parse_midi(bytes).map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+    JSON::parse(&midi.to_json())
+}
+
+/// Turns an object in the form `parseMidi` returns back into the bytes of a
+/// file.
+#[wasm_bindgen(js_name = writeMidi)]
+pub fn write_midi(midi: &JsValue) -> Result<Vec<u8>, JsValue> {
+    let json: String = JSON::stringify(midi)?.into();
+    from_json(&json).map_err(|e| JsValue::from_str(&format!("{:?}", e)))
+}
@@ -0,0 +1,365 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {Chunk, Event, Header, Key, KeyKind, MetaEvent, Midi, MidiEvent, SysexEvent, TextType,
+     TrackChunk};
+use convert::Order;
+
+
+// Standard MIDI File Writer ///////////////////////////////////////////////////
+
+/// Settings for `to_bytes_with`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct WriteOptions {
+    /// How the events on each tick of a track are put in order, or `None`
+    /// to write them in the order they're in.
+    pub order: Option<Order>,
+}
+
+impl<'a> Midi<'a> {
+    /// Serializes the file, wrapping it in a RIFF container again if it was
+    /// parsed from an RMID file. Events are written without running status.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(&WriteOptions::default())
+    }
+
+    pub fn to_bytes_with(&self, options: &WriteOptions) -> Vec<u8> {
+        let mut smf = Vec::new();
+        self.header.write_to(&mut smf, self.tracks().count() as u16);
+        for chunk in &self.chunks {
+            match (chunk, options.order) {
+                (&Chunk::Track(ref track), Some(order)) => {
+                    let mut track = track.clone();
+                    track.sort_simultaneous(order);
+                    Chunk::Track(track).write_to(&mut smf);
+                }
+                _ => chunk.write_to(&mut smf),
+            }
+        }
+        match self.rmid {
+            Some(ref rmid) => {
+                let mut out = b"RIFF\0\0\0\0RMID".to_vec();
+                riff_chunk(&mut out, b"data", &smf);
+                for chunk in &rmid.chunks {
+                    riff_chunk(&mut out, chunk.id, chunk.data);
+                }
+                let len = out.len() as u32 - 8;
+                out[4..8].copy_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8,
+                                            (len >> 24) as u8]);
+                out
+            }
+            None => smf,
+        }
+    }
+}
+
+fn riff_chunk(out: &mut Vec<u8>, id: &[u8], data: &[u8]) {
+    let len = data.len() as u32;
+    out.extend_from_slice(id);
+    out.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+    out.extend_from_slice(data);
+    if data.len() & 1 == 1 {
+        out.push(0);
+    }
+}
+
+impl Header {
+    /// Writes a header with the actual number of tracks, since the stored
+    /// count isn't always right.
+    fn write_to(&self, out: &mut Vec<u8>, tracks: u16) {
+        out.extend_from_slice(b"MThd\0\0\0\x06");
+        write_u16(out, self.format);
+        write_u16(out, tracks);
+        write_u16(out, self.division);
+    }
+}
+
+impl<'a> Chunk<'a> {
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        match *self {
+            Chunk::Track(ref track) => {
+                out.extend_from_slice(b"MTrk");
+                let body = track.to_bytes();
+                write_u32(out, body.len() as u32);
+                out.extend_from_slice(&body);
+            }
+            Chunk::Other { kind, data } => {
+                out.extend_from_slice(kind);
+                write_u32(out, data.len() as u32);
+                out.extend_from_slice(data);
+            }
+        }
+    }
+}
+
+impl<'a> TrackChunk<'a> {
+    /// Serializes the events, without the chunk header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for event in &self.events {
+            event.write_to(&mut out);
+        }
+        out
+    }
+}
+
+impl<'a> Event<'a> {
+    /// Writes the event along with its delta time.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        write_var_length(out, self.delta());
+        match *self {
+            Event::Midi(_, ref event) => event.write_to(out),
+            Event::Meta(_, ref event) => event.write_to(out),
+            Event::Sysex(_, ref event) => event.write_to(out),
+        }
+    }
+}
+
+impl MidiEvent {
+    /// Writes the message with its status byte.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        use MidiEvent::*;
+        match *self {
+            NoteOff { channel, number, velocity } => {
+                out.extend_from_slice(&[0x80 | channel & 0x0F, number, velocity])
+            }
+            NoteOn { channel, number, velocity } => {
+                out.extend_from_slice(&[0x90 | channel & 0x0F, number, velocity])
+            }
+            PolyphonicAftertouch { channel, number, pressure } => {
+                out.extend_from_slice(&[0xA0 | channel & 0x0F, number, pressure])
+            }
+            Control { channel, change } => {
+                let (controller, value) = change.parts();
+                out.extend_from_slice(&[0xB0 | channel & 0x0F, controller, value])
+            }
+            ProgramChange { channel, program_number } => {
+                out.extend_from_slice(&[0xC0 | channel & 0x0F, program_number])
+            }
+            ChannelAftertouch { channel, pressure } => {
+                out.extend_from_slice(&[0xD0 | channel & 0x0F, pressure])
+            }
+            PitchBend { channel, pitch } => {
+                out.extend_from_slice(&[0xE0 | channel & 0x0F, pitch as u8 & 0x7F,
+                                        (pitch >> 7) as u8 & 0x7F])
+            }
+            Previous(a, b) => out.extend_from_slice(&[a, b]),
+        }
+    }
+}
+
+impl<'a> MetaEvent<'a> {
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        use MetaEvent::*;
+        let kind = u8::from(self.kind());
+        match *self {
+            SequenceNumber(n) => meta(out, kind, &[(n >> 8) as u8, n as u8]),
+            Text { text, .. } => meta(out, kind, text),
+            ChannelPrefix(channel) => meta(out, kind, &[channel]),
+            EndOfTrack => meta(out, kind, &[]),
+            SetTempo(t) => meta(out, kind, &[(t >> 16) as u8, (t >> 8) as u8, t as u8]),
+            SmpteOffset { hours, minutes, seconds, frames, fractional_frames } => {
+                meta(out, kind, &[hours, minutes, seconds, frames, fractional_frames])
+            }
+            TimeSignature { numerator, denominator, clocks_per_metronome, notated_divisions } => {
+                meta(out, kind, &[numerator, denominator, clocks_per_metronome, notated_divisions])
+            }
+            KeySignature { key, kind: mode } => {
+                let sharps = match key {
+                    Key::Flats(n) => (n as i8).wrapping_neg() as u8,
+                    Key::OfC => 0,
+                    Key::Sharps(n) => n,
+                };
+                meta(out, kind, &[sharps, if mode == KeyKind::Minor { 1 } else { 0 }])
+            }
+            SequencerSpecific { data } | Other { data, .. } => meta(out, kind, data),
+        }
+    }
+}
+
+fn meta(out: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    out.extend_from_slice(&[0xFF, kind]);
+    write_var_length(out, data.len() as u32);
+    out.extend_from_slice(data);
+}
+
+impl From<TextType> for u8 {
+    fn from(kind: TextType) -> u8 {
+        use TextType::*;
+        match kind {
+            Text => 0x01,
+            Copyright => 0x02,
+            TrackName => 0x03,
+            InstrumentName => 0x04,
+            Lyric => 0x05,
+            Marker => 0x06,
+            CuePoint => 0x07,
+            ProgramName => 0x08,
+            DeviceName => 0x09,
+            Other(x) => x,
+        }
+    }
+}
+
+impl<'a> SysexEvent<'a> {
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        out.push(if self.start { 0xF0 } else { 0xF7 });
+        write_var_length(out, self.data.len() as u32);
+        out.extend_from_slice(self.data);
+    }
+}
+
+/// The largest delta time a variable length quantity can hold.
+pub const MAX_DELTA: u32 = 0x0FFF_FFFF;
+
+/// An empty text event, which only passes time, for carrying a gap longer
+/// than one delta can hold.
+pub fn spacer(delta: u32) -> Event<'static> {
+    Event::Meta(delta, MetaEvent::Text {
+        kind: TextType::Text,
+        text: b"",
+    })
+}
+
+/// Writes a variable length quantity, saturating at the largest value the
+/// format allows.
+pub fn write_var_length(out: &mut Vec<u8>, value: u32) {
+    let value = ::std::cmp::min(value, MAX_DELTA);
+    let mut started = false;
+    for shift in [21, 14, 7].iter() {
+        let group = (value >> shift) as u8 & 0x7F;
+        if started || group != 0 {
+            out.push(0x80 | group);
+            started = true;
+        }
+    }
+    out.push(value as u8 & 0x7F);
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&[(value >> 8) as u8, value as u8]);
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+                            value as u8]);
+}
+
+
+// Wire Encoding ///////////////////////////////////////////////////////////////
+
+impl<'a> Event<'a> {
+    /// The bytes of the event as a live message, ready to send to an output
+    /// port, with no delta time and always with a full status byte. Meta
+    /// events only exist in files, so they have none, and neither does a
+    /// `Previous` message, since it needs the message before it; use a
+    /// `WireEncoder` for those.
+    pub fn to_wire_bytes(&self) -> Option<Vec<u8>> {
+        WireEncoder::new().encode(self)
+    }
+}
+
+/// Turns the events of a track into live messages one at a time, keeping
+/// the status of the last channel message so that a `Previous` message can
+/// be sent with it. Running status is never used, since outputs handle
+/// each message on its own.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct WireEncoder {
+    status: Option<u8>,
+}
+
+impl WireEncoder {
+    pub fn new() -> WireEncoder {
+        WireEncoder::default()
+    }
+
+    /// The bytes of the next event, or `None` if it isn't sent. The data of
+    /// an F7 packet is sent as it is, since it either continues a sysex
+    /// message or escapes bytes to send raw.
+    pub fn encode(&mut self, event: &Event) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        match *event {
+            Event::Midi(_, MidiEvent::Previous(a, b)) => {
+                let status = self.status?;
+                out.push(status);
+                out.push(a);
+                if status & 0xE0 != 0xC0 {
+                    out.push(b);
+                }
+            }
+            Event::Midi(_, ref event) => {
+                event.write_to(&mut out);
+                self.status = Some(out[0]);
+            }
+            Event::Sysex(_, ref sysex) => {
+                // Sysex and meta events cancel running status in files.
+                self.status = None;
+                if sysex.start {
+                    out.push(0xF0);
+                }
+                out.extend_from_slice(sysex.data);
+            }
+            Event::Meta(..) => {
+                self.status = None;
+                return None;
+            }
+        }
+        Some(out)
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_round_trip() {
+    let data = [
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x20,
+        0x00, 0xFF, 0x03, 0x03, b'a', b'b', b'c',
+        0x00, 0xFF, 0x59, 0x02, 0xFD, 0x01,
+        0x00, 0x90, 0x3C, 0x40,
+        0x81, 0x00, 0xE0, 0x00, 0x40,
+        0x00, 0xF0, 0x03, 0x7E, 0x00, 0xF7,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let midi = ::parse_midi(&data).unwrap();
+    assert_eq!(&midi.to_bytes()[..], &data[..]);
+    let ordered = midi.to_bytes_with(&WriteOptions { order: Some(Order::Standard) });
+    assert_eq!(&ordered[39..], &[
+        0x81, 0x00, 0xF0, 0x03, 0x7E, 0x00, 0xF7,
+        0x00, 0xE0, 0x00, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ]);
+
+    let mut out = Vec::new();
+    write_var_length(&mut out, 0x0FFF_FFFF);
+    write_var_length(&mut out, 0x80);
+    assert_eq!(out, [0xFF, 0xFF, 0xFF, 0x7F, 0x81, 0x00]);
+
+    let note = Event::Midi(0x60, MidiEvent::NoteOn {
+        channel: 1,
+        number: 0x3C,
+        velocity: 0x40,
+    });
+    assert_eq!(note.to_wire_bytes(), Some(vec![0x91, 0x3C, 0x40]));
+    assert_eq!(Event::Meta(0, MetaEvent::EndOfTrack).to_wire_bytes(), None);
+    let mut encoder = WireEncoder::new();
+    let wire: Vec<_> = midi.tracks()
+        .next()
+        .unwrap()
+        .events()
+        .iter()
+        .filter_map(|event| encoder.encode(event))
+        .collect();
+    assert_eq!(wire, [
+        vec![0x90, 0x3C, 0x40],
+        vec![0xE0, 0x00, 0x40],
+        vec![0xF0, 0x7E, 0x00, 0xF7],
+    ]);
+    assert_eq!(encoder.encode(&Event::Midi(0, MidiEvent::Previous(1, 2))), None);
+    encoder.encode(&note);
+    assert_eq!(encoder.encode(&Event::Midi(0, MidiEvent::Previous(0x3E, 0x40))),
+               Some(vec![0x91, 0x3E, 0x40]));
+}
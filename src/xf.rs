@@ -0,0 +1,292 @@
+// Copyright (c) 2016 by Caleb Jones <code@calebjones.net>
+
+use {track_body, Chunk, Event, MetaEvent, Midi, TextType};
+
+
+// Yamaha XF Extensions ////////////////////////////////////////////////////////
+
+/// The XF data found in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xf<'a> {
+    /// The two version digits, like `b"02"` for XF version 2.
+    pub version: Option<[u8; 2]>,
+    pub info: Option<XfInfo<'a>>,
+    pub karaoke: Option<XfKaraoke<'a>>,
+    /// Every chord name event, by absolute tick.
+    pub chords: Vec<(u64, Chord)>,
+}
+
+/// A Yamaha sequencer-specific meta event, which starts with the ID 43 7B.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum XfMeta<'a> {
+    Version {
+        version: [u8; 2],
+        flags: &'a [u8],
+    },
+    Chord(Chord),
+    Other {
+        kind: u8,
+        data: &'a [u8],
+    },
+}
+
+/// A chord name, with an optional bass note for slash chords.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Chord {
+    pub root: ChordNote,
+    pub kind: ChordType,
+    pub bass: Option<(ChordNote, ChordType)>,
+}
+
+/// A note name from the C major scale, with 0 being C, plus a number of
+/// sharps (if positive) or flats (if negative).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChordNote {
+    pub note: u8,
+    pub accidental: i8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChordType(pub u8);
+
+/// The song information from the XFIH chunk. Text is usually Latin-1 or
+/// Shift JIS depending on the country, and empty fields are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XfInfo<'a> {
+    pub date: Option<&'a [u8]>,
+    pub country: Option<&'a [u8]>,
+    pub category: Option<&'a [u8]>,
+    pub beat: Option<&'a [u8]>,
+    pub melody_instrument: Option<&'a [u8]>,
+    pub vocal_type: Option<&'a [u8]>,
+    pub composer: Option<&'a [u8]>,
+    pub lyricist: Option<&'a [u8]>,
+    pub arranger: Option<&'a [u8]>,
+    pub performer: Option<&'a [u8]>,
+    pub programmer: Option<&'a [u8]>,
+    pub keywords: Option<&'a [u8]>,
+}
+
+/// The karaoke information from the XFKM chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XfKaraoke<'a> {
+    pub language: Option<&'a [u8]>,
+    pub song_name: Option<&'a [u8]>,
+    pub composer: Option<&'a [u8]>,
+    pub lyricist: Option<&'a [u8]>,
+    pub arranger: Option<&'a [u8]>,
+    pub performer: Option<&'a [u8]>,
+    pub programmer: Option<&'a [u8]>,
+}
+
+impl<'a> Midi<'a> {
+    /// Collects the XF data in the file, or `None` if it has none at all.
+    pub fn xf(&self) -> Option<Xf<'a>> {
+        let mut xf = Xf {
+            version: None,
+            info: None,
+            karaoke: None,
+            chords: Vec::new(),
+        };
+        for track in self.tracks() {
+            for (tick, event) in track.absolute_events() {
+                match event_xf(event) {
+                    Some(XfMeta::Version { version, .. }) => {
+                        xf.version = xf.version.or(Some(version));
+                    }
+                    Some(XfMeta::Chord(chord)) => xf.chords.push((tick, chord)),
+                    _ => {}
+                }
+            }
+        }
+        xf.chords.sort_by_key(|&(tick, _)| tick);
+        for chunk in self.chunks() {
+            match *chunk {
+                Chunk::Other { kind: b"XFIH", data } if xf.info.is_none() => {
+                    xf.info = chunk_text(data, b"XFhd:").map(|fields| info(&fields));
+                }
+                Chunk::Other { kind: b"XFKM", data } if xf.karaoke.is_none() => {
+                    xf.karaoke = chunk_text(data, b"XFln:").map(|fields| karaoke(&fields));
+                }
+                _ => {}
+            }
+        }
+        if xf.version.is_none() && xf.info.is_none() && xf.karaoke.is_none() &&
+           xf.chords.is_empty() {
+            None
+        } else {
+            Some(xf)
+        }
+    }
+}
+
+impl<'a> MetaEvent<'a> {
+    pub fn xf(&self) -> Option<XfMeta<'a>> {
+        match *self {
+            MetaEvent::SequencerSpecific { data } => decode(data),
+            _ => None,
+        }
+    }
+}
+
+fn event_xf<'a>(event: &Event<'a>) -> Option<XfMeta<'a>> {
+    match *event {
+        Event::Meta(_, ref meta) => meta.xf(),
+        _ => None,
+    }
+}
+
+/// Decodes the data of a sequencer-specific meta event.
+pub fn decode(data: &[u8]) -> Option<XfMeta> {
+    match data {
+        [0x43, 0x7B, 0x00, b'X', b'F', a, b, flags @ ..] => Some(XfMeta::Version {
+            version: [*a, *b],
+            flags: flags,
+        }),
+        [0x43, 0x7B, 0x01, root, kind, bass, bass_kind] => {
+            Some(XfMeta::Chord(Chord {
+                root: chord_note(*root)?,
+                kind: ChordType(*kind),
+                bass: chord_note(*bass).map(|note| (note, ChordType(*bass_kind))),
+            }))
+        }
+        [0x43, 0x7B, kind, data @ ..] => Some(XfMeta::Other {
+            kind: *kind,
+            data: data,
+        }),
+        _ => None,
+    }
+}
+
+/// The high nibble holds the accidental, with 3 meaning natural, and the low
+/// nibble the note from 1 for C to 7 for B. Chords without a bass note use
+/// 7F, which isn't valid.
+fn chord_note(byte: u8) -> Option<ChordNote> {
+    match (byte >> 4, byte & 0x0F) {
+        (accidental @ 0...6, note @ 1...7) => Some(ChordNote {
+            note: note - 1,
+            accidental: accidental as i8 - 3,
+        }),
+        _ => None,
+    }
+}
+
+impl ChordNote {
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+        name.push(b"CDEFGAB"[self.note as usize % 7] as char);
+        for _ in 0..self.accidental.abs() {
+            name.push(if self.accidental > 0 { '#' } else { 'b' });
+        }
+        name
+    }
+}
+
+impl ChordType {
+    /// The chord symbol suffix, which is empty for major chords.
+    pub fn name(&self) -> Option<&'static str> {
+        CHORD_TYPES.get(self.0 as usize).cloned()
+    }
+}
+
+impl Chord {
+    /// Spells the chord like "C#m7/E", using "?" for unknown chord types.
+    pub fn name(&self) -> String {
+        let mut name = self.root.name();
+        name.push_str(self.kind.name().unwrap_or("?"));
+        if let Some((bass, _)) = self.bass {
+            name.push('/');
+            name.push_str(&bass.name());
+        }
+        name
+    }
+}
+
+static CHORD_TYPES: &'static [&'static str] = &[
+    "", "6", "Maj7", "Maj7(#11)", "(9)", "Maj7(9)", "6(9)", "aug",
+    "m", "m6", "m7", "m7b5", "m(9)", "m7(9)", "m7(11)", "mMaj7",
+    "mMaj7(9)", "dim", "dim7", "7", "7sus4", "7b5", "7(9)", "7(#11)",
+    "7(13)", "7(b9)", "7(b13)", "7(#9)", "Maj7aug", "7aug", "1+8", "1+5",
+    "sus4", "1+2+5", "cc",
+];
+
+/// Finds the colon separated text event with the given prefix in an XF
+/// information chunk, which is laid out like a track.
+fn chunk_text<'a>(data: &'a [u8], prefix: &[u8]) -> Option<Vec<&'a [u8]>> {
+    let track = match track_body(data) {
+        Ok((_, track)) => track,
+        _ => return None,
+    };
+    let text = track.events().iter()
+        .filter_map(|event| match *event {
+            Event::Meta(_, MetaEvent::Text { kind: TextType::Text, text }) => Some(text),
+            _ => None,
+        })
+        .find(|text| text.starts_with(prefix))?;
+    Some(text[prefix.len()..].split(|&b| b == b':').collect())
+}
+
+fn field<'a>(fields: &[&'a [u8]], index: usize) -> Option<&'a [u8]> {
+    fields.get(index).cloned().and_then(|field| if field.is_empty() { None } else { Some(field) })
+}
+
+fn info<'a>(fields: &[&'a [u8]]) -> XfInfo<'a> {
+    XfInfo {
+        date: field(fields, 0),
+        country: field(fields, 1),
+        category: field(fields, 2),
+        beat: field(fields, 3),
+        melody_instrument: field(fields, 4),
+        vocal_type: field(fields, 5),
+        composer: field(fields, 6),
+        lyricist: field(fields, 7),
+        arranger: field(fields, 8),
+        performer: field(fields, 9),
+        programmer: field(fields, 10),
+        keywords: field(fields, 11),
+    }
+}
+
+fn karaoke<'a>(fields: &[&'a [u8]]) -> XfKaraoke<'a> {
+    XfKaraoke {
+        language: field(fields, 0),
+        song_name: field(fields, 1),
+        composer: field(fields, 2),
+        lyricist: field(fields, 3),
+        arranger: field(fields, 4),
+        performer: field(fields, 5),
+        programmer: field(fields, 6),
+    }
+}
+
+
+// Tests ///////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+#[test]
+fn test_xf() {
+    let mut data = vec![
+        0x4D, 0x54, 0x68, 0x64, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x60,
+        0x4D, 0x54, 0x72, 0x6B, 0x00, 0x00, 0x00, 0x1C,
+        0x00, 0xFF, 0x7F, 0x09, 0x43, 0x7B, 0x00, 0x58, 0x46, 0x30, 0x32, 0x00, 0x1B,
+        0x00, 0xFF, 0x7F, 0x07, 0x43, 0x7B, 0x01, 0x41, 0x0A, 0x33, 0x00,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let text = b"XFhd:1999:JP:::::Composer:";
+    data.extend_from_slice(b"XFIH\x00\x00\x00");
+    data.push(text.len() as u8 + 8);
+    data.extend_from_slice(&[0x00, 0xFF, 0x01, text.len() as u8]);
+    data.extend_from_slice(text);
+    data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    let midi = ::parse_midi(&data).unwrap();
+    let xf = midi.xf().unwrap();
+    assert_eq!(xf.version, Some(*b"02"));
+    assert_eq!(xf.chords.len(), 1);
+    assert_eq!(xf.chords[0].1.name(), "C#m7/E");
+    let info = xf.info.unwrap();
+    assert_eq!(info.country, Some(&b"JP"[..]));
+    assert_eq!(info.category, None);
+    assert_eq!(info.composer, Some(&b"Composer"[..]));
+}